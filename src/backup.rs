@@ -1,14 +1,33 @@
+use crate::checksum::{HashingReader, HashingWriter};
 use crate::config;
-use crate::entry::read_dir;
+use crate::encryption::Encryptor;
+use crate::entry::{Entry, read_storage};
+use crate::error::Error;
+use crate::event::{Event, OnEvent};
+use crate::hooks;
+use crate::index::Index;
+use crate::lock::{self, Claim};
+use crate::meta::{self, BackupMeta};
+use crate::permissions::FilePermissions;
+use crate::progress::{CancelToken, OnProgress, Tracker};
+use crate::signature::SigningKey;
+use crate::snapshot::{Snapshot, SnapshotKind};
+use crate::storage::{AlreadyExists, LocalStorage, Storage, Upload};
 use crate::timestamp::Timestamp;
+use crate::verify;
 use anyhow::{Context, Result};
 use chrono::{Local, Timelike};
 use flate2::Compression;
 use flate2::write::GzEncoder;
-use std::fs::File;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
 pub enum ArchiveMode {
     /// Tarball and compress if not already
     AutoDetect,
@@ -18,116 +37,931 @@ pub enum ArchiveMode {
     Force,
 }
 
-#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
 pub enum TimestampSelection {
     Now,
     FileCreated,
     FileModified,
 }
 
-#[derive(Debug)]
-pub enum BackupError {
-    TimestampConflict(String),
-    Other(anyhow::Error),
+/// A backup to create of a file, directory, the output of a command or any stream, made with
+/// [`Backup::builder`], [`Backup::command`] or [`Backup::reader`]:
+///
+/// ```no_run
+/// # use rattlebeaver::{ArchiveMode, Backup};
+/// # let config = rattlebeaver::Config::default();
+/// let path = Backup::builder("/home/me/Documents")
+///     .target("/mnt/backups")
+///     .config(&config.archive)
+///     .mode(ArchiveMode::Force)
+///     .tag("docs")
+///     .run()?;
+/// # Ok::<(), rattlebeaver::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct Backup<'a> {
+    source: BackupSource<'a>,
+    target: Option<BackupTarget<'a>>,
+    config: Option<&'a config::Archive>,
+    timestamp: TimestampSelection,
+    at: Option<Timestamp>,
+    mode: ArchiveMode,
+    compression: Compression,
+    snapshot: Option<SnapshotKind>,
+    name: Option<String>,
+    tags: Vec<String>,
+    exclude: Vec<glob::Pattern>,
+    verify: bool,
+    on_progress: Option<OnProgress<'a>>,
+    cancel: Option<CancelToken>,
+    on_event: Option<OnEvent<'a>>,
+}
+
+#[derive(Clone)]
+enum BackupSource<'a> {
+    Path(PathBuf),
+    /// A shell command whose standard output is backed up
+    Command(String),
+    /// Taken once the backup is written, so neither it nor a clone of it can write it twice
+    Reader(Rc<dyn TakeReader + 'a>),
+}
+
+#[derive(Clone)]
+enum BackupTarget<'a> {
+    /// A local directory, where the index is kept up to date
+    Dir(&'a Path),
+    Storage(&'a dyn Storage),
+    /// A stream the backup is written into, without its sidecar, taken like a reader source
+    Writer(Rc<dyn TakeWriter + 'a>),
+}
+
+/// A reader taken out of a shared cell, behind a trait object so that [`Backup`] stays covariant
+/// in its lifetime (a `RefCell` of it would not be).
+trait TakeReader {
+    fn take(&self) -> Option<Box<dyn Read + '_>>;
 }
 
-impl From<anyhow::Error> for BackupError {
-    fn from(value: anyhow::Error) -> Self {
-        BackupError::Other(value)
+impl TakeReader for RefCell<Option<Box<dyn Read + '_>>> {
+    fn take(&self) -> Option<Box<dyn Read + '_>> {
+        self.borrow_mut().take()
     }
 }
 
-impl std::fmt::Display for BackupError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let display = match self {
-            Self::TimestampConflict(s) => s.to_owned(),
-            Self::Other(e) => e.to_string(),
-        };
-        write!(f, "{display}")
+/// A writer taken out of a shared cell, like [`TakeReader`].
+trait TakeWriter {
+    fn take(&self) -> Option<Box<dyn Write + '_>>;
+}
+
+impl TakeWriter for RefCell<Option<Box<dyn Write + '_>>> {
+    fn take(&self) -> Option<Box<dyn Write + '_>> {
+        self.borrow_mut().take()
     }
 }
 
-impl std::error::Error for BackupError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        if let Self::Other(anyhow_error) = self {
-            Some(&**anyhow_error)
-        } else {
-            None
+impl<'a> Backup<'a> {
+    /// Start building a backup of the file or directory `source`.
+    pub fn builder(source: impl Into<PathBuf>) -> BackupBuilder<'a> {
+        BackupBuilder(Self::new(BackupSource::Path(source.into()), None))
+    }
+
+    /// Start building a backup of the standard output of the shell command `command`, compressed
+    /// with gzip and named after `name`. If the command fails, so does the backup, and nothing is
+    /// left of it.
+    pub fn command(command: impl Into<String>, name: impl Into<String>) -> BackupBuilder<'a> {
+        BackupBuilder(Self::new(
+            BackupSource::Command(command.into()),
+            Some(name.into()),
+        ))
+    }
+
+    /// Start building a backup of everything read from `reader`, compressed with gzip and named
+    /// after `name`, e.g. a dump generated in-process or a download. The backup is timestamped
+    /// now unless given a timestamp with [`BackupBuilder::at`]. If reading fails, so does the
+    /// backup, and nothing is left of it.
+    pub fn reader(reader: impl Read + 'a, name: impl Into<String>) -> BackupBuilder<'a> {
+        BackupBuilder(Self::new(
+            BackupSource::Reader(Rc::new(RefCell::new(Some(
+                Box::new(reader) as Box<dyn Read>
+            )))),
+            Some(name.into()),
+        ))
+    }
+
+    fn new(source: BackupSource<'a>, name: Option<String>) -> Self {
+        Self {
+            source,
+            target: None,
+            config: None,
+            timestamp: TimestampSelection::FileCreated,
+            at: None,
+            mode: ArchiveMode::AutoDetect,
+            compression: Compression::default(),
+            snapshot: None,
+            name,
+            tags: Vec::new(),
+            exclude: Vec::new(),
+            verify: false,
+            on_progress: None,
+            cancel: None,
+            on_event: None,
+        }
+    }
+
+    /// Write the backup and its sidecar, returning the backup's path (just its name if written to
+    /// a stream).
+    pub fn run(&self) -> std::result::Result<PathBuf, Error> {
+        let started = std::time::Instant::now();
+        let source = self.describe_source();
+        let _span = tracing::info_span!("backup", %source).entered();
+        let result = match (self.create(), &self.cancel) {
+            (Err(_), Some(cancel)) if cancel.is_cancelled() => Err(Error::Cancelled),
+            (result, _) => result,
+        };
+        let duration = started.elapsed().as_secs_f64();
+        self.emit(match &result {
+            Ok(path) => {
+                tracing::debug!(path = %path.display(), duration, "wrote backup");
+                Event::BackupFinished {
+                    source,
+                    path: path.clone(),
+                    duration,
+                }
+            }
+            Err(e) => {
+                let error = format!("{e:#}");
+                tracing::debug!(%error, "backup failed");
+                Event::BackupFailed { source, error }
+            }
+        });
+        result
+    }
+
+    /// The source as given in events: its path, its command, or the name of its stream.
+    fn describe_source(&self) -> String {
+        match &self.source {
+            BackupSource::Path(path) => path.display().to_string(),
+            BackupSource::Command(command) => command.clone(),
+            BackupSource::Reader(_) => self.name.clone().unwrap_or_default(),
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(on_event) = self.on_event {
+            on_event(&event);
+        }
+    }
+
+    fn create(&self) -> std::result::Result<PathBuf, Error> {
+        // Started is emitted before anything can fail, so that every Failed follows a Started
+        let timestamp = self.timestamp();
+        self.emit(Event::BackupStarted {
+            source: self.describe_source(),
+            timestamp: timestamp.as_ref().map_or_else(|_| Timestamp::now(), |t| *t),
+        });
+        let timestamp = timestamp?;
+        tracing::debug!(%timestamp, "starting backup");
+        // Without the target's own prefix, encryption and signing key, the backup could be
+        // invisible to the target, or unencrypted where it should be encrypted
+        let config =
+            (self.config).context("no config to back up with (see BackupBuilder::config)")?;
+        match self.target.as_ref() {
+            Some(&BackupTarget::Dir(target)) => {
+                write_to_dir(target, config, timestamp, |storage| {
+                    self.write(storage, config, timestamp)
+                })
+            }
+            Some(&BackupTarget::Storage(storage)) => {
+                write_to_storage(storage, config, timestamp, || {
+                    self.write(storage, config, timestamp)
+                })
+            }
+            Some(BackupTarget::Writer(writer)) => {
+                if self.verify {
+                    return Err(anyhow::anyhow!("can't verify a backup written to a stream").into());
+                }
+                let writer = writer
+                    .take()
+                    .context("the backup was already written to its stream")?;
+                let storage = WriterStorage(RefCell::new(Some(writer)));
+                let name = self.write(&storage, config, timestamp)?;
+                Ok(storage.locate(&name))
+            }
+            None => Err(anyhow::anyhow!("no target to back up to").into()),
+        }
+    }
+
+    /// The timestamp to give the backup, failing if the source doesn't exist.
+    fn timestamp(&self) -> std::result::Result<Timestamp, Error> {
+        if let BackupSource::Path(source) = &self.source {
+            std::fs::metadata(source).map_err(|e| Error::io(source, e))?;
+        }
+        let timestamp = match (&self.source, self.at) {
+            (_, Some(at)) => Timestamp(at.0.with_nanosecond(0).context("zero nanoseconds")?),
+            (BackupSource::Path(source), None) => get_file_timestamp(source, self.timestamp)?,
+            (BackupSource::Command(_) | BackupSource::Reader(_), None) => {
+                get_file_timestamp(Path::new(""), TimestampSelection::Now)?
+            }
+        };
+        Ok(timestamp)
+    }
+
+    fn write(
+        &self,
+        storage: &dyn Storage,
+        config: &config::Archive,
+        timestamp: Timestamp,
+    ) -> Result<String> {
+        match &self.source {
+            BackupSource::Path(source) => write_backup(self, source, storage, config, timestamp),
+            BackupSource::Command(command) => {
+                write_command_backup(self, command, storage, config, timestamp)
+            }
+            BackupSource::Reader(reader) => {
+                let reader = reader.take().context("the stream was already backed up")?;
+                write_stream(self, reader, storage, config, timestamp, |_| {
+                    Ok(BackupMeta::default())
+                })
+            }
         }
     }
 }
 
+/// Options of a [`Backup`], set before running or building it.
+#[derive(Clone)]
+pub struct BackupBuilder<'a>(Backup<'a>);
+
+impl<'a> BackupBuilder<'a> {
+    /// Write the backup into the directory `target`, keeping its index up to date.
+    #[must_use]
+    pub fn target(mut self, target: &'a (impl AsRef<Path> + ?Sized)) -> Self {
+        self.0.target = Some(BackupTarget::Dir(target.as_ref()));
+        self
+    }
+
+    /// Write the backup into any storage, checking for conflicts by listing it.
+    #[must_use]
+    pub fn storage(mut self, storage: &'a dyn Storage) -> Self {
+        self.0.target = Some(BackupTarget::Storage(storage));
+        self
+    }
+
+    /// Stream the backup into `writer` (e.g. stdout, to pipe it into another program) instead
+    /// of a target. It is named and encrypted as for a target, but its sidecar is left out, so
+    /// neither its metadata nor its signature are kept. It can't be verified, and what was
+    /// written of it is left as is if it fails.
+    #[must_use]
+    pub fn writer(mut self, writer: impl Write + 'a) -> Self {
+        let writer: Box<dyn Write> = Box::new(writer);
+        self.0.target = Some(BackupTarget::Writer(Rc::new(RefCell::new(Some(writer)))));
+        self
+    }
+
+    /// How to name, encrypt and sign the backup, usually the `archive` settings of the target's
+    /// config. Required.
+    #[must_use]
+    pub fn config(mut self, config: &'a config::Archive) -> Self {
+        self.0.config = Some(config);
+        self
+    }
+
+    /// How to timestamp the backup [default: when the source was created]. Commands are always
+    /// timestamped now.
+    #[must_use]
+    pub fn timestamp(mut self, timestamp: TimestampSelection) -> Self {
+        self.0.timestamp = timestamp;
+        self
+    }
+
+    /// Timestamp the backup `timestamp`, whatever the source.
+    #[must_use]
+    pub fn at(mut self, timestamp: Timestamp) -> Self {
+        self.0.at = Some(timestamp);
+        self
+    }
+
+    /// How to handle a single file [default: auto-detect].
+    #[must_use]
+    pub fn mode(mut self, mode: ArchiveMode) -> Self {
+        self.0.mode = mode;
+        self
+    }
+
+    /// Gzip compression level, from 0 (none) to 9 (best) [default: 6].
+    #[must_use]
+    pub fn compression(mut self, level: u32) -> Self {
+        self.0.compression = Compression::new(level.min(9));
+        self
+    }
+
+    /// Archive the source from a snapshot of its filesystem, removed once the archive is
+    /// written.
+    #[must_use]
+    pub fn snapshot(mut self, snapshot: SnapshotKind) -> Self {
+        self.0.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Name the backup `name` instead of after the file stem of the source.
+    #[must_use]
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.0.name = Some(name.into());
+        self
+    }
+
+    #[must_use]
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.0.tags.push(tag.into());
+        self
+    }
+
+    #[must_use]
+    pub fn tags(mut self, tags: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.0.tags.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Leave out paths in a directory source matching any of `exclude` (see [`is_excluded`]).
+    #[must_use]
+    pub fn excludes(mut self, exclude: impl IntoIterator<Item = glob::Pattern>) -> Self {
+        self.0.exclude.extend(exclude);
+        self
+    }
+
+    /// Read the backup back before writing its sidecar, and delete it if it differs from what
+    /// was written or (for unencrypted archives) is not a valid archive.
+    #[must_use]
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.0.verify = verify;
+        self
+    }
+
+    /// Report the progress of archiving the source to `on_progress` as it goes.
+    #[must_use]
+    pub fn progress(mut self, on_progress: OnProgress<'a>) -> Self {
+        self.0.on_progress = Some(on_progress);
+        self
+    }
+
+    /// Stop writing the backup once `cancel` is cancelled, deleting what was written of it, and
+    /// fail with [`Error::Cancelled`].
+    #[must_use]
+    pub fn cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.0.cancel = Some(cancel);
+        self
+    }
+
+    /// Report the backup starting, each file archived, and the backup finishing or failing to
+    /// `on_event`.
+    #[must_use]
+    pub fn events(mut self, on_event: OnEvent<'a>) -> Self {
+        self.0.on_event = Some(on_event);
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> Backup<'a> {
+        self.0
+    }
+
+    /// Write the backup and its sidecar, returning the backup's path (just its name if written to
+    /// a stream).
+    pub fn run(self) -> std::result::Result<PathBuf, Error> {
+        self.0.run()
+    }
+}
+
+/// Back up `source` into the directory `target`.
+#[deprecated(note = "use `Backup::builder(source).target(target)`")]
+#[allow(clippy::too_many_arguments)]
 pub fn create_backup(
     source: &Path,
     target: &Path,
     config: &config::Archive,
     timestamp: TimestampSelection,
     archive_behavior: ArchiveMode,
-) -> std::result::Result<PathBuf, BackupError> {
+    snapshot: Option<SnapshotKind>,
+    name: Option<&str>,
+    tags: &[String],
+    verify: bool,
+) -> std::result::Result<PathBuf, Error> {
+    path_builder(source, config, timestamp, archive_behavior, snapshot, name)
+        .target(target)
+        .tags(tags)
+        .verify(verify)
+        .run()
+}
+
+/// Back up `source` in any storage, checking for conflicts by listing it.
+#[deprecated(note = "use `Backup::builder(source).storage(storage)`")]
+#[allow(clippy::too_many_arguments)]
+pub fn create_backup_in(
+    source: &Path,
+    storage: &dyn Storage,
+    config: &config::Archive,
+    timestamp: TimestampSelection,
+    archive_behavior: ArchiveMode,
+    snapshot: Option<SnapshotKind>,
+    name: Option<&str>,
+    tags: &[String],
+    verify: bool,
+) -> std::result::Result<PathBuf, Error> {
+    path_builder(source, config, timestamp, archive_behavior, snapshot, name)
+        .storage(storage)
+        .tags(tags)
+        .verify(verify)
+        .run()
+}
+
+fn path_builder<'a>(
+    source: &Path,
+    config: &'a config::Archive,
+    timestamp: TimestampSelection,
+    archive_behavior: ArchiveMode,
+    snapshot: Option<SnapshotKind>,
+    name: Option<&str>,
+) -> BackupBuilder<'a> {
+    let mut builder = Backup::builder(source)
+        .config(config)
+        .timestamp(timestamp)
+        .mode(archive_behavior);
+    if let Some(snapshot) = snapshot {
+        builder = builder.snapshot(snapshot);
+    }
+    if let Some(name) = name {
+        builder = builder.name(name);
+    }
+    builder
+}
+
+/// Back up the standard output of the shell command `command` into the directory `target`.
+#[deprecated(note = "use `Backup::command(command, name).target(target)`")]
+pub fn create_command_backup(
+    command: &str,
+    name: &str,
+    target: &Path,
+    config: &config::Archive,
+    tags: &[String],
+    verify: bool,
+) -> std::result::Result<PathBuf, Error> {
+    Backup::command(command, name)
+        .target(target)
+        .config(config)
+        .tags(tags)
+        .verify(verify)
+        .run()
+}
+
+/// Back up the standard output of the shell command `command` in any storage.
+#[deprecated(note = "use `Backup::command(command, name).storage(storage)`")]
+pub fn create_command_backup_in(
+    command: &str,
+    name: &str,
+    storage: &dyn Storage,
+    config: &config::Archive,
+    tags: &[String],
+    verify: bool,
+) -> std::result::Result<PathBuf, Error> {
+    Backup::command(command, name)
+        .storage(storage)
+        .config(config)
+        .tags(tags)
+        .verify(verify)
+        .run()
+}
+
+/// Write a backup timestamped `timestamp` into the directory `target` with `write`, which
+/// returns its name, checking the index for conflicts first and adding the backup to it after.
+fn write_to_dir(
+    target: &Path,
+    config: &config::Archive,
+    timestamp: Timestamp,
+    write: impl FnOnce(&LocalStorage) -> Result<String>,
+) -> std::result::Result<PathBuf, Error> {
     ensure_dir(target)?;
-    let timestamp = get_file_timestamp(source, timestamp)?;
-    let existing_backups = read_dir(target, config).context("read existing backups")?;
-    for existing in existing_backups {
-        if timestamp == existing.timestamp {
-            let error = BackupError::TimestampConflict(format!(
-                "timestamp {timestamp} conflicts with existing backup: {}",
-                existing.path.display()
-            ));
-            return Err(error);
-        }
+    let storage = (LocalStorage::new(target))
+        .with_permissions(FilePermissions::from_config(config)?)
+        .with_fsync(config.fsync);
+    let Some(_claim) = Claim::acquire(target, timestamp)? else {
+        return Err(Error::TimestampConflict {
+            timestamp,
+            existing: None,
+        });
+    };
+    let index = {
+        let _index_lock = lock::lock_index(target)?;
+        Index::load(target, config).context("load index")?
+    };
+    // An indexed backup that is gone was deleted without updating the index, and is replaced
+    if let Some(existing) = index.get(target, timestamp)
+        && existing.exists()
+    {
+        return Err(conflict(timestamp, &existing));
+    }
+    let name = write(&storage).map_err(|e| match e.downcast_ref::<AlreadyExists>() {
+        // Not in the index, but there all the same
+        Some(AlreadyExists(existing)) => conflict(timestamp, existing),
+        None => Error::from(e),
+    })?;
+    let path = storage.locate(&name);
+    let entry = Entry::from_path(path.clone(), config)
+        .context("parse new backup")?
+        .context("new backup does not match the prefix")?;
+    let _index_lock = lock::lock_index(target)?;
+    let mut index = Index::load(target, config).context("load index")?;
+    index.insert(&entry)?;
+    index.save(target, config).context("save index")?;
+    Ok(path)
+}
+
+/// Write a backup timestamped `timestamp` into `storage` with `write`, which returns its name,
+/// checking for conflicts by listing the storage first.
+fn write_to_storage(
+    storage: &dyn Storage,
+    config: &config::Archive,
+    timestamp: Timestamp,
+    write: impl FnOnce() -> Result<String>,
+) -> std::result::Result<PathBuf, Error> {
+    let existing = read_storage(storage, config).context("read existing backups")?;
+    if let Some(existing) = existing.iter().find(|b| b.timestamp == timestamp) {
+        return Err(conflict(timestamp, &existing.path));
+    }
+    let name = write()?;
+    Ok(storage.locate(&name))
+}
+
+fn conflict(timestamp: Timestamp, existing: &Path) -> Error {
+    Error::TimestampConflict {
+        timestamp,
+        existing: Some(existing.to_owned()),
+    }
+}
+
+/// Delete the backup `name` after writing it failed with `error`, unless it failed because a
+/// file by that name already existed.
+fn delete_partial(storage: &dyn Storage, name: &str, error: &anyhow::Error) {
+    if error.downcast_ref::<AlreadyExists>().is_none()
+        && let Err(e) = storage.delete(name)
+    {
+        tracing::warn!("delete partial backup {name}: {e:#}");
     }
+}
+
+/// Write `backup` of `source` and its sidecar to `storage`, returning the backup's name.
+fn write_backup(
+    backup: &Backup,
+    source: &Path,
+    storage: &dyn Storage,
+    config: &config::Archive,
+    timestamp: Timestamp,
+) -> Result<String> {
+    let name = backup.name.as_deref();
+    let started = std::time::Instant::now();
     let file_name = format!(
         "{}{}",
         config.prefix,
         timestamp.as_ref().format(&config.timestamp_format),
     );
 
-    let final_target_path = if source.is_dir() {
-        let source_stem = get_file_stem(source)?;
-        let target_path = target.join(format!("{file_name}.{source_stem}.tar.gz"));
-        let tar_gz = File::create(&target_path).context("create archive file")?;
-        let enc = GzEncoder::new(tar_gz, Compression::default());
-        let mut tarball = tar::Builder::new(enc);
-        tarball
-            .append_dir_all("", source)
-            .context("add dir to tarball")?;
-        tarball.finish().context("create tarball")?;
-        target_path
-    } else if source.is_file() {
+    let encryption = config.encryption();
+    encryption.check()?;
+    let signing_key = (config.signing_key.as_deref())
+        .map(SigningKey::load)
+        .transpose()?;
+    let suffix = if encryption.is_enabled() {
+        encryption.cipher.suffix()
+    } else {
+        ""
+    };
+
+    let taken = (backup
+        .snapshot
+        .map(|kind| Snapshot::create(kind, source))
+        .transpose())
+    .context("snapshot source")?;
+    let read_from = taken.as_ref().map_or(source, Snapshot::path);
+    let uncompressed_size = meta::total_size(read_from).context("get source size");
+    let tracker = Tracker::new(
+        backup.on_progress,
+        backup.cancel.as_ref(),
+        uncompressed_size.as_ref().ok().copied(),
+    )
+    .events(backup.on_event);
+    let (final_name, written) = if read_from.is_dir() {
+        let source_stem = source_name(source, name)?;
+        let target_name = format!("{file_name}.{source_stem}.tar.gz{suffix}");
+        let written = create_tarball(storage, &target_name, config, backup.compression).and_then(
+            |mut tarball| {
+                append_dir(
+                    &mut tarball,
+                    read_from,
+                    Path::new(""),
+                    &backup.exclude,
+                    &tracker,
+                )
+                .context("add dir to tarball")?;
+                finish_tarball(tarball)
+            },
+        );
+        (target_name, written)
+    } else if read_from.is_file() {
         let is_archive = source.display().to_string().ends_with(".tar.gz");
-        let make_archive = match (archive_behavior, is_archive) {
+        let make_archive = match (backup.mode, is_archive) {
             (ArchiveMode::Force, _) | (ArchiveMode::AutoDetect, false) => true,
             (ArchiveMode::AsIs, _) | (ArchiveMode::AutoDetect, true) => false,
         };
         if make_archive {
-            let source_stem = get_file_stem(source)?;
-            let mut source_file = std::fs::File::open(source).context("open source file")?;
-            let target_path = target.join(format!("{file_name}.{source_stem}.tar.gz"));
-            let tar_gz = File::create(&target_path).context("create archive file")?;
-            let enc = GzEncoder::new(tar_gz, Compression::default());
-            let mut tarball = tar::Builder::new(enc);
-            tarball
-                .append_file(
-                    source.file_name().context("missing file name")?,
-                    &mut source_file,
-                )
-                .context("add dir to tarball")?;
-            tarball.finish().context("create tarball")?;
-            target_path
+            let source_stem = source_name(source, name)?;
+            let target_name = format!("{file_name}.{source_stem}.tar.gz{suffix}");
+            let file_name = source.file_name().context("missing file name")?;
+            let written = create_tarball(storage, &target_name, config, backup.compression)
+                .and_then(|mut tarball| {
+                    append_file(&mut tarball, read_from, Path::new(file_name), &tracker)
+                        .context("add file to tarball")?;
+                    finish_tarball(tarball)
+                });
+            (target_name, written)
         } else {
-            let source_name = source
-                .file_name()
-                .context("get file name")?
-                .to_string_lossy();
-            let target_path = target.join(format!("{file_name}.{source_name}"));
-            std::fs::copy(source, &target_path).context("copy file")?;
-            target_path
+            // Named files keep their extension, so the label stays apart from it
+            let source_name = match (name, source.extension()) {
+                (None, _) => (source.file_name().context("get file name")?)
+                    .to_string_lossy()
+                    .into_owned(),
+                (Some(_), None) => source_name(source, name)?,
+                (Some(_), Some(extension)) => format!(
+                    "{}.{}",
+                    source_name(source, name)?,
+                    extension.to_string_lossy()
+                ),
+            };
+            let target_name = format!("{file_name}.{source_name}{suffix}");
+            let written = copy_file(storage, read_from, &target_name, config, &tracker);
+            (target_name, written)
+        }
+    } else {
+        anyhow::bail!("source file is neither a file nor directory");
+    };
+    // A partial backup could later be read as a complete one
+    let (checksum, written_size) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            delete_partial(storage, &final_name, &e);
+            return Err(e);
+        }
+    };
+    let file_count = meta::file_count(read_from).context("count source files")?;
+    // The backup is complete all the same, so only the snapshot is left for manual cleanup
+    if let Some(taken) = taken
+        && let Err(e) = taken.remove()
+    {
+        tracing::warn!("remove snapshot (left for manual cleanup): {e:#}");
+    }
+    let duration = started.elapsed();
+    if backup.verify {
+        verify_or_delete(storage, &final_name, &checksum)?;
+    }
+    let signature = signing_key.map(|key| key.sign(&checksum));
+    let meta = BackupMeta {
+        source: Some(std::path::absolute(source).context("get absolute source path")?),
+        hostname: meta::hostname(),
+        user: meta::user(),
+        version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+        archive_mode: Some(backup.mode),
+        duration: Some(duration.as_secs_f64()),
+        uncompressed_size: Some(uncompressed_size?),
+        written_size: Some(written_size),
+        file_count: Some(file_count),
+        checksum: Some(checksum),
+        signature,
+        tags: backup.tags.clone(),
+        note: None,
+        command: None,
+        snapshot: backup.snapshot,
+    };
+    meta.write_to(storage, &final_name)
+        .context("write backup metadata")?;
+    Ok(final_name)
+}
+
+/// Write `backup`, the gzipped standard output of `command`, and its sidecar to `storage`,
+/// returning the backup's name. The backup is deleted if the command fails.
+fn write_command_backup(
+    backup: &Backup,
+    command: &str,
+    storage: &dyn Storage,
+    config: &config::Archive,
+    timestamp: Timestamp,
+) -> Result<String> {
+    let mut child = hooks::shell(command)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .with_context(|| format!("run {command}"))?;
+    let output = child.stdout.take().context("capture command output")?;
+    // The output is dropped before this is called, so a command still writing gets a broken
+    // pipe rather than blocking forever
+    let finish = |copied: &std::io::Result<u64>| {
+        if copied.is_err() {
+            let _ = child.kill();
         }
+        let status = child.wait().context("wait for command")?;
+        anyhow::ensure!(status.success(), "command failed ({status})");
+        Ok(BackupMeta {
+            command: Some(command.to_owned()),
+            ..BackupMeta::default()
+        })
+    };
+    write_stream(backup, output, storage, config, timestamp, finish)
+        .with_context(|| format!("back up the output of {command}"))
+}
+
+/// Write `backup`, the gzipped contents of `reader`, and its sidecar to `storage`, returning
+/// the backup's name.
+///
+/// Once `reader` is read to the end (or fails) and dropped, `finish` is given the size read and
+/// returns the sidecar's fields about the source. The backup is deleted if either fails.
+fn write_stream(
+    backup: &Backup,
+    reader: impl Read,
+    storage: &dyn Storage,
+    config: &config::Archive,
+    timestamp: Timestamp,
+    finish: impl FnOnce(&std::io::Result<u64>) -> Result<BackupMeta>,
+) -> Result<String> {
+    let started = std::time::Instant::now();
+    let encryption = config.encryption();
+    encryption.check()?;
+    let signing_key = (config.signing_key.as_deref())
+        .map(SigningKey::load)
+        .transpose()?;
+    let suffix = if encryption.is_enabled() {
+        encryption.cipher.suffix()
     } else {
-        return Err(anyhow::anyhow!("source file is neither a file nor directory").into());
+        ""
+    };
+    let target_name = format!(
+        "{}{}.{}.gz{suffix}",
+        config.prefix,
+        timestamp.as_ref().format(&config.timestamp_format),
+        source_name(Path::new(""), backup.name.as_deref())?,
+    );
+    let tracker = Tracker::new(backup.on_progress, backup.cancel.as_ref(), None);
+    let written = (storage.write_new(&target_name))
+        .and_then(|upload| {
+            let upload = HashingWriter::new(upload);
+            let writer = GzEncoder::new(
+                Encryptor::new(upload, encryption, &target_name)?,
+                backup.compression,
+            );
+            Ok(writer)
+        })
+        .and_then(|mut writer| {
+            let copied = std::io::copy(&mut tracker.reader(reader), &mut writer);
+            let meta = finish(&copied)?;
+            let size = copied.context("compress stream")?;
+            let writer = writer.finish().context("compress stream")?.finish()?;
+            let written_size = writer.written();
+            let (upload, checksum) = writer.finish().context("write backup")?;
+            upload.finish()?;
+            Ok((meta, size, written_size, checksum))
+        });
+    let (meta, size, written_size, checksum) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            delete_partial(storage, &target_name, &e);
+            return Err(e);
+        }
+    };
+    if backup.verify {
+        verify_or_delete(storage, &target_name, &checksum)?;
+    }
+    let meta = BackupMeta {
+        hostname: meta::hostname(),
+        user: meta::user(),
+        version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+        duration: Some(started.elapsed().as_secs_f64()),
+        uncompressed_size: Some(size),
+        written_size: Some(written_size),
+        signature: signing_key.map(|key| key.sign(&checksum)),
+        checksum: Some(checksum),
+        tags: backup.tags.clone(),
+        ..meta
+    };
+    meta.write_to(storage, &target_name)
+        .context("write backup metadata")?;
+    Ok(target_name)
+}
+
+/// Copy the file at `path` to `storage` as is (but encrypted if the config says so), returning
+/// its SHA-256 digest and size as stored.
+fn copy_file(
+    storage: &dyn Storage,
+    path: &Path,
+    name: &str,
+    config: &config::Archive,
+    tracker: &Tracker,
+) -> Result<(String, u64)> {
+    let source_file = std::fs::File::open(path).context("open source file")?;
+    let upload = HashingWriter::new(storage.write_new(name)?);
+    let mut writer = Encryptor::new(upload, config.encryption(), name)?;
+    let size = std::io::copy(&mut tracker.reader(source_file), &mut writer).context("copy file")?;
+    tracker.add_file(Path::new(path.file_name().unwrap_or_default()), size);
+    let writer = writer.finish()?;
+    let written_size = writer.written();
+    let (upload, checksum) = writer.finish().context("finish copy")?;
+    upload.finish()?;
+    Ok((checksum, written_size))
+}
+
+/// The storage of a backup written to a stream: the backup goes into the stream, and its sidecar
+/// is discarded. Nothing is listed, and nothing can be read back or deleted.
+struct WriterStorage<'a>(RefCell<Option<Box<dyn Write + 'a>>>);
+
+/// The stream a backup is written into, or None for its discarded sidecar.
+struct WriterUpload<'a>(Option<Box<dyn Write + 'a>>);
+
+impl Storage for WriterStorage<'_> {
+    fn list(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        anyhow::bail!("can't read back {name} from a stream")
+    }
+
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        if name.ends_with(meta::SIDECAR_SUFFIX) {
+            return Ok(Box::new(WriterUpload(None)));
+        }
+        let writer = (self.0.borrow_mut().take()).context("a stream holds a single backup")?;
+        Ok(Box::new(WriterUpload(Some(writer))))
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        anyhow::bail!("can't delete {name} from a stream")
+    }
+
+    fn rename(&self, from: &str, _to: &str) -> Result<()> {
+        anyhow::bail!("can't rename {from} in a stream")
+    }
+
+    fn locate(&self, name: &str) -> PathBuf {
+        PathBuf::from(name)
+    }
+}
+
+impl Write for WriterUpload<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.0 {
+            Some(writer) => writer.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.0 {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Upload for WriterUpload<'_> {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush().context("flush output")
+    }
+}
+
+/// Read back the backup `name` as with [`verify_written`], deleting it if it fails.
+fn verify_or_delete(storage: &dyn Storage, name: &str, checksum: &str) -> Result<()> {
+    let Err(e) = verify_written(storage, name, checksum) else {
+        return Ok(());
     };
+    Err(match storage.delete(name) {
+        Ok(()) => e.context(format!("verify {name} (deleted it)")),
+        Err(_) => e.context(format!("verify {name} (failed to delete it)")),
+    })
+}
 
-    Ok(final_target_path)
+/// Read back the backup `name`, checking that its digest is `checksum` and that it is a valid
+/// archive if it is an unencrypted `.tar.gz`, or decompresses if it is an unencrypted `.gz`.
+fn verify_written(storage: &dyn Storage, name: &str, checksum: &str) -> Result<()> {
+    let mut reader = HashingReader::new(storage.read(name)?);
+    if name.ends_with(".tar.gz") {
+        verify::read_tar_gz(&mut reader)?;
+    } else if name.ends_with(".gz") {
+        let mut decoder = flate2::read::GzDecoder::new(&mut reader);
+        std::io::copy(&mut decoder, &mut std::io::sink()).context("decompress")?;
+    } else {
+        std::io::copy(&mut reader, &mut std::io::sink()).context("read back")?;
+    }
+    let actual = reader.finish();
+    anyhow::ensure!(
+        actual == checksum,
+        "read back checksum {actual}, but wrote {checksum}"
+    );
+    Ok(())
 }
 
 fn get_file_timestamp(file: &Path, selection: TimestampSelection) -> Result<Timestamp> {
@@ -149,6 +983,114 @@ fn get_file_timestamp(file: &Path, selection: TimestampSelection) -> Result<Time
     Ok(Timestamp(timestamp))
 }
 
+type Tarball<'a> = tar::Builder<GzEncoder<Encryptor<HashingWriter<Box<dyn Upload + 'a>>>>>;
+
+/// Create a compressed tarball named `name` in `storage`, encrypted if the config says so and
+/// hashed as it is written.
+fn create_tarball<'a>(
+    storage: &'a dyn Storage,
+    name: &str,
+    config: &config::Archive,
+    compression: Compression,
+) -> Result<Tarball<'a>> {
+    let tar_gz = storage.write_new(name).context("create archive file")?;
+    let encryptor = Encryptor::new(HashingWriter::new(tar_gz), config.encryption(), name)?;
+    let enc = GzEncoder::new(encryptor, compression);
+    let mut tarball = tar::Builder::new(enc);
+    // Links are archived as links, so they can't pull in files from outside the source
+    tarball.follow_symlinks(false);
+    Ok(tarball)
+}
+
+/// Finish writing a tarball, returning its SHA-256 digest and size as stored.
+fn finish_tarball(tarball: Tarball) -> Result<(String, u64)> {
+    let enc = tarball.into_inner().context("create tarball")?;
+    let encryptor = enc.finish().context("compress tarball")?;
+    let writer = encryptor.finish()?;
+    let written_size = writer.written();
+    let (upload, checksum) = writer.finish().context("write tarball")?;
+    upload.finish()?;
+    Ok((checksum, written_size))
+}
+
+/// Add the directory `relative` of `source` to `tarball` recursively, leaving out the paths
+/// excluded by `exclude`.
+fn append_dir(
+    tarball: &mut Tarball,
+    source: &Path,
+    relative: &Path,
+    exclude: &[glob::Pattern],
+    tracker: &Tracker,
+) -> Result<()> {
+    let dir = source.join(relative);
+    let mut names: Vec<std::ffi::OsString> = dir
+        .read_dir()
+        .and_then(|children| children.map(|child| Ok(child?.file_name())).collect())
+        .with_context(|| format!("read directory {}", dir.display()))?;
+    names.sort();
+    for name in names {
+        tracker.check_cancelled()?;
+        let relative = relative.join(name);
+        if is_excluded(&relative, exclude) {
+            continue;
+        }
+        let path = source.join(&relative);
+        let file_type = (path.symlink_metadata())
+            .with_context(|| format!("get metadata of {}", path.display()))?
+            .file_type();
+        if file_type.is_dir() {
+            tarball
+                .append_dir(&relative, &path)
+                .with_context(|| format!("add {}", path.display()))?;
+            append_dir(tarball, source, &relative, exclude, tracker)?;
+        } else if file_type.is_file() {
+            append_file(tarball, &path, &relative, tracker)
+                .with_context(|| format!("add {}", path.display()))?;
+        } else {
+            tarball
+                .append_path_with_name(&path, &relative)
+                .with_context(|| format!("add {}", path.display()))?;
+            tracker.add_file(&relative, 0);
+        }
+    }
+    Ok(())
+}
+
+/// Add the regular file at `path` to `tarball` as `name`, tracking the bytes read from it.
+fn append_file(tarball: &mut Tarball, path: &Path, name: &Path, tracker: &Tracker) -> Result<()> {
+    let file = std::fs::File::open(path).context("open file")?;
+    let metadata = file.metadata().context("get file metadata")?;
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata(&metadata);
+    tarball
+        .append_data(&mut header, name, tracker.reader(file))
+        .context("write file")?;
+    tracker.add_file(name, metadata.len());
+    Ok(())
+}
+
+/// Check if `relative`, a path inside a source directory, matches any of `exclude` either as a
+/// whole (e.g. `cache/**`) or by its file name (e.g. `*.log` or `node_modules`).
+#[must_use]
+pub fn is_excluded(relative: &Path, exclude: &[glob::Pattern]) -> bool {
+    let name = relative.file_name().map(|name| name.to_string_lossy());
+    exclude.iter().any(|pattern| {
+        pattern.matches_path(relative) || name.as_ref().is_some_and(|name| pattern.matches(name))
+    })
+}
+
+/// The source name of a backup: `name` if given, otherwise the file stem of `source`.
+fn source_name(source: &Path, name: Option<&str>) -> Result<String> {
+    let Some(name) = name else {
+        return get_file_stem(source);
+    };
+    anyhow::ensure!(
+        !name.is_empty() && !name.contains(['/', '\\', '\0']),
+        "invalid backup name {name:?}"
+    );
+    Ok(name.to_owned())
+}
+
 fn get_file_stem(source: &Path) -> Result<String> {
     Ok(source
         .file_stem()
@@ -165,3 +1107,330 @@ fn ensure_dir(target: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excluded_paths() {
+        let exclude: Vec<glob::Pattern> = ["*.log", "node_modules", "cache/**"]
+            .iter()
+            .map(|pattern| glob::Pattern::new(pattern).unwrap())
+            .collect();
+        let excluded = |path: &str| is_excluded(Path::new(path), &exclude);
+        assert!(excluded("debug.log"));
+        assert!(excluded("app/debug.log"));
+        assert!(excluded("app/node_modules"));
+        assert!(excluded("cache/a/b"));
+        assert!(!excluded("app/cache/a"));
+        assert!(!excluded("src/main.rs"));
+    }
+
+    #[test]
+    fn reader_backups() {
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("connection reset"))
+            }
+        }
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let config = config::Config::default();
+        let now = Timestamp::now();
+        let written = Backup::reader(&b"hello"[..], "greeting")
+            .target(dir)
+            .config(&config.archive)
+            .at(now)
+            .run();
+        let conflict = Backup::reader(&b"again"[..], "greeting")
+            .target(dir)
+            .config(&config.archive)
+            .at(now)
+            .run();
+        let events = RefCell::new(Vec::new());
+        let on_event = |event: &Event| events.borrow_mut().push(event.clone());
+        let failed = Backup::reader(Failing, "failing")
+            .target(dir)
+            .config(&config.archive)
+            .at(now.sub_span("1h".parse().unwrap()))
+            .events(&on_event)
+            .run();
+        let mut names: Vec<String> = (dir.read_dir().unwrap())
+            .map(|file| file.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(".rattlebeaver."))
+            .collect();
+        names.sort();
+        let written = written.unwrap();
+        assert!(written.to_string_lossy().ends_with(".greeting.gz"));
+        assert!(matches!(conflict, Err(Error::TimestampConflict { .. })));
+        assert!(matches!(failed, Err(Error::Other(_))));
+        let events = events.into_inner();
+        assert!(matches!(&events[..], [
+            Event::BackupStarted { source: started, .. },
+            Event::BackupFailed { source: failed, .. },
+        ] if started == "failing" && failed == "failing"));
+        assert_eq!(names.len(), 2, "{names:?}");
+        let mut contents = String::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(written).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "hello");
+    }
+
+    #[test]
+    fn verifies_written_backups() {
+        let temp = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(temp.path());
+        let checksum = |contents: &[u8]| {
+            let mut reader = HashingReader::new(contents);
+            std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+            reader.finish()
+        };
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(b"hello").unwrap();
+        let gzipped = gzipped.finish().unwrap();
+        for (name, contents) in [("plain", &b"hello"[..]), ("valid.gz", &gzipped)] {
+            std::fs::write(temp.path().join(name), contents).unwrap();
+            verify_written(&storage, name, &checksum(contents)).unwrap();
+            assert!(verify_written(&storage, name, &checksum(b"other")).is_err());
+        }
+        // Corrupt archives fail even when the checksum matches what was written
+        for name in ["corrupt.gz", "corrupt.tar.gz"] {
+            std::fs::write(temp.path().join(name), b"not gzip").unwrap();
+            assert!(verify_written(&storage, name, &checksum(b"not gzip")).is_err());
+        }
+    }
+
+    #[test]
+    fn cancelled_backups_are_deleted() {
+        /// Cancels the backup after its first read.
+        struct Cancelling(CancelToken);
+        impl Read for Cancelling {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.0.cancel();
+                buf[0] = b'x';
+                Ok(1)
+            }
+        }
+        let temp = tempfile::tempdir().unwrap();
+        let config = config::Config::default();
+        let cancel = CancelToken::new();
+        let cancelled = Backup::reader(Cancelling(cancel.clone()), "endless")
+            .target(temp.path())
+            .config(&config.archive)
+            .cancel_token(cancel)
+            .run();
+        assert!(matches!(cancelled, Err(Error::Cancelled)));
+        let names: Vec<_> = (temp.path().read_dir().unwrap())
+            .map(|file| file.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().starts_with(".rattlebeaver."))
+            .collect();
+        assert!(names.is_empty(), "{names:?}");
+    }
+
+    #[test]
+    fn writer_backups() {
+        let config = config::Config::default();
+        let mut output = Vec::new();
+        let name = Backup::reader(&b"hello"[..], "greeting")
+            .writer(&mut output)
+            .config(&config.archive)
+            .run()
+            .unwrap();
+        assert!(name.to_string_lossy().ends_with(".greeting.gz"));
+        let mut contents = String::new();
+        flate2::read::GzDecoder::new(&output[..])
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "hello");
+
+        let verified = Backup::reader(&b"hello"[..], "greeting")
+            .writer(Vec::new())
+            .config(&config.archive)
+            .verify(true)
+            .run();
+        assert!(verified.is_err());
+    }
+
+    #[test]
+    fn started_before_failed() {
+        let temp = tempfile::tempdir().unwrap();
+        let config = config::Config::default();
+        let events = RefCell::new(Vec::new());
+        let on_event = |event: &Event| events.borrow_mut().push(event.clone());
+        let missing_source = Backup::builder(temp.path().join("missing"))
+            .target(temp.path())
+            .config(&config.archive)
+            .events(&on_event)
+            .run();
+        assert!(matches!(missing_source, Err(Error::NotFound(_))));
+        let missing_config = Backup::builder(temp.path())
+            .target(temp.path())
+            .events(&on_event)
+            .run();
+        assert!(missing_config.is_err());
+        let events = events.into_inner();
+        assert!(matches!(
+            &events[..],
+            [
+                Event::BackupStarted { .. },
+                Event::BackupFailed { .. },
+                Event::BackupStarted { .. },
+                Event::BackupFailed { .. },
+            ]
+        ));
+    }
+
+    #[test]
+    fn builder_options() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("notes.txt");
+        let target = temp.path().join("target");
+        std::fs::write(&source, "hello").unwrap();
+        let config = config::Config::default();
+        let at: Timestamp = "2025-01-02_03-04-05".parse().unwrap();
+        let written = Backup::builder(&source)
+            .target(&target)
+            .config(&config.archive)
+            .at(at)
+            .mode(ArchiveMode::Force)
+            .name("docs")
+            .tag("first")
+            .tags(["second"])
+            .compression(0)
+            .run()
+            .unwrap();
+        let entry = Entry::from_path(written.clone(), &config.archive)
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.timestamp, at);
+        assert!(written.to_string_lossy().ends_with(".docs.tar.gz"));
+        let meta = BackupMeta::load(&written).unwrap().unwrap();
+        assert_eq!(meta.tags, ["first", "second"]);
+        assert_eq!(meta.archive_mode, Some(ArchiveMode::Force));
+        assert_eq!(meta.file_count, Some(1));
+        let index = Index::load_saved(&target).unwrap().unwrap();
+        assert_eq!(index.get(&target, at), Some(written));
+
+        // The same backup in any storage, and with the deprecated function
+        let storage = LocalStorage::new(temp.path().join("storage"));
+        std::fs::create_dir_all(temp.path().join("storage")).unwrap();
+        let stored = Backup::builder(&source)
+            .storage(&storage)
+            .config(&config.archive)
+            .at(at)
+            .mode(ArchiveMode::AsIs)
+            .run()
+            .unwrap();
+        assert_eq!(
+            stored,
+            storage.locate(".rattlebeaver.2025-01-02_03-04-05.notes.txt")
+        );
+        #[allow(deprecated)]
+        let deprecated = create_backup_in(
+            &source,
+            &storage,
+            &config.archive,
+            TimestampSelection::Now,
+            ArchiveMode::AutoDetect,
+            None,
+            None,
+            &[],
+            false,
+        );
+        assert!(deprecated.is_ok(), "{deprecated:?}");
+
+        let missing_target = Backup::builder(&source).config(&config.archive).run();
+        assert!(missing_target.is_err());
+        let missing_config = Backup::builder(&source).target(&target).run();
+        assert!(missing_config.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn archives_symlinks_as_links() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        let target = temp.path().join("target");
+        std::fs::create_dir_all(source.join("dir")).unwrap();
+        std::fs::write(source.join("dir/file"), "contents").unwrap();
+        std::os::unix::fs::symlink("dir/file", source.join("link")).unwrap();
+        std::os::unix::fs::symlink("..", source.join("dir/cycle")).unwrap();
+        let config = config::Config::default();
+        let written = (Backup::builder(&source).target(&target))
+            .config(&config.archive)
+            .run()
+            .unwrap();
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(
+            std::fs::File::open(written).unwrap(),
+        ));
+        let mut entries: Vec<(String, Option<PathBuf>)> = (archive.entries().unwrap())
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let link = entry.link_name().unwrap().map(|link| link.into_owned());
+                (path, link)
+            })
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                ("dir".to_owned(), None),
+                ("dir/cycle".to_owned(), Some(PathBuf::from(".."))),
+                ("dir/file".to_owned(), None),
+                ("link".to_owned(), Some(PathBuf::from("dir/file"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn stale_index_conflicts() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let config = config::Config::default();
+        let now = Timestamp::now();
+        let written = (Backup::reader(&b"hello"[..], "greeting")
+            .target(dir)
+            .config(&config.archive)
+            .at(now))
+        .run()
+        .unwrap();
+        Index::default().save(dir, &config.archive).unwrap();
+        let conflict = (Backup::reader(&b"again"[..], "greeting")
+            .target(dir)
+            .config(&config.archive)
+            .at(now))
+        .run();
+        assert!(matches!(
+            conflict,
+            Err(Error::TimestampConflict { existing: Some(existing), .. }) if existing == written
+        ));
+        let mut contents = String::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(written).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "hello");
+    }
+
+    #[test]
+    fn deleted_but_indexed_backups_are_replaced() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let config = config::Config::default();
+        let now = Timestamp::now();
+        let backup = |contents: &'static str| {
+            (Backup::reader(contents.as_bytes(), "greeting").target(dir))
+                .config(&config.archive)
+                .at(now)
+                .run()
+        };
+        let written = backup("hello").unwrap();
+        std::fs::remove_file(&written).unwrap();
+        assert_eq!(backup("again").unwrap(), written);
+        let index = Index::load_saved(dir).unwrap().unwrap();
+        assert_eq!(index.get(dir, now), Some(written));
+    }
+}
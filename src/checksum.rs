@@ -0,0 +1,112 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// The hex-encoded SHA-256 digest of the file at `path`.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).context("read file")?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// The hex-encoded SHA-256 digest of the payload of the file at `path`: for `.tar.gz` archives
+/// the paths and contents of their members, ignoring timestamps, ownership and compression, and
+/// for other files their contents.
+pub fn sha256_contents(path: &Path) -> Result<String> {
+    if !path.to_string_lossy().ends_with(".tar.gz") {
+        return sha256_file(path);
+    }
+    let file = std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    let mut hasher = HashingWriter::new(std::io::sink());
+    for entry in archive.entries().context("read archive")? {
+        let mut entry = entry.context("read archive entry")?;
+        let entry_path = entry.path_bytes().into_owned();
+        hasher.write_all(&entry_path).context("hash entry path")?;
+        hasher.write_all(&[0]).context("hash entry path")?;
+        hasher
+            .write_all(&entry.size().to_le_bytes())
+            .context("hash entry size")?;
+        std::io::copy(&mut entry, &mut hasher).context("hash entry data")?;
+    }
+    let (_sink, digest) = hasher.finish()?;
+    Ok(digest)
+}
+
+/// A reader that computes the SHA-256 digest of everything read through it.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// The hex-encoded digest of what was read so far.
+    pub fn finish(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// A writer that computes the SHA-256 digest of everything written through it.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    written: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            written: 0,
+        }
+    }
+
+    /// Number of bytes written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Flush the inner writer and return it with the hex-encoded digest.
+    pub fn finish(mut self) -> Result<(W, String)> {
+        self.inner.flush().context("flush")?;
+        Ok((self.inner, hex::encode(self.hasher.finalize())))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,6 +1,13 @@
-use crate::timestamp::Range;
+use crate::backup::{ArchiveMode, TimestampSelection};
+use crate::encryption::{Cipher, Encryption};
+use crate::error::Error;
+use crate::size::ByteSize;
+use crate::snapshot::SnapshotKind;
+use crate::timestamp::{Range, Span, SpanUnit};
+use crate::webhook::Event;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 const DEFAULT_CONFIG_TOML: &str = r#"# Automatically generated config
 [archive]
@@ -45,16 +52,66 @@ include_last = true
 pub struct Config {
     pub archive: Archive,
     pub ranges: Ranges,
+    #[serde(default)]
+    pub delete: Delete,
+    #[serde(default)]
+    pub hooks: Hooks,
+    /// HTTP endpoints notified of added, failed and deleted backups
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<Webhook>,
+    /// Monitoring url (e.g. of healthchecks.io) pinged at the start and end of every run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ping_url: Option<String>,
+    /// Show a desktop notification when `add` finishes
+    #[serde(default)]
+    pub notify: bool,
+    /// Targets (directories or remote urls) that every new backup is copied to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+    /// Refuse to delete, rename or overwrite backups in the target, leaving pruning to another
+    /// tool with separate credentials
+    #[serde(default)]
+    pub append_only: bool,
+    #[serde(default)]
+    pub retry: Retry,
+    /// Settings of individual targets, by directory or url
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub targets: BTreeMap<String, Target>,
+    /// Sources backed up by `run`, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub jobs: BTreeMap<String, Job>,
 }
 
 impl Config {
-    pub fn from_path(path: impl AsRef<std::path::Path>) -> Result<Self> {
-        let s = std::fs::read_to_string(path).context("read config file")?;
-        toml::from_str(&s).context("decode config toml")
+    pub fn from_path(path: impl AsRef<std::path::Path>) -> std::result::Result<Self, Error> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        let mut config: Self = toml::from_str(&s).map_err(|e| Error::Parse {
+            path: path.to_owned(),
+            message: e.to_string(),
+        })?;
+        config.validate().map_err(|source| Error::Config {
+            path: path.to_owned(),
+            source,
+        })?;
+        Ok(config)
     }
 
     pub fn from_toml(toml_str: impl AsRef<str>) -> Result<Self> {
-        Ok(toml::from_str(toml_str.as_ref())?)
+        let mut config: Self = toml::from_str(toml_str.as_ref())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&mut self) -> Result<()> {
+        self.ranges.apply_keep()?;
+        self.ranges.validate()?;
+        self.retry.validate()?;
+        for (name, job) in &self.jobs {
+            job.exclude_patterns()
+                .with_context(|| format!("invalid job {name}"))?;
+        }
+        Ok(())
     }
 
     pub fn as_toml(&self) -> Result<String> {
@@ -68,23 +125,392 @@ impl Default for Config {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Archive {
     pub prefix: String,
     pub timestamp_format: String,
+    /// Recipients that new backups are encrypted to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encrypt_to: Vec<String>,
+    /// What to encrypt with (age by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<Cipher>,
+    /// File holding the passphrase of the passphrase cipher
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase_file: Option<std::path::PathBuf>,
+    /// Mode of the files rattlebeaver creates, e.g. `0o640` (`0o600` by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_mode: Option<u32>,
+    /// Owner (user name or id) of the files rattlebeaver creates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Group (name or id) of the files rattlebeaver creates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Read every new backup back to check it, as with `add --verify`
+    #[serde(default)]
+    pub verify: bool,
+    /// Flush new backups and the target directory to disk after creating or deleting backups
+    #[serde(default)]
+    pub fsync: bool,
+    /// File holding the secret key that new backups are signed with, made by `keygen`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<std::path::PathBuf>,
+    /// Public key that `verify --signatures` checks the signatures of backups against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Archive {
+    /// How new backups are encrypted.
+    #[must_use]
+    pub fn encryption(&self) -> Encryption<'_> {
+        Encryption {
+            cipher: self.cipher.unwrap_or_default(),
+            recipients: &self.encrypt_to,
+            passphrase_file: self.passphrase_file.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Delete {
+    /// Only delete backups that have been stale for at least this long
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_grace: Option<Span>,
+    /// Overwrite backups before removing them
+    #[serde(default)]
+    pub shred: bool,
+    /// Never delete backups with any of these tags
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keep_tags: Vec<String>,
+    /// Move deleted backups into the trash directory instead of removing them
+    #[serde(default)]
+    pub trash: bool,
+    /// How long trashed backups are kept before `empty-trash` removes them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash_retention: Option<Span>,
+}
+
+/// Shell commands run around adding and deleting backups.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Hooks {
+    /// Run before each source is backed up; the backup is skipped if it fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before_add: Option<String>,
+    /// Run after each source was backed up or failed to be
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after_add: Option<String>,
+    /// Run before stale backups are deleted; nothing is deleted if it fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before_delete: Option<String>,
+}
+
+/// An HTTP endpoint that summaries of runs are posted to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    /// Events to post about (all by default)
+    #[serde(default = "all_events")]
+    pub events: Vec<Event>,
+    /// Extra headers, e.g. for authorization
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+fn all_events() -> Vec<Event> {
+    Event::ALL.to_vec()
+}
+
+/// How remote operations are retried after failing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Retry {
+    /// How many times to try each operation, including the first
+    pub attempts: u32,
+    /// Delay before the first retry, doubled after every further failure
+    pub backoff: Span,
+    /// Upper bound of the delay between attempts
+    pub max_backoff: Span,
+    /// Randomize each delay between half and all of it, so clients don't retry in lockstep
+    pub jitter: bool,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Span {
+                amount: 1,
+                unit: SpanUnit::Second,
+            },
+            max_backoff: Span {
+                amount: 1,
+                unit: SpanUnit::Minute,
+            },
+            jitter: true,
+        }
+    }
+}
+
+impl Retry {
+    fn validate(&self) -> Result<()> {
+        anyhow::ensure!(self.attempts > 0, "retry.attempts must be at least 1");
+        for span in [self.backoff, self.max_backoff] {
+            anyhow::ensure!(
+                span.fixed_duration().is_some(),
+                "retry delays cannot be in months or years (got {span})"
+            );
+        }
+        Ok(())
+    }
+
+    /// The delay before retrying after `failures` failed attempts.
+    #[must_use]
+    pub fn delay(&self, failures: u32) -> std::time::Duration {
+        let backoff = self.backoff.fixed_duration().unwrap_or_default();
+        let max_backoff = self.max_backoff.fixed_duration().unwrap_or_default();
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        let delay = backoff.saturating_mul(factor).min(max_backoff);
+        if !self.jitter {
+            return delay;
+        }
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        delay / 2 + (delay / 2).mul_f64(f64::from(nanos) / 1e9)
+    }
+}
+
+/// A source backed up by `run`, with the options `add` would take for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    /// File or directory to back up
+    pub source: std::path::PathBuf,
+    /// Glob patterns of paths in the source (e.g. `cache/**`) or file names (e.g. `*.log`) to
+    /// leave out of the backup
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// How to select the timestamp of the backups
+    #[serde(default = "default_timestamp")]
+    pub timestamp: TimestampSelection,
+    /// How to handle a single file
+    #[serde(default = "default_archive_mode")]
+    pub archive_mode: ArchiveMode,
+    /// Archive the source from a snapshot of its filesystem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<SnapshotKind>,
+    /// Tags of the new backups
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Skip the job while its newest backup is younger than this (e.g. 1d), so `run` can be
+    /// scheduled more often than any one job
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub every: Option<Span>,
+}
+
+impl Job {
+    pub fn exclude_patterns(&self) -> Result<Vec<glob::Pattern>> {
+        (self.exclude.iter())
+            .map(|pattern| {
+                glob::Pattern::new(pattern).with_context(|| format!("invalid pattern {pattern}"))
+            })
+            .collect()
+    }
+}
+
+fn default_timestamp() -> TimestampSelection {
+    TimestampSelection::FileCreated
+}
+
+fn default_archive_mode() -> ArchiveMode {
+    ArchiveMode::AutoDetect
+}
+
+/// Settings that apply when writing to one target, e.g. a mirror.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Target {
+    /// Recipients that backups copied to the target are encrypted to
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub encrypt_to: Vec<String>,
+    /// What to encrypt with (age by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<Cipher>,
+    /// age identity file that decrypts backups read from the target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<std::path::PathBuf>,
+    /// File holding the passphrase of the passphrase cipher
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passphrase_file: Option<std::path::PathBuf>,
+    /// Never delete, rename or overwrite anything in the target
+    pub append_only: bool,
+}
+
+impl Target {
+    #[must_use]
+    pub fn is_encrypted(&self) -> bool {
+        !self.encrypt_to.is_empty()
+            || self.cipher.is_some()
+            || self.identity.is_some()
+            || self.passphrase_file.is_some()
+    }
+
+    /// How backups copied to the target are encrypted.
+    #[must_use]
+    pub fn encryption(&self) -> Encryption<'_> {
+        Encryption {
+            cipher: self.cipher.unwrap_or_default(),
+            recipients: &self.encrypt_to,
+            passphrase_file: self.passphrase_file.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Ranges {
-    pub latest: usize,
+    /// Compact policy (e.g. "10 latest, 24h hourly, 30d daily") applied over the other fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep: Option<String>,
+    pub latest: Latest,
+    /// Upper bound on how many backups are kept, regardless of the ranges
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_count: Option<usize>,
+    /// Prune the oldest backups until this much space is free on the target filesystem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_free_space: Option<FreeSpace>,
     pub minutes: RollingRange,
     pub hours: RollingRange,
     pub days: RollingRange,
     pub months: RollingRange,
     pub years: RollingRange,
+    /// Keep the backups closest to calendar anchors (e.g. midnight on the 1st of every month)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub calendar: Vec<CalendarRule>,
+    /// Separate ranges for backups whose source label matches a glob pattern
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub overrides: BTreeMap<String, Ranges>,
 }
 
 impl Ranges {
+    /// Apply the `keep` policy string, if any, to these ranges and their overrides.
+    ///
+    /// The policy is a comma separated list of `<amount> <kind>`, where kind is one of
+    /// `latest`, `minutely`, `hourly`, `daily`, `monthly` or `yearly`. The amount of a range may
+    /// have a matching unit for readability (e.g. `24h hourly`), and the amount of `latest` may be
+    /// a span (e.g. `7d latest`). Ranges set by a policy consider consecutive calendar buckets and
+    /// keep the last backup of each.
+    pub fn apply_keep(&mut self) -> Result<()> {
+        if let Some(keep) = self.keep.clone() {
+            self.apply_policy(&keep)
+                .with_context(|| format!("invalid keep policy {keep:?}"))?;
+        }
+        for (pattern, ranges) in &mut self.overrides {
+            ranges
+                .apply_keep()
+                .with_context(|| format!("override {pattern:?}"))?;
+        }
+        Ok(())
+    }
+
+    fn apply_policy(&mut self, policy: &str) -> Result<()> {
+        let mut latest_count = None;
+        let mut latest_within = None;
+        for item in policy.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (amount, kind) = item
+                .split_once(char::is_whitespace)
+                .with_context(|| format!("expected <amount> <kind> in {item:?}"))?;
+            let (range, units): (&mut RollingRange, &[&str]) = match kind.trim() {
+                "latest" => {
+                    if let Ok(count) = amount.parse() {
+                        latest_count = Some(count);
+                    } else {
+                        latest_within =
+                            Some(amount.parse().with_context(|| {
+                                format!("expected a count or a span in {item:?}")
+                            })?);
+                    }
+                    continue;
+                }
+                "minutely" => (&mut self.minutes, &["m", "min"]),
+                "hourly" => (&mut self.hours, &["h"]),
+                "daily" => (&mut self.days, &["d"]),
+                "monthly" => (&mut self.months, &["m", "M"]),
+                "yearly" => (&mut self.years, &["y", "Y"]),
+                other => anyhow::bail!("unknown kind {other:?} in {item:?}"),
+            };
+            let digits_end = amount
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(amount.len());
+            let (total, unit) = amount.split_at(digits_end);
+            if !unit.is_empty() && !units.contains(&unit) {
+                anyhow::bail!("unit {unit:?} does not match {kind:?} in {item:?}");
+            }
+            *range = RollingRange {
+                total: total
+                    .parse()
+                    .with_context(|| format!("invalid amount in {item:?}"))?,
+                allow_sparse: false,
+                include_first: false,
+                include_last: true,
+                every_nth: 1,
+            };
+        }
+        self.latest = match (latest_count, latest_within) {
+            (Some(count), Some(within)) => Latest::Both { count, within },
+            (Some(count), None) => Latest::Count(count),
+            (None, Some(within)) => Latest::Within(within),
+            (None, None) => self.latest,
+        };
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        for rule in &self.calendar {
+            rule.weekday()?;
+            rule.time()?;
+        }
+        for (pattern, ranges) in &self.overrides {
+            glob::Pattern::new(pattern)
+                .with_context(|| format!("invalid override pattern {pattern:?}"))?;
+            ranges
+                .validate_override()
+                .with_context(|| format!("override {pattern:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// Validate the ranges of an override, which can't set what only applies to the whole target.
+    fn validate_override(&self) -> Result<()> {
+        for (key, set) in [
+            ("max_total_count", self.max_total_count.is_some()),
+            ("min_free_space", self.min_free_space.is_some()),
+            ("overrides", !self.overrides.is_empty()),
+        ] {
+            anyhow::ensure!(!set, "{key} is only read from the top-level ranges");
+        }
+        self.validate()
+    }
+
+    /// Get the ranges that apply to backups of a source label, and the override pattern that
+    /// selected them (None for the top-level ranges).
+    #[must_use]
+    pub fn policy(&self, label: Option<&str>) -> (Option<&str>, &Ranges) {
+        let Some(label) = label else {
+            return (None, self);
+        };
+        self.overrides
+            .iter()
+            .find(|(pattern, _ranges)| {
+                glob::Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(label))
+            })
+            .map_or((None, self), |(pattern, ranges)| {
+                (Some(pattern.as_str()), ranges)
+            })
+    }
+
     #[must_use]
     pub fn iter_ranges(&self) -> [(Range, &RollingRange); 5] {
         [
@@ -97,12 +523,169 @@ impl Ranges {
     }
 }
 
+/// How many of the most recent backups to keep: a count (`10`), a span (`"7d"`) or both
+/// (`{ count = 10, within = "7d" }`).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Latest {
+    Count(usize),
+    Within(Span),
+    Both { count: usize, within: Span },
+}
+
+impl Default for Latest {
+    fn default() -> Self {
+        Self::Count(0)
+    }
+}
+
+impl Latest {
+    #[must_use]
+    pub fn count(&self) -> usize {
+        match self {
+            Self::Count(count) | Self::Both { count, .. } => *count,
+            Self::Within(_) => 0,
+        }
+    }
+
+    #[must_use]
+    pub fn within(&self) -> Option<Span> {
+        match self {
+            Self::Count(_) => None,
+            Self::Within(within) | Self::Both { within, .. } => Some(*within),
+        }
+    }
+}
+
+/// Free space on a filesystem, either in bytes (`"10G"`) or as a percentage (`"15%"`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum FreeSpace {
+    Bytes(ByteSize),
+    Percent(f64),
+}
+
+impl FreeSpace {
+    #[must_use]
+    pub fn required_bytes(&self, total_space: u64) -> u64 {
+        match self {
+            Self::Bytes(size) => size.0,
+            #[allow(
+                clippy::cast_possible_truncation,
+                clippy::cast_sign_loss,
+                clippy::cast_precision_loss
+            )]
+            Self::Percent(percent) => (total_space as f64 * percent / 100.0) as u64,
+        }
+    }
+}
+
+impl TryFrom<String> for FreeSpace {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        if let Some(percent) = value.trim().strip_suffix('%') {
+            let percent: f64 = percent
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid percentage: {value:?}"))?;
+            if !(0.0..=100.0).contains(&percent) {
+                anyhow::bail!("percentage out of range: {value:?}");
+            }
+            return Ok(Self::Percent(percent));
+        }
+        Ok(Self::Bytes(value.parse()?))
+    }
+}
+
+impl From<FreeSpace> for String {
+    fn from(value: FreeSpace) -> Self {
+        match value {
+            FreeSpace::Bytes(size) => size.to_string(),
+            FreeSpace::Percent(percent) => format!("{percent}%"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RollingRange {
     pub total: usize,
     pub allow_sparse: bool,
     pub include_first: bool,
     pub include_last: bool,
+    /// Only consider every Nth bucket (aligned to the calendar, e.g. every 3rd day)
+    pub every_nth: usize,
+}
+
+impl Default for RollingRange {
+    fn default() -> Self {
+        Self {
+            total: 0,
+            allow_sparse: true,
+            include_first: true,
+            include_last: true,
+            every_nth: 1,
+        }
+    }
+}
+
+/// Keep the backup closest to an anchor point in each of the last `total` periods.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalendarRule {
+    pub every: CalendarPeriod,
+    pub total: usize,
+    /// Day of the month for month and year anchors (clamped to the last day of the month)
+    #[serde(default = "CalendarRule::default_day")]
+    pub day: u32,
+    /// Month of the year for year anchors
+    #[serde(default = "CalendarRule::default_month")]
+    pub month: u32,
+    /// Day of the week for week anchors (e.g. "sun")
+    #[serde(default = "CalendarRule::default_weekday")]
+    pub weekday: String,
+    /// Time of day of the anchor (e.g. "00:00")
+    #[serde(default = "CalendarRule::default_time")]
+    pub time: String,
+}
+
+impl CalendarRule {
+    fn default_day() -> u32 {
+        1
+    }
+
+    fn default_month() -> u32 {
+        1
+    }
+
+    fn default_weekday() -> String {
+        String::from("mon")
+    }
+
+    fn default_time() -> String {
+        String::from("00:00")
+    }
+
+    pub fn weekday(&self) -> Result<chrono::Weekday> {
+        self.weekday
+            .parse()
+            .ok()
+            .with_context(|| format!("invalid weekday {:?}", self.weekday))
+    }
+
+    pub fn time(&self) -> Result<chrono::NaiveTime> {
+        chrono::NaiveTime::parse_from_str(&self.time, "%H:%M")
+            .with_context(|| format!("invalid time {:?} (expected e.g. 00:00)", self.time))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CalendarPeriod {
+    Day,
+    Week,
+    Month,
+    Year,
 }
 
 #[cfg(test)]
@@ -113,4 +696,85 @@ mod tests {
     fn default_config() {
         Config::default();
     }
+
+    #[test]
+    fn latest_within() {
+        let toml = DEFAULT_CONFIG_TOML.replace("latest = 10", r#"latest = "7d""#);
+        let config = Config::from_toml(toml).unwrap();
+        assert_eq!(config.ranges.latest.count(), 0);
+        assert_eq!(config.ranges.latest.within(), Some("7d".parse().unwrap()));
+    }
+
+    #[test]
+    fn retry_delay() {
+        let retry = Retry {
+            jitter: false,
+            ..Retry::default()
+        };
+        let delays: Vec<u64> = (1..=8).map(|n| retry.delay(n).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+    }
+
+    #[test]
+    fn keep_policy() {
+        let toml = r#"
+            [archive]
+            prefix = ".rattlebeaver."
+            timestamp_format = "%Y-%m-%d_%H-%M-%S"
+
+            [ranges]
+            keep = "10 latest, 24h hourly, 30d daily, 12m monthly, 5y yearly"
+        "#;
+        let config = Config::from_toml(toml).unwrap();
+        let ranges = &config.ranges;
+        assert_eq!(ranges.latest.count(), 10);
+        assert_eq!(ranges.minutes.total, 0);
+        assert_eq!(ranges.hours.total, 24);
+        assert_eq!(ranges.days.total, 30);
+        assert_eq!(ranges.months.total, 12);
+        assert_eq!(ranges.years.total, 5);
+        assert!(!ranges.days.allow_sparse);
+        let toml = toml.replace("24h hourly", "24d hourly");
+        assert!(Config::from_toml(toml).is_err());
+    }
+
+    #[test]
+    fn overrides() {
+        let toml = format!(
+            "{DEFAULT_CONFIG_TOML}\n[ranges.overrides.\"db-*\"]\nlatest = 3\n\n[ranges.overrides.\"db-*\".hours]\ntotal = 720\n"
+        );
+        let config = Config::from_toml(toml).unwrap();
+        let (pattern, ranges) = config.ranges.policy(Some("db-main"));
+        assert_eq!(pattern, Some("db-*"));
+        assert_eq!(ranges.latest.count(), 3);
+        assert_eq!(ranges.hours.total, 720);
+        assert_eq!(ranges.days.total, 0);
+        let (pattern, ranges) = config.ranges.policy(Some("dotfiles"));
+        assert_eq!(pattern, None);
+        assert_eq!(ranges.latest.count(), 10);
+        for key in [
+            "max_total_count = 5",
+            "min_free_space = \"10%\"",
+            "[ranges.overrides.\"db-*\".overrides.x]",
+        ] {
+            let toml = format!("{DEFAULT_CONFIG_TOML}\n[ranges.overrides.\"db-*\"]\n{key}\n");
+            assert!(Config::from_toml(toml).is_err(), "{key}");
+        }
+    }
+
+    #[test]
+    fn from_path_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rattlebeaver.config.toml");
+        assert!(matches!(Config::from_path(&path), Err(Error::NotFound(p)) if p == path));
+        std::fs::write(&path, "latest = [").unwrap();
+        assert!(matches!(Config::from_path(&path), Err(Error::Parse { .. })));
+        let invalid =
+            format!("{DEFAULT_CONFIG_TOML}\n[jobs.docs]\nsource = \"/docs\"\nexclude = [\"[\"]\n");
+        std::fs::write(&path, invalid).unwrap();
+        assert!(matches!(
+            Config::from_path(&path),
+            Err(Error::Config { .. })
+        ));
+    }
 }
@@ -0,0 +1,39 @@
+//! Docker volumes as sources, backed up from where Docker keeps their files on the host.
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// The directory on the host holding the files of the Docker volume `name`.
+pub fn volume_mountpoint(name: &str) -> Result<PathBuf> {
+    let output = Command::new("docker")
+        .args([
+            "volume",
+            "inspect",
+            "--format",
+            "{{.Mountpoint}}",
+            "--",
+            name,
+        ])
+        .stdin(Stdio::null())
+        .output()
+        .context("run docker")?;
+    anyhow::ensure!(
+        output.status.success(),
+        "docker volume inspect {name} failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    let mountpoint = String::from_utf8(output.stdout).context("decode mountpoint")?;
+    let mountpoint = PathBuf::from(mountpoint.trim());
+    anyhow::ensure!(
+        !mountpoint.as_os_str().is_empty(),
+        "volume {name} has no mountpoint"
+    );
+    // Docker's directory is only readable by root, and on Docker Desktop it is inside a VM
+    anyhow::ensure!(
+        mountpoint.read_dir().is_ok(),
+        "can't read {} of volume {name} (run as root on the Docker host)",
+        mountpoint.display()
+    );
+    Ok(mountpoint)
+}
@@ -0,0 +1,401 @@
+use crate::storage::TempFile;
+use crate::storage::process::{ProcessReader, ProcessWriter};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+#[cfg(feature = "passphrase")]
+mod passphrase;
+
+/// The environment variable that holds the passphrase, if there is no passphrase file.
+pub const PASSPHRASE_VAR: &str = "RATTLEBEAVER_PASSPHRASE";
+
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+/// Use `passphrase` when there is neither a passphrase file nor [`PASSPHRASE_VAR`], e.g. after
+/// asking for it. Only the first passphrase provided is used.
+pub fn provide_passphrase(passphrase: String) {
+    let _ = PASSPHRASE.set(passphrase);
+}
+
+/// Check if a passphrase is available without a passphrase file.
+#[must_use]
+pub fn has_passphrase() -> bool {
+    std::env::var_os(PASSPHRASE_VAR).is_some() || PASSPHRASE.get().is_some()
+}
+
+/// The passphrase from `file` (without the trailing newline), or [`PASSPHRASE_VAR`], or the one
+/// provided with [`provide_passphrase`].
+fn passphrase(file: Option<&Path>) -> Result<String> {
+    if let Some(file) = file {
+        let passphrase = std::fs::read_to_string(file)
+            .with_context(|| format!("read passphrase from {}", file.display()))?;
+        return Ok(passphrase.trim_end_matches(['\r', '\n']).to_owned());
+    }
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+    PASSPHRASE
+        .get()
+        .cloned()
+        .with_context(|| format!("no passphrase (set a passphrase file or {PASSPHRASE_VAR})"))
+}
+
+/// A tool to encrypt backups with, run as an external binary or built in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Cipher {
+    /// age, built in (needs the `age` feature)
+    #[default]
+    Age,
+    /// GnuPG, run as the `gpg` binary (or the one in `GPG`), using its keyring
+    Gpg,
+    /// A key derived from a passphrase, with the built in ChaCha20-Poly1305 (needs the
+    /// `passphrase` feature)
+    Passphrase,
+}
+
+impl Cipher {
+    const ALL: [Self; 3] = [Self::Age, Self::Gpg, Self::Passphrase];
+
+    /// The suffix of files encrypted with this cipher.
+    #[must_use]
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Self::Age => ".age",
+            Self::Gpg => ".gpg",
+            Self::Passphrase => ".enc",
+        }
+    }
+
+    /// The cipher that the file `name` is encrypted with, judging by its suffix.
+    #[must_use]
+    pub fn of(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|cipher| name.ends_with(cipher.suffix()))
+    }
+
+    fn command(self) -> Command {
+        let (variable, binary) = match self {
+            Self::Gpg => ("GPG", "gpg"),
+            Self::Age | Self::Passphrase => unreachable!("the {self:?} cipher is built in"),
+        };
+        Command::new(std::env::var_os(variable).unwrap_or_else(|| binary.into()))
+    }
+
+    /// A command encrypting its input to `recipients`, writing the result to `output`.
+    pub(crate) fn encrypt_command(self, recipients: &[String], output: &Path) -> Command {
+        let mut command = self.command();
+        command.args(["--batch", "--yes", "--encrypt"]);
+        for recipient in recipients {
+            command.arg("--recipient").arg(recipient);
+        }
+        command.arg("--output").arg(output);
+        command
+    }
+}
+
+/// Check if the file `name` is encrypted.
+#[must_use]
+pub fn is_encrypted(name: &str) -> bool {
+    Cipher::of(name).is_some()
+}
+
+/// The name of the file `name` without the suffix of its cipher, if any.
+#[must_use]
+pub fn strip_suffix(name: &str) -> &str {
+    Cipher::of(name).map_or(name, |cipher| &name[..name.len() - cipher.suffix().len()])
+}
+
+/// How to decrypt backups.
+#[derive(Debug, Clone, Default)]
+pub struct Decryptor {
+    /// The age identity file, not needed for GnuPG which uses its keyring
+    pub identity: Option<PathBuf>,
+    /// The file holding the passphrase, instead of [`PASSPHRASE_VAR`]
+    pub passphrase_file: Option<PathBuf>,
+}
+
+impl Decryptor {
+    /// Decrypt everything read from `source`, the file `name`, according to its suffix.
+    ///
+    /// The ciphertext is downloaded to a temporary file first, since it must be read in full
+    /// before the plaintext can be trusted.
+    pub fn decrypt(&self, mut source: impl Read, name: &str) -> Result<Decrypted> {
+        let cipher = Cipher::of(name).with_context(|| format!("{name} is not encrypted"))?;
+        let identity = match cipher {
+            Cipher::Age => Some(self.identity.as_ref().with_context(|| {
+                format!("{name} is encrypted with age (pass an identity file)")
+            })?),
+            Cipher::Gpg | Cipher::Passphrase => None,
+        };
+        let mut buffer = TempFile::create(&format!("decrypt-{name}"))?;
+        std::io::copy(&mut source, &mut buffer.file).with_context(|| format!("download {name}"))?;
+        let open = || std::fs::File::open(&buffer.path).context("open downloaded file");
+        let reader: Box<dyn Read> = match (cipher, identity) {
+            (Cipher::Age, Some(identity)) => {
+                open_with_age(open()?, identity).with_context(|| format!("decrypt {name}"))?
+            }
+            (Cipher::Passphrase, _) => {
+                let passphrase = passphrase(self.passphrase_file.as_deref())?;
+                open_with_passphrase(open()?, &passphrase)
+                    .with_context(|| format!("decrypt {name}"))?
+            }
+            (Cipher::Gpg | Cipher::Age, _) => {
+                let mut command = cipher.command();
+                command.args(["--batch", "--quiet", "--decrypt"]);
+                command.arg(&buffer.path);
+                Box::new(ProcessReader::spawn(command, name)?)
+            }
+        };
+        Ok(Decrypted {
+            reader,
+            _buffer: buffer,
+        })
+    }
+}
+
+/// The plaintext of a decrypted file.
+pub struct Decrypted {
+    reader: Box<dyn Read>,
+    _buffer: TempFile,
+}
+
+impl Read for Decrypted {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+#[cfg(feature = "passphrase")]
+fn open_with_passphrase(file: std::fs::File, passphrase: &str) -> Result<Box<dyn Read>> {
+    Ok(Box::new(passphrase::Opener::new(file, passphrase)?))
+}
+
+#[cfg(not(feature = "passphrase"))]
+fn open_with_passphrase(_file: std::fs::File, _passphrase: &str) -> Result<Box<dyn Read>> {
+    anyhow::bail!("built without the passphrase feature")
+}
+
+#[cfg(feature = "age")]
+fn open_with_age(file: std::fs::File, identity: &Path) -> Result<Box<dyn Read>> {
+    let identities = age::IdentityFile::from_file(identity.to_string_lossy().into_owned())
+        .with_context(|| format!("read identity file {}", identity.display()))?
+        .into_identities()
+        .with_context(|| format!("parse identity file {}", identity.display()))?;
+    let decryptor =
+        age::Decryptor::new_buffered(std::io::BufReader::new(file)).context("read age header")?;
+    let reader = decryptor
+        .decrypt(
+            identities
+                .iter()
+                .map(|identity| &**identity as &dyn age::Identity),
+        )
+        .context("decrypt with the identity file")?;
+    Ok(Box::new(reader))
+}
+
+#[cfg(not(feature = "age"))]
+fn open_with_age(_file: std::fs::File, _identity: &Path) -> Result<Box<dyn Read>> {
+    anyhow::bail!("built without the age feature")
+}
+
+/// How to encrypt new files.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Encryption<'a> {
+    pub cipher: Cipher,
+    /// Recipients of age or GnuPG
+    pub recipients: &'a [String],
+    /// The file holding the passphrase, instead of [`PASSPHRASE_VAR`]
+    pub passphrase_file: Option<&'a Path>,
+}
+
+impl Encryption<'_> {
+    /// Check if files are encrypted at all, i.e. there are recipients or a passphrase.
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.cipher == Cipher::Passphrase || !self.recipients.is_empty()
+    }
+
+    /// Check that files can be encrypted, e.g. that there is a passphrase, before creating them.
+    pub fn check(&self) -> Result<()> {
+        if self.cipher == Cipher::Age && self.is_enabled() {
+            anyhow::ensure!(cfg!(feature = "age"), "built without the age feature");
+        }
+        if self.cipher == Cipher::Passphrase {
+            anyhow::ensure!(
+                cfg!(feature = "passphrase"),
+                "built without the passphrase feature"
+            );
+            passphrase(self.passphrase_file)?;
+        }
+        Ok(())
+    }
+}
+
+/// Encrypts everything written through it before passing it on to the inner writer, or passes
+/// it on as is if encryption is not enabled.
+pub struct Encryptor<W: Write>(EncryptorState<W>);
+
+enum EncryptorState<W: Write> {
+    Plain(W),
+    /// An external tool encrypting into a temporary file, copied to the writer once finished
+    Process(ProcessWriter, TempFile, W),
+    #[cfg(feature = "passphrase")]
+    Passphrase(Box<passphrase::Sealer>, W),
+    #[cfg(feature = "age")]
+    Age(age::stream::StreamWriter<W>),
+}
+
+impl<W: Write> Encryptor<W> {
+    pub fn new(inner: W, settings: Encryption, name: &str) -> Result<Self> {
+        let state = if !settings.is_enabled() {
+            EncryptorState::Plain(inner)
+        } else if settings.cipher == Cipher::Passphrase {
+            passphrase_state(inner, settings.passphrase_file)?
+        } else if settings.cipher == Cipher::Age {
+            age_state(inner, settings.recipients)?
+        } else {
+            let buffer = TempFile::create(&format!("encrypt-{name}"))?;
+            let command = settings
+                .cipher
+                .encrypt_command(settings.recipients, &buffer.path);
+            EncryptorState::Process(ProcessWriter::spawn(command, name)?, buffer, inner)
+        };
+        Ok(Self(state))
+    }
+
+    /// Finish encrypting and write the ciphertext to the inner writer, returning it.
+    pub fn finish(self) -> Result<W> {
+        match self.0 {
+            EncryptorState::Plain(inner) => Ok(inner),
+            EncryptorState::Process(mut writer, buffer, mut inner) => {
+                writer.finish().context("encrypt")?;
+                // The tool may have replaced the file rather than written to it
+                let mut file = std::fs::File::open(&buffer.path).context("open encrypted file")?;
+                std::io::copy(&mut file, &mut inner).context("write encrypted file")?;
+                Ok(inner)
+            }
+            #[cfg(feature = "passphrase")]
+            EncryptorState::Passphrase(sealer, mut inner) => {
+                sealer.finish(&mut inner).context("encrypt")?;
+                Ok(inner)
+            }
+            #[cfg(feature = "age")]
+            EncryptorState::Age(writer) => writer.finish().context("encrypt"),
+        }
+    }
+}
+
+#[cfg(feature = "passphrase")]
+fn passphrase_state<W: Write>(inner: W, file: Option<&Path>) -> Result<EncryptorState<W>> {
+    let sealer = passphrase::Sealer::new(&passphrase(file)?)?;
+    Ok(EncryptorState::Passphrase(Box::new(sealer), inner))
+}
+
+#[cfg(not(feature = "passphrase"))]
+fn passphrase_state<W: Write>(_inner: W, _file: Option<&Path>) -> Result<EncryptorState<W>> {
+    anyhow::bail!("built without the passphrase feature")
+}
+
+#[cfg(feature = "age")]
+fn age_state<W: Write>(inner: W, recipients: &[String]) -> Result<EncryptorState<W>> {
+    let recipients = (recipients.iter())
+        .map(|recipient| age_recipient(recipient))
+        .collect::<Result<Vec<_>>>()?;
+    let encryptor = age::Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| &**recipient as &dyn age::Recipient),
+    )
+    .context("encrypt to the recipients")?;
+    let writer = encryptor.wrap_output(inner).context("write age header")?;
+    Ok(EncryptorState::Age(writer))
+}
+
+#[cfg(not(feature = "age"))]
+fn age_state<W: Write>(_inner: W, _recipients: &[String]) -> Result<EncryptorState<W>> {
+    anyhow::bail!("built without the age feature")
+}
+
+/// The age recipient `recipient`, an age public key or an SSH public key.
+#[cfg(feature = "age")]
+fn age_recipient(recipient: &str) -> Result<Box<dyn age::Recipient>> {
+    if let Ok(recipient) = recipient.parse::<age::x25519::Recipient>() {
+        return Ok(Box::new(recipient));
+    }
+    match recipient.parse::<age::ssh::Recipient>() {
+        Ok(recipient) => Ok(Box::new(recipient)),
+        Err(_) => anyhow::bail!("invalid age recipient {recipient:?}"),
+    }
+}
+
+impl<W: Write> Write for Encryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.0 {
+            EncryptorState::Plain(inner) => inner.write(buf),
+            EncryptorState::Process(writer, _buffer, _inner) => writer.write(buf),
+            #[cfg(feature = "passphrase")]
+            EncryptorState::Passphrase(sealer, inner) => {
+                sealer.update(buf, inner)?;
+                Ok(buf.len())
+            }
+            #[cfg(feature = "age")]
+            EncryptorState::Age(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.0 {
+            EncryptorState::Plain(inner) => inner.flush(),
+            EncryptorState::Process(writer, _buffer, _inner) => writer.flush(),
+            #[cfg(feature = "passphrase")]
+            EncryptorState::Passphrase(_sealer, inner) => inner.flush(),
+            #[cfg(feature = "age")]
+            EncryptorState::Age(writer) => writer.flush(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "age"))]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+
+    #[test]
+    fn age_round_trip() {
+        let temp = tempfile::tempdir().unwrap();
+        let identity = age::x25519::Identity::generate();
+        let identity_file = temp.path().join("identity.txt");
+        std::fs::write(&identity_file, identity.to_string().expose_secret()).unwrap();
+        let recipients = [identity.to_public().to_string()];
+        let settings = Encryption {
+            cipher: Cipher::Age,
+            recipients: &recipients,
+            passphrase_file: None,
+        };
+        let mut encryptor = Encryptor::new(Vec::new(), settings, "notes.txt.age").unwrap();
+        encryptor.write_all(b"hello").unwrap();
+        let sealed = encryptor.finish().unwrap();
+        assert!(!sealed.windows(5).any(|window| window == b"hello"));
+        let decryptor = Decryptor {
+            identity: Some(identity_file),
+            passphrase_file: None,
+        };
+        let mut plaintext = String::new();
+        (decryptor.decrypt(&sealed[..], "notes.txt.age").unwrap())
+            .read_to_string(&mut plaintext)
+            .unwrap();
+        assert_eq!(plaintext, "hello");
+        let stranger = Decryptor {
+            identity: Some(temp.path().join("missing.txt")),
+            passphrase_file: None,
+        };
+        assert!(stranger.decrypt(&sealed[..], "notes.txt.age").is_err());
+    }
+}
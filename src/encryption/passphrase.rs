@@ -0,0 +1,273 @@
+//! Symmetric encryption with a key derived from a passphrase.
+//!
+//! The key is derived with Argon2id from the passphrase and a random salt, the salt kept in a
+//! header along with the Argon2 parameters (memory, passes and lanes). The data follows in chunks sealed with
+//! ChaCha20-Poly1305, each with a nonce made of its index and whether it is the last chunk, so
+//! chunks cannot be reordered, dropped or truncated without failing to open.
+
+use anyhow::{Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 8] = b"RBPASS2\n";
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + 3 * 4 + SALT_LEN;
+/// Memory (in KiB), passes and lanes for new files, as recommended by OWASP for Argon2id
+const COST: Cost = Cost {
+    memory: 19 * 1024,
+    passes: 2,
+    lanes: 1,
+};
+/// Refuse headers asking for more, so a corrupted file can't exhaust memory or hang decryption
+const MAX_COST: Cost = Cost {
+    memory: 4 * 1024 * 1024,
+    passes: 100,
+    lanes: 64,
+};
+const CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+/// The Argon2 parameters a key is derived with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cost {
+    /// In KiB
+    memory: u32,
+    passes: u32,
+    lanes: u32,
+}
+
+impl Cost {
+    fn to_bytes(self) -> [u8; 12] {
+        let mut bytes = [0; 12];
+        bytes[..4].copy_from_slice(&self.memory.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.passes.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.lanes.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let field = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().expect("4 bytes"));
+        Self {
+            memory: field(0),
+            passes: field(4),
+            lanes: field(8),
+        }
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], cost: Cost) -> Result<LessSafeKey> {
+    let params = Params::new(cost.memory, cost.passes, cost.lanes, Some(32))
+        .map_err(|e| anyhow::anyhow!("invalid Argon2 parameters {cost:?}: {e}"))?;
+    let mut key = [0; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("derive key: {e}"))?;
+    let key = UnboundKey::new(&aead::CHACHA20_POLY1305, &key).expect("key of the right length");
+    Ok(LessSafeKey::new(key))
+}
+
+fn nonce(index: u64, last: bool) -> Nonce {
+    let mut nonce = [0; aead::NONCE_LEN];
+    nonce[3..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = u8::from(last);
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Encrypts data in chunks, writing each to the output as soon as it is complete.
+pub struct Sealer {
+    key: LessSafeKey,
+    header: Option<Vec<u8>>,
+    index: u64,
+    buffer: Vec<u8>,
+}
+
+impl Sealer {
+    pub fn new(passphrase: &str) -> Result<Self> {
+        Self::with_cost(passphrase, COST)
+    }
+
+    fn with_cost(passphrase: &str, cost: Cost) -> Result<Self> {
+        let mut salt = [0; SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| anyhow::anyhow!("generate salt"))?;
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&cost.to_bytes());
+        header.extend_from_slice(&salt);
+        Ok(Self {
+            key: derive_key(passphrase, &salt, cost)?,
+            header: Some(header),
+            index: 0,
+            buffer: Vec::with_capacity(CHUNK_LEN),
+        })
+    }
+
+    /// Encrypt `data`, writing every completed chunk to `output`.
+    pub fn update(&mut self, data: &[u8], output: &mut impl Write) -> std::io::Result<()> {
+        self.write_header(output)?;
+        self.buffer.extend_from_slice(data);
+        // The last chunk is sealed differently, so a full chunk waits until more data follows
+        while self.buffer.len() > CHUNK_LEN {
+            let rest = self.buffer.split_off(CHUNK_LEN);
+            let chunk = std::mem::replace(&mut self.buffer, rest);
+            self.seal(chunk, false, output)?;
+        }
+        Ok(())
+    }
+
+    /// Encrypt the last chunk and write it to `output`.
+    pub fn finish(mut self, output: &mut impl Write) -> std::io::Result<()> {
+        self.write_header(output)?;
+        let chunk = std::mem::take(&mut self.buffer);
+        self.seal(chunk, true, output)
+    }
+
+    fn write_header(&mut self, output: &mut impl Write) -> std::io::Result<()> {
+        match self.header.take() {
+            Some(header) => output.write_all(&header),
+            None => Ok(()),
+        }
+    }
+
+    fn seal(
+        &mut self,
+        mut chunk: Vec<u8>,
+        last: bool,
+        output: &mut impl Write,
+    ) -> std::io::Result<()> {
+        self.key
+            .seal_in_place_append_tag(nonce(self.index, last), Aad::empty(), &mut chunk)
+            .map_err(|_| std::io::Error::other("seal chunk"))?;
+        self.index += 1;
+        output.write_all(&chunk)
+    }
+}
+
+/// Decrypts data read from the inner reader, failing if it was tampered with or truncated.
+pub struct Opener<R> {
+    inner: R,
+    key: LessSafeKey,
+    index: u64,
+    /// The first byte of the next chunk, read to tell whether the current one is the last
+    lookahead: Option<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read> Opener<R> {
+    pub fn new(mut inner: R, passphrase: &str) -> Result<Self> {
+        let mut header = [0; HEADER_LEN];
+        inner.read_exact(&mut header).context("read header")?;
+        let (magic, rest) = header.split_at(MAGIC.len());
+        anyhow::ensure!(magic == MAGIC, "not encrypted with a passphrase");
+        let (cost, salt) = rest.split_at(12);
+        let cost = Cost::from_bytes(cost);
+        anyhow::ensure!(
+            cost.memory <= MAX_COST.memory
+                && cost.passes <= MAX_COST.passes
+                && cost.lanes <= MAX_COST.lanes,
+            "Argon2 parameters too costly ({cost:?})"
+        );
+        Ok(Self {
+            inner,
+            key: derive_key(passphrase, salt, cost)?,
+            index: 0,
+            lookahead: None,
+            plaintext: Vec::new(),
+            position: 0,
+            done: false,
+        })
+    }
+
+    fn open_next(&mut self) -> std::io::Result<()> {
+        let mut chunk = Vec::with_capacity(CHUNK_LEN + TAG_LEN + 1);
+        chunk.extend(self.lookahead.take());
+        (&mut self.inner)
+            .take((CHUNK_LEN + TAG_LEN + 1 - chunk.len()) as u64)
+            .read_to_end(&mut chunk)?;
+        let last = chunk.len() <= CHUNK_LEN + TAG_LEN;
+        if !last {
+            self.lookahead = chunk.pop();
+        }
+        let plaintext = self
+            .key
+            .open_in_place(nonce(self.index, last), Aad::empty(), &mut chunk)
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "decryption failed (wrong passphrase, or the file was tampered with)",
+                )
+            })?;
+        let len = plaintext.len();
+        chunk.truncate(len);
+        self.plaintext = chunk;
+        self.position = 0;
+        self.index += 1;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Opener<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.open_next()?;
+        }
+        let len = buf.len().min(self.plaintext.len() - self.position);
+        buf[..len].copy_from_slice(&self.plaintext[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seal(data: &[u8], passphrase: &str) -> Vec<u8> {
+        let cheap = Cost {
+            memory: 8,
+            passes: 1,
+            lanes: 1,
+        };
+        let mut sealer = Sealer::with_cost(passphrase, cheap).unwrap();
+        let mut output = Vec::new();
+        for part in data.chunks(1000) {
+            sealer.update(part, &mut output).unwrap();
+        }
+        sealer.finish(&mut output).unwrap();
+        output
+    }
+
+    fn open(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        Opener::new(data, passphrase)?.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn round_trip_and_tampering() {
+        for len in [0, 1, CHUNK_LEN, CHUNK_LEN + 1, 3 * CHUNK_LEN + 7] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let sealed = seal(&data, "hunter2");
+            assert_eq!(open(&sealed, "hunter2").unwrap(), data);
+            assert!(open(&sealed, "hunter3").is_err());
+            let mut flipped = sealed.clone();
+            *flipped.last_mut().unwrap() ^= 1;
+            assert!(open(&flipped, "hunter2").is_err());
+            let mut costly = sealed.clone();
+            costly[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+            assert!(open(&costly, "hunter2").is_err());
+            if len > CHUNK_LEN {
+                let truncated = &sealed[..HEADER_LEN + CHUNK_LEN + TAG_LEN];
+                assert!(open(truncated, "hunter2").is_err());
+            }
+        }
+    }
+}
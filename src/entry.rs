@@ -1,7 +1,12 @@
 use crate::config;
+use crate::encryption;
+use crate::error::Error;
+use crate::meta::{self, BackupMeta};
+use crate::storage::{LocalStorage, Storage};
 use crate::timestamp::{Range, Timestamp};
 use anyhow::{Context, Result};
-use std::collections::HashMap;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::fs::Metadata;
 use std::path::{Path, PathBuf};
 
@@ -9,11 +14,24 @@ use std::path::{Path, PathBuf};
 pub struct Entry {
     pub path: PathBuf,
     pub timestamp: Timestamp,
+    /// The source name following the timestamp in the file name
+    pub label: Option<String>,
+    /// Metadata recorded at backup time, if the backup has a sidecar file
+    pub meta: Option<BackupMeta>,
     pub fulfills: Vec<Fulfillment>,
 }
 
 impl Entry {
     pub fn from_path(path: PathBuf, config: &config::Archive) -> Result<Option<Self>> {
+        let Some(mut entry) = Self::parse(path, config)? else {
+            return Ok(None);
+        };
+        entry.meta = BackupMeta::load(&entry.path).context("load backup metadata")?;
+        Ok(Some(entry))
+    }
+
+    /// Parse the timestamp and label from the file name, without loading metadata.
+    fn parse(path: PathBuf, config: &config::Archive) -> Result<Option<Self>> {
         let filename = path
             .file_name()
             .context("no file name")?
@@ -22,21 +40,83 @@ impl Entry {
         let Some(removed_prefix) = filename.strip_prefix(config.prefix.as_str()) else {
             return Ok(None);
         };
-        let raw_timestamp = removed_prefix
+        let (raw_timestamp, rest) = removed_prefix
             .split_once('.')
-            .map_or(removed_prefix, |o| o.0);
+            .unwrap_or((removed_prefix, ""));
+        let rest = encryption::strip_suffix(rest);
         let timestamp = Timestamp::parse_from_str(raw_timestamp, config.timestamp_format.as_str())
             .context("failed to parse timestamp from filename")?;
+        let label = if rest == "tar.gz" {
+            ""
+        } else {
+            rest.strip_suffix(".tar.gz")
+                .unwrap_or_else(|| rest.rsplit_once('.').map_or(rest, |(stem, _ext)| stem))
+        };
+        let label = (!label.is_empty()).then(|| label.to_owned());
         Ok(Some(Self {
             path,
             timestamp,
+            label,
+            meta: None,
             fulfills: Vec::new(),
         }))
     }
 
+    /// The file name of the backup, by which its storage refers to it.
+    pub fn name(&self) -> Result<String> {
+        let name = self.path.file_name().context("no file name")?;
+        Ok(name.to_string_lossy().into_owned())
+    }
+
     pub fn metadata(&self) -> Result<Metadata> {
         Ok(std::fs::metadata(&self.path)?)
     }
+
+    /// The size of the backup file, or 0 if it is unknown.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        // Backups in remote targets can't be stat'ed, but their sidecars record the size
+        (self.metadata().map(|metadata| metadata.len()).ok())
+            .or_else(|| self.meta.as_ref()?.written_size)
+            .unwrap_or(0)
+    }
+
+    /// The backup file and its sidecar metadata file, if it has one.
+    #[must_use]
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = vec![self.path.clone()];
+        if self.meta.is_some() {
+            files.push(meta::sidecar_path(&self.path));
+        }
+        files
+    }
+
+    /// Check if the entry is referred to by `selector`: its path, file name or timestamp.
+    #[must_use]
+    pub fn matches(&self, selector: &str) -> bool {
+        self.path == Path::new(selector)
+            || self.path.file_name().is_some_and(|name| name == selector)
+            || self.timestamp.to_string() == selector
+            || self.timestamp.humanized() == selector
+    }
+
+    #[must_use]
+    pub fn is_pinned(&self) -> bool {
+        self.fulfills
+            .iter()
+            .any(|f| matches!(f.kind, FulfillmentKind::Pinned { .. }))
+    }
+
+    /// Check if the entry is pinned or has a tag to keep, so limits never unmark it.
+    #[must_use]
+    pub fn is_protected(&self) -> bool {
+        self.fulfills.iter().any(|f| f.kind.is_protected())
+    }
+
+    #[must_use]
+    pub fn tags(&self) -> &[String] {
+        self.meta.as_ref().map_or(&[], |meta| &meta.tags)
+    }
 }
 
 impl Eq for Entry {}
@@ -71,47 +151,174 @@ impl std::fmt::Display for Entry {
     }
 }
 
-pub(crate) fn read_dir(target: &Path, config: &config::Archive) -> Result<Vec<Entry>> {
+/// Find the single entry referred to by `selector` (see [`Entry::matches`]).
+pub fn find<'a>(entries: &'a [Entry], selector: &str) -> Result<&'a Entry> {
+    let mut found = entries.iter().filter(|e| e.matches(selector));
+    let entry = found
+        .next()
+        .with_context(|| format!("no backup matches {selector:?}"))?;
+    if found.next().is_some() {
+        anyhow::bail!("multiple backups match {selector:?}");
+    }
+    Ok(entry)
+}
+
+pub(crate) fn read_dir(
+    target: &Path,
+    config: &config::Archive,
+) -> std::result::Result<Vec<Entry>, Error> {
+    read_storage(&LocalStorage::new(target), config)
+}
+
+/// A file named like a backup that can't be read as one, skipped by lenient reads.
+#[derive(Debug)]
+pub struct Skipped {
+    pub path: PathBuf,
+    /// Why the file can't be read as a backup
+    pub reason: anyhow::Error,
+}
+
+/// Read every backup in `storage`, with its metadata, failing if any file named like a backup
+/// can't be read as one.
+pub fn read_storage(
+    storage: &dyn Storage,
+    config: &config::Archive,
+) -> std::result::Result<Vec<Entry>, Error> {
+    read_storage_into(storage, config, None)
+}
+
+/// Read every backup in `storage`, with its metadata, skipping the files named like backups that
+/// can't be read as one (e.g. with a malformed timestamp, or the timestamp of another backup)
+/// and returning them apart.
+pub fn read_storage_lenient(
+    storage: &dyn Storage,
+    config: &config::Archive,
+) -> std::result::Result<(Vec<Entry>, Vec<Skipped>), Error> {
+    let mut skipped = Vec::new();
+    let all_backups = read_storage_into(storage, config, Some(&mut skipped))?;
+    Ok((all_backups, skipped))
+}
+
+/// Read every backup in `storage`, collecting the files that can't be read as backups into
+/// `skipped` if given, or failing on the first one otherwise.
+fn read_storage_into(
+    storage: &dyn Storage,
+    config: &config::Archive,
+    mut skipped: Option<&mut Vec<Skipped>>,
+) -> std::result::Result<Vec<Entry>, Error> {
     let mut all_backups = Vec::new();
-    let mut timestamps: HashMap<Timestamp, Entry> = HashMap::new();
-    for file in target.read_dir().context("read target directory")? {
-        let file = file.context("read file from dir")?;
-        let file_name = file.file_name();
-        let file_path = file.path();
-        let entry_opt = Entry::from_path(file_path, config)
-            .with_context(|| format!("parse {}", file_name.to_string_lossy()))?;
-        let Some(backup) = entry_opt else {
+    let mut timestamps: HashMap<Timestamp, PathBuf> = HashMap::new();
+    // Sorted, so the same one of two backups with the same timestamp is skipped every time
+    let mut names = storage.list()?;
+    names.sort();
+    let sidecars: HashSet<&str> = names
+        .iter()
+        .filter_map(|name| name.strip_suffix(meta::SIDECAR_SUFFIX))
+        .collect();
+    for name in &names {
+        if name.ends_with(meta::SIDECAR_SUFFIX) {
             continue;
-        };
-        if let Some(existing) = timestamps.get(&backup.timestamp) {
-            anyhow::bail!(
-                "timestamps conflict for {} and {}",
-                backup.path.display(),
-                existing.path.display()
-            );
         }
-        timestamps.insert(backup.timestamp, backup.clone());
+        let backup = match (
+            read_entry(storage, name, config, &sidecars, &timestamps),
+            skipped.as_deref_mut(),
+        ) {
+            (Ok(Some(backup)), _) => backup,
+            (Ok(None), _) => continue,
+            (Err(reason), Some(skipped)) => {
+                skipped.push(Skipped {
+                    path: storage.locate(name),
+                    reason: reason.into(),
+                });
+                continue;
+            }
+            (Err(e), None) => return Err(e),
+        };
+        timestamps.insert(backup.timestamp, backup.path.clone());
         all_backups.push(backup);
     }
     all_backups.sort();
     Ok(all_backups)
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Read the backup `name` in `storage`, or None if it isn't named like a backup, failing if it
+/// has the timestamp of a backup in `timestamps` ([`Error::TimestampConflict`]).
+fn read_entry(
+    storage: &dyn Storage,
+    name: &str,
+    config: &config::Archive,
+    sidecars: &HashSet<&str>,
+    timestamps: &HashMap<Timestamp, PathBuf>,
+) -> std::result::Result<Option<Entry>, Error> {
+    let path = storage.locate(name);
+    let entry_opt = Entry::parse(path.clone(), config).map_err(|e| Error::Parse {
+        path,
+        message: format!("{e:#}"),
+    })?;
+    let Some(mut backup) = entry_opt else {
+        return Ok(None);
+    };
+    if sidecars.contains(name) {
+        let meta = BackupMeta::read_from(storage, name).context("load backup metadata")?;
+        backup.meta = Some(meta);
+    }
+    if let Some(existing) = timestamps.get(&backup.timestamp) {
+        return Err(Error::TimestampConflict {
+            timestamp: backup.timestamp,
+            existing: Some(existing.clone()),
+        });
+    }
+    Ok(Some(backup))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub struct Fulfillment {
-    /// The range (or None if it is one of the latest)
-    pub range: Option<Range>,
-    /// The index within the range
+    /// What the entry is kept for
+    pub kind: FulfillmentKind,
+    /// The index within the kind
     pub index: usize,
     /// If it is the first or last of the index in the range
     pub first_or_last: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(tag = "type", content = "of", rename_all = "snake_case")]
+pub enum FulfillmentKind {
+    /// One of the latest backups
+    Latest,
+    /// First or last backup of a bucket in the range
+    Range(Range),
+    /// Closest backup to a calendar anchor
+    Calendar(config::CalendarPeriod),
+    /// Explicitly pinned by the user, possibly until some time
+    Pinned { until: Option<Timestamp> },
+    /// Has a tag listed in `delete.keep_tags`
+    Tagged,
+}
+
+impl FulfillmentKind {
+    /// Check if this was explicitly requested by the user rather than by the ranges.
+    #[must_use]
+    pub fn is_protected(self) -> bool {
+        matches!(self, Self::Pinned { .. } | Self::Tagged)
+    }
+}
+
 impl Fulfillment {
     #[must_use]
     pub fn display(&self) -> String {
-        let Some(range) = self.range else {
-            return format!("latest #{}", self.index);
+        let range = match self.kind {
+            FulfillmentKind::Latest => return format!("latest #{}", self.index),
+            FulfillmentKind::Calendar(period) => {
+                let period = format!("{period:?}").to_lowercase();
+                return format!("closest to {period} anchor #{}", self.index);
+            }
+            FulfillmentKind::Pinned { until: None } => return String::from("pinned"),
+            FulfillmentKind::Pinned { until: Some(until) } => {
+                return format!("pinned until {}", until.humanized());
+            }
+            FulfillmentKind::Tagged => return String::from("tagged to keep"),
+            FulfillmentKind::Range(range) => range,
         };
         let mut repr = if self.first_or_last {
             String::from("first")
@@ -126,8 +333,20 @@ impl Fulfillment {
 
     #[must_use]
     pub fn display_short(&self) -> String {
-        let Some(range) = self.range else {
-            return format!("L#{}", self.index);
+        let range = match self.kind {
+            FulfillmentKind::Latest => return format!("L#{}", self.index),
+            FulfillmentKind::Calendar(period) => {
+                let letter = match period {
+                    config::CalendarPeriod::Day => 'd',
+                    config::CalendarPeriod::Week => 'w',
+                    config::CalendarPeriod::Month => 'M',
+                    config::CalendarPeriod::Year => 'Y',
+                };
+                return format!("@{letter}{}", self.index);
+            }
+            FulfillmentKind::Pinned { .. } => return String::from("P"),
+            FulfillmentKind::Tagged => return String::from("T"),
+            FulfillmentKind::Range(range) => range,
         };
         let mut repr = if self.first_or_last {
             String::from("⇤")
@@ -152,3 +371,74 @@ impl std::fmt::Display for Fulfillment {
         write!(f, "{}", self.display())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_labels() {
+        let config = config::Config::default().archive;
+        let label = |name: &str| {
+            let path = PathBuf::from(format!(".rattlebeaver.2024-01-01_00-00-00{name}"));
+            Entry::parse(path, &config).unwrap().unwrap().label
+        };
+        assert_eq!(label(".myproject.tar.gz").as_deref(), Some("myproject"));
+        assert_eq!(label(".myproject.tar.gz.age").as_deref(), Some("myproject"));
+        assert_eq!(label(".notes.txt").as_deref(), Some("notes"));
+        assert_eq!(label(".dump.gz").as_deref(), Some("dump"));
+        assert_eq!(label(".tar.gz"), None);
+        assert_eq!(label(""), None);
+    }
+
+    #[test]
+    fn skips_unreadable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            ".rattlebeaver.2024-01-01_00-00-00.a.tar.gz",
+            ".rattlebeaver.2024-01-01_00-00-00.b.tar.gz",
+            ".rattlebeaver.not-a-timestamp.c.tar.gz",
+            "unrelated.txt",
+        ] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        let config = config::Config::default().archive;
+        let storage = LocalStorage::new(dir.path());
+        assert!(read_storage(&storage, &config).is_err());
+        let (backups, skipped) = read_storage_lenient(&storage, &config).unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].label.as_deref(), Some("a"));
+        let mut skipped: Vec<&str> = (skipped.iter())
+            .map(|s| s.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        skipped.sort_unstable();
+        assert_eq!(
+            skipped,
+            [
+                ".rattlebeaver.2024-01-01_00-00-00.b.tar.gz",
+                ".rattlebeaver.not-a-timestamp.c.tar.gz"
+            ]
+        );
+    }
+
+    #[test]
+    fn read_errors_are_typed() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config::Config::default().archive;
+        let storage = LocalStorage::new(dir.path());
+        let write = |name: &str| std::fs::write(dir.path().join(name), "").unwrap();
+        write(".rattlebeaver.not-a-timestamp.tar.gz");
+        assert!(matches!(
+            read_storage(&storage, &config),
+            Err(Error::Parse { path, .. }) if path.ends_with(".rattlebeaver.not-a-timestamp.tar.gz")
+        ));
+        std::fs::remove_file(dir.path().join(".rattlebeaver.not-a-timestamp.tar.gz")).unwrap();
+        write(".rattlebeaver.2024-01-01_00-00-00.a.tar.gz");
+        write(".rattlebeaver.2024-01-01_00-00-00.b.tar.gz");
+        assert!(matches!(
+            read_storage(&storage, &config),
+            Err(Error::TimestampConflict { existing: Some(existing), .. })
+                if existing.ends_with(".rattlebeaver.2024-01-01_00-00-00.a.tar.gz")
+        ));
+    }
+}
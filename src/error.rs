@@ -0,0 +1,66 @@
+//! The errors of the main library functions, for callers that handle some kinds of failure.
+
+use crate::timestamp::Timestamp;
+use std::path::{Path, PathBuf};
+
+/// Why adding, reading or configuring backups failed.
+///
+/// Failures without a kind of their own are [`Error::Other`], with the context of where they
+/// happened.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// A source, target or config file doesn't exist
+    #[error("{} not found", .0.display())]
+    NotFound(PathBuf),
+    /// A backup with the same timestamp already exists
+    #[error("timestamp {timestamp} conflicts with {}", conflicting(.existing.as_deref()))]
+    TimestampConflict {
+        timestamp: Timestamp,
+        /// The existing backup, or None if it is still being written by this process
+        existing: Option<PathBuf>,
+    },
+    /// A file isn't in the expected format
+    #[error("parse {}: {message}", path.display())]
+    Parse { path: PathBuf, message: String },
+    /// The config parsed, but its values are invalid
+    #[error("invalid config {}", path.display())]
+    Config {
+        path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// Reading or writing a file failed
+    #[error("access {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The operation was cancelled before it finished
+    #[error("cancelled")]
+    Cancelled,
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+fn conflicting(existing: Option<&Path>) -> String {
+    match existing {
+        Some(existing) => format!("existing backup: {}", existing.display()),
+        None => String::from("another backup being written"),
+    }
+}
+
+impl Error {
+    /// The error of accessing `path`, [`Error::NotFound`] if it doesn't exist.
+    pub(crate) fn io(path: &Path, source: std::io::Error) -> Self {
+        if source.kind() == std::io::ErrorKind::NotFound {
+            Self::NotFound(path.to_owned())
+        } else {
+            Self::Io {
+                path: path.to_owned(),
+                source,
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Typed events of backups and deletions as they happen, so GUIs and daemons can follow what is
+//! going on without parsing output. Events serialize to JSON (e.g. as JSON lines), tagged by
+//! their kind in the `event` field.
+
+use crate::timestamp::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Something that happened to a backup.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Event {
+    /// A backup of `source` (a path, a command, or the name of a stream) started, timestamped
+    /// `timestamp` (or the current time, if the source can't be read to timestamp it)
+    BackupStarted {
+        source: String,
+        timestamp: Timestamp,
+    },
+    /// A file of the source was archived, at `path` relative to the source
+    FileArchived { path: PathBuf, size: u64 },
+    /// A backup of `source` was written to `path`, taking `duration` seconds
+    BackupFinished {
+        source: String,
+        path: PathBuf,
+        duration: f64,
+    },
+    /// A backup of `source` failed, leaving nothing behind
+    BackupFailed { source: String, error: String },
+    /// A backup fulfills no retention range, so deleting stale backups deletes it
+    StaleDetected { path: PathBuf, timestamp: Timestamp },
+    /// A backup was deleted (or moved to the trash)
+    Deleted { path: PathBuf, timestamp: Timestamp },
+}
+
+/// Called with every event as it happens.
+pub type OnEvent<'a> = &'a dyn Fn(&Event);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_tagged() {
+        let event = Event::FileArchived {
+            path: PathBuf::from("docs/notes.txt"),
+            size: 5,
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"event":"file-archived","path":"docs/notes.txt","size":5}"#
+        );
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+    }
+}
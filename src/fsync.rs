@@ -0,0 +1,34 @@
+//! Flushing files and directories to disk when `archive.fsync` is set, so a power loss right
+//! after a command reported success can't undo what it did.
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::path::Path;
+
+/// Flush the contents of the open `file` to disk, if `enabled`.
+pub fn file(file: &File, enabled: bool) -> Result<()> {
+    if enabled {
+        file.sync_all().context("sync file to disk")?;
+    }
+    Ok(())
+}
+
+/// Flush the directory at `path` to disk, if `enabled`, so files created, renamed or removed in
+/// it stay that way.
+pub fn dir(path: &Path, enabled: bool) -> Result<()> {
+    // Directories can't be opened as files on Windows, where metadata is written through
+    if enabled && cfg!(unix) {
+        File::open(path)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("sync {} to disk", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Flush the directory containing `path` to disk, if `enabled`.
+pub fn parent(path: &Path, enabled: bool) -> Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => dir(parent, enabled),
+        _ => dir(Path::new("."), enabled),
+    }
+}
@@ -0,0 +1,126 @@
+//! Running the hooks in the config, e.g. to quiesce a database before it is backed up and
+//! notify after.
+//!
+//! Hooks are run by the shell with what they are about in environment variables. Their output
+//! goes to stderr, keeping stdout for the paths that commands print.
+
+use crate::config::Hooks;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Run the `before_add` hook for backing up `source` to `target`.
+pub fn before_add(hooks: &Hooks, source: &Path, target: &str) -> Result<()> {
+    let Some(command) = &hooks.before_add else {
+        return Ok(());
+    };
+    let env = [
+        ("RATTLEBEAVER_SOURCE", source.display().to_string()),
+        ("RATTLEBEAVER_TARGET_DIR", target.to_owned()),
+    ];
+    run("before_add", command, &env)
+}
+
+/// Run the `after_add` hook with the `result` of backing up `source` to `target`.
+pub fn after_add<E: std::fmt::Display>(
+    hooks: &Hooks,
+    source: &Path,
+    target: &str,
+    result: &Result<PathBuf, E>,
+) -> Result<()> {
+    let Some(command) = &hooks.after_add else {
+        return Ok(());
+    };
+    let mut env = vec![
+        ("RATTLEBEAVER_SOURCE", source.display().to_string()),
+        ("RATTLEBEAVER_TARGET_DIR", target.to_owned()),
+    ];
+    match result {
+        Ok(path) => {
+            env.push(("RATTLEBEAVER_RESULT", "success".to_owned()));
+            env.push(("RATTLEBEAVER_BACKUP_PATH", path.display().to_string()));
+        }
+        Err(e) => {
+            env.push(("RATTLEBEAVER_RESULT", "failure".to_owned()));
+            env.push(("RATTLEBEAVER_ERROR", e.to_string()));
+        }
+    }
+    run("after_add", command, &env)
+}
+
+/// Run the `before_delete` hook for deleting `backups` from `target`.
+pub fn before_delete(hooks: &Hooks, target: &str, backups: &[&Path]) -> Result<()> {
+    let Some(command) = &hooks.before_delete else {
+        return Ok(());
+    };
+    let paths: Vec<String> = backups.iter().map(|b| b.display().to_string()).collect();
+    let env = [
+        ("RATTLEBEAVER_TARGET_DIR", target.to_owned()),
+        ("RATTLEBEAVER_DELETE_COUNT", backups.len().to_string()),
+        ("RATTLEBEAVER_DELETE_PATHS", paths.join("\n")),
+    ];
+    run("before_delete", command, &env)
+}
+
+/// A process running `command` with the shell, `sh` or `cmd` on Windows.
+pub(crate) fn shell(command: &str) -> Command {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut process = Command::new(shell);
+    process.arg(flag).arg(command);
+    process
+}
+
+fn run(name: &str, command: &str, env: &[(&str, String)]) -> Result<()> {
+    let status = shell(command)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .stdout(std::io::stderr())
+        .status()
+        .with_context(|| format!("run {name} hook"))?;
+    anyhow::ensure!(status.success(), "{name} hook failed ({status})");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn runs_hooks_with_their_environment() {
+        let temp = tempfile::tempdir().unwrap();
+        let out = temp.path().join("out");
+        let hooks = Hooks {
+            before_add: Some(String::from("exit 3")),
+            after_add: Some(format!(
+                "printf '%s %s %s' \"$RATTLEBEAVER_RESULT\" \"$RATTLEBEAVER_SOURCE\" \
+                 \"$RATTLEBEAVER_BACKUP_PATH$RATTLEBEAVER_ERROR\" >> {}",
+                out.display()
+            )),
+            before_delete: Some(format!(
+                "printf ' %s:%s' \"$RATTLEBEAVER_DELETE_COUNT\" \"$RATTLEBEAVER_DELETE_PATHS\" >> {}",
+                out.display()
+            )),
+        };
+        let error = before_add(&hooks, Path::new("/src"), "/target").unwrap_err();
+        assert!(
+            error.to_string().contains("before_add hook failed"),
+            "{error}"
+        );
+        let added: Result<PathBuf, String> = Ok(PathBuf::from("/target/backup"));
+        after_add(&hooks, Path::new("/src"), "/target", &added).unwrap();
+        let failed: Result<PathBuf, String> = Err(String::from("denied"));
+        after_add(&hooks, Path::new("/src"), "/target", &failed).unwrap();
+        before_delete(&hooks, "/target", &[Path::new("a"), Path::new("b")]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "success /src /target/backupfailure /src denied 2:a\nb"
+        );
+        // Hooks that aren't set are skipped
+        assert!(before_add(&Hooks::default(), Path::new("/src"), "/target").is_ok());
+    }
+}
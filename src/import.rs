@@ -0,0 +1,180 @@
+use crate::backup::ArchiveMode;
+use crate::checksum::sha256_file;
+use crate::config;
+use crate::entry::Entry;
+use crate::error::Error;
+use crate::fsync;
+use crate::index::Index;
+use crate::meta::BackupMeta;
+use crate::timestamp::Timestamp;
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDate, NaiveDateTime, Timelike};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TimestampFrom {
+    /// Date and time found in the file name (e.g. backup-20250101.tar.gz)
+    Name,
+    /// File modified time
+    Mtime,
+}
+
+/// Adopt an existing backup file into the target directory under the rattlebeaver naming
+/// scheme, moving it unless `copy` is set.
+pub fn import_backup(
+    source: &Path,
+    target: &Path,
+    config: &config::Archive,
+    timestamp_from: TimestampFrom,
+    copy: bool,
+) -> std::result::Result<PathBuf, Error> {
+    if !source.is_file() {
+        return Err(anyhow::anyhow!("source is not a file").into());
+    }
+    let source_name = source
+        .file_name()
+        .context("get file name")?
+        .to_str()
+        .context("file name not utf-8")?;
+    let (stem, extension) = split_extension(source_name);
+    let (timestamp, label) = match timestamp_from {
+        TimestampFrom::Name => parse_name(stem)?,
+        TimestampFrom::Mtime => {
+            let modified = source
+                .metadata()
+                .context("get file metadata")?
+                .modified()
+                .context("get file modified time")?;
+            let modified = chrono::DateTime::<Local>::from(modified)
+                .with_nanosecond(0)
+                .context("zero nanoseconds")?;
+            (Timestamp(modified), stem.to_owned())
+        }
+    };
+    let mut index = Index::load(target, config).context("load index")?;
+    if let Some(existing) = index.get(target, timestamp) {
+        return Err(Error::TimestampConflict {
+            timestamp,
+            existing: Some(existing),
+        });
+    }
+    let mut file_name = format!(
+        "{}{}",
+        config.prefix,
+        timestamp.as_ref().format(&config.timestamp_format),
+    );
+    if !label.is_empty() {
+        file_name.push('.');
+        file_name.push_str(&label);
+    }
+    file_name.push_str(extension);
+    let target_path = target.join(file_name);
+    if target_path.exists() {
+        return Err(anyhow::anyhow!("{} already exists", target_path.display()).into());
+    }
+    let absolute_source = std::path::absolute(source).context("get absolute source path")?;
+    if copy {
+        std::fs::copy(source, &target_path).context("copy file")?;
+    } else if std::fs::rename(source, &target_path).is_err() {
+        // Renaming fails across filesystems
+        std::fs::copy(source, &target_path).context("copy file")?;
+        std::fs::remove_file(source).context("remove source file")?;
+    }
+    let imported = std::fs::File::open(&target_path).context("open imported file")?;
+    fsync::file(&imported, config.fsync)?;
+    fsync::dir(target, config.fsync)?;
+
+    let meta = BackupMeta {
+        source: Some(absolute_source),
+        version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+        archive_mode: Some(ArchiveMode::AsIs),
+        checksum: Some(sha256_file(&target_path).context("hash imported file")?),
+        note: Some(format!("imported from {source_name}")),
+        ..Default::default()
+    };
+    meta.save(&target_path, config)
+        .context("write backup metadata")?;
+    let entry = Entry::from_path(target_path.clone(), config)
+        .context("parse imported backup")?
+        .context("imported backup does not match the prefix")?;
+    index.insert(&entry)?;
+    index.save(target, config).context("save index")?;
+    Ok(target_path)
+}
+
+/// Split off the extension, treating `.tar.gz` as one.
+fn split_extension(name: &str) -> (&str, &str) {
+    if let Some(stem) = name.strip_suffix(".tar.gz") {
+        return (stem, &name[stem.len()..]);
+    }
+    match name.rfind('.') {
+        Some(0) | None => (name, ""),
+        Some(dot) => name.split_at(dot),
+    }
+}
+
+/// Find the timestamp in a file stem, returning it and the rest of the stem as the label.
+///
+/// The timestamp is the first run of digits and separators (`-`, `_`, `.`, `:`, `T`) with
+/// 8 (date), 12 or 14 (date and time) digits, e.g. `backup-20250101` or
+/// `db_2025-01-01_12-00-00`.
+fn parse_name(stem: &str) -> Result<(Timestamp, String)> {
+    let is_separator = |c: char| matches!(c, '-' | '_' | '.' | ':' | 'T');
+    let mut start = 0;
+    while let Some(offset) = stem[start..].find(|c: char| c.is_ascii_digit()) {
+        let run_start = start + offset;
+        let run_len = stem[run_start..]
+            .find(|c: char| !c.is_ascii_digit() && !is_separator(c))
+            .unwrap_or(stem.len() - run_start);
+        let run = stem[run_start..run_start + run_len].trim_end_matches(is_separator);
+        let digits: String = run.chars().filter(char::is_ascii_digit).collect();
+        if let Some(timestamp) = parse_digits(&digits) {
+            let is_delimiter = |c: char| matches!(c, '-' | '_' | '.');
+            let before = stem[..run_start].trim_end_matches(is_delimiter);
+            let after = stem[run_start + run.len()..].trim_start_matches(is_delimiter);
+            let label = match (before.is_empty(), after.is_empty()) {
+                (false, false) => format!("{before}-{after}"),
+                _ => format!("{before}{after}"),
+            };
+            return Ok((timestamp, label));
+        }
+        start = run_start + 1;
+    }
+    anyhow::bail!("no timestamp found in file name {stem:?}")
+}
+
+fn parse_digits(digits: &str) -> Option<Timestamp> {
+    let datetime = match digits.len() {
+        8 => NaiveDate::parse_from_str(digits, "%Y%m%d")
+            .ok()?
+            .and_hms_opt(0, 0, 0)?,
+        12 => NaiveDateTime::parse_from_str(digits, "%Y%m%d%H%M").ok()?,
+        14 => NaiveDateTime::parse_from_str(digits, "%Y%m%d%H%M%S").ok()?,
+        _ => return None,
+    };
+    let timestamp = datetime.and_local_timezone(Local).earliest()?;
+    Some(Timestamp(timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_from_name() {
+        let (timestamp, label) = parse_name("backup-20250102").unwrap();
+        assert_eq!(timestamp.to_string(), "2025-01-02_00-00-00");
+        assert_eq!(label, "backup");
+        let (timestamp, label) = parse_name("db_2025-01-02_12-30-00_full").unwrap();
+        assert_eq!(timestamp.to_string(), "2025-01-02_12-30-00");
+        assert_eq!(label, "db-full");
+        let (timestamp, label) = parse_name("v2-20250102T1230").unwrap();
+        assert_eq!(timestamp.to_string(), "2025-01-02_12-30-00");
+        assert_eq!(label, "v2");
+        assert!(parse_name("backup-2025").is_err());
+        assert_eq!(split_extension("a.tar.gz"), ("a", ".tar.gz"));
+        assert_eq!(split_extension("a.sql"), ("a", ".sql"));
+        assert_eq!(split_extension(".hidden"), (".hidden", ""));
+    }
+}
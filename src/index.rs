@@ -0,0 +1,206 @@
+use crate::config;
+use crate::entry::{Entry, read_dir};
+use crate::fsync;
+use crate::meta::BackupMeta;
+use crate::permissions::FilePermissions;
+use crate::timestamp::Timestamp;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const INDEX_FILE_NAME: &str = "rattlebeaver.index.json";
+
+/// Index of the backups in a target directory, keyed by timestamp, kept up to date by `add`
+/// and `delete` so they don't need to read and parse every file name in the directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    #[serde(default)]
+    pub backups: BTreeMap<String, IndexedBackup>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedBackup {
+    pub file_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<BackupMeta>,
+}
+
+impl Index {
+    /// Load the index of `target`, building it from the directory if it does not exist yet.
+    pub fn load(target: &Path, config: &config::Archive) -> Result<Self> {
+        match Self::load_saved(target)? {
+            Some(index) => Ok(index),
+            None => Self::build(target, config),
+        }
+    }
+
+    /// Load the saved index of `target`, if there is one.
+    pub fn load_saved(target: &Path) -> Result<Option<Self>> {
+        let path = index_path(target);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let s =
+            std::fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+        let index =
+            serde_json::from_str(&s).with_context(|| format!("decode {}", path.display()))?;
+        Ok(Some(index))
+    }
+
+    /// Build the index by reading every backup in `target`.
+    pub fn build(target: &Path, config: &config::Archive) -> Result<Self> {
+        let mut index = Self::default();
+        for backup in read_dir(target, config).context("read existing backups")? {
+            index.insert(&backup)?;
+        }
+        Ok(index)
+    }
+
+    /// Save the index in `target`, with the file permissions of `config`, replacing it at once
+    /// so a crash or a concurrent reader never sees half an index.
+    pub fn save(&self, target: &Path, config: &config::Archive) -> Result<()> {
+        let path = index_path(target);
+        let s = serde_json::to_string_pretty(self).context("encode index")?;
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let mut file = FilePermissions::from_config(config)?.create(&temp_path)?;
+        (file.write_all(s.as_bytes())).with_context(|| format!("write {}", temp_path.display()))?;
+        fsync::file(&file, config.fsync)?;
+        std::fs::rename(&temp_path, &path).with_context(|| format!("write {}", path.display()))?;
+        fsync::parent(&path, config.fsync)
+    }
+
+    pub fn insert(&mut self, backup: &Entry) -> Result<()> {
+        let file_name = backup
+            .path
+            .file_name()
+            .context("no file name")?
+            .to_string_lossy()
+            .into_owned();
+        let indexed = IndexedBackup {
+            file_name,
+            meta: backup.meta.clone(),
+        };
+        self.backups.insert(backup.timestamp.to_string(), indexed);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, timestamp: Timestamp) {
+        self.backups.remove(&timestamp.to_string());
+    }
+
+    /// Compare with `actual`, an index built from the directory.
+    #[must_use]
+    pub fn discrepancies(&self, actual: &Self) -> Vec<Discrepancy> {
+        let mut discrepancies = Vec::new();
+        for (timestamp, indexed) in &self.backups {
+            match actual.backups.get(timestamp) {
+                None => discrepancies.push(Discrepancy::Missing {
+                    file_name: indexed.file_name.clone(),
+                }),
+                Some(found) if found.file_name != indexed.file_name => {
+                    discrepancies.push(Discrepancy::Renamed {
+                        indexed: indexed.file_name.clone(),
+                        actual: found.file_name.clone(),
+                    });
+                }
+                Some(found) if found.meta != indexed.meta => {
+                    discrepancies.push(Discrepancy::MetaChanged {
+                        file_name: found.file_name.clone(),
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        for (timestamp, found) in &actual.backups {
+            if !self.backups.contains_key(timestamp) {
+                discrepancies.push(Discrepancy::Unindexed {
+                    file_name: found.file_name.clone(),
+                });
+            }
+        }
+        discrepancies
+    }
+
+    /// The path of the backup at `timestamp`, if there is one.
+    #[must_use]
+    pub fn get(&self, target: &Path, timestamp: Timestamp) -> Option<PathBuf> {
+        self.backups
+            .get(&timestamp.to_string())
+            .map(|indexed| target.join(&indexed.file_name))
+    }
+}
+
+/// A difference between the saved index and the target directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// Indexed but not in the directory
+    Missing { file_name: String },
+    /// In the directory but not indexed
+    Unindexed { file_name: String },
+    /// Indexed under a different file name
+    Renamed { indexed: String, actual: String },
+    /// The sidecar metadata differs from the indexed metadata
+    MetaChanged { file_name: String },
+}
+
+impl std::fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing { file_name } => write!(f, "indexed but missing: {file_name}"),
+            Self::Unindexed { file_name } => write!(f, "not indexed: {file_name}"),
+            Self::Renamed { indexed, actual } => write!(f, "indexed as {indexed}: {actual}"),
+            Self::MetaChanged { file_name } => write!(f, "metadata changed: {file_name}"),
+        }
+    }
+}
+
+fn index_path(target: &Path) -> PathBuf {
+    target.join(INDEX_FILE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_and_finds_discrepancies() {
+        let temp = tempfile::tempdir().unwrap();
+        let target = temp.path();
+        let config = crate::Config::default().archive;
+        let name = |hour: u32| format!(".rattlebeaver.2025-01-01_{hour:02}-00-00.notes");
+        let at =
+            |hour: u32| -> Timestamp { format!("2025-01-01_{hour:02}-00-00").parse().unwrap() };
+        for hour in [1, 2] {
+            std::fs::write(target.join(name(hour)), "backup").unwrap();
+        }
+        assert!(Index::load_saved(target).unwrap().is_none());
+        let mut index = Index::load(target, &config).unwrap();
+        assert_eq!(index.get(target, at(1)), Some(target.join(name(1))));
+        assert_eq!(index.get(target, at(3)), None);
+        index.save(target, &config).unwrap();
+        assert!(!target.join(format!("{INDEX_FILE_NAME}.tmp")).exists());
+        let saved = Index::load_saved(target).unwrap().unwrap();
+        assert!(
+            saved
+                .discrepancies(&Index::build(target, &config).unwrap())
+                .is_empty()
+        );
+
+        std::fs::remove_file(target.join(name(1))).unwrap();
+        std::fs::write(target.join(name(3)), "backup").unwrap();
+        index.remove(at(2));
+        let actual = Index::build(target, &config).unwrap();
+        assert_eq!(
+            index.discrepancies(&actual),
+            [
+                Discrepancy::Missing { file_name: name(1) },
+                Discrepancy::Unindexed { file_name: name(2) },
+                Discrepancy::Unindexed { file_name: name(3) },
+            ]
+        );
+    }
+}
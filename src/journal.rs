@@ -0,0 +1,173 @@
+use crate::config;
+use crate::permissions::FilePermissions;
+use crate::timestamp::Timestamp;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_FILE_NAME: &str = "rattlebeaver.journal.jsonl";
+
+/// An operation that changed the target directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Add,
+    Import,
+    Delete,
+    Trash,
+    Shred,
+    EmptyTrash,
+    Migrate,
+    Mirror,
+    Sync,
+    Copy,
+    Restore,
+    Rekey,
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Add => "add",
+            Self::Import => "import",
+            Self::Delete => "delete",
+            Self::Trash => "trash",
+            Self::Shred => "shred",
+            Self::EmptyTrash => "empty-trash",
+            Self::Migrate => "migrate",
+            Self::Mirror => "mirror",
+            Self::Sync => "sync",
+            Self::Copy => "copy",
+            Self::Restore => "restore",
+            Self::Rekey => "rekey",
+        };
+        f.pad(name)
+    }
+}
+
+/// A line of the journal: what was done to which file, and how it went.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub time: Timestamp,
+    pub action: Action,
+    pub path: PathBuf,
+    /// Parameters of the operation, e.g. the source of a new backup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// The error, if the operation failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Record {
+    #[must_use]
+    pub fn new(action: Action, path: impl Into<PathBuf>) -> Self {
+        Self {
+            time: Timestamp::now(),
+            action,
+            path: path.into(),
+            detail: None,
+            error: None,
+        }
+    }
+
+    #[must_use]
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Record the outcome of the operation.
+    #[must_use]
+    pub fn outcome<T, E: std::fmt::Display>(mut self, result: &std::result::Result<T, E>) -> Self {
+        if let Err(e) = result {
+            self.error = Some(format!("{e:#}"));
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:<11} {}",
+            self.time.humanized(),
+            self.action,
+            self.path.display()
+        )?;
+        if let Some(detail) = &self.detail {
+            write!(f, " ({detail})")?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, " FAILED: {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Append `record` to the journal of `target`, created with the file permissions of `config`.
+pub fn append(target: &Path, record: &Record, config: &config::Archive) -> Result<()> {
+    let path = journal_path(target);
+    let mut file = FilePermissions::from_config(config)?.append(&path)?;
+    let mut line = serde_json::to_string(record).context("encode journal record")?;
+    line.push('\n');
+    file.write_all(line.as_bytes())
+        .with_context(|| format!("write {}", path.display()))
+}
+
+/// Read every record in the journal of `target`, oldest first.
+pub fn read(target: &Path) -> Result<Vec<Record>> {
+    let path = journal_path(target);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let file = std::fs::File::open(&path).with_context(|| format!("open {}", path.display()))?;
+    let mut records = Vec::new();
+    for (number, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("read {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line)
+            .with_context(|| format!("decode {} line {}", path.display(), number + 1))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn journal_path(target: &Path) -> PathBuf {
+    target.join(JOURNAL_FILE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_and_reads_records() {
+        let temp = tempfile::tempdir().unwrap();
+        let target = temp.path();
+        let config = crate::Config::default().archive;
+        assert!(read(target).unwrap().is_empty());
+        let added = Record::new(Action::Add, "a.tar.gz")
+            .detail("/home/notes")
+            .outcome(&Ok::<(), String>(()));
+        let failed = Record::new(Action::Delete, "b.tar.gz").outcome(&Err::<(), _>("denied"));
+        append(target, &added, &config).unwrap();
+        append(target, &failed, &config).unwrap();
+        let records = read(target).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].action, Action::Add);
+        assert_eq!(records[0].detail.as_deref(), Some("/home/notes"));
+        assert_eq!(records[0].error, None);
+        assert_eq!(records[1].path, Path::new("b.tar.gz"));
+        assert_eq!(records[1].error.as_deref(), Some("denied"));
+        assert!(
+            records[1]
+                .to_string()
+                .ends_with("delete      b.tar.gz FAILED: denied")
+        );
+    }
+}
@@ -1,10 +1,61 @@
 pub mod backup;
+pub mod checksum;
 pub mod config;
+pub mod docker;
+pub mod encryption;
 pub mod entry;
+pub mod error;
+pub mod event;
+pub mod fsync;
+pub mod hooks;
+pub mod import;
+pub mod index;
+pub mod journal;
+pub mod lock;
 pub mod mark;
+pub mod members;
+pub mod meta;
+pub mod metrics;
+pub mod migrate;
+pub mod mirror;
+#[cfg(feature = "tokio")]
+pub mod nonblocking;
+pub mod notification;
+pub mod permissions;
+pub mod pin;
+pub mod progress;
+pub mod rekey;
+pub mod restore;
+pub mod shred;
+pub mod signature;
+pub mod simulate;
+pub mod size;
+pub mod snapshot;
+pub mod stale;
+mod state;
+pub mod stats;
+pub mod status;
+pub mod storage;
+pub mod sync;
+pub mod systemd;
 pub mod timestamp;
+pub mod trash;
+pub mod verify;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub mod webhook;
 
-pub use backup::{ArchiveMode, TimestampSelection, create_backup};
+#[allow(deprecated)]
+pub use backup::{
+    ArchiveMode, Backup, BackupBuilder, TimestampSelection, create_backup, create_backup_in,
+    create_command_backup, create_command_backup_in,
+};
 pub use config::Config;
-pub use entry::{Entry, Fulfillment};
-pub use mark::read_backups;
+pub use entry::{Entry, Fulfillment, FulfillmentKind};
+pub use error::Error;
+pub use mark::{
+    Marks, mark, read_backups, read_backups_at, read_backups_lenient, read_storage_backups,
+    read_storage_backups_lenient,
+};
+pub use pin::Pins;
+pub use storage::{LocalStorage, Storage};
@@ -0,0 +1,133 @@
+//! Advisory locking of target directories, so concurrent runs (e.g. two cron jobs, or a timer
+//! and a human) can't race between checking for timestamp conflicts and writing or deleting.
+//! Remote targets are locked with [`crate::storage::locked::LockedStorage`] instead.
+
+use crate::timestamp::Timestamp;
+use anyhow::{Context, Result};
+use std::fs::{File, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub(crate) const LOCK_FILE_NAME: &str = "rattlebeaver.lock";
+const INDEX_LOCK_FILE_NAME: &str = "rattlebeaver.index.lock";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An exclusive lock of a target directory, released when dropped or when the process exits.
+#[derive(Debug)]
+pub struct TargetLock {
+    _file: File,
+}
+
+impl TargetLock {
+    /// Lock the target directory `target`, waiting up to `timeout` for another run holding the
+    /// lock to release it.
+    pub fn acquire(target: &Path, timeout: Duration) -> Result<Self> {
+        let path = target.join(LOCK_FILE_NAME);
+        // Holds nothing but the id of the process holding the lock, so default permissions do
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("open {}", path.display()))?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+                Err(TryLockError::WouldBlock) => {
+                    let mut holder = String::new();
+                    let _ = file.read_to_string(&mut holder);
+                    let holder = match holder.trim() {
+                        "" => String::new(),
+                        pid => format!(" (pid {pid})"),
+                    };
+                    anyhow::bail!("{} is locked by another run{holder}", target.display());
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(e).with_context(|| format!("lock {}", path.display()));
+                }
+            }
+        }
+        file.set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| writeln!(file, "{}", std::process::id()))
+            .with_context(|| format!("write {}", path.display()))?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// A timestamp claimed in a target directory by a backup being written into it, so that backups
+/// written concurrently (by this process or another) can't both pass the check for conflicts.
+/// Released when dropped or when the process exits.
+#[derive(Debug)]
+pub(crate) struct Claim {
+    _file: File,
+    path: PathBuf,
+}
+
+impl Claim {
+    /// Claim `timestamp` in `target`, or None if a backup being written already claimed it.
+    pub(crate) fn acquire(target: &Path, timestamp: Timestamp) -> Result<Option<Self>> {
+        let path = target.join(format!("rattlebeaver.claim.{timestamp}"));
+        let file = open_lock_file(&path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Self { _file: file, path })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => {
+                Err(e).with_context(|| format!("lock {}", path.display()))
+            }
+        }
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        // Left behind by a run that was killed, it is locked again by the next claim all the same
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::debug!("remove {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Lock the index of `target` until the returned file is dropped, waiting for other runs
+/// updating it, so concurrent backups don't drop each other's entries.
+pub(crate) fn lock_index(target: &Path) -> Result<File> {
+    let path = target.join(INDEX_LOCK_FILE_NAME);
+    let file = open_lock_file(&path)?;
+    file.lock()
+        .with_context(|| format!("lock {}", path.display()))?;
+    Ok(file)
+}
+
+fn open_lock_file(path: &Path) -> Result<File> {
+    File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("open {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claims_each_timestamp_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let timestamp = Timestamp::parse_input("2024-01-01 12:00:00").unwrap();
+        let claim = Claim::acquire(temp.path(), timestamp).unwrap();
+        assert!(claim.is_some());
+        assert!(Claim::acquire(temp.path(), timestamp).unwrap().is_none());
+        let other = timestamp.add_span("1s".parse().unwrap());
+        assert!(Claim::acquire(temp.path(), other).unwrap().is_some());
+        drop(claim);
+        assert!(Claim::acquire(temp.path(), timestamp).unwrap().is_some());
+    }
+}
@@ -1,10 +1,66 @@
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{CommandFactory, Parser, ValueEnum};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use clap_complete::env::{CompleteEnv, Shells};
 use rattlebeaver::{
-    ArchiveMode, Config, Entry, Fulfillment, TimestampSelection, backup::BackupError,
-    create_backup, read_backups,
+    ArchiveMode, Backup, BackupBuilder, Config, Entry, Error, Fulfillment, FulfillmentKind,
+    LocalStorage, Pins, Storage, TimestampSelection, checksum, config, docker,
+    encryption::{self, Cipher, Decryptor},
+    entry::{self, Skipped},
+    event::Event,
+    fsync, hooks,
+    import::{self, TimestampFrom},
+    index::Index,
+    journal::{self, Action},
+    lock::TargetLock,
+    mark, members,
+    meta::BackupMeta,
+    metrics::Metrics,
+    migrate, mirror, notification,
+    permissions::FilePermissions,
+    progress::{CancelToken, OnProgress, Progress},
+    read_backups_at, read_backups_lenient, read_storage_backups, read_storage_backups_lenient,
+    shred,
+    signature::{self, PublicKey, SigningKey},
+    size::ByteSize,
+    snapshot::SnapshotKind,
+    stale::{self, StaleSince},
+    stats::{GrowthPeriod, Stats},
+    status::{DiskUsage, Status, Thresholds},
+    storage::{self, RemoteOptions, append_only::AppendOnlyStorage},
+    sync,
+    systemd::{self, UnitSettings, Units},
+    timestamp::{Range, Span, TimeBound, Timestamp},
+    trash,
+    verify::{self, ChecksumStatus, SignatureStatus},
+    watch::{WatchSettings, Watcher},
+    webhook::{self, DeleteReason, Payload, Ping},
 };
+use std::collections::{BTreeMap, HashMap};
+use std::io::{IsTerminal, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex, OnceLock, PoisonError};
+use tracing::{error, info, warn};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, format::Writer};
+
+/// Exit code of `delete --check` when there are stale backups.
+const CHECK_STALE_EXIT_CODE: i32 = 3;
+/// Exit code of `delete --check` when the backups cannot be read.
+const CHECK_UNREADABLE_EXIT_CODE: i32 = 4;
+/// Exit code of `status` when a threshold is exceeded.
+const STATUS_UNHEALTHY_EXIT_CODE: i32 = 5;
+
+/// The progress bars of backups and restores, shared so parallel jobs draw theirs together.
+static PROGRESS_BARS: LazyLock<indicatif::MultiProgress> =
+    LazyLock::new(indicatif::MultiProgress::new);
+
+/// Cancels the backups being written on Ctrl-C, so their partial files are deleted.
+static CANCEL: LazyLock<CancelToken> = LazyLock::new(CancelToken::new);
+/// How many backups are being written, which Ctrl-C cancels rather than exiting at once.
+static BACKING_UP: AtomicUsize = AtomicUsize::new(0);
+/// Where events are written as JSON lines, if given `--events`.
+static EVENTS: OnceLock<Mutex<std::fs::File>> = OnceLock::new();
 
 #[allow(clippy::doc_markdown)]
 #[derive(Debug, Parser)]
@@ -15,6 +71,35 @@ struct Args {
     /// Path to config file [defaults to TARGET_DIR/rattlebeaver.config.toml]
     #[arg(long)]
     config: Option<PathBuf>,
+    /// Limit uploads to remote targets and mirrors to this many bytes per second (e.g. 10M)
+    #[arg(long, global = true)]
+    bwlimit: Option<ByteSize>,
+    /// List remote targets instead of using the locally cached listing
+    #[arg(long, global = true)]
+    refresh: bool,
+    /// Write Prometheus metrics of the target to this file after adding or deleting backups
+    #[arg(long, global = true)]
+    metrics_file: Option<PathBuf>,
+    /// How long to wait for another run to release the target directory [defaults to failing at
+    /// once]
+    #[arg(long, global = true)]
+    lock_timeout: Option<Span>,
+    /// Append events of backups and deletions to this file (or FIFO) as JSON lines, as they
+    /// happen
+    #[arg(long, global = true, value_name = "FILE")]
+    events: Option<PathBuf>,
+    /// Show more of what is going on (-v), down to every file archived (-vv)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only show warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Format of the messages on stderr
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Reference time for retention decisions [defaults to the current time]
+    #[arg(long, hide = true)]
+    now: Option<Timestamp>,
     /// Subcommand
     #[command(subcommand)]
     command: Command,
@@ -24,25 +109,121 @@ struct Args {
 enum Command {
     /// Add new backups
     Add(ArgsAdd),
+    /// Add backups of the jobs in the config
+    Run(ArgsRun),
+    /// Back up sources whenever they change
+    Watch(ArgsWatch),
+    /// Adopt existing backup files into the naming scheme
+    Import(ArgsImport),
     /// List existing backups
     List(ArgsList),
     /// Delete stale backups
     Delete(ArgsDelete),
+    /// Permanently delete trashed backups past the retention period
+    EmptyTrash(ArgsEmptyTrash),
+    /// Summarize the sizes, fulfillments and growth of all backups
+    Stats(ArgsStats),
+    /// Check the age, range fill and disk usage of the backups, e.g. from a monitoring script
+    Status(ArgsStatus),
+    /// Find backups with identical contents
+    Duplicates(ArgsDuplicates),
+    /// Show everything known about a backup
+    Info(ArgsInfo),
+    /// Check backups for corruption
+    Verify(ArgsVerify),
+    /// Explain why a backup is kept or stale
+    Explain(ArgsExplain),
+    /// Protect backups from deletion
+    Pin(ArgsPin),
+    /// Remove protection from pinned backups
+    Unpin(ArgsUnpin),
+    /// Rename backups after changing the prefix or timestamp format in the config
+    Migrate(ArgsMigrate),
+    /// Copy backups missing from one target to another, e.g. to seed a new disk
+    Sync(ArgsSync),
+    /// Copy or move a single backup and its metadata to another target
+    Copy(ArgsCopy),
+    /// Extract a backup into a directory
+    Restore(ArgsRestore),
+    /// Re-encrypt encrypted backups, e.g. to new recipients after a key was lost or compromised
+    Rekey(ArgsRekey),
+    /// Generate a key for signing backups
+    Keygen(ArgsKeygen),
+    /// Print the script that sets up completion of arguments in a shell
+    Completions(ArgsCompletions),
+    /// Write a systemd service and timer that add backups on a schedule
+    InstallSystemd(ArgsInstallSystemd),
+    /// Manage the index of backups
+    #[command(subcommand)]
+    Index(IndexCommand),
+    /// Add a note to a backup, shown in list and info
+    Annotate(ArgsAnnotate),
+    /// Add or remove tags of backups
+    Tag(ArgsTag),
+    /// Show the journal of operations on the target directory
+    Log(ArgsLog),
+    /// Simulate retention over a synthetic timeline of backups
+    Simulate(ArgsSimulate),
     /// Print debug info
     Debug,
 }
 
+impl Command {
+    /// Check if the command deletes, renames or overwrites backups in the target.
+    fn modifies_backups(&self) -> bool {
+        match self {
+            Self::Add(args) => args.delete,
+            Self::Run(args) => args.delete,
+            Self::Watch(args) => args.delete,
+            Self::Delete(args) => args.execute,
+            Self::EmptyTrash(args) => args.execute,
+            Self::Migrate(args) => args.execute,
+            Self::Copy(args) => args.remove,
+            Self::Rekey(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Check if the command must hold the lock of the target directory while it runs.
+    fn locks_target(&self) -> bool {
+        match self {
+            // Locks around each backup instead, so it doesn't shut out other runs while idle
+            Self::Watch(_) => false,
+            // Writing to --output leaves the target alone
+            Self::Add(args) => args.output.is_none(),
+            Self::Run(_) | Self::Import(_) => true,
+            // Rewrite the pins, sidecars and index shared with other runs
+            Self::Pin(_) | Self::Unpin(_) | Self::Tag(_) | Self::Annotate(_) => true,
+            Self::Index(IndexCommand::Rebuild(args)) => !args.dry_run,
+            _ => self.modifies_backups(),
+        }
+    }
+}
+
 #[derive(Debug, Parser, Clone)]
 struct ArgsAdd {
     /// Files or directories to add
     #[arg()]
     files: Vec<PathBuf>,
+    /// Docker volume to add, named after the volume (can be repeated)
+    #[arg(long = "docker-volume", value_name = "VOLUME")]
+    docker_volumes: Vec<String>,
+    /// Shell command whose output to add, compressed and timestamped now (e.g. `pg_dump mydb`)
+    #[arg(long, requires = "name")]
+    command: Option<String>,
+    /// Name of the backup of --command
+    #[arg(long, requires = "command")]
+    name: Option<String>,
     /// How to select the timestamp for the backups
     #[arg(short = 't', long, default_value = "file-created")]
     timestamp: TimestampSelection,
     /// How to handle single files
     #[arg(short = 'm', long, default_value = "auto-detect")]
     archive_mode: ArchiveMode,
+    /// Archive each source from a snapshot of its filesystem, for a consistent backup of files
+    /// in use
+    #[arg(long, value_name = "KIND")]
+    snapshot: Option<SnapshotKind>,
     /// Don't stop on first failure
     #[arg(short = 'f', long)]
     force: bool,
@@ -52,6 +233,163 @@ struct ArgsAdd {
     /// Also delete stale backups
     #[arg(short = 'D', long)]
     delete: bool,
+    /// Tag the new backups (can be repeated)
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    /// The `exclude` patterns of the job being run, as there is no flag for them
+    #[arg(skip)]
+    exclude: Vec<glob::Pattern>,
+    /// Don't copy the new backups to the mirrors in the config
+    #[arg(long)]
+    skip_mirrors: bool,
+    /// Read each new backup back and check it, deleting it if it is corrupt [default: archive.verify]
+    #[arg(long)]
+    verify: bool,
+    /// Write the backup of a single source to FILE, or to stdout with `-`, instead of adding it
+    /// to the target (e.g. to pipe it into ssh). It is named and encrypted by the target's config,
+    /// but has no sidecar
+    #[arg(short = 'o', long, value_name = "FILE", conflicts_with_all = ["delete", "verify"])]
+    output: Option<PathBuf>,
+}
+
+impl ArgsAdd {
+    fn sources(&self) -> Result<Vec<Source>> {
+        let files = (self.files.iter()).map(|file| Ok(Source::Path(file.clone(), None)));
+        let volumes = self.docker_volumes.iter().map(|volume| {
+            let mountpoint = docker::volume_mountpoint(volume)?;
+            Ok(Source::Path(mountpoint, Some(volume.clone())))
+        });
+        let commands = (self.command.iter().zip(&self.name))
+            .map(|(command, name)| Ok(Source::Command(command.clone(), name.clone())));
+        let sources: Vec<_> = files
+            .chain(volumes)
+            .chain(commands)
+            .collect::<Result<_>>()?;
+        anyhow::ensure!(!sources.is_empty(), "No files selected to back up.");
+        Ok(sources)
+    }
+
+    /// The backup of `source` with these options, yet to be given a target.
+    fn backup<'a>(&self, source: &Source, config: &'a Config) -> BackupBuilder<'a> {
+        let backup = match source {
+            Source::Path(file, name) => {
+                let backup = Backup::builder(file)
+                    .timestamp(self.timestamp)
+                    .mode(self.archive_mode)
+                    .excludes(self.exclude.iter().cloned());
+                let backup = match self.snapshot {
+                    Some(snapshot) => backup.snapshot(snapshot),
+                    None => backup,
+                };
+                match name {
+                    Some(name) => backup.name(name),
+                    None => backup,
+                }
+            }
+            Source::Command(command, name) => Backup::command(command, name),
+        };
+        backup
+            .config(&config.archive)
+            .tags(&self.tags)
+            .verify(self.verify || config.archive.verify)
+            .cancel_token(CANCEL.clone())
+            .events(&emit)
+    }
+}
+
+/// Something to back up with `add`.
+#[derive(Debug, Clone)]
+enum Source {
+    /// A file or directory, with the name to give its backup instead of its file stem
+    Path(PathBuf, Option<String>),
+    /// A shell command whose output to back up, with the name to give its backup
+    Command(String, String),
+}
+
+impl Source {
+    /// The path hooks, reports and errors refer to the source by, the name of a command.
+    fn path(&self) -> &Path {
+        match self {
+            Self::Path(path, _) => path,
+            Self::Command(_, name) => Path::new(name),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Path(path, _) => format!("source {}", path.display()),
+            Self::Command(command, _) => format!("command {command}"),
+        }
+    }
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsRun {
+    /// Jobs to run [defaults to all jobs in the config]
+    #[arg(value_name = "JOB")]
+    names: Vec<String>,
+    /// Number of jobs to run at a time
+    #[arg(short = 'j', long = "jobs", default_value_t = 1)]
+    parallel: usize,
+    /// Run jobs even if their newest backup is younger than their `every`
+    #[arg(long)]
+    ignore_schedule: bool,
+    /// Also delete stale backups after running the jobs
+    #[arg(short = 'D', long)]
+    delete: bool,
+    /// Don't copy the new backups to the mirrors in the config
+    #[arg(long)]
+    skip_mirrors: bool,
+    /// Read each new backup back and check it, deleting it if it is corrupt [default: archive.verify]
+    #[arg(long)]
+    verify: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsWatch {
+    /// Files or directories to watch
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    /// How to handle single files
+    #[arg(short = 'm', long, default_value = "auto-detect")]
+    archive_mode: ArchiveMode,
+    /// How long a source must go unchanged before it is backed up
+    #[arg(long, default_value = "30s")]
+    debounce: Span,
+    /// Least time between two backups of the same source
+    #[arg(long, default_value = "5m")]
+    min_interval: Span,
+    /// Also delete stale backups after each new one
+    #[arg(short = 'D', long)]
+    delete: bool,
+    /// Tag the new backups (can be repeated)
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    /// Don't copy the new backups to the mirrors in the config
+    #[arg(long)]
+    skip_mirrors: bool,
+    /// Read each new backup back and check it, deleting it if it is corrupt [default: archive.verify]
+    #[arg(long)]
+    verify: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsImport {
+    /// Backup files to import
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    /// Where to take the timestamps of the backups from
+    #[arg(long, default_value = "name")]
+    timestamp_from: TimestampFrom,
+    /// Copy the files instead of moving them
+    #[arg(short = 'c', long)]
+    copy: bool,
+    /// Don't stop on first failure
+    #[arg(short = 'f', long)]
+    force: bool,
+    /// Ignore timestamp conflicts
+    #[arg(long)]
+    ignore_conflicts: bool,
 }
 
 #[derive(Debug, Parser, Clone)]
@@ -59,16 +397,521 @@ struct ArgsList {
     /// Show all details
     #[arg(short = 'a', long)]
     all: bool,
+    /// Output format
+    #[arg(long, default_value = "text")]
+    format: ListFormat,
+    /// Only show backups from this time on (e.g. "2025-01-01" or "7d" ago)
+    #[arg(long)]
+    since: Option<TimeBound>,
+    /// Only show backups before this time (e.g. "2025-02-01" or "1d" ago)
+    #[arg(long)]
+    until: Option<TimeBound>,
+    /// Only show backups with any of these tags
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    /// Only show stale backups
+    #[arg(long, conflicts_with = "kept")]
+    stale: bool,
+    /// Only show kept backups
+    #[arg(long)]
+    kept: bool,
+    /// Only show backups kept for any of these reasons
+    #[arg(long, value_delimiter = ',')]
+    fulfills: Vec<FulfillsFilter>,
+    /// When to color stale (red) and latest (green) backups in text format
+    #[arg(long, default_value = "auto")]
+    color: ColorChoice,
+    /// Compute checksums for the checksum detail by reading every backup
+    #[arg(long)]
+    hash: bool,
+    /// Show times relative to now, e.g. "3 hours ago"
+    #[arg(short = 'R', long)]
+    relative: bool,
+    /// Sort backups by
+    #[arg(long, default_value = "time")]
+    sort: ListSort,
+    /// Reverse the order
+    #[arg(short = 'r', long)]
+    reverse: bool,
+    /// Only show the first N backups in the listed order (the oldest, unless sorted otherwise)
+    #[arg(short = 'n', long, value_name = "N")]
+    limit: Option<usize>,
+    /// Only show the last N backups in the listed order (the newest, unless sorted otherwise)
+    #[arg(long, value_name = "N", conflicts_with = "limit")]
+    tail: Option<usize>,
+    /// Group backups, with subtotals for each group
+    #[arg(long)]
+    group_by: Option<GroupBy>,
+    /// Show totals after the listing (text format only)
+    #[arg(short = 's', long)]
+    summary: bool,
+    /// Print each backup using a template such as "{timestamp}\t{size}\t{path}" (see README)
+    #[arg(long, conflicts_with_all = ["format", "all", "details"])]
+    format_template: Option<String>,
+    /// Only print paths, each terminated by a NUL byte (for `xargs -0`)
+    #[arg(short = '0', long, conflicts_with_all = ["format", "all", "details", "summary"])]
+    print0: bool,
+    /// Fail on files named like backups that can't be read as one (e.g. with a malformed
+    /// timestamp, or the timestamp of another backup) instead of skipping them with a warning
+    #[arg(long)]
+    strict: bool,
     /// Select details to show
     #[arg(last = true)]
     details: Vec<ListingDetails>,
 }
 
-#[derive(Debug, Parser, Clone)]
+impl ArgsList {
+    fn details(&self) -> Vec<ListingDetails> {
+        if self.all {
+            ListingDetails::all()
+        } else if self.details.is_empty() {
+            ListingDetails::default_list()
+        } else {
+            self.details.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum FulfillsFilter {
+    Latest,
+    Minute,
+    Hour,
+    Day,
+    Month,
+    Year,
+    /// Any calendar rule
+    Calendar,
+    Pinned,
+    /// A tag listed in delete.keep_tags
+    Tagged,
+}
+
+impl FulfillsFilter {
+    fn matches(self, kind: FulfillmentKind) -> bool {
+        match kind {
+            FulfillmentKind::Latest => self == Self::Latest,
+            FulfillmentKind::Range(range) => match range {
+                Range::Minute => self == Self::Minute,
+                Range::Hour => self == Self::Hour,
+                Range::Day => self == Self::Day,
+                Range::Month => self == Self::Month,
+                Range::Year => self == Self::Year,
+            },
+            FulfillmentKind::Calendar(_) => self == Self::Calendar,
+            FulfillmentKind::Pinned { .. } => self == Self::Pinned,
+            FulfillmentKind::Tagged => self == Self::Tagged,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum GroupBy {
+    /// The source label in the file name
+    Source,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ColorChoice {
+    /// Color if stdout is a terminal and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => {
+                std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+        }
+    }
+}
+
+const STALE_STYLE: &str = "\x1b[31m";
+const LATEST_STYLE: &str = "\x1b[32m";
+const RESET_STYLE: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ListSort {
+    Time,
+    Size,
+    /// File name
+    Name,
+    /// Number of fulfillments (least protected first)
+    Fulfillments,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ListFormat {
+    /// Selected details separated by " | "
+    Text,
+    /// An array of all details of every backup
+    Json,
+    /// Selected details as comma-separated values with a header row
+    Csv,
+    /// Selected details as tab-separated values with a header row
+    Tsv,
+}
+
+#[derive(Debug, Default, Parser, Clone)]
 struct ArgsDelete {
     /// Actually delete
     #[arg(short = 'x', long)]
     execute: bool,
+    /// Delete these backups (path, file name or timestamp) instead of the stale ones, unless
+    /// protected
+    #[arg(short = 's', long, num_args = 1.., add = ArgValueCandidates::new(complete_backups))]
+    select: Vec<String>,
+    /// Delete all backups older than this (e.g. "2y"), even if the ranges keep them
+    #[arg(long, conflicts_with = "select")]
+    older_than: Option<Span>,
+    /// Also delete pinned backups and backups with a tag in delete.keep_tags
+    #[arg(long)]
+    force: bool,
+    /// Don't delete, exit with code 3 if there are stale backups to delete (4 if unreadable)
+    #[arg(long, conflicts_with_all = ["execute", "select", "older_than"])]
+    check: bool,
+    /// Overwrite backups before removing them [also set by delete.shred in config]
+    #[arg(long)]
+    shred: bool,
+    /// Output format for the deleted backups
+    #[arg(long, default_value = "text")]
+    format: DeleteFormat,
+    /// Don't ask for confirmation
+    #[arg(short = 'y', long)]
+    yes: bool,
+    /// Allow deleting the most recent backup
+    #[arg(long)]
+    allow_delete_newest: bool,
+}
+
+impl ArgsDelete {
+    /// Whether deleted backups are moved to the trash, which `--shred` overrides.
+    fn trashes(&self, config: &Config) -> bool {
+        config.delete.trash && !self.shred
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum DeleteFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsEmptyTrash {
+    /// Actually delete
+    #[arg(short = 'x', long)]
+    execute: bool,
+    /// Ignore the retention period and empty the whole trash
+    #[arg(short = 'a', long)]
+    all: bool,
+    /// Overwrite backups before removing them [also set by delete.shred in config]
+    #[arg(long)]
+    shred: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsMigrate {
+    /// Prefix the backups are currently named with [defaults to archive.prefix in config]
+    #[arg(long)]
+    from_prefix: Option<String>,
+    /// Timestamp format the backups are currently named with [defaults to
+    /// archive.timestamp_format in config]
+    #[arg(long)]
+    from_format: Option<String>,
+    /// Actually rename
+    #[arg(short = 'x', long)]
+    execute: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsSync {
+    /// Target to copy backups from (a directory or remote url)
+    source: String,
+    /// Target to copy backups to (a directory or remote url)
+    destination: String,
+    /// Also delete backups that are not in the source
+    #[arg(long)]
+    delete: bool,
+    /// Actually copy and delete
+    #[arg(short = 'x', long)]
+    execute: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsCopy {
+    /// Backup to copy (path, file name or timestamp)
+    #[arg(add = ArgValueCandidates::new(complete_backups))]
+    backup: String,
+    /// Target to copy the backup to (a directory or remote url)
+    #[arg(long)]
+    to: String,
+    /// Delete the backup from this target once copied
+    #[arg(long = "move")]
+    remove: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsRestore {
+    /// Backup to restore (path, file name or timestamp)
+    #[arg(add = ArgValueCandidates::new(complete_backups))]
+    backup: String,
+    /// Directory to extract the backup into, which must be empty or missing
+    #[arg(long)]
+    to: PathBuf,
+    /// age identity file to decrypt encrypted backups with (GnuPG uses its keyring)
+    #[arg(short = 'i', long)]
+    identity: Option<PathBuf>,
+    /// File holding the passphrase of passphrase-encrypted backups [default: archive.passphrase_file]
+    #[arg(long)]
+    passphrase_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsRekey {
+    /// Backups to re-encrypt (by path, file name or timestamp) [default: all encrypted backups]
+    #[arg(add = ArgValueCandidates::new(complete_backups))]
+    backups: Vec<String>,
+    /// Recipient to encrypt to, replacing the old ones (repeatable) [default: archive.encrypt_to]
+    #[arg(short = 'r', long = "new-recipient")]
+    new_recipients: Vec<String>,
+    /// What to encrypt with [default: archive.cipher]
+    #[arg(long)]
+    cipher: Option<Cipher>,
+    /// age identity file to decrypt the backups with (GnuPG uses its keyring)
+    #[arg(short = 'i', long)]
+    identity: Option<PathBuf>,
+    /// File holding the old passphrase [default: archive.passphrase_file]
+    #[arg(long)]
+    passphrase_file: Option<PathBuf>,
+    /// File holding the new passphrase [default: archive.passphrase_file]
+    #[arg(long)]
+    new_passphrase_file: Option<PathBuf>,
+    /// Decrypt each result to verify it, not just that it was stored intact (always done with a
+    /// passphrase)
+    #[arg(long)]
+    decrypt: bool,
+    /// age identity file of the new recipients, to verify the results with (implies --decrypt)
+    #[arg(long)]
+    new_identity: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser, Clone)]
+enum IndexCommand {
+    /// Rebuild the index from the directory, reporting any discrepancies
+    Rebuild(ArgsIndexRebuild),
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsIndexRebuild {
+    /// Only report discrepancies, don't save the rebuilt index
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsLog {
+    /// Only show the last N records
+    #[arg(short = 'n', long)]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsStats {
+    /// Period to show the growth of backups by
+    #[arg(short = 'p', long, default_value = "month")]
+    period: GrowthPeriod,
+    /// Number of largest sources to show
+    #[arg(long, default_value_t = 5)]
+    top: usize,
+    /// Output format
+    #[arg(long, default_value = "text")]
+    format: StatsFormat,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsStatus {
+    /// Fail if the newest backup is older than this (e.g. 26h)
+    #[arg(long)]
+    max_age: Option<Span>,
+    /// Fail if less than this is available on the target filesystem (e.g. 10G)
+    #[arg(long)]
+    min_free: Option<ByteSize>,
+    /// Fail if any bucket of the configured ranges holds no backup
+    #[arg(long)]
+    fill_ranges: bool,
+    /// Output format
+    #[arg(long, default_value = "text")]
+    format: StatsFormat,
+}
+
+impl ArgsStatus {
+    fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            max_age: self.max_age,
+            min_free: self.min_free,
+            fill_ranges: self.fill_ranges,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum StatsFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsDuplicates {
+    /// Compare the files inside archives instead of the archives themselves, so archives of the
+    /// same files made at different times match
+    #[arg(short = 'c', long)]
+    contents: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsInfo {
+    /// Backup to show (by path, file name or timestamp)
+    #[arg(add = ArgValueCandidates::new(complete_backups))]
+    backup: String,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsVerify {
+    /// Backups to verify (by path, file name or timestamp) [default: all]
+    #[arg(add = ArgValueCandidates::new(complete_backups))]
+    backups: Vec<String>,
+    /// Compare against the recorded SHA-256 checksums instead of reading the archives
+    #[arg(short = 'c', long)]
+    checksums: bool,
+    /// Check the signatures of the backups instead of reading the archives
+    #[arg(short = 's', long, conflicts_with = "checksums")]
+    signatures: bool,
+    /// Public key to check signatures against [default: archive.public_key]
+    #[arg(long, requires = "signatures")]
+    public_key: Option<String>,
+    /// Decrypt encrypted archives to read them, rather than passing them unread
+    #[arg(long)]
+    decrypt: bool,
+    /// age identity file to decrypt archives with (implies --decrypt)
+    #[arg(short = 'i', long)]
+    identity: Option<PathBuf>,
+    /// File holding the passphrase of passphrase-encrypted archives (implies --decrypt)
+    #[arg(long)]
+    passphrase_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsKeygen {
+    /// File to write the secret key to
+    path: PathBuf,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsCompletions {
+    /// Shell to complete arguments in
+    shell: clap_complete::Shell,
+    /// Print completions fixed at generation time, which don't call rattlebeaver to complete
+    /// backups
+    #[arg(long = "static")]
+    fixed: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsInstallSystemd {
+    /// Files or directories to back up (can be repeated)
+    #[arg(short = 's', long = "source", required = true)]
+    sources: Vec<PathBuf>,
+    /// When to run, as a systemd calendar event (e.g. "daily" or "Mon *-*-* 03:00")
+    #[arg(long, default_value = "daily")]
+    schedule: String,
+    /// Name of the units
+    #[arg(long, default_value = "rattlebeaver")]
+    name: String,
+    /// Install system units instead of units of the current user
+    #[arg(long)]
+    system: bool,
+    /// Directory to write the units to [default: the systemd unit directory]
+    #[arg(long)]
+    dir: Option<PathBuf>,
+    /// Also delete stale backups after adding
+    #[arg(short = 'D', long)]
+    delete: bool,
+    /// Replace existing units
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsExplain {
+    /// Backup to explain (by path, file name or timestamp)
+    #[arg(add = ArgValueCandidates::new(complete_backups))]
+    backup: String,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsPin {
+    /// Backups to select (by path, file name or timestamp)
+    #[arg(required = true, add = ArgValueCandidates::new(complete_backups))]
+    backups: Vec<String>,
+    /// Protect only until this date (e.g. 2025-01-01)
+    #[arg(short = 'u', long)]
+    until: Option<Timestamp>,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsTag {
+    /// Backup to tag (by path, file name or timestamp)
+    #[arg(add = ArgValueCandidates::new(complete_backups))]
+    backup: String,
+    /// Tags to add
+    #[arg(required = true)]
+    tags: Vec<String>,
+    /// Remove the tags instead
+    #[arg(short = 'r', long)]
+    remove: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsAnnotate {
+    /// Backup to annotate (by path, file name or timestamp)
+    #[arg(add = ArgValueCandidates::new(complete_backups))]
+    backup: String,
+    /// The note, e.g. "pre-upgrade to v2" [removes the note if omitted]
+    note: Option<String>,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsUnpin {
+    /// Backups to select (by path, file name or timestamp)
+    #[arg(required = true, add = ArgValueCandidates::new(complete_backups))]
+    backups: Vec<String>,
+}
+
+#[derive(Debug, Parser, Clone)]
+struct ArgsSimulate {
+    /// Interval between backups (e.g. 1h)
+    #[arg(short = 'e', long)]
+    every: Span,
+    /// Duration of the simulation (e.g. 90d)
+    #[arg(short = 'f', long = "for")]
+    duration: Span,
+    /// Size of each backup [defaults to the average size of existing backups]
+    #[arg(short = 's', long)]
+    size: Option<ByteSize>,
+    /// Number of samples to report
+    #[arg(short = 'r', long, default_value_t = 20)]
+    rows: usize,
+    /// Simulate the ranges that apply to this source label
+    #[arg(short = 'l', long)]
+    label: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
@@ -78,6 +921,22 @@ enum ListingDetails {
     Size,
     Fulfills,
     FulfillsShort,
+    /// SHA-256 digest (as recorded, or computed with --hash)
+    Checksum,
+    /// Number of files in the archive (cached)
+    Members,
+    /// Absolute path of the backed up file or directory
+    Source,
+    /// Note added with the annotate command
+    Note,
+    /// Tags added with add --tag or the tag command
+    Tags,
+    /// Seconds it took to create the backup
+    Duration,
+    /// Number of files backed up
+    Files,
+    /// Written size relative to the backed up size
+    Ratio,
 }
 
 impl ListingDetails {
@@ -91,7 +950,35 @@ impl ListingDetails {
 }
 
 fn main() -> Result<()> {
+    CompleteEnv::with_factory(Args::command).complete();
     let args = Args::parse();
+    init_logging(&args);
+    handle_ctrl_c();
+    if let Some(path) = &args.events {
+        let file = std::fs::File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open {}", path.display()))?;
+        EVENTS.get_or_init(|| Mutex::new(file));
+    }
+
+    if let Command::Sync(subargs) = &args.command {
+        return sync(
+            args.config.as_deref(),
+            args.bwlimit,
+            args.lock_timeout,
+            args.now.unwrap_or_else(Timestamp::now),
+            subargs,
+        )
+        .context("sync targets");
+    }
+    if let Command::Keygen(subargs) = &args.command {
+        return keygen(subargs).context("generate signing key");
+    }
+    if let Command::Completions(subargs) = &args.command {
+        return completions(subargs).context("generate completions");
+    }
 
     let target_dir = if let Some(target_dir) = args.target_dir {
         target_dir
@@ -100,7 +987,87 @@ fn main() -> Result<()> {
             .context("missing RATTLEBEAVER_TARGET_DIR from environment or from CLI args")?;
         PathBuf::from(target_dir)
     };
+    let target = target_dir.to_string_lossy();
+    if let Command::InstallSystemd(subargs) = &args.command {
+        return install_systemd(&target, args.config.as_deref(), subargs)
+            .context("install systemd units");
+    }
+    if let Command::Add(subargs) = &args.command
+        && let Some(output) = &subargs.output
+    {
+        // The backup never touches the target, so it isn't locked, created or opened
+        let config_path = match args.config {
+            Some(config_path) => config_path,
+            None if storage::is_remote(&target) => {
+                anyhow::bail!("remote targets need a local config file (--config)")
+            }
+            None => target_dir.join("rattlebeaver.config.toml"),
+        };
+        let config = read_config(&config_path)?;
+        ask_for_passphrase(&config, &args.command)?;
+        let started = std::time::Instant::now();
+        send_ping(&config, Ping::Start, "");
+        let result = add_to_output(&config, subargs, output);
+        AddReport::default().send(&config, &target, &result, started);
+        return result;
+    }
+    if storage::is_remote(&target) {
+        let config_path = args
+            .config
+            .context("remote targets need a local config file (--config)")?;
+        generate_missing_config(&config_path).context("generate new default config")?;
+        let config = Config::from_path(&config_path).context("load config")?;
+        let options = remote_options(&config, args.bwlimit, args.refresh)?;
+        ask_for_passphrase(&config, &args.command)?;
+        check_append_only(&config, args.command.modifies_backups())?;
+        let lock_timeout = (args.lock_timeout.map(fixed_duration).transpose())?.unwrap_or_default();
+        let target_options = RemoteOptions {
+            lock: args.command.locks_target().then_some(lock_timeout),
+            ..options.clone()
+        };
+        let storage = storage::open_remote(&target, &target_options)?.context("open target")?;
+        let storage = guard_append_only(&config, &target, storage);
+        let now = args.now.unwrap_or_else(Timestamp::now);
+        let metrics_file = args.metrics_file.as_deref();
+        return remote(
+            &*storage,
+            &config,
+            &options,
+            now,
+            metrics_file,
+            args.command,
+        );
+    }
+    if let Command::Delete(subargs) = &args.command
+        && subargs.check
+    {
+        // Only reads the target, and fails to read it as a whole, config included
+        let config_path =
+            (args.config.clone()).unwrap_or_else(|| target_dir.join("rattlebeaver.config.toml"));
+        let now = args.now.unwrap_or_else(Timestamp::now);
+        let result =
+            read_config(&config_path).and_then(|config| check_stale(&target_dir, &config, now));
+        match result {
+            Ok(0) => info!("No stale backups."),
+            Ok(count) => {
+                info!("{count} stale backup(s) to delete.");
+                std::process::exit(CHECK_STALE_EXIT_CODE);
+            }
+            Err(e) => {
+                error!("{e:#}");
+                std::process::exit(CHECK_UNREADABLE_EXIT_CODE);
+            }
+        }
+        return Ok(());
+    }
     std::fs::create_dir_all(&target_dir).context("create target directory")?;
+    let lock_timeout = (args.lock_timeout.map(fixed_duration).transpose())?.unwrap_or_default();
+    let _lock = args
+        .command
+        .locks_target()
+        .then(|| TargetLock::acquire(&target_dir, lock_timeout))
+        .transpose()
+        .context("lock target directory")?;
 
     let config_path = args
         .config
@@ -108,137 +1075,3244 @@ fn main() -> Result<()> {
         .unwrap_or(target_dir.join("rattlebeaver.config.toml"));
     generate_missing_config(&config_path).context("generate new default config")?;
     let config = Config::from_path(&config_path).context("load config")?;
+    let now = args.now.unwrap_or_else(Timestamp::now);
+    ask_for_passphrase(&config, &args.command)?;
+    check_append_only(&config, args.command.modifies_backups())?;
 
     match args.command {
         Command::Add(subargs) => {
-            if subargs.files.is_empty() {
-                anyhow::bail!("No files selected to back up.");
-            }
-            let mut errors = Vec::new();
-            for file in subargs.files {
-                let new_backup_result = create_backup(
-                    &file,
-                    &target_dir,
-                    &config.archive,
-                    subargs.timestamp,
-                    subargs.archive_mode,
-                );
-                match new_backup_result {
-                    Ok(new_backup) => println!("{}", new_backup.display()),
-                    Err(error) => {
-                        let is_conflict = matches!(error, BackupError::TimestampConflict(_));
-                        if is_conflict && subargs.ignore_conflicts {
-                            continue;
-                        }
-                        if subargs.force {
-                            errors.push(error);
-                        } else {
-                            let context = format!("backup file: {file:?}");
-                            return Err(anyhow::Error::from(error).context(context));
-                        }
-                    }
-                }
-            }
-            if !errors.is_empty() {
-                for error in &errors {
-                    eprintln!("Encountered error: {error:?}");
+            let started = std::time::Instant::now();
+            send_ping(&config, Ping::Start, "");
+            let options = remote_options(&config, args.bwlimit, args.refresh)?;
+            let mut report = AddReport::default();
+            let result = add(&target_dir, &config, &options, now, &subargs, &mut report);
+            report.send(&config, &target, &result, started);
+            write_metrics(
+                args.metrics_file.as_deref(),
+                &target,
+                result.is_ok(),
+                started,
+                || read_backups_at(&target_dir, &config, now),
+            );
+            result?;
+        }
+        Command::Run(subargs) => {
+            let started = std::time::Instant::now();
+            send_ping(&config, Ping::Start, "");
+            let options = remote_options(&config, args.bwlimit, args.refresh)?;
+            let report = std::sync::Mutex::new(AddReport::default());
+            let result = due_jobs(&config, now, &subargs, || {
+                read_backups_at(&target_dir, &config, now)
+            })
+            .and_then(|jobs| {
+                run_jobs(&jobs, subargs.parallel, |add_args| {
+                    let mut job_report = AddReport::default();
+                    let result = add(
+                        &target_dir,
+                        &config,
+                        &options,
+                        now,
+                        add_args,
+                        &mut job_report,
+                    );
+                    let mut report = report.lock().unwrap_or_else(PoisonError::into_inner);
+                    report.added.append(&mut job_report.added);
+                    report.failed.append(&mut job_report.failed);
+                    result
+                })
+            })
+            .and_then(|()| {
+                if !subargs.delete {
+                    return Ok(());
                 }
-            }
-            if let Some(error) = errors.into_iter().next() {
-                return Err(error.into());
-            }
-            if subargs.delete {
-                delete_stale(&target_dir, &config, true).context("delete stale backups")?;
-            }
+                let delete_args = ArgsDelete {
+                    execute: true,
+                    yes: true,
+                    ..Default::default()
+                };
+                delete(&target_dir, &config, now, &delete_args).context("delete backups")
+            });
+            let report = report.into_inner().unwrap_or_else(PoisonError::into_inner);
+            report.send(&config, &target, &result, started);
+            write_metrics(
+                args.metrics_file.as_deref(),
+                &target,
+                result.is_ok(),
+                started,
+                || read_backups_at(&target_dir, &config, now),
+            );
+            result?;
+        }
+        Command::Watch(subargs) => {
+            let options = remote_options(&config, args.bwlimit, args.refresh)?;
+            let metrics_file = args.metrics_file.as_deref();
+            watch(
+                &target_dir,
+                &config,
+                &options,
+                metrics_file,
+                lock_timeout,
+                &subargs,
+            )
+            .context("watch sources")?;
+        }
+        Command::Import(subargs) => {
+            import(&target_dir, &config, &subargs).context("import backups")?;
         }
         Command::List(subargs) => {
-            let details = if subargs.all {
-                ListingDetails::all()
-            } else if subargs.details.is_empty() {
-                ListingDetails::default_list()
-            } else {
-                subargs.details
-            };
-            list(&target_dir, &config, &details).context("list backups")?;
+            list(&target_dir, &config, now, &subargs).context("list backups")?;
         }
         Command::Delete(subargs) => {
-            delete_stale(&target_dir, &config, subargs.execute).context("delete stale backups")?;
+            let started = std::time::Instant::now();
+            let result = delete(&target_dir, &config, now, &subargs).context("delete backups");
+            write_metrics(
+                args.metrics_file.as_deref(),
+                &target,
+                result.is_ok(),
+                started,
+                || read_backups_at(&target_dir, &config, now),
+            );
+            result?;
         }
-        Command::Debug => {
-            println!("Target dir: {}", target_dir.display());
-            println!("Config file path: {}", config_path.display());
-            println!("{config:#?}");
+        Command::EmptyTrash(subargs) => {
+            empty_trash(&target_dir, &config, now, &subargs).context("empty trash")?;
         }
-    }
-    Ok(())
-}
-
-fn generate_missing_config(config_file: impl AsRef<Path>) -> Result<()> {
-    if config_file.as_ref().exists() {
-        return Ok(());
-    }
-    eprintln!("Writing new config at {}", config_file.as_ref().display());
-    let default_toml = Config::default()
-        .as_toml()
-        .context("encode default config toml")?;
-    std::fs::write(&config_file, default_toml).context("write default config file")?;
+        Command::Migrate(subargs) => {
+            migrate(&target_dir, &config, &subargs).context("migrate backups")?;
+        }
+        Command::Sync(_)
+        | Command::Keygen(_)
+        | Command::Completions(_)
+        | Command::InstallSystemd(_) => {
+            unreachable!("handled before reading the target")
+        }
+        Command::Copy(subargs) => {
+            let storage = local_storage(&target_dir, &config)?;
+            let options = remote_options(&config, args.bwlimit, args.refresh)?;
+            copy(&*storage, &config, &options, &subargs, Some(&target_dir))
+                .context("copy backup")?;
+        }
+        Command::Restore(subargs) => {
+            let storage = local_storage(&target_dir, &config)?;
+            restore(&*storage, &config, &subargs, Some(&target_dir)).context("restore backup")?;
+        }
+        Command::Rekey(subargs) => {
+            let storage = local_storage(&target_dir, &config)?;
+            rekey(&*storage, &config, &subargs, Some(&target_dir)).context("re-encrypt backups")?;
+        }
+        Command::Index(IndexCommand::Rebuild(subargs)) => {
+            rebuild_index(&target_dir, &config, &subargs).context("rebuild index")?;
+        }
+        Command::Log(subargs) => {
+            let records = journal::read(&target_dir).context("read journal")?;
+            let skip = subargs
+                .limit
+                .map_or(0, |limit| records.len().saturating_sub(limit));
+            for record in &records[skip..] {
+                println!("{record}");
+            }
+        }
+        Command::Stats(subargs) => {
+            stats(&target_dir, &config, now, &subargs).context("collect stats")?;
+        }
+        Command::Status(subargs) => {
+            let backups = read_backups_at(&target_dir, &config, now).context("read backups")?;
+            let disk = DiskUsage::of(&target_dir).context("query filesystem stats")?;
+            let status = Status::collect(
+                &target,
+                &backups,
+                &config.ranges,
+                now,
+                Some(disk),
+                &subargs.thresholds(),
+            );
+            print_status(&status, now, subargs.format)?;
+            if !status.is_healthy() {
+                std::process::exit(STATUS_UNHEALTHY_EXIT_CODE);
+            }
+        }
+        Command::Duplicates(subargs) => {
+            duplicates(&target_dir, &config, now, &subargs).context("find duplicates")?;
+        }
+        Command::Info(subargs) => {
+            info(&target_dir, &config, now, &subargs.backup).context("show backup info")?;
+        }
+        Command::Verify(subargs) => {
+            verify(&target_dir, &config, now, &subargs).context("verify backups")?;
+        }
+        Command::Explain(subargs) => {
+            explain(&target_dir, &config, now, &subargs.backup).context("explain backup")?;
+        }
+        Command::Pin(subargs) => {
+            pin(&target_dir, &config, now, &subargs.backups, subargs.until)
+                .context("pin backups")?;
+        }
+        Command::Unpin(subargs) => {
+            unpin(&target_dir, &config, now, &subargs.backups).context("unpin backups")?;
+        }
+        Command::Tag(subargs) => {
+            tag(&target_dir, &config, now, &subargs).context("tag backup")?;
+        }
+        Command::Annotate(subargs) => {
+            annotate(&target_dir, &config, now, &subargs).context("annotate backup")?;
+        }
+        Command::Simulate(subargs) => {
+            simulate(&target_dir, &config, now, &subargs).context("simulate retention")?;
+        }
+        Command::Debug => {
+            println!("Target dir: {}", target_dir.display());
+            println!("Config file path: {}", config_path.display());
+            println!("{config:#?}");
+        }
+    }
+    Ok(())
+}
+
+fn generate_missing_config(config_file: impl AsRef<Path>) -> Result<()> {
+    if config_file.as_ref().exists() {
+        return Ok(());
+    }
+    info!("Writing new config at {}", config_file.as_ref().display());
+    let default_toml = Config::default()
+        .as_toml()
+        .context("encode default config toml")?;
+    FilePermissions::default()
+        .write(config_file.as_ref(), default_toml)
+        .context("write default config file")?;
+    Ok(())
+}
+
+/// The config at `config_file`, or the default config if there is none, without writing it.
+fn read_config(config_file: &Path) -> Result<Config> {
+    if !config_file.exists() {
+        return Ok(Config::default());
+    }
+    Config::from_path(config_file).context("load config")
+}
+
+/// The options to open remote targets and mirrors with, from the config and the CLI args.
+fn remote_options(
+    config: &Config,
+    bwlimit: Option<ByteSize>,
+    refresh: bool,
+) -> Result<RemoteOptions> {
+    Ok(RemoteOptions {
+        retry: config.retry.clone(),
+        bwlimit: bwlimit.map(|limit| limit.0),
+        refresh,
+        targets: config.targets.clone(),
+        lock: None,
+        permissions: FilePermissions::from_config(&config.archive)?,
+        fsync: config.archive.fsync,
+    })
+}
+
+/// Run `command` against remote storage. Only adding, listing and deleting are supported.
+fn remote(
+    storage: &dyn Storage,
+    config: &Config,
+    options: &RemoteOptions,
+    now: Timestamp,
+    metrics_file: Option<&Path>,
+    command: Command,
+) -> Result<()> {
+    let target = storage_target(storage);
+    match command {
+        Command::Add(subargs) => {
+            let started = std::time::Instant::now();
+            send_ping(config, Ping::Start, "");
+            let mut report = AddReport::default();
+            let result = remote_add(storage, config, options, now, &subargs, &mut report);
+            report.send(config, &target, &result, started);
+            write_metrics(metrics_file, &target, result.is_ok(), started, || {
+                read_storage_backups(storage, config, now)
+            });
+            result?;
+        }
+        Command::Run(subargs) => {
+            let started = std::time::Instant::now();
+            send_ping(config, Ping::Start, "");
+            anyhow::ensure!(
+                subargs.parallel <= 1,
+                "only running one job at a time is supported for remote targets"
+            );
+            let mut report = AddReport::default();
+            let result = due_jobs(config, now, &subargs, || {
+                read_storage_backups(storage, config, now)
+            })
+            .and_then(|jobs| {
+                run_jobs_in_order(&jobs, |add_args| {
+                    remote_add(storage, config, options, now, add_args, &mut report)
+                })
+            })
+            .and_then(|()| {
+                if !subargs.delete {
+                    return Ok(());
+                }
+                remote_delete(storage, config, now, true).context("delete backups")
+            });
+            report.send(config, &target, &result, started);
+            write_metrics(metrics_file, &target, result.is_ok(), started, || {
+                read_storage_backups(storage, config, now)
+            });
+            result?;
+        }
+        Command::List(subargs) => {
+            let unsupported = [ListingDetails::Size, ListingDetails::Members];
+            if subargs.format != ListFormat::Text
+                || subargs.details().iter().any(|d| unsupported.contains(d))
+                || subargs.sort == ListSort::Size
+                || subargs.summary
+                || subargs.hash
+            {
+                anyhow::bail!("only text listings without sizes are supported for remote targets");
+            }
+            let mut all_backups = if subargs.strict {
+                read_storage_backups(storage, config, now)
+            } else {
+                read_storage_backups_lenient(storage, config, now).map(warn_skipped)
+            }
+            .context("read backups")?;
+            filter_listing(&mut all_backups, &subargs, now);
+            print_listing(&all_backups, &subargs, now, &HashMap::new())?;
+        }
+        Command::Delete(subargs) => {
+            if !subargs.select.is_empty() || subargs.older_than.is_some() || subargs.check {
+                anyhow::bail!("only deleting stale backups is supported for remote targets");
+            }
+            if subargs.shred || config.delete.shred || config.delete.trash {
+                anyhow::bail!("shredding and the trash are not supported for remote targets");
+            }
+            if subargs.execute && !subargs.yes && !confirm_prompt("Delete stale backups?")? {
+                info!("Aborted.");
+                return Ok(());
+            }
+            let started = std::time::Instant::now();
+            let result =
+                remote_delete(storage, config, now, subargs.execute).context("delete backups");
+            write_metrics(metrics_file, &target, result.is_ok(), started, || {
+                read_storage_backups(storage, config, now)
+            });
+            result?;
+        }
+        Command::Status(subargs) => {
+            if subargs.min_free.is_some() {
+                anyhow::bail!("--min-free is not supported for remote targets");
+            }
+            let backups = read_storage_backups(storage, config, now).context("read backups")?;
+            let status = Status::collect(
+                &target,
+                &backups,
+                &config.ranges,
+                now,
+                None,
+                &subargs.thresholds(),
+            );
+            print_status(&status, now, subargs.format)?;
+            if !status.is_healthy() {
+                std::process::exit(STATUS_UNHEALTHY_EXIT_CODE);
+            }
+        }
+        Command::Copy(subargs) => {
+            copy(storage, config, options, &subargs, None).context("copy backup")?;
+        }
+        Command::Restore(subargs) => {
+            restore(storage, config, &subargs, None).context("restore backup")?;
+        }
+        Command::Rekey(subargs) => {
+            rekey(storage, config, &subargs, None).context("re-encrypt backups")?;
+        }
+        _ => anyhow::bail!("this command is not supported for remote targets"),
+    }
+    Ok(())
+}
+
+/// What adding backups did, for the webhooks.
+#[derive(Debug, Default)]
+struct AddReport {
+    added: Vec<PathBuf>,
+    failed: Vec<webhook::Failure>,
+}
+
+impl AddReport {
+    fn fail(&mut self, source: &Path, error: &Error) {
+        self.failed.push(webhook::Failure {
+            source: Some(source.to_owned()),
+            error: error.to_string(),
+        });
+    }
+
+    /// Send the report to the webhooks in the config, along with the `result` of the run.
+    fn send(
+        mut self,
+        config: &Config,
+        target: &str,
+        result: &Result<()>,
+        started: std::time::Instant,
+    ) {
+        if let Err(e) = result
+            && self.failed.is_empty()
+        {
+            self.failed.push(webhook::Failure {
+                source: None,
+                error: format!("{e:#}"),
+            });
+        }
+        let payload = Payload::added(target, self.added, self.failed, started.elapsed());
+        let ping = if payload.event == webhook::Event::Success {
+            Ping::Success
+        } else {
+            Ping::Failure
+        };
+        send_ping(config, ping, &payload.text);
+        send_webhooks(config, &payload);
+        if config.notify {
+            let (title, failed) = match ping {
+                Ping::Failure => ("Backup failed", true),
+                _ => ("Backup done", false),
+            };
+            if let Err(e) = notification::show(title, &payload.text, failed) {
+                warn!("show notification: {e:#}");
+            }
+        }
+    }
+}
+
+fn add(
+    target_dir: &Path,
+    config: &Config,
+    options: &RemoteOptions,
+    now: Timestamp,
+    args: &ArgsAdd,
+    report: &mut AddReport,
+) -> Result<()> {
+    let sources = args.sources()?;
+    let target = target_dir.display().to_string();
+    let mut errors = Vec::new();
+    let mut mirror_failures = 0;
+    for source in &sources {
+        let file = source.path();
+        let new_backup_result = add_with_hooks(config, file, &target, || {
+            with_progress_bar(file, |on_progress| {
+                (args.backup(source, config))
+                    .target(target_dir)
+                    .progress(on_progress)
+                    .run()
+            })
+        });
+        let path = new_backup_result.as_ref().map_or(file, |path| path);
+        let record = journal::Record::new(Action::Add, path)
+            .detail(source.describe())
+            .outcome(&new_backup_result);
+        journal::append(target_dir, &record, &config.archive).context("write journal")?;
+        match new_backup_result {
+            Ok(new_backup) => {
+                println!("{}", new_backup.display());
+                report.added.push(new_backup.clone());
+                if !args.skip_mirrors {
+                    let storage = local_storage(target_dir, config)?;
+                    mirror_failures += mirror_new_backup(
+                        &*storage,
+                        &new_backup,
+                        config,
+                        options,
+                        Some(target_dir),
+                    )?;
+                }
+            }
+            Err(error) => {
+                let is_conflict = matches!(error, Error::TimestampConflict { .. });
+                if is_conflict && args.ignore_conflicts {
+                    continue;
+                }
+                report.fail(file, &error);
+                if args.force && !matches!(error, Error::Cancelled) {
+                    errors.push(error);
+                } else {
+                    let context = format!("backup file: {file:?}");
+                    return Err(anyhow::Error::from(error).context(context));
+                }
+            }
+        }
+    }
+    if !errors.is_empty() {
+        for error in &errors {
+            error!("{error:#}");
+        }
+    }
+    if let Some(error) = errors.into_iter().next() {
+        return Err(error.into());
+    }
+    if mirror_failures > 0 {
+        anyhow::bail!("failed to copy {mirror_failures} backup(s) to mirrors");
+    }
+    if args.delete {
+        let delete_args = ArgsDelete {
+            execute: true,
+            yes: true,
+            ..Default::default()
+        };
+        delete(target_dir, config, now, &delete_args).context("delete backups")?;
+    }
+    Ok(())
+}
+
+/// Back up the single source of `args` into the file `output`, or stdout if it is `-`, running
+/// the add hooks around it. A partly written file is deleted if the backup fails.
+fn add_to_output(config: &Config, args: &ArgsAdd, output: &Path) -> Result<()> {
+    let [source] = &args.sources()?[..] else {
+        anyhow::bail!("--output takes a single source");
+    };
+    let file = source.path();
+    let to_stdout = output == Path::new("-");
+    let mut created = false;
+    let result = add_with_hooks(config, file, &output.display().to_string(), || {
+        let writer: Box<dyn Write> = if to_stdout {
+            Box::new(std::io::stdout().lock())
+        } else {
+            let file = FilePermissions::from_config(&config.archive)?.create(output)?;
+            created = true;
+            Box::new(file)
+        };
+        with_progress_bar(file, |on_progress| {
+            (args.backup(source, config))
+                .verify(false)
+                .writer(writer)
+                .progress(on_progress)
+                .run()
+        })
+    });
+    match result {
+        Ok(name) => {
+            info!("Wrote {} to {}", name.display(), output.display());
+            Ok(())
+        }
+        Err(error) => {
+            if created && let Err(e) = std::fs::remove_file(output) {
+                warn!("delete partial output {}: {e}", output.display());
+            }
+            let context = format!("backup file: {file:?}");
+            Err(anyhow::Error::from(error).context(context))
+        }
+    }
+}
+
+fn remote_add(
+    storage: &dyn Storage,
+    config: &Config,
+    options: &RemoteOptions,
+    now: Timestamp,
+    args: &ArgsAdd,
+    report: &mut AddReport,
+) -> Result<()> {
+    let sources = args.sources()?;
+    let target = storage_target(storage);
+    let mut mirror_failures = 0;
+    for source in &sources {
+        let file = source.path();
+        let result = add_with_hooks(config, file, &target, || {
+            with_progress_bar(file, |on_progress| {
+                (args.backup(source, config))
+                    .storage(storage)
+                    .progress(on_progress)
+                    .run()
+            })
+        });
+        match result {
+            Ok(new_backup) => {
+                println!("{}", new_backup.display());
+                report.added.push(new_backup.clone());
+                if !args.skip_mirrors {
+                    mirror_failures +=
+                        mirror_new_backup(storage, &new_backup, config, options, None)?;
+                }
+            }
+            Err(Error::TimestampConflict { .. }) if args.ignore_conflicts => {}
+            Err(error) if args.force && !matches!(error, Error::Cancelled) => {
+                report.fail(file, &error);
+                error!("{error:#}");
+            }
+            Err(error) => {
+                report.fail(file, &error);
+                let context = format!("backup file: {file:?}");
+                return Err(anyhow::Error::from(error).context(context));
+            }
+        }
+    }
+    if mirror_failures > 0 {
+        anyhow::bail!("failed to copy {mirror_failures} backup(s) to mirrors");
+    }
+    if args.delete {
+        remote_delete(storage, config, now, true).context("delete backups")?;
+    }
+    Ok(())
+}
+
+/// The jobs in the config selected by `args`, as the arguments `add` takes for each. Jobs are
+/// skipped while their newest backup is younger than their `every`, as found in the backups
+/// from `read_backups`.
+fn due_jobs<'a>(
+    config: &'a Config,
+    now: Timestamp,
+    args: &ArgsRun,
+    read_backups: impl FnOnce() -> Result<Vec<Entry>, Error>,
+) -> Result<Vec<(&'a str, ArgsAdd)>> {
+    let jobs = select_jobs(config, &args.names)?;
+    let scheduled = !args.ignore_schedule && jobs.iter().any(|(_, job)| job.every.is_some());
+    let backups = if scheduled {
+        read_backups().context("read backups")?
+    } else {
+        Vec::new()
+    };
+    let mut due = Vec::new();
+    for (name, job) in jobs {
+        if !args.ignore_schedule
+            && let Some(every) = job.every
+            && let Some(newest) = newest_backup_of(&backups, &job.source)
+            && newest > now.sub_span(every)
+        {
+            info!(
+                "Skipping job {name}, last backed up {}",
+                newest.relative(now)
+            );
+            continue;
+        }
+        let add_args = ArgsAdd {
+            files: vec![job.source.clone()],
+            docker_volumes: Vec::new(),
+            command: None,
+            name: None,
+            timestamp: job.timestamp,
+            archive_mode: job.archive_mode,
+            snapshot: job.snapshot,
+            force: false,
+            ignore_conflicts: false,
+            delete: false,
+            tags: job.tags.clone(),
+            exclude: job
+                .exclude_patterns()
+                .with_context(|| format!("invalid job {name}"))?,
+            skip_mirrors: args.skip_mirrors,
+            verify: args.verify,
+            output: None,
+        };
+        due.push((name, add_args));
+    }
+    Ok(due)
+}
+
+/// Run `jobs` with `add`, up to `parallel` at a time, carrying on past the jobs that fail.
+fn run_jobs(
+    jobs: &[(&str, ArgsAdd)],
+    parallel: usize,
+    add: impl Fn(&ArgsAdd) -> Result<()> + Sync,
+) -> Result<()> {
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let last_now = std::sync::Mutex::new(None);
+    let results = std::sync::Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..parallel.clamp(1, jobs.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    let Some((name, add_args)) = jobs.get(index) else {
+                        break;
+                    };
+                    let result = run_job(name, add_args, &last_now, &add);
+                    let mut results = results.lock().unwrap_or_else(PoisonError::into_inner);
+                    results.push((index, result));
+                }
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap_or_else(PoisonError::into_inner);
+    results.sort_by_key(|(index, _)| *index);
+    let results = results
+        .into_iter()
+        .map(|(index, result)| (jobs[index].0, result))
+        .collect();
+    summarize_jobs(results)
+}
+
+/// Run `jobs` with `add` one after the other, carrying on past the jobs that fail.
+fn run_jobs_in_order(
+    jobs: &[(&str, ArgsAdd)],
+    mut add: impl FnMut(&ArgsAdd) -> Result<()>,
+) -> Result<()> {
+    let last_now = std::sync::Mutex::new(None);
+    let results = jobs
+        .iter()
+        .map(|(name, add_args)| (*name, run_job(name, add_args, &last_now, &mut add)))
+        .collect();
+    summarize_jobs(results)
+}
+
+/// Run the job `name` with `add`. Jobs timestamped now start at least a second after the last
+/// one did, as timestamps have whole seconds and two backups can't share one.
+fn run_job(
+    name: &str,
+    add_args: &ArgsAdd,
+    last_now: &std::sync::Mutex<Option<chrono::DateTime<chrono::Local>>>,
+    add: impl FnOnce(&ArgsAdd) -> Result<()>,
+) -> Result<()> {
+    if add_args.timestamp == TimestampSelection::Now {
+        let mut last_now = last_now.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(last_now) = *last_now {
+            let wait = last_now + chrono::Duration::seconds(1) - chrono::Local::now();
+            std::thread::sleep(wait.to_std().unwrap_or_default());
+        }
+        *last_now = Some(chrono::Local::now());
+    }
+    info!("Running job {name}");
+    let result = add(add_args);
+    if let Err(e) = &result {
+        error!("job {name} failed: {e:#}");
+    }
+    result
+}
+
+/// Print how every job went, failing if any did.
+fn summarize_jobs(results: Vec<(&str, Result<()>)>) -> Result<()> {
+    let failed: Vec<&str> = (results.iter())
+        .filter(|(_, result)| result.is_err())
+        .map(|(name, _)| *name)
+        .collect();
+    if results.len() > 1 {
+        info!(
+            "Jobs: {} succeeded, {} failed",
+            results.len() - failed.len(),
+            failed.len()
+        );
+        for (name, result) in &results {
+            match result {
+                Ok(()) => info!("  {name:<16} ok"),
+                Err(e) => info!("  {name:<16} failed: {e:#}"),
+            }
+        }
+    }
+    anyhow::ensure!(
+        failed.is_empty(),
+        "{} of {} job(s) failed: {}",
+        failed.len(),
+        results.len(),
+        failed.join(", ")
+    );
+    Ok(())
+}
+
+/// The jobs in the config named by `names`, or all of them if none are.
+fn select_jobs<'a>(
+    config: &'a Config,
+    names: &[String],
+) -> Result<Vec<(&'a str, &'a config::Job)>> {
+    anyhow::ensure!(!config.jobs.is_empty(), "no jobs in the config");
+    if names.is_empty() {
+        return Ok(config
+            .jobs
+            .iter()
+            .map(|(name, job)| (name.as_str(), job))
+            .collect());
+    }
+    names
+        .iter()
+        .map(|name| {
+            (config.jobs.get_key_value(name))
+                .map(|(name, job)| (name.as_str(), job))
+                .with_context(|| format!("no job named {name} in the config"))
+        })
+        .collect()
+}
+
+/// The timestamp of the newest of `backups` made of `source`.
+fn newest_backup_of(backups: &[Entry], source: &Path) -> Option<Timestamp> {
+    let source = std::path::absolute(source).ok()?;
+    backups
+        .iter()
+        .rev()
+        .find(|backup| {
+            let meta = backup.meta.as_ref();
+            meta.and_then(|meta| meta.source.as_ref()) == Some(&source)
+        })
+        .map(|backup| backup.timestamp)
+}
+
+/// The url of a remote target, as given to the hooks.
+fn storage_target(storage: &dyn Storage) -> String {
+    let url = storage.locate("").display().to_string();
+    url.trim_end_matches('/').to_owned()
+}
+
+/// Back up `file` to `target` with `backup`, running the add hooks in the config around it.
+fn add_with_hooks(
+    config: &Config,
+    file: &Path,
+    target: &str,
+    backup: impl FnOnce() -> Result<PathBuf, Error>,
+) -> Result<PathBuf, Error> {
+    let result = hooks::before_add(&config.hooks, file, target)
+        .map_err(Error::from)
+        .and_then(|()| {
+            BACKING_UP.fetch_add(1, Ordering::SeqCst);
+            let result = backup();
+            BACKING_UP.fetch_sub(1, Ordering::SeqCst);
+            result
+        });
+    let after = hooks::after_add(&config.hooks, file, target, &result);
+    match (result, after) {
+        (Ok(path), Err(e)) => Err(e
+            .context(format!("after backing up to {}", path.display()))
+            .into()),
+        (Err(error), Err(e)) => {
+            error!("{e:#}");
+            Err(error)
+        }
+        (result, Ok(())) => result,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// Plain lines, with timestamps, levels and spans when verbose
+    Text,
+    /// A JSON object per line
+    Json,
+}
+
+/// Log the messages of the library and of main to stderr, at the verbosity and in the format
+/// given by `args`.
+fn init_logging(args: &Args) {
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+    use tracing_subscriber::prelude::*;
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => LevelFilter::WARN,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    // Dependencies only get to warn, so -vv shows the details of backups rather than of HTTP
+    let filter = Targets::new()
+        .with_target("rattlebeaver", level)
+        .with_default(LevelFilter::WARN);
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(|| StderrAboveBars)
+        .with_ansi(std::io::stderr().is_terminal());
+    let layer: Box<dyn tracing_subscriber::Layer<_> + Send + Sync> = match args.log_format {
+        LogFormat::Json => Box::new(layer.json()),
+        LogFormat::Text if args.verbose > 0 => Box::new(layer),
+        LogFormat::Text => Box::new(layer.event_format(PlainFormat)),
+    };
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(filter)
+        .init();
+}
+
+/// Formats messages as plain lines, prefixing warnings and errors, without the timestamps,
+/// levels and spans shown when verbose.
+struct PlainFormat;
+
+impl<S, N> FormatEvent<S, N> for PlainFormat
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &tracing::Event<'_>,
+    ) -> std::fmt::Result {
+        match *event.metadata().level() {
+            tracing::Level::ERROR => write!(writer, "Error: ")?,
+            tracing::Level::WARN => write!(writer, "Warning: ")?,
+            _ => {}
+        }
+        ctx.format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// Stderr, hiding the progress bars while writing to it so they don't garble messages.
+struct StderrAboveBars;
+
+impl Write for StderrAboveBars {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        PROGRESS_BARS.suspend(|| std::io::stderr().write(buf))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        PROGRESS_BARS.suspend(|| std::io::stderr().write_all(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+/// Cancel the backups being written on Ctrl-C, or exit if there are none (or on a second
+/// Ctrl-C).
+fn handle_ctrl_c() {
+    let set = ctrlc::set_handler(|| {
+        if BACKING_UP.load(Ordering::SeqCst) > 0 && !CANCEL.is_cancelled() {
+            info!("Cancelling, press Ctrl-C again to exit at once.");
+            CANCEL.cancel();
+        } else {
+            std::process::exit(130);
+        }
+    });
+    if let Err(e) = set {
+        warn!("handle Ctrl-C: {e}");
+    }
+}
+
+/// Write `event` to the events file, if given `--events`, warning if that fails.
+fn emit(event: &Event) {
+    let Some(file) = EVENTS.get() else {
+        return;
+    };
+    let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut line = serde_json::to_vec(event).expect("events serialize");
+    line.push(b'\n');
+    if let Err(e) = file.write_all(&line) {
+        warn!("write event: {e}");
+    }
+}
+
+/// Run `f`, showing the progress it reports for `source` in a progress bar on stderr (if stderr
+/// is a terminal) until it returns.
+fn with_progress_bar<T>(source: &Path, f: impl FnOnce(OnProgress) -> T) -> T {
+    let style = |template| {
+        indicatif::ProgressStyle::with_template(template)
+            .expect("valid progress template")
+            .progress_chars("=> ")
+    };
+    let bar = PROGRESS_BARS.add(
+        indicatif::ProgressBar::no_length()
+            .with_style(style(
+                "{spinner} {prefix} {bytes} ({binary_bytes_per_sec}) {msg}",
+            ))
+            .with_prefix(source.display().to_string()),
+    );
+    let on_progress = |progress: Progress| {
+        if let Some(total) = progress.total_bytes
+            && bar.length() != Some(total)
+        {
+            bar.set_length(total);
+            bar.set_style(style(
+                "{prefix} [{bar:30}] {bytes}/{total_bytes} ({binary_bytes_per_sec}, {eta}) {msg}",
+            ));
+        }
+        bar.set_position(progress.bytes);
+        if progress.files > 0 {
+            bar.set_message(format!("{} file(s)", progress.files));
+        }
+    };
+    let result = f(&on_progress);
+    bar.finish_and_clear();
+    PROGRESS_BARS.remove(&bar);
+    result
+}
+
+/// The length of `span`, which must not be in months or years.
+fn fixed_duration(span: Span) -> Result<std::time::Duration> {
+    span.fixed_duration()
+        .with_context(|| format!("durations cannot be in months or years (got {span})"))
+}
+
+fn watch(
+    target_dir: &Path,
+    config: &Config,
+    options: &RemoteOptions,
+    metrics_file: Option<&Path>,
+    lock_timeout: std::time::Duration,
+    args: &ArgsWatch,
+) -> Result<()> {
+    let settings = WatchSettings {
+        debounce: fixed_duration(args.debounce)?,
+        min_interval: fixed_duration(args.min_interval)?,
+    };
+    let mut watcher = Watcher::new(&args.files, settings, Some(target_dir))?;
+    info!("Watching {} source(s) for changes.", args.files.len());
+    loop {
+        for file in watcher.wait()? {
+            let _lock =
+                TargetLock::acquire(target_dir, lock_timeout).context("lock target directory")?;
+            let started = std::time::Instant::now();
+            send_ping(config, Ping::Start, "");
+            let target = target_dir.display().to_string();
+            let new_backup_result = add_with_hooks(config, &file, &target, || {
+                with_progress_bar(&file, |on_progress| {
+                    Backup::builder(&file)
+                        .target(target_dir)
+                        .config(&config.archive)
+                        .timestamp(TimestampSelection::Now)
+                        .mode(args.archive_mode)
+                        .tags(&args.tags)
+                        .verify(args.verify || config.archive.verify)
+                        .progress(on_progress)
+                        .cancel_token(CANCEL.clone())
+                        .events(&emit)
+                        .run()
+                })
+            });
+            watcher.backed_up(&file, std::time::Instant::now());
+            let path = new_backup_result.as_ref().map_or(&file, |path| path);
+            let record = journal::Record::new(Action::Add, path)
+                .detail(format!("source {}", file.display()))
+                .outcome(&new_backup_result);
+            journal::append(target_dir, &record, &config.archive).context("write journal")?;
+            let mut report = AddReport::default();
+            let new_backup = match new_backup_result {
+                Ok(new_backup) => new_backup,
+                Err(error) => {
+                    report.fail(&file, &error);
+                    report.send(config, &target, &Ok(()), started);
+                    write_metrics(metrics_file, &target, false, started, || {
+                        read_backups_at(target_dir, config, Timestamp::now())
+                    });
+                    if matches!(error, Error::Cancelled) {
+                        return Err(error.into());
+                    }
+                    let error = anyhow::Error::from(error);
+                    error!("back up {}: {error:#}", file.display());
+                    continue;
+                }
+            };
+            report.added.push(new_backup.clone());
+            report.send(config, &target, &Ok(()), started);
+            println!("{}", new_backup.display());
+            if !args.skip_mirrors {
+                let storage = local_storage(target_dir, config)?;
+                mirror_new_backup(&*storage, &new_backup, config, options, Some(target_dir))?;
+            }
+            if args.delete {
+                let delete_args = ArgsDelete {
+                    execute: true,
+                    yes: true,
+                    ..Default::default()
+                };
+                delete(target_dir, config, Timestamp::now(), &delete_args)
+                    .context("delete backups")?;
+            }
+            write_metrics(metrics_file, &target, true, started, || {
+                read_backups_at(target_dir, config, Timestamp::now())
+            });
+        }
+    }
+}
+
+/// Copy a new backup to every mirror in the config, journaling in `journal_target` if local.
+///
+/// Returns the number of mirrors that failed.
+fn mirror_new_backup(
+    storage: &dyn Storage,
+    backup: &Path,
+    config: &Config,
+    options: &RemoteOptions,
+    journal_target: Option<&Path>,
+) -> Result<usize> {
+    if config.mirrors.is_empty() {
+        return Ok(0);
+    }
+    let name = backup
+        .file_name()
+        .context("no file name")?
+        .to_string_lossy();
+    let mut failures = 0;
+    for status in mirror::mirror_backup(storage, &name, &config.mirrors, options) {
+        match &status.result {
+            Ok(()) => info!("Mirrored to {}", status.mirror),
+            Err(e) => {
+                error!("mirror to {}: {e:#}", status.mirror);
+                failures += 1;
+            }
+        }
+        if let Some(target) = journal_target {
+            let record = journal::Record::new(Action::Mirror, backup)
+                .detail(format!("to {}", status.mirror))
+                .outcome(&status.result);
+            journal::append(target, &record, &config.archive).context("write journal")?;
+        }
+    }
+    Ok(failures)
+}
+
+/// Copy a backup to another target under the same name, keeping its timestamp.
+///
+/// `source_dir` is the directory of `storage` if it is local, to journal and index a move.
+fn copy(
+    storage: &dyn Storage,
+    config: &Config,
+    options: &RemoteOptions,
+    args: &ArgsCopy,
+    source_dir: Option<&Path>,
+) -> Result<()> {
+    let all_backups = entry::read_storage(storage, &config.archive).context("read backups")?;
+    let backup = entry::find(&all_backups, &args.backup)?;
+    let name = backup.name()?;
+    let dst = storage::open(&args.to, options)?;
+    let dst_backups = entry::read_storage(&*dst, &config.archive).context("read destination")?;
+    if let Some(existing) = dst_backups.iter().find(|b| b.timestamp == backup.timestamp) {
+        anyhow::bail!(
+            "{} already has a backup from {}: {}",
+            args.to,
+            backup.timestamp,
+            existing.path.display()
+        );
+    }
+    let dst_dir = (!storage::is_remote(&args.to)).then(|| Path::new(&args.to));
+    let result = sync::copy_backup(storage, &*dst, &name, backup.meta.is_some());
+    if let Some(target) = dst_dir {
+        let record = journal::Record::new(Action::Copy, dst.locate(&name))
+            .detail(format!("from {}", backup.path.display()))
+            .outcome(&result);
+        journal::append(target, &record, &config.archive).context("write journal")?;
+        if result.is_ok() {
+            Index::build(target, &config.archive)
+                .context("rebuild index")?
+                .save(target, &config.archive)
+                .context("save index")?;
+        }
+    }
+    result?;
+    println!("{}", dst.locate(&name).display());
+    if !args.remove {
+        return Ok(());
+    }
+    let result = sync::delete_backup(storage, backup, Some(&emit));
+    if let Some(target) = source_dir {
+        let record = journal::Record::new(Action::Delete, &backup.path)
+            .detail(format!("moved to {}", args.to))
+            .outcome(&result);
+        journal::append(target, &record, &config.archive).context("write journal")?;
+        if result.is_ok() {
+            let mut index = Index::load(target, &config.archive).context("load index")?;
+            index.remove(backup.timestamp);
+            index.save(target, &config.archive).context("save index")?;
+        }
+    }
+    result
+}
+
+/// Extract a backup, journaling in `journal_target` if local.
+fn restore(
+    storage: &dyn Storage,
+    config: &Config,
+    args: &ArgsRestore,
+    journal_target: Option<&Path>,
+) -> Result<()> {
+    let all_backups = entry::read_storage(storage, &config.archive).context("read backups")?;
+    let backup = entry::find(&all_backups, &args.backup)?;
+    let decryptor = Decryptor {
+        identity: args.identity.clone(),
+        passphrase_file: (args.passphrase_file.clone())
+            .or_else(|| config.archive.passphrase_file.clone()),
+    };
+    if decryptor.passphrase_file.is_none()
+        && Cipher::of(&backup.name()?) == Some(Cipher::Passphrase)
+    {
+        prompt_passphrase()?;
+    }
+    let result = with_progress_bar(&backup.path, |on_progress| {
+        rattlebeaver::restore::restore(storage, backup, &args.to, &decryptor, Some(on_progress))
+    });
+    if let Some(target) = journal_target {
+        let record = journal::Record::new(Action::Restore, &backup.path)
+            .detail(format!("to {}", args.to.display()))
+            .outcome(&result);
+        journal::append(target, &record, &config.archive).context("write journal")?;
+    }
+    result?;
+    info!(
+        "Restored {} to {}",
+        backup.path.display(),
+        args.to.display()
+    );
+    Ok(())
+}
+
+/// Decrypt backups and encrypt them again with new settings, journaling and reindexing in
+/// `journal_target` if local.
+fn rekey(
+    storage: &dyn Storage,
+    config: &Config,
+    args: &ArgsRekey,
+    journal_target: Option<&Path>,
+) -> Result<()> {
+    let all_backups = entry::read_storage(storage, &config.archive).context("read backups")?;
+    let backups = if args.backups.is_empty() {
+        all_backups
+            .into_iter()
+            .filter(|backup| encryption::is_encrypted(&backup.path.to_string_lossy()))
+            .collect()
+    } else {
+        selected_backups(&all_backups, &args.backups)?
+    };
+    let recipients = if args.new_recipients.is_empty() {
+        &config.archive.encrypt_to
+    } else {
+        &args.new_recipients
+    };
+    let new_passphrase_file =
+        (args.new_passphrase_file.clone()).or_else(|| config.archive.passphrase_file.clone());
+    let settings = encryption::Encryption {
+        cipher: args.cipher.or(config.archive.cipher).unwrap_or_default(),
+        recipients,
+        passphrase_file: new_passphrase_file.as_deref(),
+    };
+    anyhow::ensure!(
+        settings.is_enabled(),
+        "no recipients to encrypt to (pass --new-recipient)"
+    );
+    let decryptor = Decryptor {
+        identity: args.identity.clone(),
+        passphrase_file: (args.passphrase_file.clone())
+            .or_else(|| config.archive.passphrase_file.clone()),
+    };
+    let verify_decrypt =
+        args.decrypt || args.new_identity.is_some() || settings.cipher == Cipher::Passphrase;
+    let verifier = verify_decrypt.then(|| Decryptor {
+        identity: args.new_identity.clone(),
+        passphrase_file: new_passphrase_file.clone(),
+    });
+    let decrypts_passphrase = decryptor.passphrase_file.is_none()
+        && backups
+            .iter()
+            .any(|backup| Cipher::of(&backup.path.to_string_lossy()) == Some(Cipher::Passphrase));
+    let encrypts_passphrase =
+        settings.cipher == Cipher::Passphrase && settings.passphrase_file.is_none();
+    if decrypts_passphrase || encrypts_passphrase {
+        prompt_passphrase()?;
+    }
+    let signing_key = (config.archive.signing_key.as_deref())
+        .map(SigningKey::load)
+        .transpose()?;
+    let mut failed = 0;
+    for backup in &backups {
+        let result = rattlebeaver::rekey::rekey(
+            storage,
+            backup,
+            &decryptor,
+            settings,
+            verifier.as_ref(),
+            signing_key.as_ref(),
+        );
+        if let Some(target) = journal_target {
+            let mut record = journal::Record::new(Action::Rekey, &backup.path).outcome(&result);
+            if let Ok(new_name) = &result {
+                record = record.detail(format!("to {new_name}"));
+            }
+            journal::append(target, &record, &config.archive).context("write journal")?;
+        }
+        match result {
+            Ok(new_name) => println!("{}", storage.locate(&new_name).display()),
+            Err(e) => {
+                failed += 1;
+                error!("rekey {}: {e:#}", backup.path.display());
+            }
+        }
+    }
+    if let Some(target) = journal_target {
+        Index::build(target, &config.archive)
+            .context("rebuild index")?
+            .save(target, &config.archive)
+            .context("save index")?;
+    }
+    if failed > 0 {
+        anyhow::bail!(
+            "{failed} of {} backup(s) could not be re-encrypted",
+            backups.len()
+        );
+    }
+    Ok(())
+}
+
+/// Delete the stale backups in remote storage, always keeping the newest.
+fn remote_delete(
+    storage: &dyn Storage,
+    config: &Config,
+    now: Timestamp,
+    execute: bool,
+) -> Result<()> {
+    let mut all_backups = read_storage_backups(storage, config, now).context("read backups")?;
+    all_backups.pop();
+    let grace = config.delete.stale_grace.map(|grace| (storage, grace));
+    let stale = stale::stale_backups(all_backups, grace, execute, now, Some(&emit))?;
+    if stale.is_empty() {
+        info!("No backups to delete.");
+        return Ok(());
+    }
+    info!(
+        "{}",
+        if execute {
+            "Deleting:"
+        } else {
+            "Would delete:"
+        }
+    );
+    for backup in &stale {
+        println!("{}", backup.path.display());
+    }
+    if !execute {
+        return Ok(());
+    }
+    let started = std::time::Instant::now();
+    let paths: Vec<&Path> = stale.iter().map(|b| b.path.as_path()).collect();
+    let target = storage_target(storage);
+    hooks::before_delete(&config.hooks, &target, &paths)?;
+    for backup in &stale {
+        sync::delete_backup(storage, backup, Some(&emit))?;
+    }
+    let deleted = stale.iter().map(|b| b.path.clone()).collect();
+    send_webhooks(
+        config,
+        &Payload::deleted(&target, DeleteReason::Stale, deleted, started.elapsed()),
+    );
+    Ok(())
+}
+
+fn delete(target: &Path, config: &Config, now: Timestamp, args: &ArgsDelete) -> Result<()> {
+    let all_backups = read_backups_at(target, config, now).context("read backups")?;
+    let newest = all_backups.last().map(|b| b.timestamp);
+    let (reason, mut delete_backups) = if let Some(older_than) = args.older_than {
+        let cutoff = now.sub_span(older_than);
+        let older = all_backups
+            .into_iter()
+            .filter(|b| b.timestamp < cutoff)
+            .collect();
+        (DeleteReason::OlderThan, older)
+    } else if args.select.is_empty() {
+        // The state isn't a backup, so it is saved even in append-only targets
+        let storage = (LocalStorage::new(target))
+            .with_permissions(FilePermissions::from_config(&config.archive)?)
+            .with_fsync(config.archive.fsync);
+        let grace = (config.delete.stale_grace).map(|grace| (&storage as &dyn Storage, grace));
+        let stale = stale::stale_backups(all_backups, grace, args.execute, now, Some(&emit))?;
+        (DeleteReason::Stale, stale)
+    } else {
+        let selected = selected_backups(&all_backups, &args.select)?;
+        (DeleteReason::Selected, selected)
+    };
+    // Stale backups are never protected, as pins and tags keep them
+    if !args.force && !matches!(reason, DeleteReason::Stale) {
+        delete_backups.retain(|b| {
+            if b.is_protected() {
+                info!(
+                    "Keeping the protected backup {} (use --force to delete it).",
+                    b.path.display()
+                );
+            }
+            !b.is_protected()
+        });
+    }
+    if !args.allow_delete_newest
+        && let Some(newest) = delete_backups.pop_if(|b| Some(b.timestamp) == newest)
+    {
+        info!(
+            "Keeping the newest backup {} (use --allow-delete-newest to delete it).",
+            newest.path.display()
+        );
+    }
+    // Sizes must be read before the files are deleted
+    let mut backups = Vec::new();
+    for b in &delete_backups {
+        backups.push(DeletedBackup {
+            path: b.path.clone(),
+            timestamp: b.timestamp,
+            size: b.metadata().context("get file metadata")?.len(),
+            reason,
+            kept_for: b.fulfills.iter().map(Fulfillment::display).collect(),
+        });
+    }
+    let executed = delete_entries(target, config, now, args, reason, &delete_backups)?;
+    if args.format == DeleteFormat::Json {
+        let report = DeleteReport { executed, backups };
+        let json = serde_json::to_string_pretty(&report).context("encode json report")?;
+        println!("{json}");
+    }
+    Ok(())
+}
+
+/// Report of what was (or would be) deleted, for `delete --format json`.
+#[derive(Debug, serde::Serialize)]
+struct DeleteReport {
+    executed: bool,
+    backups: Vec<DeletedBackup>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct DeletedBackup {
+    path: PathBuf,
+    timestamp: Timestamp,
+    size: u64,
+    reason: DeleteReason,
+    /// What the backup is currently kept for, if anything
+    kept_for: Vec<String>,
+}
+
+/// Delete `delete_backups`, picked for `reason`, if executing and confirmed, returning whether
+/// they were deleted.
+fn delete_entries(
+    target: &Path,
+    config: &Config,
+    now: Timestamp,
+    args: &ArgsDelete,
+    reason: DeleteReason,
+    delete_backups: &[Entry],
+) -> Result<bool> {
+    if delete_backups.is_empty() {
+        info!("No backups to delete.");
+        return Ok(false);
+    }
+    check_append_only(config, args.execute)?;
+    let confirm = args.execute && !args.yes;
+    if confirm {
+        info!("Will delete:");
+    } else if args.execute && args.trashes(config) {
+        info!("Moving to trash:");
+    } else if args.execute {
+        info!("Deleting:");
+    } else {
+        info!("Would delete:");
+    }
+    let mut total_size = 0;
+    for b in delete_backups {
+        if args.format == DeleteFormat::Text {
+            println!("{}", b.path.display());
+        }
+        if !b.fulfills.is_empty() {
+            let fulfills: Vec<String> = b.fulfills.iter().map(Fulfillment::display).collect();
+            warn!(
+                "{} is currently kept for {}",
+                b.path.display(),
+                fulfills.join(", ")
+            );
+        }
+        total_size += b.metadata().context("get file metadata")?.len();
+    }
+    if !args.execute {
+        return Ok(false);
+    }
+    if confirm {
+        let prompt = format!(
+            "Delete {} backup(s) totalling {}?",
+            delete_backups.len(),
+            ByteSize(total_size)
+        );
+        if !confirm_prompt(&prompt)? {
+            info!("Aborted.");
+            return Ok(false);
+        }
+    }
+    let started = std::time::Instant::now();
+    let paths: Vec<&Path> = delete_backups.iter().map(|b| b.path.as_path()).collect();
+    hooks::before_delete(&config.hooks, &target.display().to_string(), &paths)?;
+    let mut index = Index::load(target, &config.archive).context("load index")?;
+    let result = remove_entries(target, config, now, args, delete_backups, &mut index);
+    // Saved even if deleting failed partway, so the backups already gone are unindexed
+    index.save(target, &config.archive).context("save index")?;
+    result?;
+    let deleted = delete_backups.iter().map(|b| b.path.clone()).collect();
+    let target = target.display().to_string();
+    let payload = Payload::deleted(&target, reason, deleted, started.elapsed());
+    send_webhooks(config, &payload);
+    Ok(true)
+}
+
+/// Move `delete_backups` to the trash or delete them, removing each from `index` once it is gone.
+fn remove_entries(
+    target: &Path,
+    config: &Config,
+    now: Timestamp,
+    args: &ArgsDelete,
+    delete_backups: &[Entry],
+    index: &mut Index,
+) -> Result<()> {
+    if args.trashes(config) {
+        let result =
+            trash::move_to_trash(target, delete_backups, &config.archive, now, Some(&emit));
+        for b in delete_backups {
+            let mut record = journal::Record::new(Action::Trash, &b.path).outcome(&result);
+            if let Ok(trash_dir) = &result {
+                record = record.detail(format!("moved to {}", trash_dir.display()));
+            }
+            // Moving fails as a whole, but may have moved some backups first
+            if !b.path.exists() {
+                index.remove(b.timestamp);
+            }
+            journal::append(target, &record, &config.archive).context("write journal")?;
+        }
+        info!("Moved to {}", result?.display());
+    } else {
+        let shred = args.shred || config.delete.shred;
+        let storage = local_storage(target, config)?;
+        for b in delete_backups {
+            let _span = tracing::info_span!("delete", path = %b.path.display()).entered();
+            let result = if shred {
+                shred::shred_backup(b, Some(&emit))
+            } else {
+                sync::delete_backup(&*storage, b, Some(&emit))
+            };
+            let action = if shred { Action::Shred } else { Action::Delete };
+            let record = journal::Record::new(action, &b.path).outcome(&result);
+            journal::append(target, &record, &config.archive).context("write journal")?;
+            result?;
+            index.remove(b.timestamp);
+        }
+    }
+    Ok(())
+}
+
+/// Warn about the files skipped by a lenient read, returning the backups read.
+fn warn_skipped((backups, skipped): (Vec<Entry>, Vec<Skipped>)) -> Vec<Entry> {
+    for file in skipped {
+        warn!("skipped {}: {:#}", file.path.display(), file.reason);
+    }
+    backups
+}
+
+/// Write the metrics of a run on `target` to `path`, if given, warning if that fails.
+fn write_metrics(
+    path: Option<&Path>,
+    target: &str,
+    success: bool,
+    started: std::time::Instant,
+    read_backups: impl FnOnce() -> Result<Vec<Entry>, Error>,
+) {
+    let Some(path) = path else {
+        return;
+    };
+    let duration = started.elapsed();
+    let result = read_backups()
+        .map_err(anyhow::Error::from)
+        .and_then(|backups| {
+            Metrics::collect(target, &backups, success, Timestamp::now(), duration).write(path)
+        });
+    if let Err(e) = result {
+        warn!("write metrics: {e:#}");
+    }
+}
+
+/// Ping the monitoring url in the config, if any, warning if that fails.
+fn send_ping(config: &Config, ping: Ping, body: &str) {
+    if let Some(url) = &config.ping_url
+        && let Err(e) = webhook::ping(url, ping, body)
+    {
+        warn!("{e:#}");
+    }
+}
+
+/// Send `payload` to the webhooks in the config, warning about those that fail.
+fn send_webhooks(config: &Config, payload: &Payload) {
+    for error in webhook::send(&config.webhooks, payload) {
+        warn!("{error:#}");
+    }
+}
+
+fn empty_trash(
+    target: &Path,
+    config: &Config,
+    now: Timestamp,
+    args: &ArgsEmptyTrash,
+) -> Result<()> {
+    let retention = if args.all {
+        None
+    } else {
+        config.delete.trash_retention
+    };
+    let expired = trash::expired(target, retention, now)?;
+    if expired.is_empty() {
+        info!("Nothing to remove from trash.");
+        return Ok(());
+    }
+    if args.execute {
+        info!("Deleting:");
+    } else {
+        info!("Would delete:");
+    }
+    for dir in &expired {
+        println!("{}", dir.display());
+        if !args.execute {
+            continue;
+        }
+        let result = if args.shred || config.delete.shred {
+            shred::shred_dir(dir)
+        } else {
+            std::fs::remove_dir_all(dir).with_context(|| format!("delete {}", dir.display()))
+        }
+        .and_then(|()| fsync::parent(dir, config.archive.fsync));
+        let record = journal::Record::new(Action::EmptyTrash, dir).outcome(&result);
+        journal::append(target, &record, &config.archive).context("write journal")?;
+        result?;
+    }
+    Ok(())
+}
+
+/// Count the stale backups that `delete` would delete, without changing anything.
+fn check_stale(target: &Path, config: &Config, now: Timestamp) -> Result<usize> {
+    let mut all_backups = read_backups_at(target, config, now).context("read backups")?;
+    all_backups.pop();
+    all_backups.retain(|b| b.fulfills.is_empty());
+    if let Some(grace) = config.delete.stale_grace {
+        let stale_since =
+            StaleSince::load(&LocalStorage::new(target)).context("load stale state")?;
+        all_backups.retain(|b| stale_since.is_past_grace(b.timestamp, grace, now));
+    }
+    Ok(all_backups.len())
+}
+
+/// The backups referred to by `selectors`, oldest first.
+fn selected_backups(all_backups: &[Entry], selectors: &[String]) -> Result<Vec<Entry>> {
+    let mut selected = Vec::new();
+    for selector in selectors {
+        let backup = entry::find(all_backups, selector)?;
+        if !selected.contains(backup) {
+            selected.push(backup.clone());
+        }
+    }
+    selected.sort();
+    Ok(selected)
+}
+
+/// Ask the user a yes/no question on the terminal, defaulting to no.
+fn confirm_prompt(prompt: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("cannot ask for confirmation without a terminal (use --yes)");
+    }
+    eprint!("{prompt} [y/N] ");
+    std::io::stderr().flush().context("flush stderr")?;
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("read confirmation")?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Check if backups are encrypted with a passphrase that is not in a file, when adding them or
+/// copying them to a target.
+fn encrypts_with_passphrase(config: &Config) -> bool {
+    let archive = config.archive.encryption();
+    let targets = config.targets.values().map(config::Target::encryption);
+    std::iter::once(archive).chain(targets).any(|encryption| {
+        encryption.cipher == Cipher::Passphrase && encryption.passphrase_file.is_none()
+    })
+}
+
+/// Refuse to delete, rename or overwrite backups (if `modifies_backups`) in an append-only
+/// target.
+fn check_append_only(config: &Config, modifies_backups: bool) -> Result<()> {
+    anyhow::ensure!(
+        !(config.append_only && modifies_backups),
+        "the target is append-only (append_only in the config), refusing to modify backups"
+    );
+    Ok(())
+}
+
+/// Wrap the `storage` of `target` to refuse deleting, renaming and overwriting backups if the
+/// config makes the target append-only.
+fn guard_append_only(config: &Config, target: &str, storage: Box<dyn Storage>) -> Box<dyn Storage> {
+    if config.append_only {
+        return Box::new(AppendOnlyStorage::new(storage, target));
+    }
+    storage
+}
+
+/// The storage of the local `target` directory, append-only if the config says so.
+fn local_storage(target: &Path, config: &Config) -> Result<Box<dyn Storage>> {
+    let permissions = FilePermissions::from_config(&config.archive)?;
+    let storage = (LocalStorage::new(target))
+        .with_permissions(permissions)
+        .with_fsync(config.archive.fsync);
+    let storage = Box::new(storage);
+    Ok(guard_append_only(
+        config,
+        &target.to_string_lossy(),
+        storage,
+    ))
+}
+
+/// Ask for the passphrase before running `command` if it may encrypt with one.
+fn ask_for_passphrase(config: &Config, command: &Command) -> Result<()> {
+    if matches!(
+        command,
+        Command::Add(_) | Command::Run(_) | Command::Watch(_) | Command::Copy(_)
+    ) && encrypts_with_passphrase(config)
+    {
+        prompt_passphrase()?;
+    }
+    Ok(())
+}
+
+/// Ask for the passphrase on the terminal without echoing it, unless it is in the environment
+/// already or there is no terminal to ask on.
+fn prompt_passphrase() -> Result<()> {
+    if encryption::has_passphrase() || !std::io::stdin().is_terminal() {
+        return Ok(());
+    }
+    let stty = |arg: &str| {
+        std::process::Command::new("stty")
+            .arg(arg)
+            .stdin(std::process::Stdio::inherit())
+            .status()
+    };
+    eprint!("Passphrase: ");
+    std::io::stderr().flush().context("flush stderr")?;
+    let hidden = stty("-echo").is_ok_and(|status| status.success());
+    let mut passphrase = String::new();
+    let read = std::io::stdin().read_line(&mut passphrase);
+    if hidden {
+        let _ = stty("echo");
+        eprintln!();
+    }
+    read.context("read passphrase")?;
+    encryption::provide_passphrase(passphrase.trim_end_matches(['\r', '\n']).to_owned());
+    Ok(())
+}
+
+fn info(target: &Path, config: &Config, now: Timestamp, selector: &str) -> Result<()> {
+    let all_backups = read_backups_at(target, config, now).context("read backups")?;
+    let backup = entry::find(&all_backups, selector)?;
+    println!("{}", backup.path.display());
+    println!("Timestamp: {}", backup.timestamp.humanized());
+    if let Some(label) = &backup.label {
+        println!("Label: {label}");
+    }
+    let size = backup.metadata().context("get file metadata")?.len();
+    println!("Size: {}", ByteSize(size));
+    if backup.fulfills.is_empty() {
+        println!("Status: stale");
+    } else {
+        let fulfills: Vec<String> = backup.fulfills.iter().map(Fulfillment::display).collect();
+        println!("Status: kept for {}", fulfills.join(", "));
+    }
+    let Some(meta) = &backup.meta else {
+        println!("No recorded metadata.");
+        return Ok(());
+    };
+    if let Some(note) = &meta.note {
+        println!("Note: {note}");
+    }
+    if !meta.tags.is_empty() {
+        println!("Tags: {}", meta.tags.join(", "));
+    }
+    if let Some(source) = &meta.source {
+        println!("Source: {}", source.display());
+    }
+    if let Some(command) = &meta.command {
+        println!("Command: {command}");
+    }
+    if let Some(snapshot) = meta.snapshot {
+        println!("Snapshot: {snapshot:?}");
+    }
+    if let Some(hostname) = &meta.hostname {
+        println!("Host: {hostname}");
+    }
+    if let Some(user) = &meta.user {
+        println!("User: {user}");
+    }
+    if let Some(version) = &meta.version {
+        println!("Version: {version}");
+    }
+    if let Some(archive_mode) = meta.archive_mode {
+        let name = archive_mode
+            .to_possible_value()
+            .map(|v| v.get_name().to_owned())
+            .unwrap_or_default();
+        println!("Archive mode: {name}");
+    }
+    if let Some(duration) = meta.duration {
+        println!("Duration: {duration:.2}s");
+    }
+    if let Some(uncompressed_size) = meta.uncompressed_size {
+        println!("Uncompressed size: {}", ByteSize(uncompressed_size));
+    }
+    if let Some(written_size) = meta.written_size {
+        println!("Written size: {}", ByteSize(written_size));
+    }
+    if let Some(ratio) = meta.compression_ratio() {
+        println!("Compression ratio: {:.1}%", ratio * 100.0);
+    }
+    if let Some(file_count) = meta.file_count {
+        println!("Files: {file_count}");
+    }
+    if let Some(checksum) = &meta.checksum {
+        println!("SHA-256: {checksum}");
+    }
+    if let Some(signature) = &meta.signature {
+        println!("Signature: {signature}");
+    }
+    Ok(())
+}
+
+fn verify(target: &Path, config: &Config, now: Timestamp, args: &ArgsVerify) -> Result<()> {
+    let all_backups = read_backups_at(target, config, now).context("read backups")?;
+    let backups = if args.backups.is_empty() {
+        all_backups
+    } else {
+        selected_backups(&all_backups, &args.backups)?
+    };
+    let decrypt = args.decrypt || args.identity.is_some() || args.passphrase_file.is_some();
+    let decryptor = decrypt.then(|| Decryptor {
+        identity: args.identity.clone(),
+        passphrase_file: (args.passphrase_file.clone())
+            .or_else(|| config.archive.passphrase_file.clone()),
+    });
+    if let Some(decryptor) = &decryptor
+        && decryptor.passphrase_file.is_none()
+        && backups
+            .iter()
+            .any(|backup| Cipher::of(&backup.path.to_string_lossy()) == Some(Cipher::Passphrase))
+    {
+        prompt_passphrase()?;
+    }
+    let public_key = if args.signatures {
+        let key = (args.public_key.as_deref())
+            .or(config.archive.public_key.as_deref())
+            .context("no public key to check signatures against (set archive.public_key)")?;
+        Some(PublicKey::parse(key).context("parse public key")?)
+    } else {
+        None
+    };
+    let mut failed = 0;
+    for backup in &backups {
+        let path = backup.path.display();
+        if let Some(public_key) = &public_key {
+            match verify::verify_signature(backup, public_key)? {
+                SignatureStatus::Valid => println!("OK {path}"),
+                SignatureStatus::Invalid => {
+                    failed += 1;
+                    println!("FAILED {path}: signature does not match");
+                }
+                SignatureStatus::Unsigned => {
+                    failed += 1;
+                    println!("FAILED {path}: not signed");
+                }
+            }
+        } else if args.checksums {
+            match verify::verify_checksum(backup)? {
+                ChecksumStatus::Match => println!("OK {path}"),
+                ChecksumStatus::Missing => println!("UNKNOWN {path}: no checksum recorded"),
+                ChecksumStatus::Mismatch { recorded, actual } => {
+                    failed += 1;
+                    println!("FAILED {path}: checksum {actual} does not match {recorded}");
+                }
+            }
+        } else if let Err(e) = verify::verify_archive(&backup.path, decryptor.as_ref()) {
+            failed += 1;
+            println!("FAILED {path}: {e:#}");
+        } else {
+            println!("OK {path}");
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!(
+            "{failed} of {} backup(s) failed verification",
+            backups.len()
+        );
+    }
+    Ok(())
+}
+
+fn completions(args: &ArgsCompletions) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    if args.fixed {
+        clap_complete::generate(
+            args.shell,
+            &mut Args::command(),
+            "rattlebeaver",
+            &mut stdout,
+        );
+        return Ok(());
+    }
+    // The same script `COMPLETE=<shell> rattlebeaver` prints, calling back into this binary
+    let shells = Shells::builtins();
+    let shell = shells
+        .completer(&args.shell.to_string())
+        .with_context(|| format!("unsupported shell {}", args.shell))?;
+    let completer = std::env::current_exe().context("locate rattlebeaver")?;
+    let completer = completer.to_string_lossy();
+    shell.write_registration(
+        "COMPLETE",
+        "rattlebeaver",
+        "rattlebeaver",
+        &completer,
+        &mut stdout,
+    )?;
     Ok(())
 }
 
-fn delete_stale(target: &Path, config: &Config, execute: bool) -> Result<()> {
-    let delete_backups: Vec<Entry> = read_backups(target, config)
-        .context("read backups")?
-        .into_iter()
-        .filter(|b| b.fulfills.is_empty())
+/// The backups in the target of the command line being completed, newest first, as timestamps
+/// in the format of file names, which has no spaces for shells to split on. Only local targets
+/// are listed, and nothing if their config doesn't exist yet.
+///
+/// This relies on the dynamic completion of `clap_complete`, which is behind its
+/// `unstable-dynamic` feature and may change in any release.
+fn complete_backups() -> Vec<CompletionCandidate> {
+    // Completions are requested as `rattlebeaver -- rattlebeaver [ARGS]...`
+    let args = std::env::args_os().skip_while(|arg| arg != "--").skip(1);
+    // Parsed as far as the line goes, so that e.g. `add -t` isn't taken for the target
+    let Ok(matches) = Args::command()
+        .ignore_errors(true)
+        .try_get_matches_from(args)
+    else {
+        return Vec::new();
+    };
+    let target_dir = (matches.get_one::<PathBuf>("target_dir").cloned())
+        .or_else(|| std::env::var_os("RATTLEBEAVER_TARGET_DIR").map(PathBuf::from));
+    let Some(target_dir) = target_dir else {
+        return Vec::new();
+    };
+    if storage::is_remote(&target_dir.to_string_lossy()) {
+        return Vec::new();
+    }
+    let config_path = (matches.get_one::<PathBuf>("config").cloned())
+        .unwrap_or_else(|| target_dir.join("rattlebeaver.config.toml"));
+    let Ok(config) = Config::from_path(&config_path) else {
+        return Vec::new();
+    };
+    let Ok(backups) = read_backups_at(&target_dir, &config, Timestamp::now()) else {
+        return Vec::new();
+    };
+    (backups.iter().rev())
+        .map(|backup| {
+            let mut help = backup.label.clone().unwrap_or_default();
+            if backup.fulfills.is_empty() {
+                help.push_str(" (stale)");
+            }
+            CompletionCandidate::new(backup.timestamp.to_string())
+                .help(Some(help.trim().to_owned().into()))
+        })
+        .collect()
+}
+
+fn keygen(args: &ArgsKeygen) -> Result<()> {
+    let key = signature::generate(&args.path)?;
+    info!("Wrote the secret key to {}", args.path.display());
+    info!("Set archive.signing_key to it, and archive.public_key to:");
+    println!("{}", key.public_key());
+    Ok(())
+}
+
+fn install_systemd(target: &str, config: Option<&Path>, args: &ArgsInstallSystemd) -> Result<()> {
+    let absolute = |path: &Path| std::path::absolute(path).context("get absolute path");
+    let target = if storage::is_remote(target) {
+        target.to_owned()
+    } else {
+        absolute(Path::new(target))?.to_string_lossy().into_owned()
+    };
+    let config = config.map(absolute).transpose()?;
+    let sources = (args.sources.iter())
+        .map(|source| absolute(source))
+        .collect::<Result<Vec<_>>>()?;
+    let binary = std::env::current_exe().context("locate the rattlebeaver binary")?;
+    let units = Units::new(&UnitSettings {
+        binary: &binary,
+        target: &target,
+        config: config.as_deref(),
+        sources: &sources,
+        schedule: &args.schedule,
+        delete: args.delete,
+    })?;
+    let dir = match &args.dir {
+        Some(dir) => dir.clone(),
+        None => systemd::unit_dir(args.system)?,
+    };
+    for path in units.install(&dir, &args.name, args.force)? {
+        println!("{}", path.display());
+    }
+    let scope = if args.system { "" } else { " --user" };
+    info!(
+        "To enable: systemctl{scope} daemon-reload && systemctl{scope} enable --now {}.timer",
+        args.name
+    );
+    Ok(())
+}
+
+fn explain(target: &Path, config: &Config, now: Timestamp, selector: &str) -> Result<()> {
+    let all_backups = read_backups_at(target, config, now).context("read backups")?;
+    let backup = entry::find(&all_backups, selector)?;
+    println!("{}", backup.path.display());
+    println!("Timestamp: {}", backup.timestamp.humanized());
+    let (pattern, ranges) = config.ranges.policy(backup.label.as_deref());
+    if let Some(pattern) = pattern {
+        println!("Override: {pattern}");
+    }
+    let all_backups: Vec<Entry> = all_backups
+        .iter()
+        .filter(|b| config.ranges.policy(b.label.as_deref()).0 == pattern)
+        .cloned()
         .collect();
-    if delete_backups.is_empty() {
-        eprintln!("No stale backups.");
+    let position = all_backups
+        .iter()
+        .rev()
+        .position(|b| b == backup)
+        .unwrap_or(0)
+        + 1;
+    let latest = &ranges.latest;
+    let latest_fulfilled = backup
+        .fulfills
+        .iter()
+        .any(|f| f.kind == FulfillmentKind::Latest);
+    println!(
+        "Latest: #{position} of {} (keeping {}{}){}",
+        all_backups.len(),
+        latest.count(),
+        latest
+            .within()
+            .map(|span| format!(", and all within {span}"))
+            .unwrap_or_default(),
+        if latest_fulfilled { " - kept" } else { "" },
+    );
+    let mut chosen_by_rules = latest_fulfilled;
+    let explanations = mark::explain_ranges(&all_backups, ranges, now, backup.timestamp)?;
+    for (explanation, (_range, range_config)) in explanations.iter().zip(ranges.iter_ranges()) {
+        let name = format!("{:?}", explanation.range);
+        if range_config.total == 0 {
+            println!("{name}: disabled");
+            continue;
+        }
+        let Some(bucket) = &explanation.bucket else {
+            println!(
+                "{name}: not in any of the {} buckets considered",
+                range_config.total
+            );
+            continue;
+        };
+        println!(
+            "{name}: bucket #{} ({}) with {} backup(s)",
+            bucket.index,
+            bucket.floor.humanized(),
+            bucket.members.len()
+        );
+        let mut describe = |label: &str, included: bool, chosen: Option<&Timestamp>| {
+            let Some(chosen) = chosen else {
+                return;
+            };
+            let outcome = if !included {
+                format!("not kept (include_{label} disabled)")
+            } else if *chosen == backup.timestamp {
+                chosen_by_rules = true;
+                String::from("this backup - kept")
+            } else {
+                format!("beaten by {}", chosen.humanized())
+            };
+            println!("  {label}: {outcome}");
+        };
+        describe("first", range_config.include_first, bucket.members.first());
+        describe("last", range_config.include_last, bucket.members.last());
+    }
+    if let Some(pinned) = backup
+        .fulfills
+        .iter()
+        .find(|f| matches!(f.kind, FulfillmentKind::Pinned { .. }))
+    {
+        println!("Pin: {pinned}");
+    }
+    if backup
+        .fulfills
+        .iter()
+        .any(|f| f.kind == FulfillmentKind::Tagged)
+    {
+        println!(
+            "Tags: {} (kept by delete.keep_tags)",
+            backup.tags().join(", ")
+        );
+    }
+    if backup.fulfills.is_empty() && chosen_by_rules {
+        println!("Status: stale (unmarked by max_total_count or min_free_space)");
+    } else if backup.fulfills.is_empty() {
+        println!("Status: stale");
+    } else {
+        let reprs: Vec<String> = backup.fulfills.iter().map(Fulfillment::display).collect();
+        println!("Status: kept ({})", reprs.join(" :: "));
+    }
+    Ok(())
+}
+
+fn simulate(target: &Path, config: &Config, now: Timestamp, args: &ArgsSimulate) -> Result<()> {
+    let size = if let Some(size) = args.size {
+        Some(size.0)
+    } else {
+        let all_backups = read_backups_at(target, config, now).context("read backups")?;
+        let mut total_size = 0;
+        for backup in &all_backups {
+            total_size += backup.metadata().context("get file metadata")?.len();
+        }
+        u64::try_from(all_backups.len())
+            .ok()
+            .and_then(|count| total_size.checked_div(count))
+    };
+    let (_pattern, ranges) = config.ranges.policy(args.label.as_deref());
+    let samples = rattlebeaver::simulate::simulate(ranges, now, args.every, args.duration)?;
+    let usage = |count: usize| {
+        size.map(|size| format!(" | {}", ByteSize(size * count as u64)))
+            .unwrap_or_default()
+    };
+    let step = samples.len().div_ceil(args.rows.max(1)).max(1);
+    for (i, sample) in samples.iter().enumerate() {
+        if i % step == 0 || i == samples.len() - 1 {
+            println!(
+                "{} | {} backups{}",
+                sample.time.humanized(),
+                sample.count,
+                usage(sample.count)
+            );
+        }
+    }
+    let max_count = samples.iter().map(|s| s.count).max().unwrap_or_default();
+    let final_count = samples.last().map(|s| s.count).unwrap_or_default();
+    println!("Simulated {} backups", samples.len());
+    println!("Peak: {max_count} backups{}", usage(max_count));
+    println!("Steady state: {final_count} backups{}", usage(final_count));
+    if size.is_none() {
+        info!("Specify --size to estimate disk usage.");
+    }
+    Ok(())
+}
+
+fn pin(
+    target: &Path,
+    config: &Config,
+    now: Timestamp,
+    selectors: &[String],
+    until: Option<Timestamp>,
+) -> Result<()> {
+    let all_backups = read_backups_at(target, config, now).context("read backups")?;
+    let mut pins = Pins::load(target).context("load pins")?;
+    for selector in selectors {
+        let backup = entry::find(&all_backups, selector)?;
+        pins.pin(backup.timestamp, until);
+        match until {
+            Some(until) => println!(
+                "Pinned {} until {}",
+                backup.path.display(),
+                until.humanized()
+            ),
+            None => println!("Pinned {}", backup.path.display()),
+        }
+    }
+    pins.save(target, &config.archive).context("save pins")
+}
+
+fn unpin(target: &Path, config: &Config, now: Timestamp, selectors: &[String]) -> Result<()> {
+    let all_backups = read_backups_at(target, config, now).context("read backups")?;
+    let mut pins = Pins::load(target).context("load pins")?;
+    for selector in selectors {
+        let backup = entry::find(&all_backups, selector)?;
+        if pins.unpin(backup.timestamp) {
+            println!("Unpinned {}", backup.path.display());
+        } else {
+            info!("Not pinned: {}", backup.path.display());
+        }
+    }
+    pins.save(target, &config.archive).context("save pins")
+}
+
+fn import(target: &Path, config: &Config, args: &ArgsImport) -> Result<()> {
+    let mut errors = Vec::new();
+    for file in &args.files {
+        let result = import::import_backup(
+            file,
+            target,
+            &config.archive,
+            args.timestamp_from,
+            args.copy,
+        );
+        let path = result.as_ref().map_or(file, |path| path);
+        let record = journal::Record::new(Action::Import, path)
+            .detail(format!("from {}", file.display()))
+            .outcome(&result);
+        journal::append(target, &record, &config.archive).context("write journal")?;
+        match result {
+            Ok(imported) => println!("{}", imported.display()),
+            Err(Error::TimestampConflict { .. }) if args.ignore_conflicts => {}
+            Err(error) => {
+                let error = anyhow::Error::from(error).context(format!("import {file:?}"));
+                if !args.force {
+                    return Err(error);
+                }
+                error!("{error:#}");
+                errors.push(error);
+            }
+        }
+    }
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+fn migrate(target: &Path, config: &Config, args: &ArgsMigrate) -> Result<()> {
+    let from = config::Archive {
+        prefix: args
+            .from_prefix
+            .clone()
+            .unwrap_or_else(|| config.archive.prefix.clone()),
+        timestamp_format: args
+            .from_format
+            .clone()
+            .unwrap_or_else(|| config.archive.timestamp_format.clone()),
+        ..config.archive.clone()
+    };
+    if from.prefix == config.archive.prefix
+        && from.timestamp_format == config.archive.timestamp_format
+    {
+        anyhow::bail!("nothing to migrate: give --from-prefix or --from-format");
+    }
+    let renames = migrate::plan(target, &from, &config.archive)?;
+    if renames.is_empty() {
+        info!("No backups to migrate.");
         return Ok(());
     }
-    if execute {
-        eprintln!("Deleting:");
+    if args.execute {
+        info!("Renaming:");
     } else {
-        eprintln!("Would delete:");
+        info!("Would rename:");
     }
-    for b in delete_backups {
-        println!("{}", b.path.display());
-        if execute {
-            std::fs::remove_file(&b.path)
-                .with_context(|| format!("delete {}", b.path.display()))?;
+    for rename in &renames {
+        println!("{} -> {}", rename.from.display(), rename.to.display());
+    }
+    if args.execute {
+        let result = migrate::execute(target, &renames, &config.archive);
+        for rename in &renames {
+            let record = journal::Record::new(Action::Migrate, &rename.to)
+                .detail(format!("from {}", rename.from.display()))
+                .outcome(&result);
+            journal::append(target, &record, &config.archive).context("write journal")?;
+        }
+        result?;
+    }
+    Ok(())
+}
+
+fn sync(
+    config_path: Option<&Path>,
+    bwlimit: Option<ByteSize>,
+    lock_timeout: Option<Span>,
+    now: Timestamp,
+    args: &ArgsSync,
+) -> Result<()> {
+    let config_path = match config_path {
+        Some(path) => path.to_owned(),
+        None if !storage::is_remote(&args.source) => {
+            Path::new(&args.source).join("rattlebeaver.config.toml")
+        }
+        None => anyhow::bail!("syncing from a remote target needs a local config file (--config)"),
+    };
+    let config = Config::from_path(&config_path).context("load config")?;
+    check_append_only(&config, args.delete && args.execute)?;
+    // Compare against what is actually there
+    let options = remote_options(&config, bwlimit, true)?;
+    if encrypts_with_passphrase(&config) {
+        prompt_passphrase()?;
+    }
+    let src = match storage::open_remote(&args.source, &options)? {
+        Some(src) => src,
+        None => Box::new(LocalStorage::new(&args.source)),
+    };
+    let lock_timeout = (lock_timeout.map(fixed_duration).transpose())?.unwrap_or_default();
+    let dst_options = RemoteOptions {
+        lock: args.execute.then_some(lock_timeout),
+        ..options.clone()
+    };
+    let dst = storage::open(&args.destination, &dst_options)?;
+    let dst_dir = (!storage::is_remote(&args.destination)).then(|| Path::new(&args.destination));
+    let _lock = (dst_dir.filter(|_| args.execute))
+        .map(|dir| TargetLock::acquire(dir, lock_timeout))
+        .transpose()
+        .context("lock destination directory")?;
+    let pins = match dst_dir {
+        Some(dir) => Pins::load(dir).context("load pins")?,
+        None => Pins::default(),
+    };
+    let plan = sync::plan(&*src, &*dst, &config, &pins, now, args.delete)?;
+    for backup in &plan.kept {
+        info!("Keeping pinned or tagged {}", backup.path.display());
+    }
+    for (src_backup, dst_backup) in &plan.conflicts {
+        info!(
+            "Checksums differ: {} and {}",
+            src_backup.path.display(),
+            dst_backup.path.display()
+        );
+    }
+    if plan.copy.is_empty() && plan.delete.is_empty() {
+        info!("Nothing to copy or delete.");
+    }
+    let mut failures = 0;
+    if !plan.copy.is_empty() {
+        info!(
+            "{}",
+            if args.execute {
+                "Copying:"
+            } else {
+                "Would copy:"
+            }
+        );
+    }
+    for backup in &plan.copy {
+        println!("{}", backup.path.display());
+        if !args.execute {
+            continue;
+        }
+        let name = backup.name()?;
+        let result = sync::copy_backup(&*src, &*dst, &name, backup.meta.is_some());
+        if let Err(e) = &result {
+            error!("copy {name}: {e:#}");
+            failures += 1;
+        }
+        if let Some(target) = dst_dir {
+            let record = journal::Record::new(Action::Sync, dst.locate(&name))
+                .detail(format!("from {}", backup.path.display()))
+                .outcome(&result);
+            journal::append(target, &record, &config.archive).context("write journal")?;
+        }
+    }
+    if !plan.delete.is_empty() {
+        info!(
+            "{}",
+            if args.execute {
+                "Deleting:"
+            } else {
+                "Would delete:"
+            }
+        );
+    }
+    for backup in &plan.delete {
+        println!("{}", backup.path.display());
+        if !args.execute {
+            continue;
+        }
+        let result = sync::delete_backup(&*dst, backup, Some(&emit));
+        if let Err(e) = &result {
+            error!("delete {}: {e:#}", backup.path.display());
+            failures += 1;
+        }
+        if let Some(target) = dst_dir {
+            let record = journal::Record::new(Action::Delete, &backup.path)
+                .detail(format!("not in {}", args.source))
+                .outcome(&result);
+            journal::append(target, &record, &config.archive).context("write journal")?;
+        }
+    }
+    if args.execute
+        && let Some(target) = dst_dir
+        && !(plan.copy.is_empty() && plan.delete.is_empty())
+    {
+        Index::build(target, &config.archive)
+            .context("rebuild index")?
+            .save(target, &config.archive)
+            .context("save index")?;
+    }
+    if failures > 0 {
+        anyhow::bail!("failed to sync {failures} backup(s)");
+    }
+    if !plan.conflicts.is_empty() {
+        anyhow::bail!(
+            "{} backup(s) differ between the targets",
+            plan.conflicts.len()
+        );
+    }
+    Ok(())
+}
+
+fn rebuild_index(target: &Path, config: &Config, args: &ArgsIndexRebuild) -> Result<()> {
+    let saved = match Index::load_saved(target) {
+        Ok(Some(saved)) => saved,
+        Ok(None) => {
+            info!("No saved index.");
+            Index::default()
+        }
+        Err(e) => {
+            info!("Saved index is unreadable: {e:#}");
+            Index::default()
+        }
+    };
+    let rebuilt = Index::build(target, &config.archive)?;
+    let mut problems = 0;
+    for discrepancy in saved.discrepancies(&rebuilt) {
+        println!("{discrepancy}");
+        problems += 1;
+    }
+    for indexed in rebuilt.backups.values() {
+        if let Err(e) = verify::verify_archive(&target.join(&indexed.file_name), None) {
+            println!("unreadable archive: {}: {e:#}", indexed.file_name);
+            problems += 1;
+        }
+    }
+    if problems == 0 {
+        info!("Index matches the directory.");
+    }
+    if !args.dry_run {
+        rebuilt.save(target, &config.archive)?;
+        info!("Rebuilt index of {} backup(s).", rebuilt.backups.len());
+    }
+    Ok(())
+}
+
+fn stats(target: &Path, config: &Config, now: Timestamp, args: &ArgsStats) -> Result<()> {
+    let all_backups = read_backups_at(target, config, now).context("read backups")?;
+    let mut stats = Stats::collect(&all_backups, args.period)?;
+    stats.sources.truncate(args.top);
+    if args.format == StatsFormat::Json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&stats).context("encode stats")?
+        );
+        return Ok(());
+    }
+    println!(
+        "Total: {} backup(s), {}",
+        stats.count,
+        ByteSize(stats.total_size)
+    );
+    println!(
+        "Stale: {} backup(s), {}",
+        stats.stale_count,
+        ByteSize(stats.stale_size)
+    );
+    if let Some(ratio) = stats.average_compression_ratio {
+        println!("Average compression ratio: {:.1}%", ratio * 100.0);
+    }
+    if !stats.fulfillments.is_empty() {
+        println!("\nKept for:");
+        for (kind, count) in &stats.fulfillments {
+            println!("  {kind:<16} {count}");
+        }
+    }
+    if !stats.growth.is_empty() {
+        println!(
+            "\nAdded per {}:",
+            format!("{:?}", args.period).to_lowercase()
+        );
+        for (period, added) in &stats.growth {
+            println!(
+                "  {period:<10} {:>4} {:>8}",
+                added.count,
+                ByteSize(added.size).to_string()
+            );
+        }
+    }
+    if !stats.sources.is_empty() {
+        println!("\nLargest sources:");
+        for source in &stats.sources {
+            println!(
+                "  {:>8} {:>4} {}",
+                ByteSize(source.size).to_string(),
+                source.count,
+                source.source
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_status(status: &Status, now: Timestamp, format: StatsFormat) -> Result<()> {
+    if format == StatsFormat::Json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(status).context("encode status")?
+        );
+        return Ok(());
+    }
+    match status.newest {
+        Some(newest) => println!("Newest: {} ({})", newest.humanized(), newest.relative(now)),
+        None => println!("Newest: none"),
+    }
+    println!(
+        "Backups: {}, {} ({} stale)",
+        status.count,
+        ByteSize(status.total_size),
+        status.stale_count
+    );
+    if let Some(disk) = status.disk {
+        println!(
+            "Disk: {} available of {}",
+            ByteSize(disk.available),
+            ByteSize(disk.total)
+        );
+    }
+    if !status.ranges.is_empty() {
+        println!("\nRanges:");
+        for range in &status.ranges {
+            let name = match &range.policy {
+                Some(policy) => format!("{} ({policy})", range.range),
+                None => range.range.clone(),
+            };
+            let fill = format!("{}/{}", range.filled, range.total);
+            if range.unmet == 0 {
+                println!("  {name:<16} {fill}");
+            } else {
+                println!("  {name:<16} {fill:<9} {} unmet", range.unmet);
+            }
+        }
+    }
+    if status.is_healthy() {
+        println!("\nStatus: ok");
+    } else {
+        println!("\nStatus: unhealthy");
+        for problem in &status.problems {
+            println!("  {problem}");
         }
     }
     Ok(())
 }
 
-fn list(target: &Path, config: &Config, details: &[ListingDetails]) -> Result<()> {
-    let all_backups = read_backups(target, config).context("read backups")?;
+fn duplicates(target: &Path, config: &Config, now: Timestamp, args: &ArgsDuplicates) -> Result<()> {
+    let all_backups = read_backups_at(target, config, now).context("read backups")?;
+    let mut by_digest: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
     for backup in &all_backups {
-        let mut display_strings = Vec::new();
-        for desired in details {
-            let display = match desired {
-                ListingDetails::Name => backup.path.display().to_string(),
-                ListingDetails::Time => backup.timestamp.humanized(),
-                ListingDetails::Fulfills => {
-                    let reprs: Vec<String> =
-                        backup.fulfills.iter().map(Fulfillment::display).collect();
-                    reprs.join(" :: ")
-                }
-                ListingDetails::FulfillsShort => {
-                    let reprs: Vec<String> = backup
-                        .fulfills
-                        .iter()
-                        .map(Fulfillment::display_short)
-                        .collect();
-                    reprs.join(" ")
-                }
-                ListingDetails::Size => {
-                    let file_size_bytes = backup.metadata().context("get file metadata")?.len();
-                    format!("{file_size_bytes} bytes")
+        let recorded = backup.meta.as_ref().and_then(|meta| meta.checksum.clone());
+        let digest = match recorded {
+            _ if args.contents => checksum::sha256_contents(&backup.path)?,
+            Some(recorded) => recorded,
+            None => checksum::sha256_file(&backup.path)?,
+        };
+        by_digest.entry(digest).or_default().push(backup);
+    }
+    let mut groups: Vec<Vec<&Entry>> = by_digest
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect();
+    groups.sort();
+    let (mut duplicate_count, mut savings) = (0, 0);
+    for group in &groups {
+        println!("Identical:");
+        for (i, backup) in group.iter().enumerate() {
+            println!("  {}", backup.path.display());
+            if i > 0 {
+                duplicate_count += 1;
+                savings += backup.metadata().context("get file metadata")?.len();
+            }
+        }
+    }
+    if duplicate_count == 0 {
+        info!("No duplicate backups.");
+    } else {
+        info!(
+            "{duplicate_count} duplicate backup(s), keeping only the oldest of each would save {}",
+            ByteSize(savings)
+        );
+    }
+    Ok(())
+}
+
+fn tag(target: &Path, config: &Config, now: Timestamp, args: &ArgsTag) -> Result<()> {
+    let all_backups = read_backups_at(target, config, now).context("read backups")?;
+    let mut backup = entry::find(&all_backups, &args.backup)?.clone();
+    let meta = backup.meta.get_or_insert_default();
+    for tag in &args.tags {
+        if args.remove {
+            meta.tags.retain(|t| t != tag);
+        } else if !meta.tags.contains(tag) {
+            meta.tags.push(tag.clone());
+        }
+    }
+    meta.save(&backup.path, &config.archive)
+        .context("save backup metadata")?;
+    let mut index = Index::load(target, &config.archive).context("load index")?;
+    index.insert(&backup)?;
+    index.save(target, &config.archive).context("save index")?;
+    println!("{}: {}", backup.path.display(), backup.tags().join(", "));
+    Ok(())
+}
+
+fn annotate(target: &Path, config: &Config, now: Timestamp, args: &ArgsAnnotate) -> Result<()> {
+    let all_backups = read_backups_at(target, config, now).context("read backups")?;
+    let mut backup = entry::find(&all_backups, &args.backup)?.clone();
+    let meta = backup.meta.get_or_insert_default();
+    meta.note = args.note.clone().filter(|note| !note.is_empty());
+    meta.save(&backup.path, &config.archive)
+        .context("save backup metadata")?;
+    let mut index = Index::load(target, &config.archive).context("load index")?;
+    index.insert(&backup)?;
+    index.save(target, &config.archive).context("save index")?;
+    match &args.note {
+        Some(note) => println!("Annotated {}: {note}", backup.path.display()),
+        None => println!("Removed note from {}", backup.path.display()),
+    }
+    Ok(())
+}
+
+fn list(target: &Path, config: &Config, now: Timestamp, args: &ArgsList) -> Result<()> {
+    // Machine-readable listings are left without a footer to parse around
+    anyhow::ensure!(
+        !args.summary || args.format == ListFormat::Text,
+        "--summary only applies to the text format"
+    );
+    let mut all_backups = if args.strict {
+        read_backups_at(target, config, now)
+    } else {
+        read_backups_lenient(target, config, now).map(warn_skipped)
+    }
+    .context("read backups")?;
+    filter_listing(&mut all_backups, args, now);
+    match args.sort {
+        ListSort::Time => {}
+        ListSort::Size => {
+            let mut sized = Vec::new();
+            for backup in all_backups {
+                let size = backup.metadata().context("get file metadata")?.len();
+                sized.push((size, backup));
+            }
+            sized.sort_by_key(|(size, _backup)| *size);
+            all_backups = sized.into_iter().map(|(_size, backup)| backup).collect();
+        }
+        ListSort::Name => all_backups.sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name())),
+        ListSort::Fulfillments => all_backups.sort_by_key(|b| b.fulfills.len()),
+    }
+    if args.reverse {
+        all_backups.reverse();
+    }
+    if let Some(limit) = args.limit {
+        all_backups.truncate(limit);
+    }
+    if let Some(tail) = args.tail {
+        all_backups.drain(..all_backups.len().saturating_sub(tail));
+    }
+    if args.print0 {
+        let mut stdout = std::io::stdout().lock();
+        for backup in &all_backups {
+            stdout
+                .write_all(backup.path.as_os_str().as_encoded_bytes())
+                .and_then(|()| stdout.write_all(b"\0"))
+                .context("write path")?;
+        }
+        return stdout.flush().context("flush output");
+    }
+    let members = if args.details().contains(&ListingDetails::Members) {
+        members::member_counts(target, &all_backups, &config.archive)
+            .context("count archive members")?
+    } else {
+        HashMap::new()
+    };
+    match args.group_by {
+        None => print_listing(&all_backups, args, now, &members)?,
+        Some(GroupBy::Source) => {
+            if args.format != ListFormat::Text {
+                anyhow::bail!("--group-by is only supported with the text format");
+            }
+            let mut groups: BTreeMap<Option<&str>, Vec<Entry>> = BTreeMap::new();
+            for backup in &all_backups {
+                groups
+                    .entry(backup.label.as_deref())
+                    .or_default()
+                    .push(backup.clone());
+            }
+            for (label, group) in groups {
+                println!("{}:", label.unwrap_or("(no source)"));
+                print_listing(&group, args, now, &members)?;
+                let mut size = 0;
+                for backup in &group {
+                    size += backup.metadata().context("get file metadata")?.len();
+                }
+                println!("Subtotal: {} backup(s), {}\n", group.len(), ByteSize(size));
+            }
+        }
+    }
+    if args.summary {
+        println!("{}", summarize(&all_backups)?);
+    }
+    Ok(())
+}
+
+/// Remove the backups not matching the filters of `args`.
+fn filter_listing(all_backups: &mut Vec<Entry>, args: &ArgsList, now: Timestamp) {
+    if let Some(since) = args.since {
+        let since = since.resolve(now);
+        all_backups.retain(|b| b.timestamp >= since);
+    }
+    if let Some(until) = args.until {
+        let until = until.resolve(now);
+        all_backups.retain(|b| b.timestamp < until);
+    }
+    if !args.tags.is_empty() {
+        all_backups.retain(|b| b.tags().iter().any(|tag| args.tags.contains(tag)));
+    }
+    if args.stale {
+        all_backups.retain(|b| b.fulfills.is_empty());
+    }
+    if args.kept {
+        all_backups.retain(|b| !b.fulfills.is_empty());
+    }
+    if !args.fulfills.is_empty() {
+        all_backups.retain(|b| {
+            b.fulfills
+                .iter()
+                .any(|f| args.fulfills.iter().any(|filter| filter.matches(f.kind)))
+        });
+    }
+}
+
+/// Print the backups in the selected details and format.
+fn print_listing(
+    all_backups: &[Entry],
+    args: &ArgsList,
+    now: Timestamp,
+    members: &HashMap<PathBuf, usize>,
+) -> Result<()> {
+    if let Some(template) = &args.format_template {
+        for backup in all_backups {
+            println!("{}", render_template(template, backup, now)?);
+        }
+        return Ok(());
+    }
+    let details = args.details();
+    match args.format {
+        ListFormat::Json => {
+            let mut listed = Vec::new();
+            for backup in all_backups {
+                listed.push(ListedBackup {
+                    path: &backup.path,
+                    timestamp: backup.timestamp,
+                    label: backup.label.as_deref(),
+                    size: backup.metadata().context("get file metadata")?.len(),
+                    fulfills: &backup.fulfills,
+                    meta: backup.meta.as_ref(),
+                });
+            }
+            let json = serde_json::to_string_pretty(&listed).context("encode json")?;
+            println!("{json}");
+        }
+        ListFormat::Text => {
+            let mut rows = Vec::new();
+            for backup in all_backups {
+                let mut display_strings = Vec::new();
+                for desired in &details {
+                    display_strings.push(detail_value(backup, *desired, args, now, members)?);
+                }
+                rows.push(display_strings);
+            }
+            let mut widths = vec![0; details.len()];
+            for row in &rows {
+                for (width, value) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(value.chars().count());
                 }
+            }
+            let color = args.color.enabled();
+            for (backup, row) in all_backups.iter().zip(rows) {
+                let mut line = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(value, width)| format!("{value:width$}"))
+                    .collect::<Vec<String>>()
+                    .join(" | ");
+                line.truncate(line.trim_end().len());
+                let style = if backup.fulfills.is_empty() {
+                    STALE_STYLE
+                } else if backup
+                    .fulfills
+                    .iter()
+                    .any(|f| f.kind == FulfillmentKind::Latest)
+                {
+                    LATEST_STYLE
+                } else {
+                    ""
+                };
+                if color && !style.is_empty() {
+                    println!("{style}{line}{RESET_STYLE}");
+                } else {
+                    println!("{line}");
+                }
+            }
+        }
+        ListFormat::Csv | ListFormat::Tsv => {
+            let delimiter = if args.format == ListFormat::Tsv {
+                b'\t'
+            } else {
+                b','
             };
-            display_strings.push(display);
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(std::io::stdout());
+            let header = details.iter().map(|d| {
+                d.to_possible_value()
+                    .map(|v| v.get_name().to_owned())
+                    .unwrap_or_default()
+            });
+            writer.write_record(header).context("write header")?;
+            for backup in all_backups {
+                let mut record = Vec::new();
+                for desired in &details {
+                    record.push(detail_value(backup, *desired, args, now, members)?);
+                }
+                writer.write_record(&record).context("write record")?;
+            }
+            writer.flush().context("flush output")?;
         }
-        println!("{}", display_strings.join(" | "));
     }
     Ok(())
 }
+
+/// Render a `--format-template` for `backup`, replacing `{placeholder}`s and the escapes `\t`,
+/// `\n` and `\\`. Literal braces are written as `{{` and `}}`.
+fn render_template(template: &str, backup: &Entry, now: Timestamp) -> Result<String> {
+    let mut rendered = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => rendered.push('\t'),
+                Some('n') => rendered.push('\n'),
+                Some('\\') => rendered.push('\\'),
+                Some(other) => {
+                    rendered.push('\\');
+                    rendered.push(other);
+                }
+                None => rendered.push('\\'),
+            },
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                rendered.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                rendered.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let end = rest.find('}').context("unclosed placeholder in template")?;
+                rendered.push_str(&placeholder_value(&rest[..end], backup, now)?);
+                chars = rest[end + 1..].chars();
+            }
+            c => rendered.push(c),
+        }
+    }
+    Ok(rendered)
+}
+
+fn placeholder_value(placeholder: &str, backup: &Entry, now: Timestamp) -> Result<String> {
+    let fulfills = |display: fn(&Fulfillment) -> String, separator: &str| {
+        let reprs: Vec<String> = backup.fulfills.iter().map(display).collect();
+        reprs.join(separator)
+    };
+    let value = match placeholder {
+        "path" => backup.path.display().to_string(),
+        "name" => backup
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        "label" => backup.label.clone().unwrap_or_default(),
+        "timestamp" => backup.timestamp.humanized(),
+        "rfc3339" => backup.timestamp.as_ref().to_rfc3339(),
+        "relative" => backup.timestamp.relative(now),
+        "size" => backup
+            .metadata()
+            .context("get file metadata")?
+            .len()
+            .to_string(),
+        "size_human" => ByteSize(backup.metadata().context("get file metadata")?.len()).to_string(),
+        "status" => String::from(if backup.fulfills.is_empty() {
+            "stale"
+        } else {
+            "kept"
+        }),
+        "tags" => backup.tags().join(","),
+        "note" => backup
+            .meta
+            .as_ref()
+            .and_then(|meta| meta.note.clone())
+            .unwrap_or_default(),
+        "fulfills" => fulfills(Fulfillment::display, " :: "),
+        "fulfills_short" => fulfills(Fulfillment::display_short, " "),
+        _ => anyhow::bail!("unknown template placeholder {{{placeholder}}}"),
+    };
+    Ok(value)
+}
+
+/// Total count and size of all and of stale backups, and the oldest and newest timestamps.
+fn summarize(all_backups: &[Entry]) -> Result<String> {
+    let (mut total_size, mut stale_count, mut stale_size) = (0, 0, 0);
+    for backup in all_backups {
+        let size = backup.metadata().context("get file metadata")?.len();
+        total_size += size;
+        if backup.fulfills.is_empty() {
+            stale_count += 1;
+            stale_size += size;
+        }
+    }
+    let mut lines = vec![
+        format!(
+            "Total: {} backup(s), {}",
+            all_backups.len(),
+            ByteSize(total_size)
+        ),
+        format!("Stale: {stale_count} backup(s), {}", ByteSize(stale_size)),
+    ];
+    if let (Some(oldest), Some(newest)) = (all_backups.iter().min(), all_backups.iter().max()) {
+        lines.push(format!("Oldest: {}", oldest.timestamp.humanized()));
+        lines.push(format!("Newest: {}", newest.timestamp.humanized()));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// The value of a listing detail of `backup`. Sizes are plain byte counts outside text format.
+fn detail_value(
+    backup: &Entry,
+    detail: ListingDetails,
+    args: &ArgsList,
+    now: Timestamp,
+    members: &HashMap<PathBuf, usize>,
+) -> Result<String> {
+    let value = match detail {
+        ListingDetails::Name => backup.path.display().to_string(),
+        ListingDetails::Time if args.relative => backup.timestamp.relative(now),
+        ListingDetails::Time => backup.timestamp.humanized(),
+        ListingDetails::Fulfills => {
+            let reprs: Vec<String> = backup.fulfills.iter().map(Fulfillment::display).collect();
+            reprs.join(" :: ")
+        }
+        ListingDetails::FulfillsShort => {
+            let reprs: Vec<String> = backup
+                .fulfills
+                .iter()
+                .map(Fulfillment::display_short)
+                .collect();
+            reprs.join(" ")
+        }
+        ListingDetails::Checksum if args.hash => checksum::sha256_file(&backup.path)?,
+        ListingDetails::Checksum => backup
+            .meta
+            .as_ref()
+            .and_then(|meta| meta.checksum.clone())
+            .unwrap_or_default(),
+        ListingDetails::Source => backup
+            .meta
+            .as_ref()
+            .and_then(|meta| meta.source.as_ref())
+            .map(|source| source.display().to_string())
+            .unwrap_or_default(),
+        ListingDetails::Tags => backup.tags().join(","),
+        ListingDetails::Duration => backup
+            .meta
+            .as_ref()
+            .and_then(|meta| meta.duration)
+            .map(|duration| format!("{duration:.2}"))
+            .unwrap_or_default(),
+        ListingDetails::Files => backup
+            .meta
+            .as_ref()
+            .and_then(|meta| meta.file_count)
+            .map(|count| count.to_string())
+            .unwrap_or_default(),
+        ListingDetails::Ratio => backup
+            .meta
+            .as_ref()
+            .and_then(BackupMeta::compression_ratio)
+            .map(|ratio| format!("{ratio:.3}"))
+            .unwrap_or_default(),
+        ListingDetails::Note => backup
+            .meta
+            .as_ref()
+            .and_then(|meta| meta.note.clone())
+            .unwrap_or_default(),
+        ListingDetails::Members => members
+            .get(&backup.path)
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+        ListingDetails::Size => {
+            let file_size_bytes = backup.metadata().context("get file metadata")?.len();
+            if args.format == ListFormat::Text {
+                format!("{file_size_bytes} bytes")
+            } else {
+                file_size_bytes.to_string()
+            }
+        }
+    };
+    Ok(value)
+}
+
+/// A backup in the output of `list --format json`.
+#[derive(Debug, serde::Serialize)]
+struct ListedBackup<'a> {
+    path: &'a Path,
+    timestamp: Timestamp,
+    label: Option<&'a str>,
+    size: u64,
+    fulfills: &'a [Fulfillment],
+    meta: Option<&'a BackupMeta>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_with_one_timestamp_conflict() {
+        let temp = tempfile::tempdir().unwrap();
+        let config = Config::default();
+        let at = Timestamp::now();
+        let jobs: Vec<(&str, ArgsAdd)> = (["first", "second"].into_iter())
+            .map(|name| (name, ArgsAdd::parse_from(["add", name])))
+            .collect();
+        let add = |target: &Path, args: &ArgsAdd| -> Result<()> {
+            let name = args.files[0].to_string_lossy();
+            Backup::reader(name.as_bytes(), name.as_ref())
+                .target(target)
+                .config(&config.archive)
+                .at(at)
+                .run()?;
+            Ok(())
+        };
+        let count = |target: &Path| read_backups_at(target, &config, at).unwrap().len();
+
+        // Both jobs check for conflicts at once, and only one gets to write
+        let concurrent = temp.path().join("concurrent");
+        let barrier = std::sync::Barrier::new(2);
+        let result = run_jobs(&jobs, 2, |args| {
+            barrier.wait();
+            add(&concurrent, args)
+        });
+        assert!(result.is_err());
+        assert_eq!(count(&concurrent), 1);
+
+        let in_order = temp.path().join("in-order");
+        let result = run_jobs_in_order(&jobs, |args| add(&in_order, args));
+        assert!(result.is_err());
+        assert_eq!(count(&in_order), 1);
+    }
+
+    #[test]
+    fn delete_older_than_keeps_protected() {
+        let temp = tempfile::tempdir().unwrap();
+        let target = temp.path();
+        let mut config = Config::default();
+        config.delete.keep_tags = vec![String::from("keep")];
+        let now = Timestamp::now();
+        let old = now.sub_span("3y".parse().unwrap());
+        let backup = |name: &str, at: Timestamp, tag: Option<&str>| {
+            let mut builder = (Backup::reader(name.as_bytes(), name).target(target))
+                .config(&config.archive)
+                .at(at);
+            if let Some(tag) = tag {
+                builder = builder.tag(tag);
+            }
+            builder.run().unwrap();
+        };
+        backup("pinned", old, None);
+        backup("tagged", old.add_span("1h".parse().unwrap()), Some("keep"));
+        backup("plain", old.add_span("2h".parse().unwrap()), None);
+        backup("newest", now, None);
+        let mut pins = Pins::default();
+        pins.pin(old, None);
+        pins.save(target, &config.archive).unwrap();
+        let remaining = || -> Vec<String> {
+            let backups = read_backups_at(target, &config, now).unwrap();
+            backups.iter().filter_map(|b| b.label.clone()).collect()
+        };
+        let mut args = ArgsDelete {
+            execute: true,
+            yes: true,
+            older_than: Some("1y".parse().unwrap()),
+            ..Default::default()
+        };
+        delete(target, &config, now, &args).unwrap();
+        assert_eq!(remaining(), ["pinned", "tagged", "newest"]);
+        args.force = true;
+        delete(target, &config, now, &args).unwrap();
+        assert_eq!(remaining(), ["newest"]);
+    }
+
+    #[test]
+    fn delete_select_keeps_tagged() {
+        let temp = tempfile::tempdir().unwrap();
+        let target = temp.path();
+        let mut config = Config::default();
+        config.delete.keep_tags = vec![String::from("keep")];
+        let now = Timestamp::now();
+        let tagged = (Backup::reader(&b"tagged"[..], "tagged").target(target))
+            .config(&config.archive)
+            .at(now.sub_span("1d".parse().unwrap()))
+            .tag("keep")
+            .run()
+            .unwrap();
+        Backup::reader(&b"newest"[..], "newest")
+            .target(target)
+            .config(&config.archive)
+            .at(now)
+            .run()
+            .unwrap();
+        let mut args = ArgsDelete {
+            execute: true,
+            yes: true,
+            select: vec![tagged.display().to_string()],
+            ..Default::default()
+        };
+        delete(target, &config, now, &args).unwrap();
+        assert!(tagged.exists());
+        args.force = true;
+        delete(target, &config, now, &args).unwrap();
+        assert!(!tagged.exists());
+    }
+
+    #[test]
+    fn delete_stale_to_trash_or_for_good() {
+        let temp = tempfile::tempdir().unwrap();
+        let target = temp.path();
+        let mut config = Config::default();
+        config.ranges = rattlebeaver::config::Ranges::default();
+        config.ranges.latest = rattlebeaver::config::Latest::Count(1);
+        let now = Timestamp::now();
+        let archive = config.archive.clone();
+        let backup = |name: &str, ago: &str| {
+            (Backup::reader(name.as_bytes(), name).target(target))
+                .config(&archive)
+                .at(now.sub_span(ago.parse().unwrap()))
+                .run()
+                .unwrap()
+        };
+        let first = backup("first", "2h");
+        let second = backup("second", "1h");
+        backup("newest", "0s");
+        let remaining = |config: &Config| -> Vec<String> {
+            let backups = read_backups_at(target, config, now).unwrap();
+            backups.iter().filter_map(|b| b.label.clone()).collect()
+        };
+        let actions = || -> Vec<Action> {
+            let records = journal::read(target).unwrap();
+            records.iter().map(|record| record.action).collect()
+        };
+        let mut args = ArgsDelete {
+            yes: true,
+            ..Default::default()
+        };
+        delete(target, &config, now, &args).unwrap();
+        assert_eq!(remaining(&config), ["first", "second", "newest"]);
+
+        args.execute = true;
+        config.delete.trash = true;
+        delete(target, &config, now, &args).unwrap();
+        assert_eq!(remaining(&config), ["newest"]);
+        let trashed = trash::expired(target, None, now).unwrap();
+        assert_eq!(trashed.len(), 1);
+        for path in [&first, &second] {
+            assert!(trashed[0].join(path.file_name().unwrap()).exists());
+        }
+        assert_eq!(actions(), [Action::Trash, Action::Trash]);
+
+        let third = backup("third", "3h");
+        config.delete.trash = false;
+        delete(target, &config, now, &args).unwrap();
+        assert!(!third.exists());
+        assert_eq!(remaining(&config), ["newest"]);
+        assert_eq!(actions(), [Action::Trash, Action::Trash, Action::Delete]);
+    }
+
+    #[test]
+    fn delete_shred_overrides_trash() {
+        let temp = tempfile::tempdir().unwrap();
+        let target = temp.path();
+        let mut config = Config::default();
+        config.delete.trash = true;
+        let now = Timestamp::now();
+        let selected = (Backup::reader(&b"secret"[..], "secret").target(target))
+            .config(&config.archive)
+            .at(now.sub_span("1d".parse().unwrap()))
+            .run()
+            .unwrap();
+        (Backup::reader(&b"newest"[..], "newest").target(target))
+            .config(&config.archive)
+            .at(now)
+            .run()
+            .unwrap();
+        let args = ArgsDelete {
+            execute: true,
+            yes: true,
+            shred: true,
+            select: vec![selected.display().to_string()],
+            ..Default::default()
+        };
+        delete(target, &config, now, &args).unwrap();
+        assert!(!selected.exists());
+        assert!(trash::expired(target, None, now).unwrap().is_empty());
+        let records = journal::read(target).unwrap();
+        assert!(matches!(&records[..], [record] if record.action == Action::Shred));
+    }
+
+    /// Backups labeled "old" (10 days ago, tagged "keep"), "mid" (2 days ago) and "new" (now),
+    /// where only the newest is kept as latest and the tagged one for its tag.
+    fn list_fixture(target: &Path) -> (Vec<Entry>, Timestamp) {
+        let mut config = Config::default();
+        config.ranges = rattlebeaver::config::Ranges::default();
+        config.ranges.latest = rattlebeaver::config::Latest::Count(1);
+        config.delete.keep_tags = vec![String::from("keep")];
+        let now = Timestamp::now();
+        for (name, ago, tag) in [("old", "10d", Some("keep")), ("mid", "2d", None)] {
+            let mut builder = (Backup::reader(name.as_bytes(), name).target(target))
+                .config(&config.archive)
+                .at(now.sub_span(ago.parse().unwrap()));
+            if let Some(tag) = tag {
+                builder = builder.tag(tag);
+            }
+            builder.run().unwrap();
+        }
+        (Backup::reader(&b"new"[..], "new").target(target))
+            .config(&config.archive)
+            .at(now)
+            .run()
+            .unwrap();
+        (read_backups_at(target, &config, now).unwrap(), now)
+    }
+
+    /// The labels of `all_backups` listed with the filters of `args`.
+    fn listed(all_backups: &[Entry], args: &[&str], now: Timestamp) -> Vec<String> {
+        let args = ArgsList::parse_from([&["list"], args].concat());
+        let mut backups = all_backups.to_vec();
+        filter_listing(&mut backups, &args, now);
+        backups.iter().filter_map(|b| b.label.clone()).collect()
+    }
+
+    #[test]
+    fn list_date_range_filters() {
+        let temp = tempfile::tempdir().unwrap();
+        let (all_backups, now) = list_fixture(temp.path());
+        let listed = |args: &[&str]| listed(&all_backups, args, now);
+        assert_eq!(listed(&[]), ["old", "mid", "new"]);
+        assert_eq!(listed(&["--since", "5d"]), ["mid", "new"]);
+        assert_eq!(listed(&["--until", "5d"]), ["old"]);
+        assert_eq!(listed(&["--since", "5d", "--until", "1d"]), ["mid"]);
+    }
+
+    #[test]
+    fn list_stale_and_kept_filters() {
+        let temp = tempfile::tempdir().unwrap();
+        let (all_backups, now) = list_fixture(temp.path());
+        assert_eq!(listed(&all_backups, &["--stale"], now), ["mid"]);
+        assert_eq!(listed(&all_backups, &["--kept"], now), ["old", "new"]);
+    }
+
+    #[test]
+    fn list_fulfillment_filters() {
+        let temp = tempfile::tempdir().unwrap();
+        let (all_backups, now) = list_fixture(temp.path());
+        let listed = |args: &[&str]| listed(&all_backups, args, now);
+        assert_eq!(listed(&["--fulfills", "latest"]), ["new"]);
+        assert_eq!(listed(&["--fulfills", "tagged,latest"]), ["old", "new"]);
+        assert!(listed(&["--fulfills", "day"]).is_empty());
+    }
+
+    #[test]
+    fn list_templates() {
+        let temp = tempfile::tempdir().unwrap();
+        let (all_backups, now) = list_fixture(temp.path());
+        let new = all_backups.last().unwrap();
+        let name = new.path.file_name().unwrap().to_string_lossy();
+        let rendered = render_template(r"{label}\t{{{name}}}\n{tags}|{status}", new, now);
+        assert_eq!(rendered.unwrap(), format!("new\t{{{name}}}\n|kept"));
+        let old = render_template("{tags} {relative}", &all_backups[0], now).unwrap();
+        assert_eq!(old, "keep 10 days ago");
+        let mid = render_template(r"{status} {fulfills_short}\\", &all_backups[1], now).unwrap();
+        assert_eq!(mid, r"stale \");
+        assert!(render_template("{unknown}", new, now).is_err());
+        assert!(render_template("{label", new, now).is_err());
+    }
+
+    #[test]
+    fn list_tag_filter() {
+        let temp = tempfile::tempdir().unwrap();
+        let (all_backups, now) = list_fixture(temp.path());
+        assert_eq!(listed(&all_backups, &["--tag", "keep"], now), ["old"]);
+        assert!(listed(&all_backups, &["--tag", "other"], now).is_empty());
+    }
+}
@@ -1,77 +1,343 @@
 use crate::config;
-use crate::entry::{Entry, Fulfillment, read_dir};
+use crate::entry::{
+    Entry, Fulfillment, FulfillmentKind, Skipped, read_dir, read_storage, read_storage_lenient,
+};
+use crate::error::Error;
+use crate::pin::Pins;
+use crate::storage::{LocalStorage, Storage};
 use crate::timestamp::{Range, Timestamp};
 use anyhow::{Context, Result};
-use std::collections::{HashMap, HashSet};
+use chrono::Datelike;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::Path;
 
-pub fn read_backups(target: &Path, config: &config::Config) -> Result<Vec<Entry>> {
-    let mut all_backups = read_dir(target, &config.archive)?;
+/// Fulfillments assigned to each backup timestamp.
+pub type Marks = BTreeMap<Timestamp, Vec<Fulfillment>>;
+
+pub fn read_backups(
+    target: &Path,
+    config: &config::Config,
+) -> std::result::Result<Vec<Entry>, Error> {
+    read_backups_at(target, config, Timestamp::now())
+}
+
+/// Read and mark the backups in `target` as if the current time were `now`.
+pub fn read_backups_at(
+    target: &Path,
+    config: &config::Config,
+    now: Timestamp,
+) -> std::result::Result<Vec<Entry>, Error> {
+    std::fs::metadata(target).map_err(|e| Error::io(target, e))?;
+    let all_backups = read_dir(target, &config.archive)?;
+    mark_dir(target, all_backups, config, now)
+}
+
+/// Read and mark the backups in `target` as with [`read_backups_at`], skipping the files that
+/// can't be read as backups and returning them apart (see [`read_storage_lenient`]).
+pub fn read_backups_lenient(
+    target: &Path,
+    config: &config::Config,
+    now: Timestamp,
+) -> std::result::Result<(Vec<Entry>, Vec<Skipped>), Error> {
+    std::fs::metadata(target).map_err(|e| Error::io(target, e))?;
+    let (all_backups, skipped) = read_storage_lenient(&LocalStorage::new(target), &config.archive)?;
+    Ok((mark_dir(target, all_backups, config, now)?, skipped))
+}
+
+/// Mark `all_backups`, the backups in the directory `target`, by `config` and its pins.
+fn mark_dir(
+    target: &Path,
+    mut all_backups: Vec<Entry>,
+    config: &config::Config,
+    now: Timestamp,
+) -> std::result::Result<Vec<Entry>, Error> {
+    let pins = Pins::load(target).context("load pins")?;
+    mark_entries(&mut all_backups, config, &pins, now)?;
+    if let Some(min_free_space) = config.ranges.min_free_space {
+        ensure_free_space(target, &mut all_backups, min_free_space).context("ensure free space")?;
+    }
+    Ok(all_backups)
+}
+
+/// Read and mark the backups in `storage` as if the current time were `now`.
+///
+/// Unlike [`read_backups_at`], pins and `ranges.min_free_space` do not apply.
+pub fn read_storage_backups(
+    storage: &dyn Storage,
+    config: &config::Config,
+    now: Timestamp,
+) -> std::result::Result<Vec<Entry>, Error> {
+    let mut all_backups = read_storage(storage, &config.archive)?;
+    mark_entries(&mut all_backups, config, &Pins::default(), now)?;
+    Ok(all_backups)
+}
+
+/// Read and mark the backups in `storage` as with [`read_storage_backups`], skipping the files
+/// that can't be read as backups and returning them apart.
+pub fn read_storage_backups_lenient(
+    storage: &dyn Storage,
+    config: &config::Config,
+    now: Timestamp,
+) -> std::result::Result<(Vec<Entry>, Vec<Skipped>), Error> {
+    let (mut all_backups, skipped) = read_storage_lenient(storage, &config.archive)?;
+    mark_entries(&mut all_backups, config, &Pins::default(), now)?;
+    Ok((all_backups, skipped))
+}
+
+/// Set the fulfillments of every backup by the ranges, overrides, pins and tags of `config`.
+fn mark_entries(
+    all_backups: &mut [Entry],
+    config: &config::Config,
+    pins: &Pins,
+    now: Timestamp,
+) -> Result<()> {
+    let keep_tagged: HashSet<Timestamp> = all_backups
+        .iter()
+        .filter(|b| {
+            b.tags()
+                .iter()
+                .any(|tag| config.delete.keep_tags.contains(tag))
+        })
+        .map(|b| b.timestamp)
+        .collect();
+    // Each source label is marked separately by the ranges that apply to it
+    let mut groups: BTreeMap<Option<&str>, (&config::Ranges, Vec<Timestamp>)> = BTreeMap::new();
+    for backup in all_backups.iter() {
+        let (pattern, ranges) = config.ranges.policy(backup.label.as_deref());
+        groups
+            .entry(pattern)
+            .or_insert_with(|| (ranges, Vec::new()))
+            .1
+            .push(backup.timestamp);
+    }
+    let mut marks = Marks::new();
+    for (pattern, (ranges, timestamps)) in groups {
+        let mut group_marks = mark(&timestamps, ranges, now)
+            .with_context(|| format!("override {}", pattern.unwrap_or("(none)")))?;
+        for (timestamp, fulfills) in &mut group_marks {
+            if let Some(pin) = pins.active(*timestamp, now) {
+                fulfills.push(Fulfillment {
+                    kind: FulfillmentKind::Pinned { until: pin.until },
+                    index: 1,
+                    first_or_last: true,
+                });
+            }
+            if keep_tagged.contains(timestamp) {
+                fulfills.push(Fulfillment {
+                    kind: FulfillmentKind::Tagged,
+                    index: 1,
+                    first_or_last: true,
+                });
+            }
+        }
+        marks.extend(group_marks);
+    }
+    // The cap holds for the whole target, whichever ranges marked the backups
+    if let Some(max_total_count) = config.ranges.max_total_count {
+        cap_total_count(&mut marks, max_total_count);
+    }
+    for backup in all_backups {
+        backup.fulfills = marks.remove(&backup.timestamp).unwrap_or_default();
+    }
+    Ok(())
+}
+
+/// Mark which of `timestamps` fulfill the latest and range rules of `config` at `now`.
+///
+/// This is the core retention algorithm, independent of any directory: timestamps with no
+/// fulfillments are stale. Overrides, pins and limits are applied by [`read_backups`].
+pub fn mark(timestamps: &[Timestamp], config: &config::Ranges, now: Timestamp) -> Result<Marks> {
+    let _span = tracing::debug_span!("mark", backups = timestamps.len(), %now).entered();
+    let mut marks: Marks = timestamps.iter().map(|ts| (*ts, Vec::new())).collect();
     // Mark latest
-    all_backups
+    let latest_count = config.latest.count();
+    let latest_cutoff = config.latest.within().map(|span| now.sub_span(span));
+    marks
         .iter_mut()
         .rev()
-        .take(config.ranges.latest)
         .enumerate()
-        .for_each(|(i, b)| {
-            b.fulfills.push(Fulfillment {
-                range: None,
+        .take_while(|(i, (timestamp, _fulfills))| {
+            *i < latest_count || latest_cutoff.is_some_and(|cutoff| **timestamp >= cutoff)
+        })
+        .for_each(|(i, (_timestamp, fulfills))| {
+            fulfills.push(Fulfillment {
+                kind: FulfillmentKind::Latest,
                 index: i + 1,
                 first_or_last: true,
             });
         });
-    let mut all_backups: HashMap<Timestamp, Entry> =
-        all_backups.into_iter().map(|b| (b.timestamp, b)).collect();
-    let now = Timestamp::now();
-    for (range, range_config) in config.ranges.iter_ranges() {
-        mark_range(&mut all_backups, now, range, range_config)
-            .with_context(|| format!("{range:?}"))?;
+    for (range, range_config) in config.iter_ranges() {
+        mark_range(&mut marks, now, range, range_config).with_context(|| format!("{range:?}"))?;
     }
-    let mut final_backups: Vec<Entry> = all_backups.into_values().collect();
-    final_backups.sort();
-    Ok(final_backups)
+    for rule in &config.calendar {
+        mark_calendar(&mut marks, now, rule)
+            .with_context(|| format!("calendar rule every {:?}", rule.every))?;
+    }
+    let kept = marks
+        .values()
+        .filter(|fulfills| !fulfills.is_empty())
+        .count();
+    tracing::debug!(kept, stale = marks.len() - kept, "marked backups");
+    Ok(marks)
 }
 
-fn mark_range(
-    all_backups: &mut HashMap<Timestamp, Entry>,
+/// Unmark the least fulfilled (and then oldest) backups until at most `max_total_count` remain.
+/// Pinned and tagged backups are counted but never unmarked.
+pub fn cap_total_count(marks: &mut Marks, max_total_count: usize) {
+    let is_protected = |fulfills: &[Fulfillment]| fulfills.iter().any(|f| f.kind.is_protected());
+    let protected_count = marks.values().filter(|f| is_protected(f)).count();
+    let max_total_count = max_total_count.saturating_sub(protected_count);
+    let mut kept: Vec<(&Timestamp, &mut Vec<Fulfillment>)> = marks
+        .iter_mut()
+        .filter(|(_timestamp, fulfills)| !fulfills.is_empty() && !is_protected(fulfills))
+        .collect();
+    let Some(excess) = kept.len().checked_sub(max_total_count) else {
+        return;
+    };
+    kept.sort_by(|(a_timestamp, a), (b_timestamp, b)| {
+        a.len().cmp(&b.len()).then(a_timestamp.cmp(b_timestamp))
+    });
+    for (_timestamp, fulfills) in kept.into_iter().take(excess) {
+        fulfills.clear();
+    }
+}
+
+/// Unmark the oldest backups (never the newest) until deleting all stale backups would leave
+/// at least `min_free_space` available on the target filesystem.
+fn ensure_free_space(
+    target: &Path,
+    all_backups: &mut [Entry],
+    min_free_space: config::FreeSpace,
+) -> Result<()> {
+    let stats = fs4::statvfs(target).context("query filesystem stats")?;
+    let required = min_free_space.required_bytes(stats.total_space());
+    let mut projected = stats.available_space();
+    for backup in all_backups.iter().filter(|b| b.fulfills.is_empty()) {
+        projected += backup.metadata().context("get file metadata")?.len();
+    }
+    let expendable = all_backups.len().saturating_sub(1);
+    for backup in all_backups.iter_mut().take(expendable) {
+        if projected >= required {
+            break;
+        }
+        if backup.fulfills.is_empty() || backup.is_protected() {
+            continue;
+        }
+        projected += backup.metadata().context("get file metadata")?.len();
+        backup.fulfills.clear();
+    }
+    Ok(())
+}
+
+/// How a backup was considered by a range.
+#[derive(Debug, Clone)]
+pub struct RangeExplanation {
+    pub range: Range,
+    /// The bucket containing the backup, or None if it is not one of the buckets considered
+    pub bucket: Option<BucketExplanation>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BucketExplanation {
+    /// The index of the bucket in the range (1 is the most recent)
+    pub index: usize,
+    /// The start of the bucket
+    pub floor: Timestamp,
+    /// All backups in the bucket, oldest first
+    pub members: Vec<Timestamp>,
+}
+
+/// Explain how the backup at `timestamp` is considered by every range in `config`.
+pub fn explain_ranges(
+    all_backups: &[Entry],
+    config: &config::Ranges,
+    now: Timestamp,
+    timestamp: Timestamp,
+) -> Result<Vec<RangeExplanation>> {
+    let mut explanations = Vec::new();
+    for (range, range_config) in config.iter_ranges() {
+        let buckets = range_buckets(
+            all_backups.iter().map(|b| b.timestamp),
+            now,
+            range,
+            range_config,
+        )
+        .with_context(|| format!("{range:?}"))?;
+        let floor = timestamp.floor(range);
+        let bucket = buckets
+            .sorted()
+            .into_iter()
+            .enumerate()
+            .find(|(_i, (bucket_timestamp, _members))| *bucket_timestamp == floor)
+            .map(|(i, (floor, members))| BucketExplanation {
+                index: i + 1,
+                floor,
+                members,
+            });
+        explanations.push(RangeExplanation { range, bucket });
+    }
+    Ok(explanations)
+}
+
+fn range_buckets(
+    timestamps: impl Iterator<Item = Timestamp> + Clone,
     now: Timestamp,
     range: Range,
     config: &config::RollingRange,
-) -> Result<()> {
+) -> Result<Buckets> {
     // Create all buckets
+    let every_nth = i64::try_from(config.every_nth.max(1)).context("every_nth too large")?;
+    let is_aligned = |ts: &Timestamp| ts.ordinal(range).rem_euclid(every_nth) == 0;
     let mut bucket_timestamps: Vec<Timestamp> = Vec::new();
     if config.allow_sparse {
-        let mut all_backup_floors: Vec<Timestamp> = all_backups
-            .keys()
+        let mut all_backup_floors: Vec<Timestamp> = timestamps
+            .clone()
             .map(|ts| ts.floor(range))
+            .filter(is_aligned)
             .collect::<HashSet<Timestamp>>()
             .into_iter()
             .collect();
         all_backup_floors.sort();
         bucket_timestamps.extend(all_backup_floors.into_iter().rev().take(config.total));
     } else {
-        for shift_amount in 0..config.total {
-            let shift_amount = i32::try_from(shift_amount)
-                .with_context(|| format!("shifting by {shift_amount}"))?;
+        let mut shift_amount: i32 = 0;
+        while bucket_timestamps.len() < config.total {
             let ts = now.floor(range).shift(range, -shift_amount);
-            bucket_timestamps.push(ts);
+            if is_aligned(&ts) {
+                bucket_timestamps.push(ts);
+            }
+            shift_amount = shift_amount
+                .checked_add(1)
+                .context("too many buckets to shift")?;
         }
     }
     let mut buckets = Buckets::new(bucket_timestamps);
     // Place backups in buckets
-    for backup in all_backups.values() {
-        let backup_floored = backup.timestamp.floor(range);
-        let _found_bucket = buckets.push(backup_floored, backup.timestamp);
+    for timestamp in timestamps {
+        let _found_bucket = buckets.push(timestamp.floor(range), timestamp);
     }
+    Ok(buckets)
+}
+
+#[allow(clippy::collapsible_if)]
+fn mark_range(
+    marks: &mut Marks,
+    now: Timestamp,
+    range: Range,
+    config: &config::RollingRange,
+) -> Result<()> {
+    let _span = tracing::debug_span!("mark_range", ?range).entered();
+    let buckets = range_buckets(marks.keys().copied(), now, range, config)?;
+    tracing::trace!(buckets = buckets.0.len(), "bucketed backups");
     // Sort buckets and take first/last
     for (i, (_bucket_timestamp, backup_timestamps)) in buckets.sorted().iter_mut().enumerate() {
         if config.include_first {
             if let Some(first_backup_timestamp) = backup_timestamps.first_mut() {
-                let Some(original) = all_backups.get_mut(first_backup_timestamp) else {
+                let Some(fulfills) = marks.get_mut(first_backup_timestamp) else {
                     anyhow::bail!("{first_backup_timestamp} not found in original list");
                 };
-                original.fulfills.push(Fulfillment {
-                    range: Some(range),
+                fulfills.push(Fulfillment {
+                    kind: FulfillmentKind::Range(range),
                     index: i + 1,
                     first_or_last: true,
                 });
@@ -79,11 +345,11 @@ fn mark_range(
         }
         if config.include_last {
             if let Some(last_backup_timestamp) = backup_timestamps.last_mut() {
-                let Some(original) = all_backups.get_mut(last_backup_timestamp) else {
+                let Some(fulfills) = marks.get_mut(last_backup_timestamp) else {
                     anyhow::bail!("{last_backup_timestamp} not found in original list");
                 };
-                original.fulfills.push(Fulfillment {
-                    range: Some(range),
+                fulfills.push(Fulfillment {
+                    kind: FulfillmentKind::Range(range),
                     index: i + 1,
                     first_or_last: false,
                 });
@@ -93,6 +359,81 @@ fn mark_range(
     Ok(())
 }
 
+/// Mark the backup closest to each of the last `total` anchors of a calendar rule. A backup is
+/// only considered for an anchor if it is closer to it than to the neighboring anchors.
+fn mark_calendar(marks: &mut Marks, now: Timestamp, rule: &config::CalendarRule) -> Result<()> {
+    let total = i32::try_from(rule.total).context("calendar total too large")?;
+    // Newest first, including the next anchor and one before the oldest for their midpoints
+    let mut anchors = Vec::new();
+    for k in -1..=total.saturating_add(1) {
+        anchors.push(calendar_anchor(rule, now, k)?);
+    }
+    let mut index = 0;
+    for window in anchors.windows(3) {
+        let [newer, anchor, older] = [window[0], window[1], window[2]];
+        if anchor > now {
+            continue;
+        }
+        index += 1;
+        if index > rule.total {
+            break;
+        }
+        let lower = older.0 + (anchor.0 - older.0) / 2;
+        let upper = anchor.0 + (newer.0 - anchor.0) / 2;
+        let closest = marks
+            .range(Timestamp(lower)..Timestamp(upper))
+            .map(|(ts, _fulfills)| *ts)
+            .min_by_key(|ts| (ts.0 - anchor.0).abs());
+        if let Some(closest) = closest {
+            marks.entry(closest).or_default().push(Fulfillment {
+                kind: FulfillmentKind::Calendar(rule.every),
+                index,
+                first_or_last: true,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The anchor of a calendar rule in the period `k` periods before the one containing `now`.
+fn calendar_anchor(rule: &config::CalendarRule, now: Timestamp, k: i32) -> Result<Timestamp> {
+    let day_start = match rule.every {
+        config::CalendarPeriod::Day => now.floor(Range::Day).shift(Range::Day, -k),
+        config::CalendarPeriod::Week => {
+            let weekday = rule.weekday()?;
+            let days_back = i32::try_from(
+                (7 + now.as_ref().weekday().num_days_from_monday()
+                    - weekday.num_days_from_monday())
+                    % 7,
+            )
+            .context("days back")?;
+            now.floor(Range::Day).shift(Range::Day, -days_back - 7 * k)
+        }
+        config::CalendarPeriod::Month => {
+            let month_start = now.floor(Range::Month).shift(Range::Month, -k);
+            day_of_month(month_start, rule.day)?
+        }
+        config::CalendarPeriod::Year => {
+            let month = i32::try_from(rule.month.clamp(1, 12)).context("month")?;
+            let month_start = now
+                .floor(Range::Year)
+                .shift(Range::Year, -k)
+                .shift(Range::Month, month - 1);
+            day_of_month(month_start, rule.day)?
+        }
+    };
+    let time = rule.time()? - chrono::NaiveTime::MIN;
+    Ok(Timestamp(day_start.0 + time))
+}
+
+/// The start of `day` in the month starting at `month_start`, clamped to the last day.
+fn day_of_month(month_start: Timestamp, day: u32) -> Result<Timestamp> {
+    let days_in_month = (month_start.shift(Range::Month, 1).0 - month_start.0).num_days();
+    let day = i64::from(day).clamp(1, days_in_month);
+    let offset = i32::try_from(day - 1).context("day of month")?;
+    Ok(month_start.shift(Range::Day, offset))
+}
+
 struct Buckets(HashMap<Timestamp, Vec<Timestamp>>);
 
 impl Buckets {
@@ -124,3 +465,138 @@ impl Buckets {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> Timestamp {
+        Timestamp::parse_input(s).unwrap()
+    }
+
+    fn ranges(toml: &str) -> config::Ranges {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn mark_latest() {
+        let timestamps = [
+            ts("2025-01-01 10:00"),
+            ts("2025-01-01 11:00"),
+            ts("2025-01-01 12:00"),
+        ];
+        let marks = mark(&timestamps, &ranges("latest = 2"), ts("2025-01-02")).unwrap();
+        assert!(marks[&timestamps[0]].is_empty());
+        assert_eq!(marks[&timestamps[1]][0].index, 2);
+        assert_eq!(marks[&timestamps[2]][0].index, 1);
+    }
+
+    #[test]
+    fn mark_range_first_and_last() {
+        let timestamps = [
+            ts("2025-01-01 10:00"),
+            ts("2025-01-01 11:00"),
+            ts("2025-01-01 12:00"),
+            ts("2025-01-02 10:00"),
+        ];
+        let config = ranges("[days]\ntotal = 2\ninclude_first = false");
+        let marks = mark(&timestamps, &config, ts("2025-01-03")).unwrap();
+        let kept: Vec<Timestamp> = marks
+            .iter()
+            .filter(|(_ts, fulfills)| !fulfills.is_empty())
+            .map(|(ts, _fulfills)| *ts)
+            .collect();
+        assert_eq!(kept, [timestamps[2], timestamps[3]]);
+        assert_eq!(
+            marks[&timestamps[2]][0],
+            Fulfillment {
+                kind: FulfillmentKind::Range(Range::Day),
+                index: 2,
+                first_or_last: false,
+            }
+        );
+    }
+
+    #[test]
+    fn mark_range_not_sparse() {
+        let timestamps = [ts("2025-01-01 10:00"), ts("2025-01-05 10:00")];
+        let config = ranges("[days]\ntotal = 3\nallow_sparse = false");
+        let marks = mark(&timestamps, &config, ts("2025-01-06 12:00")).unwrap();
+        assert!(marks[&timestamps[0]].is_empty());
+        assert_eq!(marks[&timestamps[1]].len(), 2);
+    }
+
+    #[test]
+    fn mark_every_nth() {
+        let timestamps: Vec<Timestamp> = (1..=9)
+            .map(|day| ts(&format!("2025-01-0{day} 10:00")))
+            .collect();
+        let config = ranges("[days]\ntotal = 2\nevery_nth = 3\ninclude_first = false");
+        let marks = mark(&timestamps, &config, ts("2025-01-10")).unwrap();
+        let kept: Vec<Timestamp> = marks
+            .iter()
+            .filter(|(_ts, fulfills)| !fulfills.is_empty())
+            .map(|(ts, _fulfills)| *ts)
+            .collect();
+        assert_eq!(kept.len(), 2);
+        let gap = kept[1].ordinal(Range::Day) - kept[0].ordinal(Range::Day);
+        assert_eq!(gap, 3);
+        assert!(kept.iter().all(|ts| ts.ordinal(Range::Day) % 3 == 0));
+    }
+
+    #[test]
+    fn mark_calendar_closest() {
+        let timestamps = [
+            ts("2025-01-31 22:00"),
+            ts("2025-02-01 03:00"),
+            ts("2025-02-15 00:00"),
+            ts("2025-03-02 12:00"),
+        ];
+        let config = ranges("[[calendar]]\nevery = \"month\"\ntotal = 3");
+        let marks = mark(&timestamps, &config, ts("2025-03-10")).unwrap();
+        let kind = FulfillmentKind::Calendar(config::CalendarPeriod::Month);
+        let index_of = |t: &Timestamp| marks[t].iter().find(|f| f.kind == kind).map(|f| f.index);
+        assert_eq!(index_of(&timestamps[0]), Some(2));
+        assert_eq!(index_of(&timestamps[1]), None);
+        assert_eq!(index_of(&timestamps[2]), None);
+        assert_eq!(index_of(&timestamps[3]), Some(1));
+    }
+
+    #[test]
+    fn cap_total() {
+        let timestamps = [
+            ts("2025-01-01 10:00"),
+            ts("2025-01-01 11:00"),
+            ts("2025-01-01 12:00"),
+        ];
+        let config = ranges("latest = 3\n[days]\ntotal = 1\ninclude_first = false");
+        let mut marks = mark(&timestamps, &config, ts("2025-01-02")).unwrap();
+        cap_total_count(&mut marks, 1);
+        assert!(marks[&timestamps[0]].is_empty());
+        assert!(marks[&timestamps[1]].is_empty());
+        assert_eq!(marks[&timestamps[2]].len(), 2);
+    }
+
+    #[test]
+    fn cap_total_across_overrides() {
+        let temp = tempfile::tempdir().unwrap();
+        for name in [
+            "2025-01-01_10-00-00.db-main",
+            "2025-01-01_11-00-00.dotfiles",
+            "2025-01-01_12-00-00.db-main",
+            "2025-01-01_13-00-00.dotfiles",
+        ] {
+            std::fs::write(temp.path().join(format!(".rattlebeaver.{name}")), name).unwrap();
+        }
+        let config = config::Config {
+            ranges: ranges("latest = 2\nmax_total_count = 3\n[overrides.\"db-*\"]\nlatest = 2"),
+            ..Default::default()
+        };
+        let backups = read_backups_at(temp.path(), &config, ts("2025-01-02")).unwrap();
+        let kept: Vec<&str> = (backups.iter())
+            .filter(|b| !b.fulfills.is_empty())
+            .filter_map(|b| b.label.as_deref())
+            .collect();
+        assert_eq!(kept, ["dotfiles", "db-main", "dotfiles"]);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::config;
+use crate::entry::Entry;
+use crate::permissions::FilePermissions;
+use crate::state::{load_toml, save_toml};
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+const CACHE_FILE_NAME: &str = "rattlebeaver.cache.toml";
+
+/// Cached number of files in each archive, keyed by file name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MemberCache {
+    #[serde(default)]
+    pub members: BTreeMap<String, CachedCount>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CachedCount {
+    /// File size when counted, to detect a changed file
+    pub size: u64,
+    /// Modification time (seconds since the epoch) when counted
+    pub modified: u64,
+    pub count: usize,
+}
+
+impl MemberCache {
+    pub fn load(target: &Path) -> Result<Self> {
+        load_toml(&cache_path(target)).context("load cache file")
+    }
+
+    /// Save the cache in `target`, with the file permissions of `config`.
+    pub fn save(&self, target: &Path, config: &config::Archive) -> Result<()> {
+        save_toml(
+            &cache_path(target),
+            self,
+            &FilePermissions::from_config(config)?,
+        )
+        .context("save cache file")
+    }
+
+    /// Get the number of files in the archive of `backup`, counting it if not cached.
+    pub fn count(&mut self, backup: &Entry) -> Result<usize> {
+        let metadata = backup.metadata().context("get file metadata")?;
+        let size = metadata.len();
+        let modified = metadata
+            .modified()
+            .context("get modification time")?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let key = backup
+            .path
+            .file_name()
+            .context("no file name")?
+            .to_string_lossy()
+            .into_owned();
+        if let Some(cached) = self.members.get(&key)
+            && cached.size == size
+            && cached.modified == modified
+        {
+            return Ok(cached.count);
+        }
+        let count = count_members(&backup.path)?;
+        self.members.insert(
+            key,
+            CachedCount {
+                size,
+                modified,
+                count,
+            },
+        );
+        Ok(count)
+    }
+}
+
+/// Count the number of members of all `backups`, using and updating the cache in `target`
+/// (saved with the file permissions of `config`).
+pub fn member_counts(
+    target: &Path,
+    backups: &[Entry],
+    config: &config::Archive,
+) -> Result<HashMap<PathBuf, usize>> {
+    let mut cache = MemberCache::load(target)?;
+    let mut counts = HashMap::new();
+    for backup in backups {
+        let count = cache
+            .count(backup)
+            .with_context(|| format!("count members of {}", backup.path.display()))?;
+        counts.insert(backup.path.clone(), count);
+    }
+    // Forget backups that no longer exist
+    cache
+        .members
+        .retain(|name, _cached| target.join(name).exists());
+    cache.save(target, config)?;
+    Ok(counts)
+}
+
+/// Count the files in a `.tar.gz` archive by decompressing it. Other files count as one.
+fn count_members(path: &Path) -> Result<usize> {
+    if !path.to_string_lossy().ends_with(".tar.gz") {
+        return Ok(1);
+    }
+    let file = std::fs::File::open(path).context("open archive")?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let mut count = 0;
+    for entry in archive.entries().context("read archive")? {
+        let entry = entry.context("read archive entry")?;
+        if entry.header().entry_type().is_file() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn cache_path(target: &Path) -> PathBuf {
+    target.join(CACHE_FILE_NAME)
+}
@@ -0,0 +1,176 @@
+use crate::backup::ArchiveMode;
+use crate::config;
+use crate::permissions::FilePermissions;
+use crate::snapshot::SnapshotKind;
+use crate::storage::Storage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Appended to the file name of a backup to get the name of its sidecar metadata file.
+pub const SIDECAR_SUFFIX: &str = ".meta.toml";
+
+/// Metadata recorded when a backup was created, stored in a sidecar file next to it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BackupMeta {
+    /// Absolute path of the backed up file or directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    /// Host the backup was made on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// User that made the backup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Version of rattlebeaver that made the backup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// How single files were archived
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_mode: Option<ArchiveMode>,
+    /// Seconds it took to create the backup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// Total size in bytes of the backed up files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uncompressed_size: Option<u64>,
+    /// Size in bytes of the backup file as written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub written_size: Option<u64>,
+    /// Number of files backed up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_count: Option<u64>,
+    /// Hex-encoded SHA-256 digest of the backup file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Hex-encoded Ed25519 signature of the checksum, made with `archive.signing_key`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Tags for filtering and for exempting backups from deletion
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Free-text note on why the backup was made
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Shell command whose output was backed up, for backups made with `add --command`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Kind of filesystem snapshot the source was archived from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<SnapshotKind>,
+}
+
+impl BackupMeta {
+    /// Load the sidecar of the backup at `backup_path`, if it has one.
+    pub fn load(backup_path: &Path) -> Result<Option<Self>> {
+        let path = sidecar_path(backup_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let s =
+            std::fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+        let meta = toml::from_str(&s).with_context(|| format!("decode {}", path.display()))?;
+        Ok(Some(meta))
+    }
+
+    /// Read the sidecar of the backup named `backup_name` from `storage`.
+    pub fn read_from(storage: &dyn Storage, backup_name: &str) -> Result<Self> {
+        let name = format!("{backup_name}{SIDECAR_SUFFIX}");
+        let mut s = String::new();
+        storage
+            .read(&name)?
+            .read_to_string(&mut s)
+            .with_context(|| format!("read {name}"))?;
+        toml::from_str(&s).with_context(|| format!("decode {name}"))
+    }
+
+    /// Write the sidecar of the backup named `backup_name` to `storage`.
+    pub fn write_to(&self, storage: &dyn Storage, backup_name: &str) -> Result<()> {
+        let name = format!("{backup_name}{SIDECAR_SUFFIX}");
+        let s = toml::to_string_pretty(self).context("encode backup metadata")?;
+        let mut writer = storage.write(&name)?;
+        writer
+            .write_all(s.as_bytes())
+            .with_context(|| format!("write {name}"))?;
+        writer.finish()
+    }
+
+    /// Written size relative to the uncompressed size, if both were recorded.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn compression_ratio(&self) -> Option<f64> {
+        let uncompressed = self.uncompressed_size.filter(|&size| size > 0)?;
+        Some(self.written_size? as f64 / uncompressed as f64)
+    }
+
+    /// Save the sidecar of the backup at `backup_path`, with the file permissions of `config`.
+    pub fn save(&self, backup_path: &Path, config: &config::Archive) -> Result<()> {
+        let path = sidecar_path(backup_path);
+        let s = toml::to_string_pretty(self).context("encode backup metadata")?;
+        FilePermissions::from_config(config)?.write(&path, s)
+    }
+}
+
+/// The name of this host, if it can be determined.
+#[must_use]
+pub fn hostname() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+}
+
+/// The name of the current user, if it can be determined.
+#[must_use]
+pub fn user() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+/// Total size in bytes of the files at `path`, recursively.
+pub fn total_size(path: &Path) -> Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)
+        .with_context(|| format!("get metadata of {}", path.display()))?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for child in path.read_dir().context("read directory")? {
+        size += total_size(&child.context("read file from dir")?.path())?;
+    }
+    Ok(size)
+}
+
+/// Number of files at `path`, recursively.
+pub fn file_count(path: &Path) -> Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)
+        .with_context(|| format!("get metadata of {}", path.display()))?;
+    if !metadata.is_dir() {
+        return Ok(1);
+    }
+    let mut count = 0;
+    for child in path.read_dir().context("read directory")? {
+        count += file_count(&child.context("read file from dir")?.path())?;
+    }
+    Ok(count)
+}
+
+/// The path of the sidecar metadata file of the backup at `backup_path`.
+#[must_use]
+pub fn sidecar_path(backup_path: &Path) -> PathBuf {
+    let mut path = backup_path.as_os_str().to_owned();
+    path.push(SIDECAR_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Check if `path` is a sidecar metadata file rather than a backup.
+#[must_use]
+pub fn is_sidecar(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().ends_with(SIDECAR_SUFFIX))
+}
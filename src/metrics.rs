@@ -0,0 +1,158 @@
+//! Metrics of the last run and the backups in a target, in the Prometheus text format read by
+//! the textfile collector of node_exporter.
+
+use crate::entry::Entry;
+use crate::timestamp::Timestamp;
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::path::Path;
+
+const LAST_SUCCESS: &str = "rattlebeaver_last_success_timestamp_seconds";
+
+/// The outcome of a run and the state of the target after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub target: String,
+    pub success: bool,
+    /// When a run last succeeded, in seconds since the epoch
+    pub last_success: Option<i64>,
+    /// Seconds the run took
+    pub duration: f64,
+    pub count: usize,
+    pub total_size: u64,
+    pub stale_count: usize,
+    /// Timestamp of the newest backup, in seconds since the epoch
+    pub newest: Option<i64>,
+}
+
+impl Metrics {
+    /// The metrics of a run on `target` that ended at `now`, given the `backups` in it after the
+    /// run, which should already be marked.
+    #[must_use]
+    pub fn collect(
+        target: &str,
+        backups: &[Entry],
+        success: bool,
+        now: Timestamp,
+        duration: std::time::Duration,
+    ) -> Self {
+        Self {
+            target: target.to_owned(),
+            success,
+            last_success: success.then(|| now.as_ref().timestamp()),
+            duration: duration.as_secs_f64(),
+            count: backups.len(),
+            total_size: backups.iter().map(Entry::size).sum(),
+            stale_count: backups.iter().filter(|b| b.fulfills.is_empty()).count(),
+            newest: backups.last().map(|b| b.timestamp.as_ref().timestamp()),
+        }
+    }
+
+    /// The metrics in the Prometheus text format.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let labels = format!("{{target=\"{}\"}}", escape_label(&self.target));
+        let mut text = String::new();
+        let mut gauge = |name: &str, help: &str, value: &dyn std::fmt::Display| {
+            let _ = write!(
+                text,
+                "# HELP {name} {help}\n# TYPE {name} gauge\n{name}{labels} {value}\n"
+            );
+        };
+        gauge(
+            "rattlebeaver_last_run_success",
+            "Whether the last run succeeded",
+            &u8::from(self.success),
+        );
+        if let Some(last_success) = self.last_success {
+            gauge(
+                LAST_SUCCESS,
+                "When a run last succeeded, in seconds since the epoch",
+                &last_success,
+            );
+        }
+        gauge(
+            "rattlebeaver_last_run_duration_seconds",
+            "How long the last run took",
+            &self.duration,
+        );
+        gauge(
+            "rattlebeaver_backups",
+            "Number of backups in the target",
+            &self.count,
+        );
+        gauge(
+            "rattlebeaver_backups_bytes",
+            "Total size of the backups in the target",
+            &self.total_size,
+        );
+        gauge(
+            "rattlebeaver_stale_backups",
+            "Number of stale backups in the target",
+            &self.stale_count,
+        );
+        if let Some(newest) = self.newest {
+            gauge(
+                "rattlebeaver_newest_backup_timestamp_seconds",
+                "Timestamp of the newest backup, in seconds since the epoch",
+                &newest,
+            );
+        }
+        text
+    }
+
+    /// Write the metrics to the file at `path`, replacing it at once so the collector never
+    /// reads half a file. If this run failed, the time of the last success is kept from the
+    /// metrics already in the file.
+    pub fn write(mut self, path: &Path) -> Result<()> {
+        if self.last_success.is_none() {
+            self.last_success = std::fs::read_to_string(path)
+                .ok()
+                .and_then(|previous| parse_last_success(&previous));
+        }
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        // Left with the default permissions, as the collector runs as another user
+        std::fs::write(&temp_path, self.render())
+            .with_context(|| format!("write {}", Path::new(&temp_path).display()))?;
+        std::fs::rename(&temp_path, path).with_context(|| format!("write {}", path.display()))
+    }
+}
+
+fn parse_last_success(metrics: &str) -> Option<i64> {
+    metrics
+        .lines()
+        .find(|line| line.starts_with(LAST_SUCCESS))
+        .and_then(|line| line.rsplit(' ').next()?.parse().ok())
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_and_parse() {
+        let metrics = Metrics {
+            target: "/mnt/\"backups\"".to_owned(),
+            success: true,
+            last_success: Some(1_700_000_000),
+            duration: 1.5,
+            count: 3,
+            total_size: 1024,
+            stale_count: 1,
+            newest: None,
+        };
+        let text = metrics.render();
+        assert!(text.contains("rattlebeaver_backups{target=\"/mnt/\\\"backups\\\"\"} 3\n"));
+        assert!(text.contains("rattlebeaver_last_run_duration_seconds{"));
+        assert!(!text.contains("rattlebeaver_newest_backup_timestamp_seconds"));
+        assert_eq!(parse_last_success(&text), Some(1_700_000_000));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::config;
+use crate::entry::Entry;
+use crate::index::Index;
+use crate::meta;
+use crate::storage::{LocalStorage, Storage};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A backup to rename from the old naming scheme to the new one.
+#[derive(Debug, Clone)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    from_name: String,
+    to_name: String,
+}
+
+/// Plan renaming every backup in `target` named by the `from` scheme to the `to` scheme.
+///
+/// Files already named by the new scheme are left alone. Fails without renaming anything if
+/// a new name could not be parsed back, or would collide with another file.
+pub fn plan(target: &Path, from: &config::Archive, to: &config::Archive) -> Result<Vec<Rename>> {
+    let mut renames = Vec::new();
+    let mut new_names: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let storage = LocalStorage::new(target);
+    let names = storage.list()?;
+    for name in &names {
+        let path = storage.locate(name);
+        if meta::is_sidecar(&path) || !path.is_file() {
+            continue;
+        }
+        let Ok(Some(backup)) = Entry::from_path(path.clone(), from) else {
+            continue;
+        };
+        if let Ok(Some(_)) = Entry::from_path(path.clone(), to) {
+            continue;
+        }
+        let rest = name[from.prefix.len()..]
+            .split_once('.')
+            .map(|(_timestamp, rest)| rest);
+        let mut new_name = format!(
+            "{}{}",
+            to.prefix,
+            backup.timestamp.as_ref().format(&to.timestamp_format)
+        );
+        if let Some(rest) = rest {
+            new_name.push('.');
+            new_name.push_str(rest);
+        }
+        let new_path = storage.locate(&new_name);
+        Entry::from_path(new_path.clone(), to)
+            .with_context(|| format!("new name {new_name:?} is not valid"))?
+            .with_context(|| format!("new name {new_name:?} does not match the prefix"))?;
+        if names.contains(&new_name) {
+            anyhow::bail!(
+                "cannot rename {} to {}: file exists",
+                path.display(),
+                new_path.display()
+            );
+        }
+        if let Some(other) = new_names.insert(new_path.clone(), path.clone()) {
+            anyhow::bail!(
+                "both {} and {} would be renamed to {}",
+                other.display(),
+                path.display(),
+                new_path.display()
+            );
+        }
+        renames.push(Rename {
+            from: path,
+            to: new_path,
+            from_name: name.clone(),
+            to_name: new_name,
+        });
+    }
+    renames.sort_by(|a, b| a.from.cmp(&b.from));
+    Ok(renames)
+}
+
+/// Rename the backups and their sidecar files, then rebuild the index for the new scheme.
+pub fn execute(target: &Path, renames: &[Rename], to: &config::Archive) -> Result<()> {
+    let storage = LocalStorage::new(target).with_fsync(to.fsync);
+    let names = storage.list()?;
+    for rename in renames {
+        storage.rename(&rename.from_name, &rename.to_name)?;
+        let sidecar = format!("{}{}", rename.from_name, meta::SIDECAR_SUFFIX);
+        if names.contains(&sidecar) {
+            let new_sidecar = format!("{}{}", rename.to_name, meta::SIDECAR_SUFFIX);
+            storage.rename(&sidecar, &new_sidecar)?;
+        }
+    }
+    Index::build(target, to)
+        .context("rebuild index")?
+        .save(target, to)
+        .context("save index")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renames_backups_to_the_new_scheme() {
+        let temp = tempfile::tempdir().unwrap();
+        let target = temp.path();
+        let to = crate::Config::default().archive;
+        let from = config::Archive {
+            prefix: String::from("backup-"),
+            ..to.clone()
+        };
+        let old = "backup-2025-01-01_00-00-00.notes.tar.gz";
+        let new = ".rattlebeaver.2025-01-01_00-00-00.notes.tar.gz";
+        std::fs::write(target.join(old), "backup").unwrap();
+        std::fs::write(target.join(format!("{old}{}", meta::SIDECAR_SUFFIX)), "").unwrap();
+        std::fs::write(target.join("unrelated.txt"), "").unwrap();
+
+        let renames = plan(target, &from, &to).unwrap();
+        assert_eq!(renames.len(), 1);
+        assert_eq!(renames[0].from, target.join(old));
+        assert_eq!(renames[0].to, target.join(new));
+        execute(target, &renames, &to).unwrap();
+        assert!(!target.join(old).exists());
+        assert!(target.join(new).exists());
+        assert!(
+            target
+                .join(format!("{new}{}", meta::SIDECAR_SUFFIX))
+                .exists()
+        );
+        let index = Index::load_saved(target).unwrap().unwrap();
+        let timestamp = "2025-01-01_00-00-00".parse().unwrap();
+        assert_eq!(index.get(target, timestamp), Some(target.join(new)));
+        assert!(plan(target, &from, &to).unwrap().is_empty());
+
+        // A new name taken by another file fails the whole plan
+        std::fs::write(target.join(old), "backup").unwrap();
+        assert!(plan(target, &from, &to).is_err());
+    }
+}
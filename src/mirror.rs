@@ -0,0 +1,36 @@
+use crate::meta;
+use crate::storage::{self, RemoteOptions, Storage};
+use crate::sync;
+use anyhow::{Context, Result};
+
+/// How copying a backup to one mirror went.
+#[derive(Debug)]
+pub struct MirrorStatus {
+    pub mirror: String,
+    pub result: Result<()>,
+}
+
+/// Copy the backup `name` and its sidecar from `storage` to each of the `mirrors`.
+///
+/// A failing mirror does not stop the others.
+pub fn mirror_backup(
+    storage: &dyn Storage,
+    name: &str,
+    mirrors: &[String],
+    options: &RemoteOptions,
+) -> Vec<MirrorStatus> {
+    let sidecar = format!("{name}{}", meta::SIDECAR_SUFFIX);
+    let has_sidecar = storage.list().is_ok_and(|names| names.contains(&sidecar));
+    mirrors
+        .iter()
+        .map(|mirror| {
+            let result = storage::open(mirror, options)
+                .with_context(|| format!("open mirror {mirror}"))
+                .and_then(|target| sync::copy_backup(storage, &*target, name, has_sidecar));
+            MirrorStatus {
+                mirror: mirror.clone(),
+                result,
+            }
+        })
+        .collect()
+}
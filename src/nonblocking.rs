@@ -0,0 +1,171 @@
+//! Async versions of creating, reading, restoring and deleting backups, for services running on
+//! tokio (e.g. a web admin panel). The work runs on tokio's blocking thread pool, where the
+//! target is opened too, so it doesn't hold up the runtime's other tasks.
+//!
+//! Targets are local directories or remote storage URLs, opened with the retry policy and
+//! per-target settings of the config.
+
+use crate::backup::BackupBuilder;
+use crate::config::Config;
+use crate::encryption::Decryptor;
+use crate::entry::Entry;
+use crate::error::Error;
+use crate::index::Index;
+use crate::permissions::FilePermissions;
+use crate::storage::{self, RemoteOptions, Storage};
+use crate::timestamp::Timestamp;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Write the backup made by `backup` into `target`, with the archive settings of `config`,
+/// returning the backup's path.
+///
+/// ```no_run
+/// # async fn example(config: std::sync::Arc<rattlebeaver::Config>) -> Result<(), rattlebeaver::Error> {
+/// use rattlebeaver::{Backup, nonblocking};
+/// let path = nonblocking::create_backup("/mnt/backups", config, || {
+///     Backup::builder("/home/me/Documents").tag("docs")
+/// })
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn create_backup(
+    target: impl Into<String>,
+    config: Arc<Config>,
+    backup: impl FnOnce() -> BackupBuilder<'static> + Send + 'static,
+) -> Result<PathBuf, Error> {
+    let target = target.into();
+    spawn(move || {
+        let backup = backup().config(&config.archive);
+        if storage::is_remote(&target) {
+            let storage = open(&target, &config)?;
+            backup.storage(&*storage).run()
+        } else {
+            backup.target(&target).run()
+        }
+    })
+    .await
+}
+
+/// Read and mark the backups in `target`, as with [`crate::read_backups`] for local targets
+/// and [`crate::read_storage_backups`] for remote ones.
+pub async fn read_backups(
+    target: impl Into<String>,
+    config: Arc<Config>,
+) -> Result<Vec<Entry>, Error> {
+    let target = target.into();
+    spawn(move || {
+        if storage::is_remote(&target) {
+            let storage = open(&target, &config)?;
+            crate::read_storage_backups(&*storage, &config, Timestamp::now())
+        } else {
+            crate::read_backups(Path::new(&target), &config)
+        }
+    })
+    .await
+}
+
+/// Extract `backup` from `target` into the directory `destination`, as with
+/// [`crate::restore::restore`].
+pub async fn restore(
+    target: impl Into<String>,
+    config: Arc<Config>,
+    backup: Entry,
+    destination: PathBuf,
+    decryptor: Decryptor,
+) -> Result<(), Error> {
+    let target = target.into();
+    spawn(move || {
+        let storage = open(&target, &config)?;
+        crate::restore::restore(&*storage, &backup, &destination, &decryptor, None)
+            .context("restore backup")?;
+        Ok(())
+    })
+    .await
+}
+
+/// Delete `backup` and its sidecar from `target`, removing it from the index of a local target.
+///
+/// Unlike `rattlebeaver delete`, the backup is deleted outright even if it is pinned: it isn't
+/// moved to the trash or shredded, nor recorded in the journal, and only the index is locked,
+/// not the target.
+pub async fn delete_backup(
+    target: impl Into<String>,
+    config: Arc<Config>,
+    backup: Entry,
+) -> Result<(), Error> {
+    let target = target.into();
+    spawn(move || {
+        let storage = open(&target, &config)?;
+        crate::sync::delete_backup(&*storage, &backup, None).context("delete backup")?;
+        let _index_lock = (!storage::is_remote(&target))
+            .then(|| crate::lock::lock_index(Path::new(&target)))
+            .transpose()?;
+        if !storage::is_remote(&target)
+            && let Some(mut index) = Index::load_saved(Path::new(&target))?
+        {
+            index.remove(backup.timestamp);
+            index
+                .save(Path::new(&target), &config.archive)
+                .context("save index")?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+fn open(target: &str, config: &Config) -> anyhow::Result<Box<dyn Storage>> {
+    let options = RemoteOptions {
+        retry: config.retry.clone(),
+        targets: config.targets.clone(),
+        permissions: FilePermissions::from_config(&config.archive)?,
+        fsync: config.archive.fsync,
+        ..RemoteOptions::default()
+    };
+    storage::open(target, &options)
+}
+
+/// Run `f` on the blocking thread pool, passing on its panics.
+async fn spawn<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => match e.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(e) => Err(anyhow::Error::from(e).context("run blocking task").into()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backup;
+
+    #[test]
+    fn create_read_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("notes.txt");
+        let target = dir.path().join("target").display().to_string();
+        std::fs::write(&source, "hello").unwrap();
+        let config = Arc::new(Config::default());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let result = runtime.block_on(async {
+            let path = create_backup(target.clone(), config.clone(), move || {
+                Backup::builder(source)
+            })
+            .await?;
+            let backups = read_backups(target.clone(), config.clone()).await?;
+            assert_eq!(backups.len(), 1);
+            assert_eq!(backups[0].path, path);
+            delete_backup(target.clone(), config.clone(), backups[0].clone()).await?;
+            read_backups(target.clone(), config.clone()).await
+        });
+        assert!(result.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,30 @@
+//! Desktop notifications of finished runs, shown through the notification service of the
+//! desktop (D-Bus on Linux and the BSDs, Notification Center on macOS, toasts on Windows).
+
+use anyhow::Result;
+
+/// Show a desktop notification titled `title`, marked urgent if `failed`.
+#[cfg(feature = "notifications")]
+pub fn show(title: &str, body: &str, failed: bool) -> Result<()> {
+    use anyhow::Context;
+
+    let mut notification = notify_rust::Notification::new();
+    notification
+        .appname("rattlebeaver")
+        .summary(title)
+        .body(body);
+    // Only the freedesktop notification spec has urgency
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if failed {
+        notification.urgency(notify_rust::Urgency::Critical);
+    }
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    let _ = failed;
+    notification.show().context("show notification")?;
+    Ok(())
+}
+
+#[cfg(not(feature = "notifications"))]
+pub fn show(_title: &str, _body: &str, _failed: bool) -> Result<()> {
+    anyhow::bail!("built without the notifications feature")
+}
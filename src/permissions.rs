@@ -0,0 +1,118 @@
+//! Permissions and ownership of the files rattlebeaver creates.
+//!
+//! Backups, sidecars, the index, the journal and the other state files are all created through
+//! here, so they get the mode (and owner and group) of the archive settings instead of whatever
+//! the umask allows.
+
+use crate::config;
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// The mode of created files unless the config says otherwise.
+pub const DEFAULT_FILE_MODE: u32 = 0o600;
+
+/// Permissions and ownership given to created files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilePermissions {
+    pub mode: u32,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Default for FilePermissions {
+    fn default() -> Self {
+        Self {
+            mode: DEFAULT_FILE_MODE,
+            uid: None,
+            gid: None,
+        }
+    }
+}
+
+impl FilePermissions {
+    /// The permissions in the archive settings, looking up the owner and group by name.
+    pub fn from_config(archive: &config::Archive) -> Result<Self> {
+        let mode = archive.file_mode.unwrap_or(DEFAULT_FILE_MODE);
+        anyhow::ensure!(
+            mode <= 0o7777,
+            "archive.file_mode must be a mode like 0o600 (got {mode:#o})"
+        );
+        let uid = (archive.owner.as_deref())
+            .map(|owner| lookup_id("/etc/passwd", owner).context("look up archive.owner"))
+            .transpose()?;
+        let gid = (archive.group.as_deref())
+            .map(|group| lookup_id("/etc/group", group).context("look up archive.group"))
+            .transpose()?;
+        Ok(Self { mode, uid, gid })
+    }
+
+    /// Give the open `file` these permissions and ownership.
+    #[cfg(unix)]
+    fn apply(&self, file: &File) -> std::io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(self.mode))?;
+        if self.uid.is_some() || self.gid.is_some() {
+            std::os::unix::fs::fchown(file, self.uid, self.gid)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply(&self, _file: &File) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn open(&self, path: &Path, options: &mut OpenOptions) -> Result<File> {
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(options, self.mode);
+        let file = options
+            .open(path)
+            .with_context(|| format!("create {}", path.display()))?;
+        self.apply(&file)
+            .with_context(|| format!("set permissions of {}", path.display()))?;
+        Ok(file)
+    }
+
+    /// Create or truncate the file at `path`, with these permissions before anything is written
+    /// to it.
+    pub fn create(&self, path: &Path) -> Result<File> {
+        self.open(
+            path,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )
+    }
+
+    /// Create the file at `path` with these permissions, failing if it already exists.
+    pub fn create_new(&self, path: &Path) -> Result<File> {
+        self.open(path, OpenOptions::new().write(true).create_new(true))
+    }
+
+    /// Open the file at `path` for appending, creating it with these permissions.
+    pub fn append(&self, path: &Path) -> Result<File> {
+        self.open(path, OpenOptions::new().create(true).append(true))
+    }
+
+    /// Write `contents` to the file at `path`, like [`std::fs::write`] but with these
+    /// permissions.
+    pub fn write(&self, path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+        self.create(path)?
+            .write_all(contents.as_ref())
+            .with_context(|| format!("write {}", path.display()))
+    }
+}
+
+/// The id of the user or group `name` in the database at `path`, or `name` itself if numeric.
+fn lookup_id(path: &str, name: &str) -> Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    let database = std::fs::read_to_string(path).with_context(|| format!("read {path}"))?;
+    database
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&name))
+        .and_then(|fields| fields.get(2)?.parse().ok())
+        .with_context(|| format!("{name:?} not found in {path}"))
+}
@@ -0,0 +1,60 @@
+use crate::config;
+use crate::permissions::FilePermissions;
+use crate::state::{load_toml, save_toml};
+use crate::timestamp::Timestamp;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const PINS_FILE_NAME: &str = "rattlebeaver.pins.toml";
+
+/// Backups protected from deletion, keyed by timestamp.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Pins {
+    #[serde(default)]
+    pub pins: BTreeMap<String, Pin>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Pin {
+    /// Protect only until this time, after which normal retention resumes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<Timestamp>,
+}
+
+impl Pins {
+    pub fn load(target: &Path) -> Result<Self> {
+        load_toml(&pins_path(target)).context("load pins file")
+    }
+
+    /// Save the pins in `target`, with the file permissions of `config`.
+    pub fn save(&self, target: &Path, config: &config::Archive) -> Result<()> {
+        save_toml(
+            &pins_path(target),
+            self,
+            &FilePermissions::from_config(config)?,
+        )
+        .context("save pins file")
+    }
+
+    pub fn pin(&mut self, timestamp: Timestamp, until: Option<Timestamp>) {
+        self.pins.insert(timestamp.to_string(), Pin { until });
+    }
+
+    pub fn unpin(&mut self, timestamp: Timestamp) -> bool {
+        self.pins.remove(&timestamp.to_string()).is_some()
+    }
+
+    /// Get the pin protecting `timestamp`, unless it is missing or expired at `now`.
+    #[must_use]
+    pub fn active(&self, timestamp: Timestamp, now: Timestamp) -> Option<&Pin> {
+        self.pins
+            .get(&timestamp.to_string())
+            .filter(|pin| pin.until.is_none_or(|until| now < until))
+    }
+}
+
+fn pins_path(target: &Path) -> PathBuf {
+    target.join(PINS_FILE_NAME)
+}
@@ -0,0 +1,172 @@
+//! Progress of backups and restores, reported to a callback as they go, so a long backup of a
+//! large directory can show how far it has got, and cancellation of backups between reads.
+
+use crate::event::{Event, OnEvent};
+use std::cell::Cell;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How far a backup or restore has got.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes of the source read so far, or of the backup when restoring
+    pub bytes: u64,
+    /// Bytes to read in all, if known
+    pub total_bytes: Option<u64>,
+    /// Files archived so far
+    pub files: u64,
+}
+
+/// Called with the progress so far, every time more of the source is read.
+pub type OnProgress<'a> = &'a dyn Fn(Progress);
+
+/// Cancels the backups given a clone of it when [`CancelToken::cancel`] is called, e.g. from
+/// another thread or a signal handler.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The progress of one backup or restore, reported to its callback if it has one, failing its
+/// reads once it is cancelled.
+pub(crate) struct Tracker<'a> {
+    progress: Cell<Progress>,
+    on_progress: Option<OnProgress<'a>>,
+    cancel: Option<&'a CancelToken>,
+    on_event: Option<OnEvent<'a>>,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(
+        on_progress: Option<OnProgress<'a>>,
+        cancel: Option<&'a CancelToken>,
+        total_bytes: Option<u64>,
+    ) -> Self {
+        Self {
+            progress: Cell::new(Progress {
+                total_bytes,
+                ..Progress::default()
+            }),
+            on_progress,
+            cancel,
+            on_event: None,
+        }
+    }
+
+    /// Also report each file archived to `on_event`.
+    pub(crate) fn events(mut self, on_event: Option<OnEvent<'a>>) -> Self {
+        self.on_event = on_event;
+        self
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.is_some_and(CancelToken::is_cancelled)
+    }
+
+    pub(crate) fn check_cancelled(&self) -> std::io::Result<()> {
+        if self.is_cancelled() {
+            return Err(std::io::Error::other("cancelled"));
+        }
+        Ok(())
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Progress)) {
+        let mut progress = self.progress.get();
+        update(&mut progress);
+        self.progress.set(progress);
+        if let Some(on_progress) = self.on_progress {
+            on_progress(progress);
+        }
+    }
+
+    /// Count the file at `path` relative to the source, `size` bytes, as archived.
+    pub(crate) fn add_file(&self, path: &Path, size: u64) {
+        tracing::trace!(path = %path.display(), size, "file done");
+        self.update(|progress| progress.files += 1);
+        if let Some(on_event) = self.on_event {
+            on_event(&Event::FileArchived {
+                path: path.to_owned(),
+                size,
+            });
+        }
+    }
+
+    /// `reader`, counting the bytes read through it.
+    pub(crate) fn reader<R: Read>(&self, reader: R) -> TrackedReader<'_, 'a, R> {
+        TrackedReader {
+            inner: reader,
+            tracker: self,
+        }
+    }
+}
+
+pub(crate) struct TrackedReader<'t, 'a, R> {
+    inner: R,
+    tracker: &'t Tracker<'a>,
+}
+
+impl<R: Read> Read for TrackedReader<'_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.tracker.check_cancelled()?;
+        let read = self.inner.read(buf)?;
+        if read > 0 {
+            self.tracker
+                .update(|progress| progress.bytes += read as u64);
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_reads() {
+        let reports = std::cell::RefCell::new(Vec::new());
+        let on_progress = |progress: Progress| reports.borrow_mut().push(progress);
+        let tracker = Tracker::new(Some(&on_progress), None, Some(5));
+        let mut read = Vec::new();
+        tracker
+            .reader(&b"hello"[..])
+            .read_to_end(&mut read)
+            .unwrap();
+        tracker.add_file(Path::new("hello"), 5);
+        let last = *reports.borrow().last().unwrap();
+        assert_eq!(
+            last,
+            Progress {
+                bytes: 5,
+                total_bytes: Some(5),
+                files: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn cancels_reads() {
+        let cancel = CancelToken::new();
+        let tracker = Tracker::new(None, Some(&cancel), None);
+        let mut reader = tracker.reader(&b"hello"[..]);
+        let mut buf = [0; 2];
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        cancel.clone().cancel();
+        assert!(reader.read(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use crate::checksum::HashingWriter;
+use crate::encryption::{self, Decryptor, Encryption, Encryptor};
+use crate::entry::Entry;
+use crate::meta::BackupMeta;
+use crate::signature::SigningKey;
+use crate::storage::Storage;
+use anyhow::{Context, Result};
+use std::io::Read;
+
+/// Where the new ciphertext is written before it replaces the backup. It does not start with
+/// the archive prefix, so a leftover from an interrupted run is not mistaken for a backup.
+const REKEY_NAME: &str = "rattlebeaver.rekey.tmp";
+
+/// Decrypt `backup` with `decryptor` and encrypt it again with `encryption`, returning its new
+/// name, which differs from the old one if the cipher changed.
+///
+/// The new ciphertext is written under a temporary name and read back to check that it was
+/// stored intact, and decrypted with `verifier` if given to check that it holds the same
+/// plaintext. Only then does it replace the backup, and the checksum and size in the sidecar are
+/// updated to match. The old signature no longer applies, so it is replaced with one made with
+/// `signing_key`, or removed without one.
+pub fn rekey(
+    storage: &dyn Storage,
+    backup: &Entry,
+    decryptor: &Decryptor,
+    encryption: Encryption,
+    verifier: Option<&Decryptor>,
+    signing_key: Option<&SigningKey>,
+) -> Result<String> {
+    let name = backup.name()?;
+    anyhow::ensure!(encryption::is_encrypted(&name), "{name} is not encrypted");
+    anyhow::ensure!(encryption.is_enabled(), "no recipients to encrypt to");
+    encryption.check()?;
+    let new_name = format!(
+        "{}{}",
+        encryption::strip_suffix(&name),
+        encryption.cipher.suffix()
+    );
+
+    let written = write_verified(storage, &name, &new_name, decryptor, encryption, verifier);
+    let (checksum, written_size) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = storage.delete(REKEY_NAME);
+            return Err(e);
+        }
+    };
+
+    storage.rename(REKEY_NAME, &new_name)?;
+    if backup.meta.is_some() {
+        let mut meta = BackupMeta::read_from(storage, &name)?;
+        meta.signature = signing_key.map(|key| key.sign(&checksum));
+        meta.checksum = Some(checksum);
+        meta.written_size = Some(written_size);
+        meta.write_to(storage, &new_name)?;
+    }
+    if new_name != name {
+        crate::sync::delete_backup(storage, backup, None)?;
+    }
+    Ok(new_name)
+}
+
+/// Write the backup `name` encrypted anew to [`REKEY_NAME`] and verify it, returning the
+/// checksum and size of the new ciphertext.
+fn write_verified(
+    storage: &dyn Storage,
+    name: &str,
+    new_name: &str,
+    decryptor: &Decryptor,
+    encryption: Encryption,
+    verifier: Option<&Decryptor>,
+) -> Result<(String, u64)> {
+    let mut plaintext = decryptor.decrypt(storage.read(name)?, name)?;
+    let upload = HashingWriter::new(storage.write(REKEY_NAME)?);
+    let mut writer = HashingWriter::new(Encryptor::new(upload, encryption, new_name)?);
+    std::io::copy(&mut plaintext, &mut writer).with_context(|| format!("decrypt {name}"))?;
+    drop(plaintext);
+    let (encryptor, plain_checksum) = writer.finish()?;
+    let upload = encryptor.finish()?;
+    let written_size = upload.written();
+    let (upload, checksum) = upload.finish().context("write re-encrypted backup")?;
+    upload.finish()?;
+
+    let stored_checksum = digest(storage.read(REKEY_NAME)?).context("read back")?;
+    anyhow::ensure!(
+        stored_checksum == checksum,
+        "re-encrypted backup was not stored intact (read {stored_checksum}, wrote {checksum})"
+    );
+    if let Some(verifier) = verifier {
+        // Named after the backup, so the verifier knows the cipher
+        let reader = verifier.decrypt(storage.read(REKEY_NAME)?, new_name)?;
+        let decrypted = digest(reader).context("decrypt re-encrypted backup")?;
+        anyhow::ensure!(
+            decrypted == plain_checksum,
+            "re-encrypted backup does not decrypt to the original"
+        );
+    }
+    Ok((checksum, written_size))
+}
+
+/// The hex-encoded SHA-256 digest of everything read from `reader`.
+fn digest(mut reader: impl Read) -> Result<String> {
+    let mut hasher = HashingWriter::new(std::io::sink());
+    std::io::copy(&mut reader, &mut hasher)?;
+    let (_sink, digest) = hasher.finish()?;
+    Ok(digest)
+}
@@ -0,0 +1,67 @@
+use crate::encryption::{self, Decryptor};
+use crate::entry::Entry;
+use crate::progress::{OnProgress, Tracker};
+use crate::storage::Storage;
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::io::Read;
+use std::path::Path;
+
+/// Extract `backup` from `storage` into the directory `destination`, which must be empty or
+/// missing. Encrypted backups are decrypted with `decryptor`, and the progress of reading the
+/// backup is reported to `on_progress`.
+///
+/// Archives are unpacked, other backups are copied under the file name of their source.
+pub fn restore(
+    storage: &dyn Storage,
+    backup: &Entry,
+    destination: &Path,
+    decryptor: &Decryptor,
+    on_progress: Option<OnProgress>,
+) -> Result<()> {
+    if destination.exists() {
+        let mut files = destination
+            .read_dir()
+            .with_context(|| format!("read {}", destination.display()))?;
+        anyhow::ensure!(
+            files.next().is_none(),
+            "{} is not empty",
+            destination.display()
+        );
+    }
+    let name = backup.name()?;
+    let written_size = backup.meta.as_ref().and_then(|meta| meta.written_size);
+    let tracker = Tracker::new(on_progress, None, written_size);
+    let reader: Box<dyn Read + '_> = Box::new(tracker.reader(storage.read(&name)?));
+    let plain_name = encryption::strip_suffix(&name);
+    let mut reader: Box<dyn Read + '_> = if encryption::is_encrypted(&name) {
+        Box::new(decryptor.decrypt(reader, &name)?)
+    } else {
+        reader
+    };
+    std::fs::create_dir_all(destination)
+        .with_context(|| format!("create {}", destination.display()))?;
+    if plain_name.ends_with(".tar.gz") {
+        let mut archive = tar::Archive::new(GzDecoder::new(reader));
+        archive.unpack(destination).context("extract archive")?;
+        // Decryption only fails at the end if the ciphertext was tampered with
+        let mut rest = archive.into_inner().into_inner();
+        std::io::copy(&mut rest, &mut std::io::sink()).context("read archive")?;
+        return Ok(());
+    }
+    let source_name = backup
+        .meta
+        .as_ref()
+        .and_then(|meta| meta.source.as_ref())
+        .and_then(|source| source.file_name())
+        .map_or_else(
+            || plain_name.to_owned(),
+            |n| n.to_string_lossy().into_owned(),
+        );
+    let path = destination.join(source_name);
+    let mut file =
+        std::fs::File::create(&path).with_context(|| format!("create {}", path.display()))?;
+    let size = std::io::copy(&mut reader, &mut file).context("restore file")?;
+    tracker.add_file(&path, size);
+    Ok(())
+}
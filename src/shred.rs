@@ -0,0 +1,118 @@
+use crate::entry::Entry;
+use crate::event::{Event, OnEvent};
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::Path;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Overwrite the contents of the file at `path` with zeros, flush it to disk and remove it,
+/// flushing its removal to disk too.
+///
+/// This makes the data harder to recover, but gives no guarantee on copy-on-write or journaling
+/// filesystems, or on SSDs that remap blocks.
+pub fn shred(path: &Path) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!("open {}", path.display()))?;
+    let mut remaining = file.metadata().context("get file metadata")?.len();
+    let zeros = vec![0; CHUNK_SIZE];
+    while remaining > 0 {
+        let chunk = usize::try_from(remaining).map_or(CHUNK_SIZE, |r| r.min(CHUNK_SIZE));
+        file.write_all(&zeros[..chunk]).context("overwrite file")?;
+        remaining -= chunk as u64;
+    }
+    file.sync_all().context("sync file")?;
+    drop(file);
+    std::fs::remove_file(path).with_context(|| format!("delete {}", path.display()))?;
+    crate::fsync::parent(path, true)
+}
+
+/// Shred a backup and its sidecar (see [`shred`]), reporting it to `on_event`.
+pub fn shred_backup(backup: &Entry, on_event: Option<OnEvent>) -> Result<()> {
+    for path in backup.files() {
+        shred(&path)?;
+    }
+    if let Some(on_event) = on_event {
+        on_event(&Event::Deleted {
+            path: backup.path.clone(),
+            timestamp: backup.timestamp,
+        });
+    }
+    Ok(())
+}
+
+/// Shred every file in the directory at `path` (recursively) and remove it. Symlinks are removed
+/// without following them, so nothing outside the directory is overwritten.
+pub fn shred_dir(path: &Path) -> Result<()> {
+    for child in path.read_dir().context("read directory")? {
+        let child = child.context("read file from dir")?.path();
+        let file_type = (child.symlink_metadata())
+            .with_context(|| format!("get metadata of {}", child.display()))?
+            .file_type();
+        if file_type.is_symlink() {
+            std::fs::remove_file(&child).with_context(|| format!("delete {}", child.display()))?;
+        } else if file_type.is_dir() {
+            shred_dir(&child)?;
+        } else {
+            shred(&child)?;
+        }
+    }
+    std::fs::remove_dir(path).with_context(|| format!("delete {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Event;
+    use crate::timestamp::Timestamp;
+    use std::cell::RefCell;
+
+    #[test]
+    fn shreds_backups_and_dirs() {
+        let temp = tempfile::tempdir().unwrap();
+        let config = crate::Config::default().archive;
+        let path = temp.path().join(".rattlebeaver.2025-01-01_00-00-00.notes");
+        std::fs::write(&path, "secret").unwrap();
+        let backup = Entry::from_path(path.clone(), &config).unwrap().unwrap();
+        let events = RefCell::new(Vec::new());
+        let on_event = |event: &Event| events.borrow_mut().push(event.clone());
+        shred_backup(&backup, Some(&on_event)).unwrap();
+        assert!(!path.exists());
+        let at: Timestamp = "2025-01-01_00-00-00".parse().unwrap();
+        assert_eq!(
+            events.into_inner(),
+            [Event::Deleted {
+                path,
+                timestamp: at
+            }]
+        );
+
+        let dir = temp.path().join("trash");
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("a"), "secret").unwrap();
+        std::fs::write(dir.join("nested/b"), "secret").unwrap();
+        shred_dir(&dir).unwrap();
+        assert!(!dir.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn leaves_symlink_targets_alone() {
+        let temp = tempfile::tempdir().unwrap();
+        let outside = temp.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("keep"), "precious").unwrap();
+        let dir = temp.path().join("trash");
+        std::fs::create_dir(&dir).unwrap();
+        std::os::unix::fs::symlink(&outside, dir.join("dir-link")).unwrap();
+        std::os::unix::fs::symlink(outside.join("keep"), dir.join("file-link")).unwrap();
+        shred_dir(&dir).unwrap();
+        assert!(!dir.exists());
+        assert_eq!(
+            std::fs::read_to_string(outside.join("keep")).unwrap(),
+            "precious"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Ed25519 signatures of backups, kept in their sidecars.
+//!
+//! A signature covers the SHA-256 digest of the backup file as stored, so checking it only
+//! takes re-hashing the file, and whoever can change both a backup and its sidecar still can't
+//! make them pass without the secret key.
+
+use crate::permissions::FilePermissions;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Prepended to the checksum before signing, so a signature can't be passed off as one made for
+/// another purpose with the same key.
+#[cfg(feature = "signatures")]
+const CONTEXT: &str = "rattlebeaver backup signature v1";
+
+#[cfg(feature = "signatures")]
+fn message(checksum: &str) -> String {
+    format!("{CONTEXT}\n{checksum}\n")
+}
+
+fn supported() -> Result<()> {
+    anyhow::ensure!(
+        cfg!(feature = "signatures"),
+        "built without the signatures feature"
+    );
+    Ok(())
+}
+
+/// A secret key that signs new backups.
+pub struct SigningKey {
+    #[cfg(feature = "signatures")]
+    pair: ring::signature::Ed25519KeyPair,
+}
+
+impl SigningKey {
+    /// Load the secret key from the file at `path`, as written by [`generate`].
+    pub fn load(path: &Path) -> Result<Self> {
+        supported()?;
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("read signing key {}", path.display()))?;
+        let pkcs8 = hex::decode(contents.trim())
+            .with_context(|| format!("decode signing key {}", path.display()))?;
+        Self::from_pkcs8(&pkcs8).with_context(|| format!("load signing key {}", path.display()))
+    }
+
+    #[cfg(feature = "signatures")]
+    fn from_pkcs8(pkcs8: &[u8]) -> Result<Self> {
+        let pair = ring::signature::Ed25519KeyPair::from_pkcs8(pkcs8)
+            .map_err(|e| anyhow::anyhow!("not an Ed25519 key: {e}"))?;
+        Ok(Self { pair })
+    }
+
+    #[cfg(not(feature = "signatures"))]
+    fn from_pkcs8(_pkcs8: &[u8]) -> Result<Self> {
+        supported()?;
+        unreachable!("checked above")
+    }
+
+    /// The hex-encoded signature of a backup whose digest is `checksum`.
+    #[cfg(feature = "signatures")]
+    #[must_use]
+    pub fn sign(&self, checksum: &str) -> String {
+        hex::encode(self.pair.sign(message(checksum).as_bytes()))
+    }
+
+    #[cfg(not(feature = "signatures"))]
+    #[must_use]
+    pub fn sign(&self, _checksum: &str) -> String {
+        unreachable!("signing keys can't be loaded without the signatures feature")
+    }
+
+    /// The hex-encoded public key that checks the signatures of this key.
+    #[cfg(feature = "signatures")]
+    #[must_use]
+    pub fn public_key(&self) -> String {
+        use ring::signature::KeyPair;
+        hex::encode(self.pair.public_key())
+    }
+
+    #[cfg(not(feature = "signatures"))]
+    #[must_use]
+    pub fn public_key(&self) -> String {
+        unreachable!("signing keys can't be loaded without the signatures feature")
+    }
+}
+
+/// Generate a new secret key and write it to a file at `path`, which must not exist yet,
+/// returning the key.
+pub fn generate(path: &Path) -> Result<SigningKey> {
+    supported()?;
+    anyhow::ensure!(!path.exists(), "{} already exists", path.display());
+    let pkcs8 = generate_pkcs8()?;
+    // A secret, so only ever readable by this user
+    FilePermissions::default().write(path, format!("{}\n", hex::encode(&pkcs8)))?;
+    SigningKey::from_pkcs8(&pkcs8)
+}
+
+#[cfg(feature = "signatures")]
+fn generate_pkcs8() -> Result<Vec<u8>> {
+    let document =
+        ring::signature::Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new())
+            .map_err(|_| anyhow::anyhow!("generate signing key"))?;
+    Ok(document.as_ref().to_vec())
+}
+
+#[cfg(not(feature = "signatures"))]
+fn generate_pkcs8() -> Result<Vec<u8>> {
+    supported()?;
+    unreachable!("checked above")
+}
+
+/// A public key that checks the signatures of backups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey([u8; 32]);
+
+impl PublicKey {
+    /// Parse a hex-encoded public key, as printed by `keygen`.
+    pub fn parse(key: &str) -> Result<Self> {
+        supported()?;
+        let bytes = hex::decode(key.trim()).context("decode public key")?;
+        let bytes = bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("public key must be 32 bytes"))?;
+        Ok(Self(bytes))
+    }
+
+    /// Check that the hex-encoded `signature` was made by the secret key of this public key for
+    /// a backup whose digest is `checksum`.
+    #[cfg(feature = "signatures")]
+    #[must_use]
+    pub fn verify(&self, checksum: &str, signature: &str) -> bool {
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, &self.0)
+            .verify(message(checksum).as_bytes(), &signature)
+            .is_ok()
+    }
+
+    #[cfg(not(feature = "signatures"))]
+    #[must_use]
+    pub fn verify(&self, _checksum: &str, _signature: &str) -> bool {
+        unreachable!("public keys can't be parsed without the signatures feature")
+    }
+}
+
+#[cfg(all(test, feature = "signatures"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() {
+        let key = SigningKey::from_pkcs8(&generate_pkcs8().unwrap()).unwrap();
+        let public_key = PublicKey::parse(&key.public_key()).unwrap();
+        let signature = key.sign("abc123");
+        assert!(public_key.verify("abc123", &signature));
+        assert!(!public_key.verify("abc124", &signature));
+        assert!(!public_key.verify("abc123", &signature[2..]));
+        let other = SigningKey::from_pkcs8(&generate_pkcs8().unwrap()).unwrap();
+        assert!(
+            !PublicKey::parse(&other.public_key())
+                .unwrap()
+                .verify("abc123", &signature)
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use crate::config;
+use crate::mark::{cap_total_count, mark};
+use crate::timestamp::{Span, Timestamp};
+use anyhow::{Context, Result};
+
+const MAX_STEPS: usize = 1_000_000;
+
+/// The number of backups kept at a point in the simulated timeline.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub time: Timestamp,
+    pub count: usize,
+}
+
+/// Simulate adding a backup `every` span for a `duration` starting at `start`, deleting stale
+/// backups after each one is added.
+///
+/// Pins and free space limits depend on the target directory and are not simulated.
+pub fn simulate(
+    ranges: &config::Ranges,
+    start: Timestamp,
+    every: Span,
+    duration: Span,
+) -> Result<Vec<Sample>> {
+    let end = start.add_span(duration);
+    let mut timestamps: Vec<Timestamp> = Vec::new();
+    let mut samples = Vec::new();
+    let mut now = start;
+    while now <= end {
+        if samples.len() >= MAX_STEPS {
+            anyhow::bail!("simulation exceeds {MAX_STEPS} backups");
+        }
+        timestamps.push(now);
+        let mut marks = mark(&timestamps, ranges, now).context("mark backups")?;
+        if let Some(max_total_count) = ranges.max_total_count {
+            cap_total_count(&mut marks, max_total_count);
+        }
+        timestamps.retain(|ts| marks.get(ts).is_some_and(|f| !f.is_empty()));
+        samples.push(Sample {
+            time: now,
+            count: timestamps.len(),
+        });
+        let next = now.add_span(every);
+        if next <= now {
+            anyhow::bail!("simulation interval must be positive");
+        }
+        now = next;
+    }
+    Ok(samples)
+}
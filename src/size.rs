@@ -0,0 +1,56 @@
+use anyhow::{Context, Result};
+
+const UNITS: [(&str, u64); 5] = [
+    ("T", 1 << 40),
+    ("G", 1 << 30),
+    ("M", 1 << 20),
+    ("K", 1 << 10),
+    ("B", 1),
+];
+
+/// A size in bytes, parsed from strings such as `512`, `10K`, `1.5G` or `2GB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteSize(pub u64);
+
+impl std::str::FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let upper = s.to_uppercase();
+        let without_suffix = upper
+            .strip_suffix("IB")
+            .or_else(|| upper.strip_suffix('B').filter(|rest| !rest.is_empty()))
+            .unwrap_or(&upper);
+        let (number, multiplier) = UNITS
+            .iter()
+            .find_map(|(unit, multiplier)| {
+                without_suffix
+                    .strip_suffix(unit)
+                    .map(|number| (number, *multiplier))
+            })
+            .unwrap_or((without_suffix, 1));
+        let number: f64 = number
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid size: {s:?}"))?;
+        if number < 0.0 {
+            anyhow::bail!("negative size: {s:?}");
+        }
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        Ok(Self((number * multiplier as f64) as u64))
+    }
+}
+
+impl std::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (unit, multiplier) in UNITS {
+            if self.0 >= multiplier && multiplier > 1 {
+                #[allow(clippy::cast_precision_loss)]
+                let value = self.0 as f64 / multiplier as f64;
+                return write!(f, "{value:.1}{unit}");
+            }
+        }
+        write!(f, "{}B", self.0)
+    }
+}
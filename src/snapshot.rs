@@ -0,0 +1,302 @@
+//! Filesystem snapshots of sources, so files that change while being archived (e.g. live
+//! databases and VM images) are backed up as they were at a single instant.
+//!
+//! Taking and removing snapshots runs the `btrfs` or LVM tools, which usually takes root, or
+//! PowerShell for Volume Shadow Copies on Windows, which takes an elevated prompt.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf, Prefix};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Space reserved for changes to the origin while an LVM snapshot exists. The snapshot becomes
+/// invalid, and the backup fails, if more than this changes while archiving.
+const LVM_SNAPSHOT_EXTENTS: &str = "10%ORIGIN";
+
+/// Distinguishes the snapshots taken by concurrent backups in this process.
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// How to snapshot a source before archiving it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum SnapshotKind {
+    /// Read-only snapshot of the btrfs subvolume holding the source
+    Btrfs,
+    /// Snapshot of the LVM logical volume holding the source, mounted read-only
+    Lvm,
+    /// Volume Shadow Copy of the Windows drive holding the source
+    Vss,
+}
+
+/// A snapshot of a source, removed when dropped (ignoring errors) or by [`Snapshot::remove`].
+#[derive(Debug)]
+pub struct Snapshot {
+    /// Where the source is found in the snapshot
+    path: PathBuf,
+    cleanup: Option<Cleanup>,
+}
+
+#[derive(Debug)]
+enum Cleanup {
+    Btrfs {
+        subvolume: PathBuf,
+    },
+    Lvm {
+        /// The snapshot volume, as `vg/lv`
+        volume: String,
+        mountpoint: PathBuf,
+    },
+    Vss {
+        /// The shadow copy's id, a GUID
+        id: String,
+    },
+}
+
+impl Snapshot {
+    /// Snapshot the filesystem holding `source`.
+    pub fn create(kind: SnapshotKind, source: &Path) -> Result<Self> {
+        let source = std::fs::canonicalize(source)
+            .with_context(|| format!("resolve {}", source.display()))?;
+        let id = format!(
+            "rattlebeaver-snapshot-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        match kind {
+            SnapshotKind::Btrfs => Self::btrfs(&source, &id),
+            SnapshotKind::Lvm => Self::lvm(&source, &id),
+            SnapshotKind::Vss => Self::vss(&source),
+        }
+    }
+
+    /// Where the source is found in the snapshot.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Remove the snapshot.
+    pub fn remove(mut self) -> Result<()> {
+        match self.cleanup.take() {
+            Some(cleanup) => cleanup.run(),
+            None => Ok(()),
+        }
+    }
+
+    fn btrfs(source: &Path, id: &str) -> Result<Self> {
+        let root = subvolume_root(source)?;
+        // Snapshots must be on the same filesystem, and a snapshot doesn't hold itself
+        let subvolume = root.join(format!(".{id}"));
+        run(Command::new("btrfs")
+            .args(["subvolume", "snapshot", "-r"])
+            .arg(&root)
+            .arg(&subvolume))?;
+        let relative = source.strip_prefix(&root).unwrap_or(Path::new(""));
+        Ok(Self {
+            path: subvolume.join(relative),
+            cleanup: Some(Cleanup::Btrfs { subvolume }),
+        })
+    }
+
+    fn lvm(source: &Path, id: &str) -> Result<Self> {
+        let mount = run(Command::new("findmnt")
+            .args(["--noheadings", "--raw", "--output", "SOURCE,TARGET,FSTYPE"])
+            .arg("--target")
+            .arg(source))?;
+        let fields: Vec<String> = mount.split_whitespace().map(unescape_findmnt).collect();
+        let [device, target, fstype] = fields.as_slice() else {
+            anyhow::bail!("unexpected findmnt output: {mount}");
+        };
+        let origin = run(Command::new("lvs")
+            .args(["--noheadings", "--options", "vg_name,lv_name"])
+            .arg(device))
+        .with_context(|| format!("{device} is not an LVM logical volume"))?;
+        let [vg, lv] = origin.split_whitespace().collect::<Vec<_>>()[..] else {
+            anyhow::bail!("unexpected lvs output: {origin}");
+        };
+        let name = format!("{lv}-{id}");
+        run(Command::new("lvcreate")
+            .args(["--snapshot", "--extents", LVM_SNAPSHOT_EXTENTS, "--name"])
+            .arg(&name)
+            .arg(format!("{vg}/{lv}")))?;
+        let volume = format!("{vg}/{name}");
+        let mountpoint = std::env::temp_dir().join(id);
+        // From here on, dropping the snapshot cleans up whatever got done
+        let snapshot = Self {
+            path: mountpoint.join(source.strip_prefix(target).unwrap_or(Path::new(""))),
+            cleanup: Some(Cleanup::Lvm {
+                volume,
+                mountpoint: mountpoint.clone(),
+            }),
+        };
+        std::fs::create_dir(&mountpoint)
+            .with_context(|| format!("create {}", mountpoint.display()))?;
+        // XFS refuses to mount a filesystem with the same UUID as one already mounted
+        let options = if fstype == "xfs" { "ro,nouuid" } else { "ro" };
+        run(Command::new("mount")
+            .args(["-o", options])
+            .arg(format!("/dev/{vg}/{name}"))
+            .arg(&mountpoint))?;
+        Ok(snapshot)
+    }
+
+    fn vss(source: &Path) -> Result<Self> {
+        anyhow::ensure!(
+            cfg!(windows),
+            "Volume Shadow Copies are only supported on Windows"
+        );
+        let (volume, relative) = split_drive(source)?;
+        let created = powershell(&format!(
+            "$result = (Get-WmiObject -List Win32_ShadowCopy).Create('{volume}', 'ClientAccessible')
+             if ($result.ReturnValue -ne 0) {{
+                 Write-Error \"creating the shadow copy failed ($($result.ReturnValue))\"
+                 exit 1
+             }}
+             $shadow = Get-WmiObject Win32_ShadowCopy -Filter \"ID='$($result.ShadowID)'\"
+             Write-Output $shadow.ID $shadow.DeviceObject"
+        ))?;
+        let [id, device] = created.lines().map(str::trim).collect::<Vec<_>>()[..] else {
+            anyhow::bail!("unexpected output creating shadow copy: {created}");
+        };
+        Ok(Self {
+            // The root directory of the device is only found with the trailing separator
+            path: Path::new(&format!("{device}\\")).join(relative),
+            cleanup: Some(Cleanup::Vss { id: id.to_owned() }),
+        })
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if let Some(cleanup) = self.cleanup.take() {
+            let _ = cleanup.run();
+        }
+    }
+}
+
+impl Cleanup {
+    fn run(self) -> Result<()> {
+        match self {
+            Self::Btrfs { subvolume } => run(Command::new("btrfs")
+                .args(["subvolume", "delete"])
+                .arg(&subvolume))
+            .map(drop),
+            // Each step is tried even if an earlier one fails, as the snapshot may be half made
+            Self::Lvm { volume, mountpoint } => {
+                let unmounted = run(Command::new("umount").arg(&mountpoint)).map(drop);
+                let removed_dir = std::fs::remove_dir(&mountpoint)
+                    .with_context(|| format!("remove {}", mountpoint.display()));
+                let removed = run(Command::new("lvremove").args(["--yes", &volume])).map(drop);
+                unmounted.and(removed_dir).and(removed)
+            }
+            Self::Vss { id } => powershell(&format!(
+                "Get-WmiObject Win32_ShadowCopy -Filter \"ID='{id}'\" | ForEach-Object {{ $_.Delete() }}"
+            ))
+            .map(drop),
+        }
+    }
+}
+
+/// The root of the btrfs subvolume holding `path`, the closest ancestor that is a subvolume.
+#[cfg(target_os = "linux")]
+fn subvolume_root(path: &Path) -> Result<PathBuf> {
+    use nix::sys::statfs::{BTRFS_SUPER_MAGIC, statfs};
+    use std::os::unix::fs::MetadataExt;
+    /// The inode number of the root directory of every btrfs subvolume
+    const SUBVOLUME_ROOT_INODE: u64 = 256;
+    // Other filesystems have directories with that inode number too
+    let filesystem =
+        statfs(path).with_context(|| format!("get filesystem of {}", path.display()))?;
+    anyhow::ensure!(
+        filesystem.filesystem_type() == BTRFS_SUPER_MAGIC,
+        "{} is not on a btrfs filesystem",
+        path.display()
+    );
+    for ancestor in path.ancestors() {
+        let metadata = std::fs::metadata(ancestor)
+            .with_context(|| format!("get metadata of {}", ancestor.display()))?;
+        if metadata.is_dir() && metadata.ino() == SUBVOLUME_ROOT_INODE {
+            return Ok(ancestor.to_owned());
+        }
+    }
+    anyhow::bail!("{} is not on a btrfs subvolume", path.display())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn subvolume_root(_path: &Path) -> Result<PathBuf> {
+    anyhow::bail!("btrfs snapshots are only supported on Linux")
+}
+
+/// The root of the drive holding the absolute `path` (e.g. `C:\\`), and the path relative to it.
+fn split_drive(path: &Path) -> Result<(String, PathBuf)> {
+    let mut components = path.components();
+    let Some(Component::Prefix(prefix)) = components.next() else {
+        anyhow::bail!("{} is not on a drive", path.display());
+    };
+    let (Prefix::Disk(drive) | Prefix::VerbatimDisk(drive)) = prefix.kind() else {
+        anyhow::bail!("{} is not on a drive", path.display());
+    };
+    let relative = components.filter(|c| *c != Component::RootDir).collect();
+    Ok((format!("{}:\\", char::from(drive)), relative))
+}
+
+/// Run the PowerShell `script`, returning its output.
+fn powershell(script: &str) -> Result<String> {
+    run(Command::new("powershell").args(["-NoProfile", "-NonInteractive", "-Command", script]))
+}
+
+/// Run `command`, returning its output or failing with its error output.
+fn run(command: &mut Command) -> Result<String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("run {program}"))?;
+    anyhow::ensure!(
+        output.status.success(),
+        "{program} failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    String::from_utf8(output.stdout).with_context(|| format!("decode output of {program}"))
+}
+
+/// Undo the `\x20`-style escapes of `findmnt --raw`.
+fn unescape_findmnt(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'\\' && bytes.get(i + 1) == Some(&b'x'))
+            .then(|| bytes.get(i + 2..i + 4))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                unescaped.push(byte);
+                i += 4;
+            }
+            None => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn findmnt_escapes() {
+        assert_eq!(unescape_findmnt("/mnt/my\\x20disk"), "/mnt/my disk");
+        assert_eq!(
+            unescape_findmnt("/dev/mapper/vg-root"),
+            "/dev/mapper/vg-root"
+        );
+        assert_eq!(unescape_findmnt("trailing\\x2"), "trailing\\x2");
+    }
+}
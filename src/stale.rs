@@ -0,0 +1,126 @@
+use crate::entry::Entry;
+use crate::event::{Event, OnEvent};
+use crate::storage::Storage;
+use crate::timestamp::{Span, Timestamp};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+const STALE_FILE_NAME: &str = "rattlebeaver.stale.toml";
+
+/// When each currently stale backup was first seen stale, keyed by timestamp. It is kept in the
+/// storage of the backups, so it works the same for local and remote targets.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StaleSince {
+    #[serde(default)]
+    pub since: BTreeMap<String, Timestamp>,
+}
+
+impl StaleSince {
+    /// Load the state from `storage`, empty if it was never saved.
+    pub fn load(storage: &dyn Storage) -> Result<Self> {
+        if !storage.list()?.iter().any(|name| name == STALE_FILE_NAME) {
+            return Ok(Self::default());
+        }
+        let mut s = String::new();
+        (storage.read(STALE_FILE_NAME)?)
+            .read_to_string(&mut s)
+            .with_context(|| format!("read {STALE_FILE_NAME}"))?;
+        toml::from_str(&s).with_context(|| format!("decode {STALE_FILE_NAME}"))
+    }
+
+    /// Save the state in `storage`.
+    pub fn save(&self, storage: &dyn Storage) -> Result<()> {
+        let s = toml::to_string_pretty(self).context("encode stale state")?;
+        let mut upload = storage.write(STALE_FILE_NAME)?;
+        (upload.write_all(s.as_bytes())).with_context(|| format!("write {STALE_FILE_NAME}"))?;
+        upload.finish()
+    }
+
+    /// Record stale backups not seen before as stale since `now`, and forget the rest.
+    pub fn update(&mut self, all_backups: &[Entry], now: Timestamp) {
+        let mut since = BTreeMap::new();
+        for backup in all_backups.iter().filter(|b| b.fulfills.is_empty()) {
+            let key = backup.timestamp.to_string();
+            let first_seen = self.since.get(&key).copied().unwrap_or(now);
+            since.insert(key, first_seen);
+        }
+        self.since = since;
+    }
+
+    /// Check if the backup at `timestamp` has been stale for at least `grace`.
+    #[must_use]
+    pub fn is_past_grace(&self, timestamp: Timestamp, grace: Span, now: Timestamp) -> bool {
+        self.since
+            .get(&timestamp.to_string())
+            .is_some_and(|since| since.add_span(grace) <= now)
+    }
+}
+
+/// The backups of `all_backups` that fulfill no retention range, reporting each to `on_event`.
+/// Given the `grace` period and the storage of the backups, those stale for less than it are left
+/// out. When each was first seen stale is then recorded in the storage if `record` (i.e. when
+/// deleting rather than previewing, under the target lock).
+pub fn stale_backups(
+    all_backups: Vec<Entry>,
+    grace: Option<(&dyn Storage, Span)>,
+    record: bool,
+    now: Timestamp,
+    on_event: Option<OnEvent>,
+) -> Result<Vec<Entry>> {
+    let mut stale: Vec<Entry> = all_backups
+        .into_iter()
+        .filter(|b| b.fulfills.is_empty())
+        .collect();
+    if let Some((storage, grace)) = grace {
+        let mut stale_since = StaleSince::load(storage).context("load stale state")?;
+        stale_since.update(&stale, now);
+        if record {
+            stale_since.save(storage).context("save stale state")?;
+        }
+        let stale_count = stale.len();
+        stale.retain(|b| stale_since.is_past_grace(b.timestamp, grace, now));
+        let in_grace = stale_count - stale.len();
+        if in_grace > 0 {
+            tracing::info!("Keeping {in_grace} stale backup(s) within the {grace} grace period.");
+        }
+    }
+    if let Some(on_event) = on_event {
+        for b in &stale {
+            on_event(&Event::StaleDetected {
+                path: b.path.clone(),
+                timestamp: b.timestamp,
+            });
+        }
+    }
+    Ok(stale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+
+    #[test]
+    fn grace_recorded_only_when_asked() {
+        let temp = tempfile::tempdir().unwrap();
+        let target = temp.path();
+        let storage = LocalStorage::new(target);
+        let config = crate::Config::default().archive;
+        let path = target.join(".rattlebeaver.2025-01-01_00-00-00.notes");
+        std::fs::write(&path, "backup").unwrap();
+        let backups = vec![Entry::from_path(path, &config).unwrap().unwrap()];
+        let grace: Span = "1d".parse().unwrap();
+        let now: Timestamp = "2025-02-01_00-00-00".parse().unwrap();
+        let stale = |record, now| {
+            stale_backups(backups.clone(), Some((&storage, grace)), record, now, None)
+                .unwrap()
+                .len()
+        };
+        assert_eq!(stale(false, now), 0);
+        assert!(!target.join(STALE_FILE_NAME).exists());
+        assert_eq!(stale(true, now), 0);
+        assert_eq!(stale(false, now.add_span(grace)), 1);
+    }
+}
@@ -0,0 +1,23 @@
+use crate::permissions::FilePermissions;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+/// Load a toml state file, or the default state if it does not exist.
+pub(crate) fn load_toml<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let s = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    toml::from_str(&s).with_context(|| format!("decode {}", path.display()))
+}
+
+pub(crate) fn save_toml<T: Serialize>(
+    path: &Path,
+    state: &T,
+    permissions: &FilePermissions,
+) -> Result<()> {
+    let s = toml::to_string_pretty(state).with_context(|| format!("encode {}", path.display()))?;
+    permissions.write(path, s)
+}
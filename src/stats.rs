@@ -0,0 +1,168 @@
+use crate::entry::{Entry, FulfillmentKind};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// How to group backups when summarizing growth over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum GrowthPeriod {
+    Week,
+    Month,
+}
+
+impl GrowthPeriod {
+    fn format(self) -> &'static str {
+        match self {
+            Self::Week => "%G-W%V",
+            Self::Month => "%Y-%m",
+        }
+    }
+}
+
+/// An at-a-glance summary of the backups in a target directory.
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    pub count: usize,
+    pub total_size: u64,
+    pub stale_count: usize,
+    pub stale_size: u64,
+    /// Number of backups kept for each kind of fulfillment
+    pub fulfillments: BTreeMap<String, usize>,
+    /// Number and size of backups made in each period, oldest first
+    pub growth: BTreeMap<String, PeriodStats>,
+    /// Average of the recorded compression ratios
+    pub average_compression_ratio: Option<f64>,
+    /// Sources by total size of their backups, largest first
+    pub sources: Vec<SourceStats>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct PeriodStats {
+    pub count: usize,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceStats {
+    pub source: String,
+    pub count: usize,
+    pub size: u64,
+}
+
+impl Stats {
+    /// Summarize `backups`, which should already be marked.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn collect(backups: &[Entry], period: GrowthPeriod) -> Result<Self> {
+        let mut stats = Self::default();
+        let mut ratios = Vec::new();
+        let mut sources: BTreeMap<String, SourceStats> = BTreeMap::new();
+        for backup in backups {
+            let size = backup.metadata().context("get file metadata")?.len();
+            stats.count += 1;
+            stats.total_size += size;
+            if backup.fulfills.is_empty() {
+                stats.stale_count += 1;
+                stats.stale_size += size;
+            }
+            let mut kinds: Vec<String> =
+                backup.fulfills.iter().map(|f| kind_name(f.kind)).collect();
+            kinds.sort();
+            kinds.dedup();
+            for kind in kinds {
+                *stats.fulfillments.entry(kind).or_default() += 1;
+            }
+            let period_key = backup
+                .timestamp
+                .as_ref()
+                .format(period.format())
+                .to_string();
+            let period_stats = stats.growth.entry(period_key).or_default();
+            period_stats.count += 1;
+            period_stats.size += size;
+            if let Some(ratio) = backup.meta.as_ref().and_then(|m| m.compression_ratio()) {
+                ratios.push(ratio);
+            }
+            let source = backup
+                .meta
+                .as_ref()
+                .and_then(|meta| meta.source.as_ref())
+                .map(|source| source.display().to_string())
+                .or_else(|| backup.label.clone())
+                .unwrap_or_default();
+            let source_stats = sources.entry(source.clone()).or_insert(SourceStats {
+                source,
+                count: 0,
+                size: 0,
+            });
+            source_stats.count += 1;
+            source_stats.size += size;
+        }
+        if !ratios.is_empty() {
+            stats.average_compression_ratio =
+                Some(ratios.iter().sum::<f64>() / ratios.len() as f64);
+        }
+        stats.sources = sources.into_values().collect();
+        stats.sources.sort_by_key(|source| std::cmp::Reverse(source.size));
+        Ok(stats)
+    }
+}
+
+/// A short name for the kind of a fulfillment, e.g. "day" or "calendar month".
+fn kind_name(kind: FulfillmentKind) -> String {
+    match kind {
+        FulfillmentKind::Latest => String::from("latest"),
+        FulfillmentKind::Range(range) => format!("{range:?}").to_lowercase(),
+        FulfillmentKind::Calendar(period) => format!("calendar {period:?}").to_lowercase(),
+        FulfillmentKind::Pinned { .. } => String::from("pinned"),
+        FulfillmentKind::Tagged => String::from("tagged"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Fulfillment;
+
+    #[test]
+    fn summarizes_backups() {
+        let temp = tempfile::tempdir().unwrap();
+        let config = crate::Config::default().archive;
+        let backup = |name: &str, size: usize, kinds: &[FulfillmentKind]| {
+            let path = temp.path().join(format!(".rattlebeaver.{name}"));
+            std::fs::write(&path, vec![0; size]).unwrap();
+            let mut backup = Entry::from_path(path, &config).unwrap().unwrap();
+            backup.fulfills = (kinds.iter())
+                .map(|&kind| Fulfillment {
+                    kind,
+                    index: 1,
+                    first_or_last: true,
+                })
+                .collect();
+            backup
+        };
+        let backups = [
+            backup("2025-01-01_00-00-00.db", 10, &[FulfillmentKind::Latest]),
+            backup("2025-01-15_00-00-00.db", 20, &[]),
+            backup(
+                "2025-02-01_00-00-00.notes",
+                5,
+                &[FulfillmentKind::Latest, FulfillmentKind::Tagged],
+            ),
+        ];
+        let stats = Stats::collect(&backups, GrowthPeriod::Month).unwrap();
+        assert_eq!((stats.count, stats.total_size), (3, 35));
+        assert_eq!((stats.stale_count, stats.stale_size), (1, 20));
+        let fulfillments: Vec<_> = stats.fulfillments.into_iter().collect();
+        assert_eq!(fulfillments, [("latest".into(), 2), ("tagged".into(), 1)]);
+        let growth: Vec<_> = (stats.growth.iter())
+            .map(|(period, stats)| (period.as_str(), stats.count, stats.size))
+            .collect();
+        assert_eq!(growth, [("2025-01", 2, 30), ("2025-02", 1, 5)]);
+        let sources: Vec<_> = (stats.sources.iter())
+            .map(|source| (source.source.as_str(), source.count, source.size))
+            .collect();
+        assert_eq!(sources, [("db", 2, 30), ("notes", 1, 5)]);
+        assert_eq!(stats.average_compression_ratio, None);
+    }
+}
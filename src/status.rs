@@ -0,0 +1,230 @@
+//! The health of a target at a glance, for monitoring and check scripts.
+
+use crate::config::Ranges;
+use crate::entry::{Entry, FulfillmentKind};
+use crate::size::ByteSize;
+use crate::timestamp::{Span, Timestamp};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// Limits past which a target is unhealthy.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Thresholds {
+    /// Oldest the newest backup may be
+    pub max_age: Option<Span>,
+    /// Least space that must be available on the target filesystem
+    pub min_free: Option<ByteSize>,
+    /// Whether a range with unfilled buckets is a problem
+    pub fill_ranges: bool,
+}
+
+/// The state of the backups in a target, and what is wrong with it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub target: String,
+    pub count: usize,
+    pub stale_count: usize,
+    pub total_size: u64,
+    pub newest: Option<Timestamp>,
+    /// Seconds since the newest backup
+    pub newest_age: Option<i64>,
+    pub ranges: Vec<RangeStatus>,
+    /// Space on the target filesystem, unknown for remote targets
+    pub disk: Option<DiskUsage>,
+    /// Why the target is unhealthy by the thresholds given, empty if it is healthy
+    pub problems: Vec<String>,
+}
+
+/// How many buckets of a range hold a backup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RangeStatus {
+    /// The override pattern the range belongs to, or None for the default ranges
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
+    /// e.g. "latest" or "day"
+    pub range: String,
+    pub filled: usize,
+    pub total: usize,
+    /// Buckets without a backup
+    pub unmet: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DiskUsage {
+    pub available: u64,
+    pub total: u64,
+}
+
+impl DiskUsage {
+    /// The space on the filesystem of the local `target`.
+    pub fn of(target: &std::path::Path) -> anyhow::Result<Self> {
+        let stats = fs4::statvfs(target)?;
+        Ok(Self {
+            available: stats.available_space(),
+            total: stats.total_space(),
+        })
+    }
+}
+
+impl Status {
+    /// The status of `target` at `now`, given its `backups`, which should already be marked by
+    /// `ranges`.
+    #[must_use]
+    pub fn collect(
+        target: &str,
+        backups: &[Entry],
+        ranges: &Ranges,
+        now: Timestamp,
+        disk: Option<DiskUsage>,
+        thresholds: &Thresholds,
+    ) -> Self {
+        let newest = backups.last().map(|backup| backup.timestamp);
+        let mut status = Self {
+            target: target.to_owned(),
+            count: backups.len(),
+            stale_count: backups.iter().filter(|b| b.fulfills.is_empty()).count(),
+            total_size: backups.iter().map(Entry::size).sum(),
+            newest,
+            newest_age: newest.map(|newest| (now.0 - newest.0).num_seconds()),
+            ranges: range_statuses(backups, ranges),
+            disk,
+            problems: Vec::new(),
+        };
+        status.problems = status.problems(now, thresholds);
+        status
+    }
+
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn problems(&self, now: Timestamp, thresholds: &Thresholds) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(max_age) = thresholds.max_age {
+            match self.newest {
+                None => problems.push(String::from("no backups")),
+                Some(newest) if newest < now.sub_span(max_age) => {
+                    problems.push(format!(
+                        "newest backup is {}, older than {max_age}",
+                        newest.relative(now)
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+        if let Some(min_free) = thresholds.min_free {
+            match self.disk {
+                Some(disk) if disk.available < min_free.0 => problems.push(format!(
+                    "{} available, less than {min_free}",
+                    ByteSize(disk.available)
+                )),
+                Some(_) => {}
+                None => problems.push(String::from("available space is unknown")),
+            }
+        }
+        if thresholds.fill_ranges {
+            for range in self.ranges.iter().filter(|range| range.unmet > 0) {
+                let policy = (range.policy.as_ref())
+                    .map_or_else(String::new, |policy| format!(" of {policy}"));
+                problems.push(format!(
+                    "{} of {} {} buckets{policy} are empty",
+                    range.unmet, range.total, range.range
+                ));
+            }
+        }
+        problems
+    }
+}
+
+/// The fill of every configured range, for the default ranges and for each override that
+/// applies to some backup.
+fn range_statuses(backups: &[Entry], ranges: &Ranges) -> Vec<RangeStatus> {
+    let policies = std::iter::once(None).chain(ranges.overrides.keys().map(|p| Some(p.as_str())));
+    let mut statuses = Vec::new();
+    for policy in policies {
+        let policy_ranges = policy.map_or(ranges, |pattern| &ranges.overrides[pattern]);
+        let members: Vec<&Entry> = backups
+            .iter()
+            .filter(|backup| ranges.policy(backup.label.as_deref()).0 == policy)
+            .collect();
+        if policy.is_some() && members.is_empty() {
+            continue;
+        }
+        let filled = |kind: FulfillmentKind| {
+            let indices: BTreeSet<usize> = (members.iter())
+                .flat_map(|backup| &backup.fulfills)
+                .filter(|fulfillment| fulfillment.kind == kind)
+                .map(|fulfillment| fulfillment.index)
+                .collect();
+            indices.len()
+        };
+        let mut push = |range: String, kind: FulfillmentKind, total: usize| {
+            if total == 0 {
+                return;
+            }
+            let filled = filled(kind).min(total);
+            statuses.push(RangeStatus {
+                policy: policy.map(str::to_owned),
+                range,
+                filled,
+                total,
+                unmet: total - filled,
+            });
+        };
+        push(
+            String::from("latest"),
+            FulfillmentKind::Latest,
+            policy_ranges.latest.count(),
+        );
+        for (range, rolling) in policy_ranges.iter_ranges() {
+            let name = format!("{range:?}").to_lowercase();
+            push(name, FulfillmentKind::Range(range), rolling.total);
+        }
+    }
+    statuses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds() {
+        let now = Timestamp::now();
+        let max_age: Span = "26h".parse().unwrap();
+        let mut status = Status {
+            target: String::from("/mnt/backups"),
+            count: 1,
+            stale_count: 0,
+            total_size: 0,
+            newest: Some(now.sub_span("1d".parse().unwrap())),
+            newest_age: None,
+            ranges: vec![RangeStatus {
+                policy: None,
+                range: String::from("day"),
+                filled: 1,
+                total: 7,
+                unmet: 6,
+            }],
+            disk: None,
+            problems: Vec::new(),
+        };
+        let thresholds = Thresholds {
+            max_age: Some(max_age),
+            ..Thresholds::default()
+        };
+        assert!(status.problems(now, &thresholds).is_empty());
+        status.newest = Some(now.sub_span("2d".parse().unwrap()));
+        assert_eq!(status.problems(now, &thresholds).len(), 1);
+        let thresholds = Thresholds {
+            min_free: Some(ByteSize(1)),
+            fill_ranges: true,
+            ..Thresholds::default()
+        };
+        assert_eq!(
+            status.problems(now, &thresholds),
+            ["available space is unknown", "6 of 7 day buckets are empty"]
+        );
+    }
+}
@@ -0,0 +1,378 @@
+use crate::permissions::FilePermissions;
+use crate::{config, fsync};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+pub mod append_only;
+pub mod cache;
+pub mod encrypt;
+pub mod locked;
+pub(crate) mod process;
+pub mod rclone;
+pub mod retry;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod ssh;
+pub mod throttle;
+#[cfg(feature = "webdav")]
+pub mod webdav;
+
+/// Check if `target` is a url (e.g. `s3://bucket/prefix`) rather than a local directory.
+#[must_use]
+pub fn is_remote(target: &str) -> bool {
+    target.contains("://")
+}
+
+/// How to access remote targets.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteOptions {
+    pub retry: config::Retry,
+    /// Upload rate limit in bytes per second
+    pub bwlimit: Option<u64>,
+    /// List the storage instead of using the cached listing
+    pub refresh: bool,
+    /// Settings of individual targets, by directory or url
+    pub targets: BTreeMap<String, config::Target>,
+    /// Lock remote targets while they are open, waiting up to this long for another run to
+    /// release them (see [`locked::LockedStorage`])
+    pub lock: Option<std::time::Duration>,
+    /// Permissions and ownership of the files created in local directories
+    pub permissions: FilePermissions,
+    /// Flush what is written to local directories to disk (see [`LocalStorage::with_fsync`])
+    pub fsync: bool,
+}
+
+impl RemoteOptions {
+    /// The settings of `target`, if any.
+    #[must_use]
+    pub fn target(&self, target: &str) -> Option<&config::Target> {
+        let target = target.trim_end_matches('/');
+        self.targets
+            .iter()
+            .find(|(key, _settings)| key.trim_end_matches('/') == target)
+            .map(|(_key, settings)| settings)
+    }
+
+    /// Wrap the storage of `target` to encrypt what is written to it and to refuse deleting
+    /// from it, if its settings say so.
+    fn wrap(&self, target: &str, mut storage: Box<dyn Storage>) -> Box<dyn Storage> {
+        let Some(settings) = self.target(target) else {
+            return storage;
+        };
+        if settings.is_encrypted() {
+            storage = Box::new(encrypt::EncryptedStorage::new(storage, settings));
+        }
+        if settings.append_only {
+            storage = Box::new(append_only::AppendOnlyStorage::new(storage, target));
+        }
+        storage
+    }
+}
+
+/// Open the remote storage at `target` if it is a url, or `None` for local directories.
+pub fn open_remote(target: &str, options: &RemoteOptions) -> Result<Option<Box<dyn Storage>>> {
+    let Some(storage) = open_backend(target, options.bwlimit)? else {
+        return Ok(None);
+    };
+    let mut storage: Box<dyn Storage> =
+        Box::new(retry::RetryStorage::new(storage, options.retry.clone()));
+    // Beneath the cache, so the lock of another run is seen
+    if let Some(timeout) = options.lock {
+        storage = Box::new(locked::LockedStorage::acquire(storage, timeout)?);
+    }
+    let storage = cache::CachedStorage::new(storage, target, options.refresh)?;
+    Ok(Some(options.wrap(target, Box::new(storage))))
+}
+
+fn open_backend(target: &str, bwlimit: Option<u64>) -> Result<Option<Box<dyn Storage>>> {
+    let Some((scheme, _rest)) = target.split_once("://") else {
+        return Ok(None);
+    };
+    match scheme {
+        #[cfg(feature = "s3")]
+        "s3" => Ok(Some(Box::new(
+            s3::S3Storage::from_url(target)?.with_bwlimit(bwlimit),
+        ))),
+        "rclone" => Ok(Some(Box::new(
+            rclone::RcloneStorage::from_url(target)?.with_bwlimit(bwlimit),
+        ))),
+        "sftp" | "ssh" => Ok(Some(Box::new(
+            ssh::SshStorage::from_url(target)?.with_bwlimit(bwlimit),
+        ))),
+        #[cfg(feature = "webdav")]
+        "dav" | "davs" => Ok(Some(Box::new(
+            webdav::WebDavStorage::from_url(target)?.with_bwlimit(bwlimit),
+        ))),
+        _ => anyhow::bail!("unsupported target {target:?} (missing the {scheme:?} feature?)"),
+    }
+}
+
+/// Open `target`, which is either a remote url or a local directory (created if missing).
+pub fn open(target: &str, options: &RemoteOptions) -> Result<Box<dyn Storage>> {
+    if let Some(storage) = open_remote(target, options)? {
+        return Ok(storage);
+    }
+    std::fs::create_dir_all(target).with_context(|| format!("create {target}"))?;
+    let storage = (LocalStorage::new(target))
+        .with_permissions(options.permissions)
+        .with_fsync(options.fsync);
+    Ok(options.wrap(target, Box::new(storage)))
+}
+
+/// Copy the file `name` from one storage to another.
+pub fn copy(from: &dyn Storage, to: &dyn Storage, name: &str) -> Result<()> {
+    let mut reader = from.read(name)?;
+    let mut upload = to.write(name)?;
+    std::io::copy(&mut reader, &mut upload).with_context(|| format!("copy {name}"))?;
+    upload.finish()
+}
+
+/// A file [`Storage::write_new`] was to create already exists.
+#[derive(Debug, thiserror::Error)]
+#[error("{} already exists", .0.display())]
+pub struct AlreadyExists(pub PathBuf);
+
+/// A failure that may go away by trying again, such as a dropped connection or a server error,
+/// unlike e.g. a missing file or denied access. Only these are retried by [`retry::RetryStorage`].
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct Transient(pub anyhow::Error);
+
+/// Where backups are kept. Files are addressed by name, relative to the root of the storage.
+pub trait Storage {
+    /// Names of the files in the root of the storage, in no particular order.
+    fn list(&self) -> Result<Vec<String>>;
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>>;
+
+    /// Create or replace a file. It is complete once the upload is finished.
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>>;
+
+    /// Create a file that must not exist yet, failing with [`AlreadyExists`] if it does.
+    ///
+    /// Storages that can't create files exclusively replace existing ones like [`Storage::write`].
+    fn write_new(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        self.write(name)
+    }
+
+    fn delete(&self, name: &str) -> Result<()>;
+
+    fn rename(&self, from: &str, to: &str) -> Result<()>;
+
+    /// How to refer to a file in output and in [`crate::Entry::path`].
+    fn locate(&self, name: &str) -> PathBuf;
+
+    /// Upload the local file at `path` as `name`.
+    ///
+    /// Storages that support it resume the upload where an earlier failed call for the same
+    /// file and `name` stopped, even in another process, recording their progress in
+    /// [`upload_state_path`].
+    fn upload_file(&self, name: &str, path: &Path) -> Result<()> {
+        let mut file =
+            std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+        let mut upload = self.write(name)?;
+        std::io::copy(&mut file, &mut upload).with_context(|| format!("upload {name}"))?;
+        upload.finish()
+    }
+
+    /// Set the permissions of a file, if the storage supports them.
+    fn set_permissions(&self, name: &str, permissions: std::fs::Permissions) -> Result<()> {
+        let _ = (name, permissions);
+        Ok(())
+    }
+}
+
+/// Where the progress of uploading the local file at `path` to `location` (see
+/// [`Storage::locate`]) is recorded in the cache directory, to resume it. It is keyed by the
+/// checksum of the file, so it is only ever resumed with the same contents, which takes reading
+/// the file once more.
+pub fn upload_state_path(location: &Path, path: &Path) -> Result<PathBuf> {
+    let checksum = crate::checksum::sha256_file(path)?;
+    let key = format!("{}\n{checksum}", location.display());
+    let digest = hex::encode(Sha256::digest(key.as_bytes()));
+    let dir = cache_dir()?.join("uploads");
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    Ok(dir.join(format!("{}.toml", &digest[..16])))
+}
+
+/// `$XDG_CACHE_HOME/rattlebeaver`, or `~/.cache/rattlebeaver`.
+pub(crate) fn cache_dir() -> Result<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&std::env::var_os("HOME").context("missing HOME")?).join(".cache"),
+    };
+    Ok(base.join("rattlebeaver"))
+}
+
+/// A file being written to a [`Storage`].
+pub trait Upload: Write {
+    /// Complete the file, e.g. by sending it to a remote server.
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+impl Upload for std::fs::File {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush().context("flush file")
+    }
+}
+
+/// A directory on the local filesystem.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+    permissions: FilePermissions,
+    fsync: bool,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            permissions: FilePermissions::default(),
+            fsync: false,
+        }
+    }
+
+    /// Create files with `permissions` [default: readable and writable only by this user].
+    #[must_use]
+    pub fn with_permissions(mut self, permissions: FilePermissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Flush files to disk when they are finished, and the directory when files are created,
+    /// renamed or deleted in it.
+    #[must_use]
+    pub fn with_fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Storage for LocalStorage {
+    fn list(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for file in self.root.read_dir().context("read target directory")? {
+            let file_name = file.context("read file from dir")?.file_name();
+            let name = file_name
+                .into_string()
+                .map_err(|name| anyhow::anyhow!("file name not utf-8: {name:?}"))?;
+            names.push(name);
+        }
+        Ok(names)
+    }
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        let path = self.locate(name);
+        let file =
+            std::fs::File::open(&path).with_context(|| format!("open {}", path.display()))?;
+        Ok(Box::new(file))
+    }
+
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        let path = self.locate(name);
+        let file = self.permissions.create(&path)?;
+        Ok(Box::new(LocalUpload {
+            file,
+            path,
+            fsync: self.fsync,
+        }))
+    }
+
+    fn write_new(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        let path = self.locate(name);
+        let file = self.permissions.create_new(&path).map_err(|e| {
+            match e.downcast_ref::<std::io::Error>().map(std::io::Error::kind) {
+                Some(std::io::ErrorKind::AlreadyExists) => AlreadyExists(path.clone()).into(),
+                _ => e,
+            }
+        })?;
+        Ok(Box::new(LocalUpload {
+            file,
+            path,
+            fsync: self.fsync,
+        }))
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        let path = self.locate(name);
+        std::fs::remove_file(&path).with_context(|| format!("delete {}", path.display()))?;
+        fsync::dir(&self.root, self.fsync)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let from = self.locate(from);
+        std::fs::rename(&from, self.locate(to))
+            .with_context(|| format!("rename {}", from.display()))?;
+        fsync::dir(&self.root, self.fsync)
+    }
+
+    fn locate(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    fn set_permissions(&self, name: &str, permissions: std::fs::Permissions) -> Result<()> {
+        let path = self.locate(name);
+        std::fs::set_permissions(&path, permissions)
+            .with_context(|| format!("set permissions of {}", path.display()))
+    }
+}
+
+/// A file being written to a [`LocalStorage`], flushed to disk when finished if configured.
+struct LocalUpload {
+    file: std::fs::File,
+    path: PathBuf,
+    fsync: bool,
+}
+
+impl Write for LocalUpload {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Upload for LocalUpload {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.file.flush().context("flush file")?;
+        fsync::file(&self.file, self.fsync)
+            .with_context(|| format!("write {}", self.path.display()))?;
+        fsync::parent(&self.path, self.fsync)
+    }
+}
+
+/// A temporary file, removed when dropped.
+pub(crate) struct TempFile {
+    pub(crate) file: std::fs::File,
+    pub(crate) path: tempfile::TempPath,
+}
+
+impl TempFile {
+    /// Create a new temporary file named after `name`, with a random suffix so nobody can
+    /// create it (or a link by its name) first. It is only ever readable by this user.
+    pub(crate) fn create(name: &str) -> Result<Self> {
+        let (file, path) = tempfile::Builder::new()
+            .prefix(&format!("rattlebeaver-{name}-"))
+            .tempfile()
+            .with_context(|| format!("create temporary file for {name}"))?
+            .into_parts();
+        Ok(Self { file, path })
+    }
+}
+
+impl Read for TempFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.file.read(buf)
+    }
+}
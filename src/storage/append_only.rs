@@ -0,0 +1,72 @@
+use super::{Storage, Upload};
+use anyhow::Result;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Refuses to delete, rename or overwrite files in another storage, so backups written to it
+/// can't be destroyed through rattlebeaver (e.g. by ransomware holding its credentials).
+pub struct AppendOnlyStorage {
+    inner: Box<dyn Storage>,
+    target: String,
+}
+
+impl AppendOnlyStorage {
+    #[must_use]
+    pub fn new(inner: Box<dyn Storage>, target: &str) -> Self {
+        Self {
+            inner,
+            target: target.to_owned(),
+        }
+    }
+
+    fn refuse(&self, operation: &str, name: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "{} is append-only, refusing to {operation} {name}",
+            self.target
+        )
+    }
+}
+
+impl Storage for AppendOnlyStorage {
+    fn list(&self) -> Result<Vec<String>> {
+        self.inner.list()
+    }
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        self.inner.read(name)
+    }
+
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        if self.inner.list()?.iter().any(|existing| existing == name) {
+            return Err(self.refuse("overwrite", name));
+        }
+        self.inner.write(name)
+    }
+
+    fn write_new(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        self.inner.write_new(name)
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        Err(self.refuse("delete", name))
+    }
+
+    fn rename(&self, from: &str, _to: &str) -> Result<()> {
+        Err(self.refuse("rename", from))
+    }
+
+    fn locate(&self, name: &str) -> PathBuf {
+        self.inner.locate(name)
+    }
+
+    fn upload_file(&self, name: &str, path: &std::path::Path) -> Result<()> {
+        if self.inner.list()?.iter().any(|existing| existing == name) {
+            return Err(self.refuse("overwrite", name));
+        }
+        self.inner.upload_file(name, path)
+    }
+
+    fn set_permissions(&self, name: &str, permissions: std::fs::Permissions) -> Result<()> {
+        self.inner.set_permissions(name, permissions)
+    }
+}
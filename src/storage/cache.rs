@@ -0,0 +1,311 @@
+use super::{Storage, Upload};
+use crate::meta;
+use crate::permissions::FilePermissions;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Keeps the file names and sidecar metadata of another storage in a local file, so listing
+/// backups doesn't need to enumerate the remote storage on every run.
+///
+/// Changes made through this storage update the cache. Changes made by others are only seen
+/// after refreshing.
+pub struct CachedStorage {
+    inner: Box<dyn Storage>,
+    path: PathBuf,
+    cache: RefCell<Listing>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Listing {
+    /// Names of all files, if they have been listed
+    names: Option<Vec<String>>,
+    /// Contents of sidecar files by name
+    sidecars: BTreeMap<String, String>,
+}
+
+impl CachedStorage {
+    /// Cache the listing of the storage at `url`, discarding what was cached before if `refresh`.
+    pub fn new(inner: Box<dyn Storage>, url: &str, refresh: bool) -> Result<Self> {
+        let digest = hex::encode(Sha256::digest(url.as_bytes()));
+        let path = super::cache_dir()?.join(format!("listing-{}.json", &digest[..16]));
+        Self::at_path(inner, path, refresh)
+    }
+
+    /// Cache the listing of `inner` in the file `path`.
+    fn at_path(inner: Box<dyn Storage>, path: PathBuf, refresh: bool) -> Result<Self> {
+        let cache = if refresh || !path.exists() {
+            Listing::default()
+        } else {
+            let data = std::fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+            serde_json::from_slice(&data).unwrap_or_else(|e| {
+                tracing::warn!("discard corrupt listing cache {}: {e}", path.display());
+                Listing::default()
+            })
+        };
+        Ok(Self {
+            inner,
+            path,
+            cache: RefCell::new(cache),
+        })
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Listing)) -> Result<()> {
+        let mut cache = self.cache.borrow_mut();
+        change(&mut cache);
+        let parent = self.path.parent().context("cache file has no parent")?;
+        std::fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+        let data = serde_json::to_vec(&*cache).context("encode listing cache")?;
+        FilePermissions::default().write(&self.path, data)
+    }
+
+    /// Record a new file, with its contents if it is a sidecar.
+    fn added(&self, name: &str, sidecar: Option<String>) -> Result<()> {
+        self.update(|cache| {
+            match sidecar {
+                Some(contents) => cache.sidecars.insert(name.to_owned(), contents),
+                None => cache.sidecars.remove(name),
+            };
+            if let Some(names) = &mut cache.names
+                && !names.iter().any(|existing| existing == name)
+            {
+                names.push(name.to_owned());
+            }
+        })
+    }
+
+    fn removed(&self, name: &str) -> Result<()> {
+        self.update(|cache| {
+            cache.sidecars.remove(name);
+            if let Some(names) = &mut cache.names {
+                names.retain(|existing| existing != name);
+            }
+        })
+    }
+}
+
+impl Storage for CachedStorage {
+    fn list(&self) -> Result<Vec<String>> {
+        if let Some(names) = &self.cache.borrow().names {
+            return Ok(names.clone());
+        }
+        let names = self.inner.list()?;
+        self.update(|cache| cache.names = Some(names.clone()))?;
+        Ok(names)
+    }
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        if !name.ends_with(meta::SIDECAR_SUFFIX) {
+            return self.inner.read(name);
+        }
+        if let Some(contents) = self.cache.borrow().sidecars.get(name) {
+            return Ok(Box::new(std::io::Cursor::new(
+                contents.clone().into_bytes(),
+            )));
+        }
+        let mut contents = String::new();
+        self.inner
+            .read(name)?
+            .read_to_string(&mut contents)
+            .with_context(|| format!("read {name}"))?;
+        self.update(|cache| {
+            cache.sidecars.insert(name.to_owned(), contents.clone());
+        })?;
+        Ok(Box::new(std::io::Cursor::new(contents.into_bytes())))
+    }
+
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        // Forget the old contents before they are replaced
+        self.removed(name)?;
+        Ok(Box::new(CachedUpload {
+            storage: self,
+            upload: self.inner.write(name)?,
+            name: name.to_owned(),
+            sidecar: name.ends_with(meta::SIDECAR_SUFFIX).then(Vec::new),
+        }))
+    }
+
+    fn write_new(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        let upload = self.inner.write_new(name)?;
+        self.removed(name)?;
+        Ok(Box::new(CachedUpload {
+            storage: self,
+            upload,
+            name: name.to_owned(),
+            sidecar: name.ends_with(meta::SIDECAR_SUFFIX).then(Vec::new),
+        }))
+    }
+
+    fn upload_file(&self, name: &str, path: &Path) -> Result<()> {
+        self.removed(name)?;
+        self.inner.upload_file(name, path)?;
+        self.added(name, None)
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        self.inner.delete(name)?;
+        self.removed(name)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        self.removed(to)?;
+        self.inner.rename(from, to)?;
+        self.removed(from)?;
+        self.added(to, None)
+    }
+
+    fn locate(&self, name: &str) -> PathBuf {
+        self.inner.locate(name)
+    }
+
+    fn set_permissions(&self, name: &str, permissions: std::fs::Permissions) -> Result<()> {
+        self.inner.set_permissions(name, permissions)
+    }
+}
+
+/// Adds the file to the cached listing once it is complete.
+struct CachedUpload<'a> {
+    storage: &'a CachedStorage,
+    upload: Box<dyn Upload + 'a>,
+    name: String,
+    /// Contents written so far, if the file is a sidecar
+    sidecar: Option<Vec<u8>>,
+}
+
+impl Write for CachedUpload<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.upload.write(buf)?;
+        if let Some(contents) = &mut self.sidecar {
+            contents.extend_from_slice(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.upload.flush()
+    }
+}
+
+impl Upload for CachedUpload<'_> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.upload.finish()?;
+        let sidecar = self
+            .sidecar
+            .and_then(|contents| String::from_utf8(contents).ok());
+        self.storage.added(&self.name, sidecar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Counts the listings and reads that reach the storage.
+    struct Counting {
+        inner: LocalStorage,
+        lists: Rc<Cell<u32>>,
+        reads: Rc<Cell<u32>>,
+    }
+
+    impl Storage for Counting {
+        fn list(&self) -> Result<Vec<String>> {
+            self.lists.set(self.lists.get() + 1);
+            self.inner.list()
+        }
+
+        fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+            self.reads.set(self.reads.get() + 1);
+            self.inner.read(name)
+        }
+
+        fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+            self.inner.write(name)
+        }
+
+        fn delete(&self, name: &str) -> Result<()> {
+            self.inner.delete(name)
+        }
+
+        fn rename(&self, from: &str, to: &str) -> Result<()> {
+            self.inner.rename(from, to)
+        }
+
+        fn locate(&self, name: &str) -> PathBuf {
+            self.inner.locate(name)
+        }
+    }
+
+    #[test]
+    fn caches_until_changed_or_refreshed() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("storage");
+        let path = temp.path().join("listing.json");
+        std::fs::create_dir_all(&dir).unwrap();
+        let sidecar = format!("a{}", meta::SIDECAR_SUFFIX);
+        std::fs::write(dir.join("a"), "backup").unwrap();
+        std::fs::write(dir.join(&sidecar), "old").unwrap();
+        let (lists, reads) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let open = |refresh| {
+            let counting = Counting {
+                inner: LocalStorage::new(&dir),
+                lists: lists.clone(),
+                reads: reads.clone(),
+            };
+            CachedStorage::at_path(Box::new(counting), path.clone(), refresh).unwrap()
+        };
+        let read = |storage: &CachedStorage, name: &str| {
+            let mut contents = String::new();
+            storage
+                .read(name)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+        let sorted = |mut names: Vec<String>| {
+            names.sort();
+            names
+        };
+
+        // Misses, then hits, also when opened again
+        let cached = open(false);
+        assert_eq!(sorted(cached.list().unwrap()), ["a", sidecar.as_str()]);
+        assert_eq!(read(&cached, &sidecar), "old");
+        cached.list().unwrap();
+        assert_eq!(read(&cached, &sidecar), "old");
+        let cached = open(false);
+        cached.list().unwrap();
+        assert_eq!(read(&cached, &sidecar), "old");
+        assert_eq!((lists.get(), reads.get()), (1, 1));
+
+        // Changes made through the cache update it
+        let mut upload = cached.write(&sidecar).unwrap();
+        upload.write_all(b"new").unwrap();
+        upload.finish().unwrap();
+        cached.delete("a").unwrap();
+        let mut upload = cached.write("b").unwrap();
+        upload.write_all(b"backup").unwrap();
+        upload.finish().unwrap();
+        assert_eq!(sorted(cached.list().unwrap()), [sidecar.as_str(), "b"]);
+        assert_eq!(read(&cached, &sidecar), "new");
+        assert_eq!((lists.get(), reads.get()), (1, 1));
+
+        // Changes made by others are seen once refreshed
+        std::fs::write(dir.join("c"), "backup").unwrap();
+        assert!(!open(false).list().unwrap().contains(&String::from("c")));
+        assert!(open(true).list().unwrap().contains(&String::from("c")));
+        assert_eq!(lists.get(), 2);
+
+        // A corrupt cache is discarded
+        std::fs::write(&path, "not json").unwrap();
+        open(false).list().unwrap();
+        assert_eq!(lists.get(), 3);
+    }
+}
@@ -0,0 +1,144 @@
+use super::{Storage, TempFile, Upload};
+use crate::config;
+use crate::encryption::{self, Decryptor, Encryptor};
+use crate::meta;
+use anyhow::{Context, Result};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+/// Encrypts backups written to another storage, and decrypts them when read.
+///
+/// Backups are stored with the suffix of the cipher (e.g. `.age`), hidden from callers, and
+/// their sidecars are named to match. Sidecar metadata stays readable so listings work without
+/// the key. Backups that are already encrypted are stored as they are.
+pub struct EncryptedStorage {
+    inner: Box<dyn Storage>,
+    settings: config::Target,
+    decryptor: Decryptor,
+}
+
+impl EncryptedStorage {
+    #[must_use]
+    pub fn new(inner: Box<dyn Storage>, settings: &config::Target) -> Self {
+        Self {
+            inner,
+            settings: settings.clone(),
+            decryptor: Decryptor {
+                identity: settings.identity.clone(),
+                passphrase_file: settings.passphrase_file.clone(),
+            },
+        }
+    }
+
+    /// The name of the file in the inner storage. Sidecars are named after the stored backup.
+    fn stored(&self, name: &str) -> String {
+        if let Some(backup) = name.strip_suffix(meta::SIDECAR_SUFFIX) {
+            return format!("{}{}", self.stored(backup), meta::SIDECAR_SUFFIX);
+        }
+        if encryption::is_encrypted(name) {
+            name.to_owned()
+        } else {
+            format!("{name}{}", self.settings.encryption().cipher.suffix())
+        }
+    }
+
+    /// The name of a file in the inner storage as callers see it, or `None` for files that were
+    /// not written through here (e.g. backups copied before encryption was enabled).
+    fn unstored(&self, stored: &str) -> Option<String> {
+        if let Some(backup) = stored.strip_suffix(meta::SIDECAR_SUFFIX) {
+            let backup = self.unstored(backup)?;
+            return Some(format!("{backup}{}", meta::SIDECAR_SUFFIX));
+        }
+        match stored.strip_suffix(self.settings.encryption().cipher.suffix()) {
+            Some(name) => Some(name.to_owned()),
+            None => encryption::is_encrypted(stored).then(|| stored.to_owned()),
+        }
+    }
+}
+
+/// Check if the file `name` is encrypted when written, which is every file but sidecars and
+/// those encrypted already.
+fn encrypts(name: &str) -> bool {
+    !name.ends_with(meta::SIDECAR_SUFFIX) && !encryption::is_encrypted(name)
+}
+
+impl Storage for EncryptedStorage {
+    fn list(&self) -> Result<Vec<String>> {
+        let names = self.inner.list()?;
+        Ok(names
+            .iter()
+            .filter_map(|name| self.unstored(name))
+            .collect())
+    }
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        let stored = self.stored(name);
+        if !encrypts(name) {
+            return self.inner.read(&stored);
+        }
+        let reader = self.inner.read(&stored)?;
+        Ok(Box::new(self.decryptor.decrypt(reader, &stored)?))
+    }
+
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        if !encrypts(name) {
+            return self.inner.write(&self.stored(name));
+        }
+        let encryption = self.settings.encryption();
+        anyhow::ensure!(encryption.is_enabled(), "no recipients to encrypt to");
+        let buffer = TempFile::create(&format!("upload-{name}"))?;
+        let file = buffer.file.try_clone().context("open temporary file")?;
+        Ok(Box::new(EncryptUpload {
+            storage: self,
+            writer: Encryptor::new(file, encryption, name)?,
+            name: name.to_owned(),
+            buffer,
+        }))
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        self.inner.delete(&self.stored(name))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        self.inner.rename(&self.stored(from), &self.stored(to))
+    }
+
+    fn locate(&self, name: &str) -> PathBuf {
+        self.inner.locate(name)
+    }
+
+    fn set_permissions(&self, name: &str, permissions: std::fs::Permissions) -> Result<()> {
+        self.inner.set_permissions(&self.stored(name), permissions)
+    }
+}
+
+/// Encrypts into a temporary file, uploaded in full when finished so it can be resumed.
+struct EncryptUpload<'a> {
+    storage: &'a EncryptedStorage,
+    writer: Encryptor<std::fs::File>,
+    name: String,
+    buffer: TempFile,
+}
+
+impl Write for EncryptUpload<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Upload for EncryptUpload<'_> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        let mut file = self
+            .writer
+            .finish()
+            .with_context(|| format!("encrypt {}", self.name))?;
+        file.flush().context("flush encrypted file")?;
+        let stored = self.storage.stored(&self.name);
+        self.storage.inner.upload_file(&stored, &self.buffer.path)
+    }
+}
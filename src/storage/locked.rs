@@ -0,0 +1,142 @@
+use super::{AlreadyExists, Storage, Upload};
+use crate::lock::LOCK_FILE_NAME;
+use anyhow::{Context, Result};
+use std::hash::{BuildHasher, RandomState};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often to check if another run released the lock, less often than for a local lock since
+/// it takes listing the storage.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Holds the lock of another storage while it exists, so concurrent runs against a remote target
+/// can't race like [`crate::lock::TargetLock`] keeps them from doing on local ones.
+///
+/// The lock is a file in the storage, naming the process holding it. Unlike a local lock, it is
+/// left behind if the process is killed, and must then be deleted by hand. On storages that can't
+/// create files exclusively, two runs taking it at the same moment may both get it.
+pub struct LockedStorage {
+    inner: Box<dyn Storage>,
+}
+
+impl LockedStorage {
+    /// Lock `inner`, waiting up to `timeout` for another run holding the lock to release it.
+    pub fn acquire(inner: Box<dyn Storage>, timeout: Duration) -> Result<Self> {
+        let token = format!(
+            "{} {:016x}",
+            std::process::id(),
+            RandomState::new().hash_one(Instant::now())
+        );
+        let deadline = Instant::now() + timeout;
+        loop {
+            if !inner.list()?.iter().any(|name| name == LOCK_FILE_NAME) {
+                let created = match inner.write_new(LOCK_FILE_NAME) {
+                    Ok(mut upload) => {
+                        upload.write_all(token.as_bytes()).context("write lock")?;
+                        upload.finish()?;
+                        true
+                    }
+                    Err(e) if e.is::<AlreadyExists>() => false,
+                    Err(e) => return Err(e.context("create lock")),
+                };
+                // Where the lock was overwritten, the last run to write it holds it
+                if created && read_lock(&*inner)? == token {
+                    return Ok(Self { inner });
+                }
+            } else if Instant::now() >= deadline {
+                let holder = read_lock(&*inner).unwrap_or_default();
+                let holder = match holder.split_whitespace().next() {
+                    Some(pid) => format!(" (pid {pid})"),
+                    None => String::new(),
+                };
+                anyhow::bail!(
+                    "{} is locked by another run{holder}, delete it if that run is gone",
+                    inner.locate(LOCK_FILE_NAME).display()
+                );
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+fn read_lock(storage: &dyn Storage) -> Result<String> {
+    let mut lock = String::new();
+    (storage.read(LOCK_FILE_NAME)?)
+        .read_to_string(&mut lock)
+        .context("read lock")?;
+    Ok(lock)
+}
+
+impl Drop for LockedStorage {
+    fn drop(&mut self) {
+        if let Err(e) = self.inner.delete(LOCK_FILE_NAME) {
+            tracing::warn!(
+                "release the lock of {}: {e:#}",
+                self.inner.locate("").display()
+            );
+        }
+    }
+}
+
+impl Storage for LockedStorage {
+    fn list(&self) -> Result<Vec<String>> {
+        self.inner.list()
+    }
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        self.inner.read(name)
+    }
+
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        self.inner.write(name)
+    }
+
+    fn write_new(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        self.inner.write_new(name)
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        self.inner.delete(name)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn locate(&self, name: &str) -> PathBuf {
+        self.inner.locate(name)
+    }
+
+    fn upload_file(&self, name: &str, path: &Path) -> Result<()> {
+        self.inner.upload_file(name, path)
+    }
+
+    fn set_permissions(&self, name: &str, permissions: std::fs::Permissions) -> Result<()> {
+        self.inner.set_permissions(name, permissions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+
+    #[test]
+    fn locks_until_dropped() {
+        let temp = tempfile::tempdir().unwrap();
+        let open = || Box::new(LocalStorage::new(temp.path()));
+        let locked = LockedStorage::acquire(open(), Duration::ZERO).unwrap();
+        assert!(temp.path().join(LOCK_FILE_NAME).exists());
+        let error = LockedStorage::acquire(open(), Duration::ZERO)
+            .err()
+            .unwrap();
+        assert!(
+            format!("{error:#}").contains("locked by another run"),
+            "{error:#}"
+        );
+        drop(locked);
+        assert!(!temp.path().join(LOCK_FILE_NAME).exists());
+        assert!(LockedStorage::acquire(open(), Duration::ZERO).is_ok());
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{Context, Result};
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// Reads the output of a command, failing at the end if the command failed.
+pub struct ProcessReader {
+    child: Child,
+    stdout: ChildStdout,
+    name: String,
+}
+
+impl ProcessReader {
+    /// Spawn `command` to read the file `name`.
+    pub fn spawn(mut command: Command, name: &str) -> Result<Self> {
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("run {:?}", command.get_program()))?;
+        let stdout = child.stdout.take().context("get stdout")?;
+        Ok(Self {
+            child,
+            stdout,
+            name: name.to_owned(),
+        })
+    }
+}
+
+impl Read for ProcessReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() && !self.child.wait()?.success() {
+            let message = format!("failed to read {}", self.name);
+            return Err(std::io::Error::other(message));
+        }
+        Ok(read)
+    }
+}
+
+/// Writes to the input of a command, e.g. one uploading it.
+pub struct ProcessWriter {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    name: String,
+}
+
+impl ProcessWriter {
+    /// Spawn `command` to write the file `name`.
+    pub fn spawn(mut command: Command, name: &str) -> Result<Self> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("run {:?}", command.get_program()))?;
+        let stdin = child.stdin.take().context("get stdin")?;
+        Ok(Self {
+            child,
+            stdin: Some(stdin),
+            name: name.to_owned(),
+        })
+    }
+
+    /// Close the input and wait for the command to succeed.
+    pub fn finish(&mut self) -> Result<()> {
+        drop(self.stdin.take());
+        let status = self.child.wait().context("wait for upload")?;
+        anyhow::ensure!(status.success(), "failed to upload {}", self.name);
+        Ok(())
+    }
+}
+
+impl Write for ProcessWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let stdin = self.stdin.as_mut().ok_or(std::io::ErrorKind::BrokenPipe)?;
+        stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stdin.as_mut().map_or(Ok(()), Write::flush)
+    }
+}
+
+impl super::Upload for ProcessWriter {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        ProcessWriter::finish(&mut self)
+    }
+}
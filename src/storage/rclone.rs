@@ -0,0 +1,119 @@
+use super::process::{ProcessReader, ProcessWriter};
+use super::throttle::Throttle;
+use super::{Storage, Transient, Upload};
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Any remote configured in rclone, addressed as `rclone://remote:path`.
+///
+/// Runs the `rclone` binary (or `RCLONE` if set), so every provider and option configured with
+/// `rclone config` is available.
+pub struct RcloneStorage {
+    url: String,
+    remote: String,
+    bwlimit: Option<u64>,
+}
+
+impl RcloneStorage {
+    pub fn from_url(url: &str) -> Result<Self> {
+        let url = url.trim_end_matches('/');
+        let remote = url
+            .strip_prefix("rclone://")
+            .context("expected an rclone:// url")?;
+        anyhow::ensure!(
+            remote.contains(':'),
+            "expected rclone://remote:path, got {url:?}"
+        );
+        Ok(Self {
+            url: url.to_owned(),
+            remote: remote.to_owned(),
+            bwlimit: None,
+        })
+    }
+
+    /// Limit uploads to `bwlimit` bytes per second.
+    #[must_use]
+    pub fn with_bwlimit(mut self, bwlimit: Option<u64>) -> Self {
+        self.bwlimit = bwlimit;
+        self
+    }
+
+    fn path(&self, name: &str) -> String {
+        if self.remote.ends_with(':') {
+            format!("{}{name}", self.remote)
+        } else {
+            format!("{}/{name}", self.remote)
+        }
+    }
+
+    fn command<I, S>(args: I) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        let program = std::env::var_os("RCLONE").unwrap_or_else(|| "rclone".into());
+        let mut command = Command::new(program);
+        command.args(args);
+        command
+    }
+
+    fn run<I, S>(args: I) -> Result<Vec<u8>>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        let mut command = Self::command(args);
+        let output = command
+            .stdin(Stdio::null())
+            .output()
+            .context("run rclone")?;
+        if !output.status.success() {
+            let args: Vec<_> = command.get_args().collect();
+            let error = anyhow::anyhow!(
+                "rclone {args:?} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            // rclone exits with 5 on temporary errors, that more retries might fix
+            if output.status.code() == Some(5) {
+                return Err(Transient(error).into());
+            }
+            return Err(error);
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl Storage for RcloneStorage {
+    fn list(&self) -> Result<Vec<String>> {
+        let output = Self::run(["lsf", "--files-only", &self.remote])?;
+        let output = String::from_utf8(output).context("file names not utf-8")?;
+        Ok(output.lines().map(str::to_owned).collect())
+    }
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        let command = Self::command(["cat", &self.path(name)]);
+        Ok(Box::new(ProcessReader::spawn(command, name)?))
+    }
+
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        let command = Self::command(["rcat", &self.path(name)]);
+        let writer = ProcessWriter::spawn(command, name)?;
+        Ok(Box::new(Throttle::new(writer, self.bwlimit)))
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        Self::run(["deletefile", &self.path(name)])?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        Self::run(["moveto", &self.path(from), &self.path(to)])?;
+        Ok(())
+    }
+
+    fn locate(&self, name: &str) -> PathBuf {
+        PathBuf::from(format!("{}/{name}", self.url))
+    }
+}
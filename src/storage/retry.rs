@@ -0,0 +1,235 @@
+use super::{Storage, TempFile, Transient, Upload};
+use crate::config;
+use anyhow::{Context, Result};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// Retries the operations of another storage after transient failures (see [`is_transient`]),
+/// with exponential backoff.
+///
+/// Uploads and downloads are buffered in a temporary file so they can be sent again, so each
+/// takes as much space in the temporary directory (`TMPDIR`) as the file being sent. Uploads
+/// resume where they stopped if the storage supports it.
+pub struct RetryStorage {
+    inner: Box<dyn Storage>,
+    policy: config::Retry,
+}
+
+impl RetryStorage {
+    #[must_use]
+    pub fn new(inner: Box<dyn Storage>, policy: config::Retry) -> Self {
+        Self { inner, policy }
+    }
+
+    fn retry<T>(&self, what: &str, mut operation: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut errors: Vec<String> = Vec::new();
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(error) if attempt >= self.policy.attempts || !is_transient(&error) => {
+                    let last = format!("{error:#}");
+                    errors.retain(|earlier| *earlier != last);
+                    return Err(error.context(summary(what, attempt, &errors)));
+                }
+                Err(error) => {
+                    errors.push(format!("{error:#}"));
+                    std::thread::sleep(self.policy.delay(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Check if `error` may go away by trying again: if it is [`Transient`], or an I/O error of a
+/// dropped or timed out connection.
+#[must_use]
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    use std::io::ErrorKind;
+    error.chain().any(|cause| {
+        cause.is::<Transient>()
+            || cause.downcast_ref::<std::io::Error>().is_some_and(|e| {
+                matches!(
+                    e.kind(),
+                    ErrorKind::ConnectionReset
+                        | ErrorKind::ConnectionAborted
+                        | ErrorKind::NotConnected
+                        | ErrorKind::BrokenPipe
+                        | ErrorKind::TimedOut
+                        | ErrorKind::Interrupted
+                        | ErrorKind::UnexpectedEof
+                )
+            })
+    })
+}
+
+/// Describe the errors of earlier attempts that differ from the last, which is reported in full.
+fn summary(what: &str, attempts: u32, earlier: &[String]) -> String {
+    let mut summary = format!("{what} failed after {attempts} attempt(s)");
+    let mut distinct = earlier.to_vec();
+    distinct.dedup();
+    for (i, error) in distinct.iter().enumerate() {
+        if i == 0 {
+            summary.push_str(", earlier errors: ");
+        } else {
+            summary.push_str("; ");
+        }
+        summary.push_str(error);
+    }
+    summary
+}
+
+impl Storage for RetryStorage {
+    fn list(&self) -> Result<Vec<String>> {
+        self.retry("list", || self.inner.list())
+    }
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        let mut buffer = TempFile::create(&format!("download-{name}"))?;
+        self.retry(&format!("read {name}"), || {
+            buffer.file.rewind().context("rewind download")?;
+            buffer.file.set_len(0).context("truncate download")?;
+            let mut reader = self.inner.read(name)?;
+            std::io::copy(&mut reader, &mut buffer.file).context("receive download")?;
+            Ok(())
+        })?;
+        buffer.file.rewind().context("rewind download")?;
+        Ok(Box::new(buffer))
+    }
+
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        Ok(Box::new(RetryUpload {
+            storage: self,
+            name: name.to_owned(),
+            buffer: TempFile::create(&format!("upload-{name}"))?,
+        }))
+    }
+
+    fn upload_file(&self, name: &str, path: &Path) -> Result<()> {
+        self.retry(&format!("upload {name}"), || {
+            self.inner.upload_file(name, path)
+        })
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        self.retry(&format!("delete {name}"), || self.inner.delete(name))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        self.retry(&format!("rename {from}"), || self.inner.rename(from, to))
+    }
+
+    fn locate(&self, name: &str) -> PathBuf {
+        self.inner.locate(name)
+    }
+
+    fn set_permissions(&self, name: &str, permissions: std::fs::Permissions) -> Result<()> {
+        self.retry(&format!("set permissions of {name}"), || {
+            self.inner.set_permissions(name, permissions.clone())
+        })
+    }
+}
+
+/// A file buffered on disk, then uploaded in full on every attempt.
+struct RetryUpload<'a> {
+    storage: &'a RetryStorage,
+    name: String,
+    buffer: TempFile,
+}
+
+impl Write for RetryUpload<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.buffer.file.flush()
+    }
+}
+
+impl Upload for RetryUpload<'_> {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.buffer.file.flush().context("flush upload")?;
+        self.storage.upload_file(&self.name, &self.buffer.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::{Span, SpanUnit};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Fails to delete with `error` the first `failures` times, counting every attempt.
+    struct Flaky {
+        failures: u32,
+        attempts: Rc<Cell<u32>>,
+        error: fn() -> anyhow::Error,
+    }
+
+    impl Storage for Flaky {
+        fn list(&self) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+
+        fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+            anyhow::bail!("no {name}")
+        }
+
+        fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+            anyhow::bail!("no {name}")
+        }
+
+        fn delete(&self, _name: &str) -> Result<()> {
+            self.attempts.set(self.attempts.get() + 1);
+            if self.attempts.get() <= self.failures {
+                return Err((self.error)());
+            }
+            Ok(())
+        }
+
+        fn rename(&self, _from: &str, _to: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn locate(&self, name: &str) -> PathBuf {
+            PathBuf::from(name)
+        }
+    }
+
+    /// Delete through a [`RetryStorage`] of three attempts, returning if it succeeded and after
+    /// how many attempts.
+    fn delete(failures: u32, error: fn() -> anyhow::Error) -> (bool, u32) {
+        let attempts = Rc::new(Cell::new(0));
+        let flaky = Flaky {
+            failures,
+            attempts: Rc::clone(&attempts),
+            error,
+        };
+        let policy = config::Retry {
+            backoff: Span {
+                amount: 0,
+                unit: SpanUnit::Second,
+            },
+            ..config::Retry::default()
+        };
+        let result = RetryStorage::new(Box::new(flaky), policy).delete("backup");
+        (result.is_ok(), attempts.get())
+    }
+
+    #[test]
+    fn retries_only_transient_errors() {
+        let transient = || Transient(anyhow::anyhow!("status 503: Slow Down")).into();
+        let dropped = || {
+            let error = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+            anyhow::Error::new(error).context("receive download")
+        };
+        let denied = || anyhow::anyhow!("status 403: Access Denied");
+        assert_eq!(delete(2, transient), (true, 3));
+        assert_eq!(delete(3, transient), (false, 3));
+        assert_eq!(delete(1, dropped), (true, 2));
+        assert_eq!(delete(1, denied), (false, 1));
+    }
+}
@@ -0,0 +1,473 @@
+use super::throttle::Throttle;
+use super::{Storage, TempFile, Transient, Upload};
+use crate::permissions::FilePermissions;
+use crate::state;
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// An S3-compatible bucket (AWS, MinIO, ...), addressed as `s3://bucket/prefix`.
+///
+/// Credentials are read from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and optionally
+/// `AWS_SESSION_TOKEN`, the region from `AWS_REGION` (default `us-east-1`) and the endpoint
+/// from `AWS_ENDPOINT_URL` (default AWS). Requests use path-style addressing.
+pub struct S3Storage {
+    url: String,
+    bucket: String,
+    prefix: String,
+    endpoint: String,
+    host: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+    agent: ureq::Agent,
+    bwlimit: Option<u64>,
+}
+
+impl S3Storage {
+    pub fn from_url(url: &str) -> Result<Self> {
+        let url = url.trim_end_matches('/');
+        let path = url.strip_prefix("s3://").context("expected an s3:// url")?;
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        anyhow::ensure!(!bucket.is_empty(), "missing bucket in {url:?}");
+        let region = std::env::var("AWS_REGION")
+            .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| String::from("us-east-1"));
+        let endpoint = std::env::var("AWS_ENDPOINT_URL")
+            .unwrap_or_else(|_| format!("https://s3.{region}.amazonaws.com"));
+        let endpoint = endpoint.trim_end_matches('/').to_owned();
+        let host = endpoint
+            .split_once("://")
+            .map_or(endpoint.as_str(), |(_scheme, host)| host)
+            .to_owned();
+        Ok(Self {
+            url: url.to_owned(),
+            bucket: bucket.to_owned(),
+            prefix: prefix.to_owned(),
+            endpoint,
+            host,
+            region,
+            access_key: std::env::var("AWS_ACCESS_KEY_ID").context("missing AWS_ACCESS_KEY_ID")?,
+            secret_key: std::env::var("AWS_SECRET_ACCESS_KEY")
+                .context("missing AWS_SECRET_ACCESS_KEY")?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            agent: ureq::Agent::new(),
+            bwlimit: None,
+        })
+    }
+
+    /// Limit uploads to `bwlimit` bytes per second.
+    #[must_use]
+    pub fn with_bwlimit(mut self, bwlimit: Option<u64>) -> Self {
+        self.bwlimit = bwlimit;
+        self
+    }
+
+    fn key(&self, name: &str) -> String {
+        if self.prefix.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}/{name}", self.prefix)
+        }
+    }
+
+    /// A request signed with AWS Signature Version 4.
+    fn request(
+        &self,
+        method: &str,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        payload_hash: &str,
+    ) -> ureq::Request {
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let mut uri = format!("/{}", uri_encode(&self.bucket, false));
+        if let Some(key) = key {
+            uri.push('/');
+            uri.push_str(&uri_encode(key, true));
+        }
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(k, v)| (uri_encode(k, false), uri_encode(v, false)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join("&");
+        let mut signed: Vec<(String, String)> = vec![
+            (String::from("host"), self.host.clone()),
+            (
+                String::from("x-amz-content-sha256"),
+                payload_hash.to_owned(),
+            ),
+            (String::from("x-amz-date"), amz_date.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            signed.push((String::from("x-amz-security-token"), token.clone()));
+        }
+        for (name, value) in headers {
+            signed.push((name.to_lowercase(), (*value).to_owned()));
+        }
+        signed.sort();
+        let canonical_headers: String = signed
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect();
+        let signed_headers = signed
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{method}\n{uri}\n{query}\n{canonical_headers}\n{signed_headers}\n{payload_hash}"
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut signing_key = format!("AWS4{}", self.secret_key).into_bytes();
+        for part in [date.as_str(), self.region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac(&signing_key, part.as_bytes());
+        }
+        let signature = hex::encode(hmac(&signing_key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, \
+             Signature={signature}",
+            self.access_key
+        );
+        let mut url = format!("{}{uri}", self.endpoint);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query);
+        }
+        let mut request = self
+            .agent
+            .request(method, &url)
+            .set("Authorization", &authorization);
+        for (name, value) in &signed {
+            if name != "host" {
+                request = request.set(name, value);
+            }
+        }
+        request
+    }
+}
+
+/// Progress of a multipart upload, to resume it after a failure.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MultipartState {
+    upload_id: Option<String>,
+    /// ETags of the uploaded parts, in order
+    etags: Vec<String>,
+}
+
+impl S3Storage {
+    /// Upload a large file in parts, resuming from the state at `state_path` if any.
+    fn upload_multipart(
+        &self,
+        name: &str,
+        mut file: &std::fs::File,
+        length: u64,
+        state_path: &Path,
+    ) -> Result<()> {
+        let key = self.key(name);
+        let mut state: MultipartState = state::load_toml(state_path)?;
+        let upload_id = match &state.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let body = self
+                    .request(
+                        "POST",
+                        Some(&key),
+                        &[("uploads", "")],
+                        &[],
+                        EMPTY_PAYLOAD_HASH,
+                    )
+                    .call()
+                    .map_err(status_error)
+                    .with_context(|| format!("start upload of {name}"))?
+                    .into_string()
+                    .context("read upload id")?;
+                let upload_id = xml_values(&body, "UploadId")
+                    .into_iter()
+                    .next()
+                    .context("missing upload id")?;
+                state.upload_id = Some(upload_id.clone());
+                state::save_toml(state_path, &state, &FilePermissions::default())?;
+                upload_id
+            }
+        };
+        let parts = length.div_ceil(PART_SIZE);
+        for index in state.etags.len() as u64..parts {
+            let offset = index * PART_SIZE;
+            let size = PART_SIZE.min(length - offset);
+            file.seek(std::io::SeekFrom::Start(offset))
+                .context("seek upload")?;
+            let part_number = (index + 1).to_string();
+            let query = [
+                ("partNumber", part_number.as_str()),
+                ("uploadId", &upload_id),
+            ];
+            let response = self
+                .request("PUT", Some(&key), &query, &[], "UNSIGNED-PAYLOAD")
+                .set("Content-Length", &size.to_string())
+                .send(Throttle::new(file.take(size), self.bwlimit))
+                .map_err(status_error)
+                .with_context(|| format!("upload part {part_number} of {name}"))?;
+            let etag = response.header("ETag").context("missing part ETag")?;
+            state.etags.push(etag.to_owned());
+            state::save_toml(state_path, &state, &FilePermissions::default())?;
+        }
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (index, etag) in state.etags.iter().enumerate() {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{etag}</ETag></Part>",
+                index + 1
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        let payload_hash = hex::encode(Sha256::digest(body.as_bytes()));
+        let response = self
+            .request(
+                "POST",
+                Some(&key),
+                &[("uploadId", &upload_id)],
+                &[],
+                &payload_hash,
+            )
+            .send_string(&body)
+            .map_err(status_error)
+            .with_context(|| format!("complete upload of {name}"))?
+            .into_string()
+            .context("read upload response")?;
+        // Completing can fail after the response status was sent
+        if response.contains("<Error>") {
+            let message = xml_values(&response, "Message").into_iter().next();
+            anyhow::bail!("complete upload of {name}: {}", message.unwrap_or(response));
+        }
+        std::fs::remove_file(state_path).context("remove upload state")?;
+        Ok(())
+    }
+}
+
+impl Storage for S3Storage {
+    fn list(&self) -> Result<Vec<String>> {
+        let prefix = if self.prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", self.prefix)
+        };
+        let mut names = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("list-type", "2"),
+                ("prefix", prefix.as_str()),
+                ("delimiter", "/"),
+            ];
+            if let Some(token) = &continuation {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let body = self
+                .request("GET", None, &query, &[], EMPTY_PAYLOAD_HASH)
+                .call()
+                .map_err(status_error)
+                .context("list objects")?
+                .into_string()
+                .context("read object listing")?;
+            for key in xml_values(&body, "Key") {
+                if let Some(name) = key.strip_prefix(&prefix) {
+                    names.push(name.to_owned());
+                }
+            }
+            let truncated = xml_values(&body, "IsTruncated")
+                .first()
+                .is_some_and(|value| value == "true");
+            continuation = xml_values(&body, "NextContinuationToken")
+                .into_iter()
+                .next();
+            if !truncated || continuation.is_none() {
+                break;
+            }
+        }
+        Ok(names)
+    }
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        let response = self
+            .request("GET", Some(&self.key(name)), &[], &[], EMPTY_PAYLOAD_HASH)
+            .call()
+            .map_err(status_error)
+            .with_context(|| format!("get {name}"))?;
+        Ok(response.into_reader())
+    }
+
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        Ok(Box::new(S3Upload {
+            storage: self,
+            name: name.to_owned(),
+            buffer: TempFile::create(&format!("upload-{name}"))?,
+        }))
+    }
+
+    fn upload_file(&self, name: &str, path: &Path) -> Result<()> {
+        let file = std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+        let length = file.metadata().context("get upload size")?.len();
+        if length <= PART_SIZE {
+            self.request("PUT", Some(&self.key(name)), &[], &[], "UNSIGNED-PAYLOAD")
+                .set("Content-Length", &length.to_string())
+                .send(Throttle::new(&file, self.bwlimit))
+                .map_err(status_error)
+                .with_context(|| format!("upload {name}"))?;
+            return Ok(());
+        }
+        let state_path = super::upload_state_path(&self.locate(name), path)?;
+        self.upload_multipart(name, &file, length, &state_path)
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        self.request(
+            "DELETE",
+            Some(&self.key(name)),
+            &[],
+            &[],
+            EMPTY_PAYLOAD_HASH,
+        )
+        .call()
+        .map_err(status_error)
+        .with_context(|| format!("delete {name}"))?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let source = format!("/{}/{}", self.bucket, uri_encode(&self.key(from), true));
+        self.request(
+            "PUT",
+            Some(&self.key(to)),
+            &[],
+            &[("x-amz-copy-source", &source)],
+            EMPTY_PAYLOAD_HASH,
+        )
+        .call()
+        .map_err(status_error)
+        .with_context(|| format!("copy {from} to {to}"))?;
+        self.delete(from)
+    }
+
+    fn locate(&self, name: &str) -> PathBuf {
+        PathBuf::from(format!("{}/{name}", self.url))
+    }
+}
+
+/// A file buffered on disk and uploaded when finished, since S3 needs the length up front.
+struct S3Upload<'a> {
+    storage: &'a S3Storage,
+    name: String,
+    buffer: TempFile,
+}
+
+impl Write for S3Upload<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.buffer.file.flush()
+    }
+}
+
+impl Upload for S3Upload<'_> {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.buffer.file.flush().context("flush upload")?;
+        self.storage.upload_file(&self.name, &self.buffer.path)
+    }
+}
+
+/// Files larger than this are uploaded in parts of this size, so a failed upload can resume.
+const PART_SIZE: u64 = 64 << 20;
+
+/// SHA-256 of an empty request body.
+const EMPTY_PAYLOAD_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode everything but unreserved characters (and `/` in paths).
+fn uri_encode(s: &str, keep_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char);
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// The text of every `<tag>` element in `xml`, unescaped.
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        let value = rest[..end]
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&");
+        values.push(value);
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+/// Include the response body of failed requests in the error.
+fn status_error(error: ureq::Error) -> anyhow::Error {
+    match error {
+        ureq::Error::Status(code, response) => {
+            let body = response.into_string().unwrap_or_default();
+            let message = xml_values(&body, "Message").into_iter().next();
+            let error = anyhow::anyhow!("status {code}: {}", message.unwrap_or(body));
+            if code >= 500 || code == 429 {
+                Transient(error).into()
+            } else {
+                error
+            }
+        }
+        error => Transient(error.into()).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_and_parsing() {
+        assert_eq!(uri_encode("a b/c~d", true), "a%20b/c~d");
+        assert_eq!(uri_encode("a/b", false), "a%2Fb");
+        let xml = "<R><Key>a&amp;b</Key><Key>c</Key></R>";
+        assert_eq!(xml_values(xml, "Key"), ["a&b", "c"]);
+    }
+}
@@ -0,0 +1,248 @@
+use super::process::{ProcessReader, ProcessWriter};
+use super::throttle::Throttle;
+use super::{Storage, Transient, Upload};
+use anyhow::{Context, Result};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A directory on a server reachable over SSH, addressed as `sftp://[user@]host[:port]/path`
+/// (with IPv6 hosts in brackets, as in `sftp://[::1]:22/path`).
+///
+/// Runs the system `ssh` client, so keys, agents and `~/.ssh/config` work as usual. Paths
+/// starting with `~/` are relative to the remote home directory.
+pub struct SshStorage {
+    url: String,
+    destination: String,
+    port: Option<String>,
+    dir: String,
+    bwlimit: Option<u64>,
+}
+
+impl SshStorage {
+    pub fn from_url(url: &str) -> Result<Self> {
+        let url = url.trim_end_matches('/');
+        let (_scheme, rest) = url.split_once("://").context("expected an sftp:// url")?;
+        let (authority, dir) = rest.split_once('/').unwrap_or((rest, ""));
+        anyhow::ensure!(!authority.is_empty(), "missing host in {url:?}");
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (Some(user), host_port),
+            None => (None, authority),
+        };
+        let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+            let (host, rest) = (bracketed.split_once(']')).context("unclosed '[' in the host")?;
+            match rest {
+                "" => (host, None),
+                _ => (
+                    host,
+                    Some(rest.strip_prefix(':').context("expected ':' after ']'")?),
+                ),
+            }
+        } else {
+            match host_port.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            }
+        };
+        anyhow::ensure!(!host.is_empty(), "missing host in {url:?}");
+        if let Some(port) = port {
+            (port.parse::<u16>()).with_context(|| format!("invalid port {port:?} in {url:?}"))?;
+        }
+        let destination = match user {
+            Some(user) => format!("{user}@{host}"),
+            None => host.to_owned(),
+        };
+        let dir = match dir.strip_prefix('~') {
+            Some(home_relative) => home_relative.trim_start_matches('/').to_owned(),
+            None => format!("/{dir}"),
+        };
+        Ok(Self {
+            url: url.to_owned(),
+            destination,
+            port: port.map(str::to_owned),
+            dir,
+            bwlimit: None,
+        })
+    }
+
+    /// Limit uploads to `bwlimit` bytes per second.
+    #[must_use]
+    pub fn with_bwlimit(mut self, bwlimit: Option<u64>) -> Self {
+        self.bwlimit = bwlimit;
+        self
+    }
+
+    fn path(&self, name: &str) -> String {
+        match self.dir.as_str() {
+            "" => name.to_owned(),
+            "/" => format!("/{name}"),
+            dir => format!("{dir}/{name}"),
+        }
+    }
+
+    /// A command running `remote_command` on the server.
+    fn command(&self, remote_command: &str) -> Command {
+        let mut command = Command::new("ssh");
+        if let Some(port) = &self.port {
+            command.args(["-p", port]);
+        }
+        // A destination starting with `-` is not an option
+        command.arg("--").arg(&self.destination).arg(remote_command);
+        command
+    }
+
+    fn run(&self, remote_command: &str) -> Result<Vec<u8>> {
+        let output = self
+            .command(remote_command)
+            .stdin(Stdio::null())
+            .output()
+            .context("run ssh")?;
+        if !output.status.success() {
+            let error = anyhow::anyhow!(
+                "{remote_command:?} failed on {}: {}",
+                self.destination,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            // ssh exits with 255 if it couldn't connect, rather than the command failing
+            if output.status.code() == Some(255) {
+                return Err(Transient(error).into());
+            }
+            return Err(error);
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl Storage for SshStorage {
+    fn list(&self) -> Result<Vec<String>> {
+        let dir = if self.dir.is_empty() { "." } else { &self.dir };
+        let output = self.run(&format!("ls -1A -- {}", quote(dir)))?;
+        let output = String::from_utf8(output).context("file names not utf-8")?;
+        Ok(output.lines().map(str::to_owned).collect())
+    }
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        let command = self.command(&format!("cat -- {}", quote(&self.path(name))));
+        Ok(Box::new(ProcessReader::spawn(command, name)?))
+    }
+
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        let partial = self.path(&format!("{name}.partial"));
+        let command = self.command(&format!("cat > {}", quote(&partial)));
+        Ok(Box::new(SshUpload {
+            storage: self,
+            writer: Throttle::new(ProcessWriter::spawn(command, name)?, self.bwlimit),
+            partial,
+            name: name.to_owned(),
+        }))
+    }
+
+    fn upload_file(&self, name: &str, path: &Path) -> Result<()> {
+        let partial = self.path(&format!("{name}.partial"));
+        let state_path = super::upload_state_path(&self.locate(name), path)?;
+        let mut file =
+            std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+        let length = file.metadata().context("get upload size")?.len();
+        // Only resume a partial file this upload started, never one left by another
+        let mut offset = 0;
+        if state_path.exists() {
+            let output = self.run(&format!(
+                "if [ -f {partial} ]; then wc -c < {partial}; else echo 0; fi",
+                partial = quote(&partial)
+            ))?;
+            let size = String::from_utf8_lossy(&output).trim().parse::<u64>();
+            offset = size.ok().filter(|size| *size <= length).unwrap_or(0);
+        } else {
+            std::fs::write(&state_path, &partial)
+                .with_context(|| format!("write {}", state_path.display()))?;
+        }
+        file.seek(std::io::SeekFrom::Start(offset))
+            .context("seek upload")?;
+        let redirect = if offset == 0 { ">" } else { ">>" };
+        let command = self.command(&format!("cat {redirect} {}", quote(&partial)));
+        let mut writer = Throttle::new(ProcessWriter::spawn(command, name)?, self.bwlimit);
+        std::io::copy(&mut file, &mut writer).with_context(|| format!("upload {name}"))?;
+        writer.get_mut().finish()?;
+        let target = quote(&self.path(name));
+        self.run(&format!("mv -- {} {target}", quote(&partial)))?;
+        std::fs::remove_file(&state_path).context("remove upload state")?;
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        self.run(&format!("rm -- {}", quote(&self.path(name))))?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let (from, to) = (quote(&self.path(from)), quote(&self.path(to)));
+        self.run(&format!("mv -- {from} {to}"))?;
+        Ok(())
+    }
+
+    fn locate(&self, name: &str) -> PathBuf {
+        PathBuf::from(format!("{}/{name}", self.url))
+    }
+}
+
+/// Streams to a `.partial` file, renamed into place when finished.
+struct SshUpload<'a> {
+    storage: &'a SshStorage,
+    writer: Throttle<ProcessWriter>,
+    partial: String,
+    name: String,
+}
+
+impl Write for SshUpload<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Upload for SshUpload<'_> {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.writer.get_mut().finish()?;
+        let target = quote(&self.storage.path(&self.name));
+        self.storage
+            .run(&format!("mv -- {} {target}", quote(&self.partial)))?;
+        Ok(())
+    }
+}
+
+/// Quote `s` for a POSIX shell.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_urls() {
+        let parse = |url| {
+            let storage = SshStorage::from_url(url).unwrap();
+            (storage.destination, storage.port, storage.dir)
+        };
+        let some = |port: &str| Some(port.to_owned());
+        assert_eq!(
+            parse("sftp://user@host:2222/backups/"),
+            ("user@host".into(), some("2222"), "/backups".into())
+        );
+        assert_eq!(parse("sftp://host/~/b"), ("host".into(), None, "b".into()));
+        assert_eq!(
+            parse("sftp://user@[::1]:22/b"),
+            ("user@::1".into(), some("22"), "/b".into())
+        );
+        assert_eq!(
+            parse("sftp://[fe80::1]"),
+            ("fe80::1".into(), None, "/".into())
+        );
+        assert!(SshStorage::from_url("sftp://[::1/b").is_err());
+        assert!(SshStorage::from_url("sftp://host:ssh/b").is_err());
+    }
+}
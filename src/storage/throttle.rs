@@ -0,0 +1,76 @@
+use super::Upload;
+use anyhow::Result;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// Limits the rate of data read from or written to the wrapped value, if a limit is set.
+pub struct Throttle<T> {
+    inner: T,
+    bytes_per_second: Option<u64>,
+    start: Instant,
+    transferred: u64,
+}
+
+impl<T> Throttle<T> {
+    pub fn new(inner: T, bytes_per_second: Option<u64>) -> Self {
+        Self {
+            inner,
+            bytes_per_second,
+            start: Instant::now(),
+            transferred: 0,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// How much of `len` bytes to transfer at once, so the rate stays smooth.
+    fn chunk(&self, len: usize) -> usize {
+        self.bytes_per_second.map_or(len, |limit| {
+            let chunk = usize::try_from(limit / 10).unwrap_or(usize::MAX);
+            len.min(chunk.max(1))
+        })
+    }
+
+    /// Account for `amount` transferred bytes, sleeping until they are due.
+    #[allow(clippy::cast_precision_loss)]
+    fn pace(&mut self, amount: usize) {
+        let Some(limit) = self.bytes_per_second.filter(|limit| *limit > 0) else {
+            return;
+        };
+        self.transferred += amount as u64;
+        let due = Duration::from_secs_f64(self.transferred as f64 / limit as f64);
+        if let Some(wait) = due.checked_sub(self.start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+impl<T: Read> Read for Throttle<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let chunk = self.chunk(buf.len());
+        let read = self.inner.read(&mut buf[..chunk])?;
+        self.pace(read);
+        Ok(read)
+    }
+}
+
+impl<T: Write> Write for Throttle<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let chunk = self.chunk(buf.len());
+        let written = self.inner.write(&buf[..chunk])?;
+        self.pace(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Upload> Upload for Throttle<T> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        Box::new(self.inner).finish()
+    }
+}
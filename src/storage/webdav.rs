@@ -0,0 +1,286 @@
+use super::throttle::Throttle;
+use super::{Storage, TempFile, Transient, Upload};
+use anyhow::{Context, Result};
+use base64::Engine;
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+
+/// A WebDAV collection (Nextcloud, NAS devices, ...), addressed as `davs://[user@]host/path`
+/// (HTTPS) or `dav://[user@]host/path` (plain HTTP).
+///
+/// The password is read from `WEBDAV_PASSWORD` and sent with basic authentication.
+pub struct WebDavStorage {
+    url: String,
+    /// The collection url, ending with `/`
+    base: String,
+    authorization: Option<String>,
+    agent: ureq::Agent,
+    bwlimit: Option<u64>,
+}
+
+impl WebDavStorage {
+    pub fn from_url(url: &str) -> Result<Self> {
+        let url = url.trim_end_matches('/');
+        let (scheme, rest) = url.split_once("://").context("expected a dav:// url")?;
+        let scheme = match scheme {
+            "dav" => "http",
+            "davs" => "https",
+            _ => anyhow::bail!("expected a dav:// or davs:// url"),
+        };
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (user, host) = match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(user), host),
+            None => (None, authority),
+        };
+        anyhow::ensure!(!host.is_empty(), "missing host in {url:?}");
+        let password = std::env::var("WEBDAV_PASSWORD").ok();
+        let authorization = match (user, password) {
+            (None, None) => None,
+            (user, password) => {
+                let credentials =
+                    format!("{}:{}", user.unwrap_or(""), password.unwrap_or_default());
+                let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+                Some(format!("Basic {encoded}"))
+            }
+        };
+        let mut base = format!("{scheme}://{host}/");
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            base.push_str(&percent_encode(segment));
+            base.push('/');
+        }
+        Ok(Self {
+            url: url.to_owned(),
+            base,
+            authorization,
+            agent: ureq::Agent::new(),
+            bwlimit: None,
+        })
+    }
+
+    /// Limit uploads to `bwlimit` bytes per second.
+    #[must_use]
+    pub fn with_bwlimit(mut self, bwlimit: Option<u64>) -> Self {
+        self.bwlimit = bwlimit;
+        self
+    }
+
+    fn request(&self, method: &str, name: &str) -> ureq::Request {
+        let url = format!("{}{}", self.base, percent_encode(name));
+        let request = self.agent.request(method, &url);
+        match &self.authorization {
+            Some(authorization) => request.set("Authorization", authorization),
+            None => request,
+        }
+    }
+}
+
+impl Storage for WebDavStorage {
+    fn list(&self) -> Result<Vec<String>> {
+        let body = self
+            .request("PROPFIND", "")
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml")
+            .send_string(PROPFIND_BODY)
+            .map_err(status_error)
+            .context("list collection")?
+            .into_string()
+            .context("read collection listing")?;
+        let mut names = Vec::new();
+        for response in elements(&body, "response") {
+            if !elements(&response, "collection").is_empty() {
+                continue;
+            }
+            let Some(href) = elements(&response, "href").into_iter().next() else {
+                continue;
+            };
+            let href = percent_decode(href.trim())?;
+            if let Some(name) = href.trim_end_matches('/').rsplit('/').next() {
+                names.push(name.to_owned());
+            }
+        }
+        Ok(names)
+    }
+
+    fn read(&self, name: &str) -> Result<Box<dyn Read + '_>> {
+        let response = self
+            .request("GET", name)
+            .call()
+            .map_err(status_error)
+            .with_context(|| format!("get {name}"))?;
+        Ok(response.into_reader())
+    }
+
+    fn write(&self, name: &str) -> Result<Box<dyn Upload + '_>> {
+        Ok(Box::new(WebDavUpload {
+            storage: self,
+            name: name.to_owned(),
+            buffer: TempFile::create(&format!("upload-{name}"))?,
+        }))
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        self.request("DELETE", name)
+            .call()
+            .map_err(status_error)
+            .with_context(|| format!("delete {name}"))?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let destination = format!("{}{}", self.base, percent_encode(to));
+        self.request("MOVE", from)
+            .set("Destination", &destination)
+            .set("Overwrite", "T")
+            .call()
+            .map_err(status_error)
+            .with_context(|| format!("move {from} to {to}"))?;
+        Ok(())
+    }
+
+    fn locate(&self, name: &str) -> PathBuf {
+        PathBuf::from(format!("{}/{name}", self.url))
+    }
+}
+
+/// A file buffered on disk and uploaded when finished, since some servers refuse chunked uploads.
+struct WebDavUpload<'a> {
+    storage: &'a WebDavStorage,
+    name: String,
+    buffer: TempFile,
+}
+
+impl Write for WebDavUpload<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.buffer.file.flush()
+    }
+}
+
+impl Upload for WebDavUpload<'_> {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let length = self
+            .buffer
+            .file
+            .stream_position()
+            .context("get upload size")?;
+        self.buffer.file.rewind().context("rewind upload")?;
+        self.storage
+            .request("PUT", &self.name)
+            .set("Content-Length", &length.to_string())
+            .send(Throttle::new(&self.buffer.file, self.storage.bwlimit))
+            .map_err(status_error)
+            .with_context(|| format!("upload {}", self.name))?;
+        Ok(())
+    }
+}
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<propfind xmlns="DAV:"><prop><resourcetype/></prop></propfind>"#;
+
+/// Percent-encode everything but unreserved characters.
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char);
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Result<String> {
+    let mut decoded = Vec::new();
+    let mut bytes = s.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+            let hex = std::str::from_utf8(&hex).context("invalid percent-encoding")?;
+            decoded.push(u8::from_str_radix(hex, 16).context("invalid percent-encoding")?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    String::from_utf8(decoded).context("file name not utf-8")
+}
+
+/// The contents of every element named `name` in `xml`, whatever its namespace prefix.
+fn elements(xml: &str, name: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+        let Some(tag_name) = tag.trim_end_matches('/').split_whitespace().next() else {
+            continue;
+        };
+        let local_name = tag_name.rsplit(':').next().unwrap_or(tag_name);
+        if local_name != name || tag.starts_with('/') {
+            continue;
+        }
+        if tag.ends_with('/') {
+            values.push(String::new());
+            continue;
+        }
+        let close = format!("</{tag_name}>");
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        let value = rest[..end]
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&");
+        values.push(value);
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+/// Include the status text of failed requests in the error.
+fn status_error(error: ureq::Error) -> anyhow::Error {
+    match error {
+        ureq::Error::Status(code, response) => {
+            let error = anyhow::anyhow!("status {code}: {}", response.status_text());
+            if code >= 500 || code == 429 {
+                Transient(error).into()
+            } else {
+                error
+            }
+        }
+        error => Transient(error.into()).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_propfind() {
+        let xml = r#"<d:multistatus xmlns:d="DAV:">
+            <d:response><d:href>/dav/backups/</d:href>
+                <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop>
+                </d:propstat></d:response>
+            <d:response><d:href>/dav/backups/a%20b.tar.gz</d:href>
+                <d:propstat><d:prop><d:resourcetype/></d:prop></d:propstat></d:response>
+            </d:multistatus>"#;
+        let responses = elements(xml, "response");
+        assert_eq!(responses.len(), 2);
+        assert_eq!(elements(&responses[0], "collection").len(), 1);
+        assert!(elements(&responses[1], "collection").is_empty());
+        let href = elements(&responses[1], "href").remove(0);
+        assert_eq!(percent_decode(&href).unwrap(), "/dav/backups/a b.tar.gz");
+        assert_eq!(percent_encode("a b"), "a%20b");
+    }
+}
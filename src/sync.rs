@@ -0,0 +1,162 @@
+use crate::config;
+use crate::entry::{self, Entry};
+use crate::event::{Event, OnEvent};
+use crate::meta;
+use crate::pin::Pins;
+use crate::storage::{self, Storage};
+use crate::timestamp::Timestamp;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+/// What it takes to make a destination target match a source target.
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    /// Backups missing from the destination
+    pub copy: Vec<Entry>,
+    /// Backups in the destination that are not in the source
+    pub delete: Vec<Entry>,
+    /// Backups in the destination that are not in the source, but are pinned or have a tag in
+    /// `delete.keep_tags`, so they are not deleted
+    pub kept: Vec<Entry>,
+    /// Backups with the same timestamp but different checksums, as (source, destination)
+    pub conflicts: Vec<(Entry, Entry)>,
+}
+
+/// Compare the backups of `src` and `dst` by timestamp, and by checksum where both are recorded.
+///
+/// Backups only in the destination are planned for deletion if `propagate_deletes`, unless they
+/// are pinned by the destination's `pins` at `now` or have a tag in `delete.keep_tags`.
+pub fn plan(
+    src: &dyn Storage,
+    dst: &dyn Storage,
+    config: &config::Config,
+    pins: &Pins,
+    now: Timestamp,
+    propagate_deletes: bool,
+) -> Result<SyncPlan> {
+    let archive = &config.archive;
+    let src_backups = entry::read_storage(src, archive).context("read source backups")?;
+    let dst_backups = entry::read_storage(dst, archive).context("read destination backups")?;
+    let mut dst_by_time: HashMap<Timestamp, Entry> = dst_backups
+        .into_iter()
+        .map(|backup| (backup.timestamp, backup))
+        .collect();
+    let mut plan = SyncPlan::default();
+    for backup in src_backups {
+        let Some(existing) = dst_by_time.remove(&backup.timestamp) else {
+            plan.copy.push(backup);
+            continue;
+        };
+        let checksum = |b: &Entry| b.meta.as_ref().and_then(|m| m.checksum.clone());
+        if let (Some(a), Some(b)) = (checksum(&backup), checksum(&existing))
+            && a != b
+        {
+            plan.conflicts.push((backup, existing));
+        }
+    }
+    if propagate_deletes {
+        let is_kept = |backup: &Entry| {
+            pins.active(backup.timestamp, now).is_some()
+                || (backup.tags().iter()).any(|tag| config.delete.keep_tags.contains(tag))
+        };
+        (plan.kept, plan.delete) = dst_by_time.into_values().partition(is_kept);
+        plan.kept.sort();
+        plan.delete.sort();
+    }
+    Ok(plan)
+}
+
+/// Copy the backup `name`, and its sidecar if `with_sidecar`, between storages.
+pub fn copy_backup(
+    from: &dyn Storage,
+    to: &dyn Storage,
+    name: &str,
+    with_sidecar: bool,
+) -> Result<()> {
+    storage::copy(from, to, name)?;
+    if with_sidecar {
+        storage::copy(from, to, &format!("{name}{}", meta::SIDECAR_SUFFIX))?;
+    }
+    Ok(())
+}
+
+/// Delete a backup and its sidecar, reporting it to `on_event`.
+pub fn delete_backup(
+    storage: &dyn Storage,
+    backup: &Entry,
+    on_event: Option<OnEvent>,
+) -> Result<()> {
+    let name = backup.name()?;
+    let _span = tracing::info_span!("delete", %name).entered();
+    storage.delete(&name)?;
+    if backup.meta.is_some() {
+        storage.delete(&format!("{name}{}", meta::SIDECAR_SUFFIX))?;
+    }
+    tracing::debug!("deleted backup");
+    if let Some(on_event) = on_event {
+        on_event(&Event::Deleted {
+            path: backup.path.clone(),
+            timestamp: backup.timestamp,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::Backup;
+    use crate::storage::LocalStorage;
+
+    #[test]
+    fn plans_copies_deletes_and_conflicts() {
+        let temp = tempfile::tempdir().unwrap();
+        let (src_dir, dst_dir) = (temp.path().join("src"), temp.path().join("dst"));
+        let (src, dst) = (LocalStorage::new(&src_dir), LocalStorage::new(&dst_dir));
+        let mut config = config::Config::default();
+        config.delete.keep_tags = vec![String::from("keep")];
+        let at =
+            |hour: u32| -> Timestamp { format!("2025-01-01_{hour:02}-00-00").parse().unwrap() };
+        let add = |storage: &LocalStorage, hour, contents: &'static str, tag: Option<&str>| {
+            let mut backup = Backup::reader(contents.as_bytes(), "notes")
+                .storage(storage)
+                .config(&config.archive)
+                .at(at(hour));
+            if let Some(tag) = tag {
+                backup = backup.tag(tag);
+            }
+            backup.run().unwrap();
+        };
+        std::fs::create_dir_all(&src_dir).unwrap();
+        std::fs::create_dir_all(&dst_dir).unwrap();
+        add(&src, 1, "same", None);
+        add(&dst, 1, "same", None);
+        add(&src, 2, "missing", None);
+        add(&src, 3, "source", None);
+        add(&dst, 3, "destination", None);
+        add(&dst, 4, "extra", None);
+        add(&dst, 5, "pinned", None);
+        add(&dst, 6, "tagged", Some("keep"));
+        let mut pins = Pins::default();
+        pins.pin(at(5), None);
+        let now = at(12);
+
+        let timestamps =
+            |backups: &[Entry]| backups.iter().map(|b| b.timestamp).collect::<Vec<_>>();
+        let planned = plan(&src, &dst, &config, &pins, now, true).unwrap();
+        assert_eq!(timestamps(&planned.copy), [at(2)]);
+        assert_eq!(timestamps(&planned.delete), [at(4)]);
+        assert_eq!(timestamps(&planned.kept), [at(5), at(6)]);
+        let conflicts: Vec<_> = (planned.conflicts.iter())
+            .map(|(a, b)| (a.timestamp, b.timestamp))
+            .collect();
+        assert_eq!(conflicts, [(at(3), at(3))]);
+
+        let without_deletes = plan(&src, &dst, &config, &pins, now, false).unwrap();
+        assert!(without_deletes.delete.is_empty() && without_deletes.kept.is_empty());
+        // An expired pin no longer keeps its backup
+        pins.pin(at(5), Some(at(7)));
+        let expired = plan(&src, &dst, &config, &pins, now, true).unwrap();
+        assert_eq!(timestamps(&expired.delete), [at(4), at(5)]);
+    }
+}
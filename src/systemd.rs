@@ -0,0 +1,120 @@
+//! Service and timer units for running backups on a schedule with systemd.
+
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// What the generated units back up, where to, and when.
+#[derive(Debug, Clone)]
+pub struct UnitSettings<'a> {
+    /// The rattlebeaver binary to run
+    pub binary: &'a Path,
+    /// Target directory or url
+    pub target: &'a str,
+    /// Config file, if not the default one in the target directory
+    pub config: Option<&'a Path>,
+    /// Files or directories to back up
+    pub sources: &'a [PathBuf],
+    /// When to run, as a systemd calendar event (e.g. `daily` or `Mon *-*-* 03:00`)
+    pub schedule: &'a str,
+    /// Also delete stale backups after adding
+    pub delete: bool,
+}
+
+/// The contents of a service unit and the timer unit that starts it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Units {
+    pub service: String,
+    pub timer: String,
+}
+
+impl Units {
+    pub fn new(settings: &UnitSettings) -> Result<Self> {
+        anyhow::ensure!(!settings.sources.is_empty(), "no sources to back up");
+        anyhow::ensure!(
+            !settings.schedule.chars().any(char::is_control),
+            "schedule must be a single line"
+        );
+        let mut command = format!("{} -t {}", quote(settings.binary), quote(settings.target));
+        if let Some(config) = settings.config {
+            write!(command, " --config {}", quote(config))?;
+        }
+        command.push_str(" add --timestamp now --force");
+        if settings.delete {
+            command.push_str(" --delete");
+        }
+        for source in settings.sources {
+            write!(command, " {}", quote(source))?;
+        }
+        let service = format!(
+            "[Unit]\n\
+             Description=Rolling backups with rattlebeaver\n\
+             \n\
+             [Service]\n\
+             Type=oneshot\n\
+             ExecStart={command}\n"
+        );
+        let timer = format!(
+            "[Unit]\n\
+             Description=Rolling backups with rattlebeaver ({schedule})\n\
+             \n\
+             [Timer]\n\
+             OnCalendar={schedule}\n\
+             Persistent=true\n\
+             \n\
+             [Install]\n\
+             WantedBy=timers.target\n",
+            schedule = settings.schedule,
+        );
+        Ok(Self { service, timer })
+    }
+
+    /// Write the units as `<name>.service` and `<name>.timer` in `dir`, returning their paths.
+    /// Existing units are only replaced if `overwrite` is set.
+    pub fn install(&self, dir: &Path, name: &str, overwrite: bool) -> Result<[PathBuf; 2]> {
+        std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+        let service_path = dir.join(format!("{name}.service"));
+        let timer_path = dir.join(format!("{name}.timer"));
+        for path in [&service_path, &timer_path] {
+            anyhow::ensure!(
+                overwrite || !path.exists(),
+                "{} already exists",
+                path.display()
+            );
+        }
+        // Units are read by systemd, and hold nothing secret
+        std::fs::write(&service_path, &self.service)
+            .with_context(|| format!("write {}", service_path.display()))?;
+        std::fs::write(&timer_path, &self.timer)
+            .with_context(|| format!("write {}", timer_path.display()))?;
+        Ok([service_path, timer_path])
+    }
+}
+
+/// The directory of system units, or of the units of the current user.
+pub fn unit_dir(system: bool) -> Result<PathBuf> {
+    if system {
+        return Ok(PathBuf::from("/etc/systemd/system"));
+    }
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .context("neither XDG_CONFIG_HOME nor HOME is set")?;
+    Ok(config.join("systemd").join("user"))
+}
+
+/// Quote `arg` for a systemd command line, where `%` starts a specifier and `$` a variable.
+fn quote(arg: impl AsRef<std::ffi::OsStr>) -> String {
+    let arg = arg.as_ref().to_string_lossy();
+    let mut quoted = String::from('"');
+    for c in arg.chars() {
+        match c {
+            '"' | '\\' => quoted.extend(['\\', c]),
+            '%' => quoted.push_str("%%"),
+            '$' => quoted.push_str("$$"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
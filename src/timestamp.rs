@@ -1,8 +1,10 @@
 use anyhow::{Context, Result};
-use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, Timelike};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, Timelike};
 use chronoutil::RelativeDuration;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub struct Timestamp(pub DateTime<Local>);
 
 impl AsRef<DateTime<Local>> for Timestamp {
@@ -26,6 +28,33 @@ impl Timestamp {
         Ok(Self(timestamp))
     }
 
+    /// Parse a user-provided timestamp: RFC 3339, a date (`2025-01-01`) or a date and time
+    /// (`2025-01-01 12:00`, `2025-01-01 12:00:00` or `2025-01-01_12-00-00`).
+    pub fn parse_input(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+            return Ok(Self(timestamp.with_timezone(&Local)));
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            let datetime = date.and_hms_opt(0, 0, 0).context("midnight")?;
+            return Self::from_naive(datetime);
+        }
+        for format in ["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d_%H-%M-%S"] {
+            if let Ok(datetime) = NaiveDateTime::parse_from_str(s, format) {
+                return Self::from_naive(datetime);
+            }
+        }
+        anyhow::bail!("invalid timestamp {s:?} (expected e.g. 2025-01-01 or 2025-01-01 12:00)")
+    }
+
+    fn from_naive(datetime: NaiveDateTime) -> Result<Self> {
+        let timestamp = datetime
+            .and_local_timezone(Local)
+            .earliest()
+            .context("failed to convert to local timezone")?;
+        Ok(Self(timestamp))
+    }
+
     #[must_use]
     pub fn shift(&self, range: Range, amount: i32) -> Self {
         let timestamp = match range {
@@ -38,6 +67,28 @@ impl Timestamp {
         Self(timestamp)
     }
 
+    #[must_use]
+    pub fn add_span(&self, span: Span) -> Self {
+        self.shift_span(span, span.amount)
+    }
+
+    #[must_use]
+    pub fn sub_span(&self, span: Span) -> Self {
+        self.shift_span(span, -span.amount)
+    }
+
+    fn shift_span(&self, span: Span, amount: i32) -> Self {
+        match span.unit {
+            SpanUnit::Second => Self(self.0 + Duration::seconds(amount.into())),
+            SpanUnit::Minute => self.shift(Range::Minute, amount),
+            SpanUnit::Hour => self.shift(Range::Hour, amount),
+            SpanUnit::Day => self.shift(Range::Day, amount),
+            SpanUnit::Week => Self(self.0 + Duration::weeks(amount.into())),
+            SpanUnit::Month => self.shift(Range::Month, amount),
+            SpanUnit::Year => self.shift(Range::Year, amount),
+        }
+    }
+
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn floor(&self, range: Range) -> Self {
@@ -60,10 +111,44 @@ impl Timestamp {
         }
     }
 
+    /// The absolute position of the timestamp's bucket in a range, e.g. days since the epoch.
+    #[must_use]
+    pub fn ordinal(&self, range: Range) -> i64 {
+        let naive = self.0.naive_local();
+        match range {
+            Range::Minute => naive.and_utc().timestamp().div_euclid(60),
+            Range::Hour => naive.and_utc().timestamp().div_euclid(60 * 60),
+            Range::Day => i64::from(naive.date().num_days_from_ce()),
+            Range::Month => i64::from(naive.year()) * 12 + i64::from(naive.month0()),
+            Range::Year => i64::from(naive.year()),
+        }
+    }
+
     #[must_use]
     pub fn humanized(&self) -> String {
         self.0.format("%Y-%m-%d %H:%M:%S").to_string()
     }
+
+    /// How long before (or after) `now` this is, in the largest whole unit: "3 hours ago".
+    #[must_use]
+    pub fn relative(&self, now: Self) -> String {
+        let delta = now.0 - self.0;
+        let seconds = delta.num_seconds().abs();
+        let (amount, unit) = match seconds {
+            0..60 => (seconds, "second"),
+            60..3600 => (seconds / 60, "minute"),
+            3600..86_400 => (seconds / 3600, "hour"),
+            86_400..2_592_000 => (seconds / 86_400, "day"),
+            2_592_000..31_536_000 => (seconds / 2_592_000, "month"),
+            _ => (seconds / 31_536_000, "year"),
+        };
+        let plural = if amount == 1 { "" } else { "s" };
+        if delta < Duration::zero() {
+            format!("in {amount} {unit}{plural}")
+        } else {
+            format!("{amount} {unit}{plural} ago")
+        }
+    }
 }
 
 impl std::fmt::Display for Timestamp {
@@ -72,7 +157,30 @@ impl std::fmt::Display for Timestamp {
     }
 }
 
-#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, PartialOrd, Ord)]
+impl std::str::FromStr for Timestamp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse_input(s)
+    }
+}
+
+impl TryFrom<String> for Timestamp {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<Timestamp> for String {
+    fn from(value: Timestamp) -> Self {
+        value.0.to_rfc3339()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Range {
     Minute = 1,
     Hour = 2,
@@ -80,3 +188,162 @@ pub enum Range {
     Month = 4,
     Year = 5,
 }
+
+/// A calendar-aware span of time, parsed from strings such as `30s`, `15m`, `24h`, `7d`, `2w`,
+/// `6M` or `1y`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Span {
+    pub amount: i32,
+    pub unit: SpanUnit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpanUnit {
+    Second,
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl SpanUnit {
+    fn letter(self) -> char {
+        match self {
+            Self::Second => 's',
+            Self::Minute => 'm',
+            Self::Hour => 'h',
+            Self::Day => 'd',
+            Self::Week => 'w',
+            Self::Month => 'M',
+            Self::Year => 'y',
+        }
+    }
+}
+
+impl Span {
+    /// The length of the span, unless it is in months or years which vary in length.
+    #[must_use]
+    pub fn fixed_duration(self) -> Option<std::time::Duration> {
+        let unit_seconds = match self.unit {
+            SpanUnit::Second => 1,
+            SpanUnit::Minute => 60,
+            SpanUnit::Hour => 60 * 60,
+            SpanUnit::Day => 24 * 60 * 60,
+            SpanUnit::Week => 7 * 24 * 60 * 60,
+            SpanUnit::Month | SpanUnit::Year => return None,
+        };
+        let seconds = u64::from(self.amount.unsigned_abs()) * unit_seconds;
+        Some(std::time::Duration::from_secs(seconds))
+    }
+}
+
+impl std::str::FromStr for Span {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let Some(letter) = s.chars().last() else {
+            anyhow::bail!("empty span");
+        };
+        let unit = match letter {
+            's' => SpanUnit::Second,
+            'm' => SpanUnit::Minute,
+            'h' => SpanUnit::Hour,
+            'd' => SpanUnit::Day,
+            'w' => SpanUnit::Week,
+            'M' => SpanUnit::Month,
+            'y' | 'Y' => SpanUnit::Year,
+            _ => anyhow::bail!("invalid span unit in {s:?} (expected one of s, m, h, d, w, M, y)"),
+        };
+        let amount: u16 = s[..s.len() - letter.len_utf8()]
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid span amount in {s:?}"))?;
+        Ok(Self {
+            amount: amount.into(),
+            unit,
+        })
+    }
+}
+
+impl TryFrom<String> for Span {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<Span> for String {
+    fn from(value: Span) -> Self {
+        value.to_string()
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.amount, self.unit.letter())
+    }
+}
+
+/// A point in time given either as a timestamp or as a span before now (e.g. `7d`).
+#[derive(Debug, Clone, Copy)]
+pub enum TimeBound {
+    At(Timestamp),
+    Ago(Span),
+}
+
+impl TimeBound {
+    #[must_use]
+    pub fn resolve(self, now: Timestamp) -> Timestamp {
+        match self {
+            Self::At(timestamp) => timestamp,
+            Self::Ago(span) => now.sub_span(span),
+        }
+    }
+}
+
+impl std::str::FromStr for TimeBound {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(span) = s.parse() {
+            return Ok(Self::Ago(span));
+        }
+        Timestamp::parse_input(s)
+            .map(Self::At)
+            .with_context(|| format!("expected a timestamp or a span like 7d, got {s:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_span() {
+        let span: Span = "7d".parse().unwrap();
+        assert_eq!(span.amount, 7);
+        assert_eq!(span.unit, SpanUnit::Day);
+        assert_eq!("6M".parse::<Span>().unwrap().unit, SpanUnit::Month);
+        assert_eq!("15m".parse::<Span>().unwrap().unit, SpanUnit::Minute);
+        assert_eq!("2w".parse::<Span>().unwrap().to_string(), "2w");
+        assert!("7".parse::<Span>().is_err());
+        assert!("-1d".parse::<Span>().is_err());
+    }
+
+    #[test]
+    fn relative_to_now() {
+        let now: Timestamp = "2025-01-10_12-00-00".parse().unwrap();
+        let relative = |at: &str| at.parse::<Timestamp>().unwrap().relative(now);
+        assert_eq!(relative("2025-01-10_12-00-00"), "0 seconds ago");
+        assert_eq!(relative("2025-01-10_11-59-00"), "1 minute ago");
+        assert_eq!(relative("2025-01-10_09-30-00"), "2 hours ago");
+        assert_eq!(relative("2025-01-03_12-00-00"), "7 days ago");
+        assert_eq!(relative("2023-01-10_12-00-00"), "2 years ago");
+        assert_eq!(relative("2025-01-10_12-00-30"), "in 30 seconds");
+    }
+}
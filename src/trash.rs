@@ -0,0 +1,108 @@
+use crate::config;
+use crate::entry::Entry;
+use crate::event::{Event, OnEvent};
+use crate::fsync;
+use crate::timestamp::{Span, Timestamp};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+const TRASH_DIR_NAME: &str = ".trash";
+
+/// Move `backups` and their sidecars into a trash directory named after `now`, reporting each to
+/// `on_event` once moved, and returning that directory. The moves are flushed to disk if
+/// `config` says so.
+pub fn move_to_trash(
+    target: &Path,
+    backups: &[Entry],
+    config: &config::Archive,
+    now: Timestamp,
+    on_event: Option<OnEvent>,
+) -> Result<PathBuf> {
+    let run_dir = trash_path(target).join(now.to_string());
+    let _span = tracing::info_span!("trash", dir = %run_dir.display()).entered();
+    std::fs::create_dir_all(&run_dir).context("create trash directory")?;
+    for backup in backups {
+        for path in backup.files() {
+            tracing::debug!(path = %path.display(), "moving to trash");
+            let file_name = path.file_name().context("no file name")?;
+            std::fs::rename(&path, run_dir.join(file_name))
+                .with_context(|| format!("move {} to trash", path.display()))?;
+        }
+        if let Some(on_event) = on_event {
+            on_event(&Event::Deleted {
+                path: backup.path.clone(),
+                timestamp: backup.timestamp,
+            });
+        }
+    }
+    fsync::dir(&run_dir, config.fsync)?;
+    fsync::dir(target, config.fsync)?;
+    Ok(run_dir)
+}
+
+/// Trash directories that have been kept for at least `retention` (or all of them).
+pub fn expired(target: &Path, retention: Option<Span>, now: Timestamp) -> Result<Vec<PathBuf>> {
+    let trash = trash_path(target);
+    if !trash.exists() {
+        return Ok(Vec::new());
+    }
+    let mut expired = Vec::new();
+    for dir in trash.read_dir().context("read trash directory")? {
+        let path = dir.context("read file from dir")?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Ok(trashed) = Timestamp::parse_input(name) else {
+            continue;
+        };
+        if retention.is_none_or(|retention| trashed.add_span(retention) <= now) {
+            expired.push(path);
+        }
+    }
+    expired.sort();
+    Ok(expired)
+}
+
+fn trash_path(target: &Path) -> PathBuf {
+    target.join(TRASH_DIR_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn trashes_backups_and_reports_them() {
+        let temp = tempfile::tempdir().unwrap();
+        let target = temp.path();
+        let config = crate::Config::default().archive;
+        let path = target.join(".rattlebeaver.2024-01-01_00-00-00.notes.txt");
+        std::fs::write(&path, "notes").unwrap();
+        let backup = Entry::from_path(path.clone(), &config).unwrap().unwrap();
+        let now = Timestamp::parse_input("2024-02-01 00:00:00").unwrap();
+        let events = RefCell::new(Vec::new());
+        let on_event = |event: &Event| events.borrow_mut().push(event.clone());
+        let run_dir = move_to_trash(
+            target,
+            std::slice::from_ref(&backup),
+            &config,
+            now,
+            Some(&on_event),
+        )
+        .unwrap();
+        assert!(!path.exists());
+        assert!(run_dir.join(path.file_name().unwrap()).exists());
+        assert_eq!(
+            events.into_inner(),
+            [Event::Deleted {
+                path,
+                timestamp: backup.timestamp
+            }]
+        );
+        let retention = "30d".parse().unwrap();
+        assert!(expired(target, Some(retention), now).unwrap().is_empty());
+        let later = now.add_span(retention);
+        assert_eq!(expired(target, Some(retention), later).unwrap(), [run_dir]);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::checksum;
+use crate::encryption::{self, Decryptor};
+use crate::entry::Entry;
+use crate::signature::PublicKey;
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::io::Read;
+use std::path::Path;
+
+/// The result of checking a backup against its recorded checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    Match,
+    Mismatch {
+        recorded: String,
+        actual: String,
+    },
+    /// The backup has no recorded checksum to compare against
+    Missing,
+}
+
+/// Re-hash `backup` and compare the digest with the checksum recorded in its sidecar.
+pub fn verify_checksum(backup: &Entry) -> Result<ChecksumStatus> {
+    let Some(recorded) = backup.meta.as_ref().and_then(|meta| meta.checksum.clone()) else {
+        return Ok(ChecksumStatus::Missing);
+    };
+    let actual = checksum::sha256_file(&backup.path)?;
+    if actual == recorded {
+        Ok(ChecksumStatus::Match)
+    } else {
+        Ok(ChecksumStatus::Mismatch { recorded, actual })
+    }
+}
+
+/// The result of checking the signature of a backup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureStatus {
+    Valid,
+    /// The signature was not made for this file by the secret key of the public key
+    Invalid,
+    /// The backup has no recorded signature
+    Unsigned,
+}
+
+/// Re-hash `backup` and check the signature in its sidecar against `public_key`.
+pub fn verify_signature(backup: &Entry, public_key: &PublicKey) -> Result<SignatureStatus> {
+    let Some(signature) = backup
+        .meta
+        .as_ref()
+        .and_then(|meta| meta.signature.as_ref())
+    else {
+        return Ok(SignatureStatus::Unsigned);
+    };
+    let actual = checksum::sha256_file(&backup.path)?;
+    if public_key.verify(&actual, signature) {
+        Ok(SignatureStatus::Valid)
+    } else {
+        Ok(SignatureStatus::Invalid)
+    }
+}
+
+/// Check that a `.tar.gz` archive can be fully decompressed and read, decrypting it first with
+/// `decryptor` if it is encrypted. Other files always pass, as do encrypted archives without a
+/// decryptor.
+pub fn verify_archive(path: &Path, decryptor: Option<&Decryptor>) -> Result<()> {
+    let name = path.file_name().context("no file name")?.to_string_lossy();
+    if !encryption::strip_suffix(&name).ends_with(".tar.gz") {
+        return Ok(());
+    }
+    let file = std::fs::File::open(path).context("open archive")?;
+    let file: Box<dyn Read> = match decryptor {
+        Some(decryptor) if encryption::is_encrypted(&name) => {
+            Box::new(decryptor.decrypt(file, &name)?)
+        }
+        None if encryption::is_encrypted(&name) => return Ok(()),
+        _ => Box::new(file),
+    };
+    read_tar_gz(file)
+}
+
+/// Read a `.tar.gz` archive to the end, failing if it is corrupted.
+pub fn read_tar_gz(reader: impl Read) -> Result<()> {
+    let mut archive = tar::Archive::new(GzDecoder::new(reader));
+    for entry in archive.entries().context("read archive")? {
+        let mut entry = entry.context("read archive entry")?;
+        std::io::copy(&mut entry, &mut std::io::sink()).context("read archive entry data")?;
+    }
+    // Decryption only fails at the end if the ciphertext was tampered with
+    let mut rest = archive.into_inner().into_inner();
+    std::io::copy(&mut rest, &mut std::io::sink()).context("read archive")?;
+    Ok(())
+}
@@ -0,0 +1,226 @@
+//! Noticing changes to sources, for backing them up as they change.
+//!
+//! Changes are reported by the platform's file notifications (inotify, FSEvents, ...) through
+//! `notify`, debounced per file, so a source is due once its changed files have settled.
+//! Directories are watched recursively, and single files through the directory holding them, so
+//! replacing a file (as editors do) or creating a missing one counts as a change too.
+
+use anyhow::{Context, Result};
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{DebounceEventResult, DebouncedEventKind, Debouncer};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// When changed sources are due to be backed up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchSettings {
+    /// How long a source must go unchanged before it is backed up, so a burst of writes makes one
+    /// backup rather than many
+    pub debounce: Duration,
+    /// Least time between two backups of the same source
+    pub min_interval: Duration,
+}
+
+/// Watches sources for changes.
+pub struct Watcher {
+    sources: Vec<Source>,
+    settings: WatchSettings,
+    /// Changes under here are ignored, so backups written into a watched source don't count
+    ignore: Option<PathBuf>,
+    events: Receiver<DebounceEventResult>,
+    /// Watches for as long as it is kept
+    _debouncer: Debouncer<RecommendedWatcher>,
+}
+
+#[derive(Debug)]
+struct Source {
+    /// As given, and returned when due
+    path: PathBuf,
+    /// Compared with the paths of changes
+    absolute: PathBuf,
+    /// Whether the source changed since its last backup
+    changed: bool,
+    last_backup: Option<Instant>,
+}
+
+impl Source {
+    /// Check if the source is due at `now`.
+    fn is_due(&self, now: Instant, settings: WatchSettings) -> bool {
+        let rested = (self.last_backup)
+            .is_none_or(|last_backup| now.duration_since(last_backup) >= settings.min_interval);
+        self.changed && rested
+    }
+}
+
+impl Watcher {
+    /// Start watching `paths`, taking their current state as unchanged.
+    pub fn new(paths: &[PathBuf], settings: WatchSettings, ignore: Option<&Path>) -> Result<Self> {
+        let ignore = ignore.map(std::path::absolute).transpose()?;
+        let (sender, events) = std::sync::mpsc::channel();
+        let mut debouncer = notify_debouncer_mini::new_debouncer(settings.debounce, sender)
+            .context("start watching for changes")?;
+        let mut sources = Vec::new();
+        for path in paths {
+            let absolute = std::path::absolute(path)?;
+            let (watched, mode) = if absolute.is_dir() {
+                (absolute.as_path(), RecursiveMode::Recursive)
+            } else {
+                let parent = absolute.parent().context("watch the root directory")?;
+                (parent, RecursiveMode::NonRecursive)
+            };
+            (debouncer.watcher().watch(watched, mode))
+                .with_context(|| format!("watch {}", watched.display()))?;
+            sources.push(Source {
+                path: path.clone(),
+                absolute,
+                changed: false,
+                last_backup: None,
+            });
+        }
+        Ok(Self {
+            sources,
+            settings,
+            ignore,
+            events,
+            _debouncer: debouncer,
+        })
+    }
+
+    /// Block until some sources are due to be backed up, returning them.
+    pub fn wait(&mut self) -> Result<Vec<PathBuf>> {
+        loop {
+            let now = Instant::now();
+            let due = self.poll(now)?;
+            if !due.is_empty() {
+                return Ok(due);
+            }
+            // Changed sources backed up too recently are due once their minimum interval passes
+            let until_due = (self.sources.iter())
+                .filter(|source| source.changed)
+                .filter_map(|source| source.last_backup)
+                .map(|last_backup| {
+                    (last_backup + self.settings.min_interval).saturating_duration_since(now)
+                })
+                .min();
+            let events = match until_due {
+                Some(timeout) => match self.events.recv_timeout(timeout) {
+                    Ok(events) => events,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => anyhow::bail!("stopped watching"),
+                },
+                None => self.events.recv().context("stopped watching")?,
+            };
+            self.take(events)?;
+        }
+    }
+
+    /// Take in the changes reported so far, returning the sources that are due to be backed up.
+    pub fn poll(&mut self, now: Instant) -> Result<Vec<PathBuf>> {
+        while let Ok(events) = self.events.try_recv() {
+            self.take(events)?;
+        }
+        Ok((self.sources.iter())
+            .filter(|source| source.is_due(now, self.settings))
+            .map(|source| source.path.clone())
+            .collect())
+    }
+
+    /// Mark the sources with settled changes in `events` as changed.
+    fn take(&mut self, events: DebounceEventResult) -> Result<()> {
+        let events = events.context("watch for changes")?;
+        // Paths still changing are reported again once they settle
+        let settled = events
+            .iter()
+            .filter(|event| event.kind == DebouncedEventKind::Any)
+            .filter(|event| {
+                (self.ignore.as_deref()).is_none_or(|ignore| !event.path.starts_with(ignore))
+            });
+        for event in settled {
+            for source in &mut self.sources {
+                if event.path.starts_with(&source.absolute) {
+                    source.changed = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that `path` was backed up at `now`, whether or not that succeeded, so a failing
+    /// source is retried after the minimum interval rather than right away.
+    pub fn backed_up(&mut self, path: &Path, now: Instant) {
+        if let Some(source) = self.sources.iter_mut().find(|source| source.path == path) {
+            source.changed = false;
+            source.last_backup = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_interval() {
+        let settings = WatchSettings {
+            debounce: Duration::from_secs(10),
+            min_interval: Duration::from_secs(60),
+        };
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut source = Source {
+            path: PathBuf::from("source"),
+            absolute: PathBuf::from("/source"),
+            changed: false,
+            last_backup: None,
+        };
+        assert!(!source.is_due(at(0), settings));
+        source.changed = true;
+        assert!(source.is_due(at(1), settings));
+        source.changed = false;
+        source.last_backup = Some(at(1));
+        assert!(!source.is_due(at(40), settings));
+        source.changed = true;
+        assert!(!source.is_due(at(60), settings));
+        assert!(source.is_due(at(61), settings));
+    }
+
+    #[test]
+    fn notices_changes() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        let file = temp.path().join("file.txt");
+        let target = source.join("backups");
+        std::fs::create_dir_all(&target).unwrap();
+        let settings = WatchSettings {
+            debounce: Duration::from_millis(50),
+            min_interval: Duration::ZERO,
+        };
+        let sources = [source.clone(), file.clone()];
+        let mut watcher = Watcher::new(&sources, settings, Some(&target)).unwrap();
+        std::fs::write(target.join("backup"), "ignored").unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(watcher.poll(Instant::now()).unwrap().is_empty());
+        std::fs::write(source.join("notes.txt"), "changed").unwrap();
+        assert_eq!(watcher.wait().unwrap(), std::slice::from_ref(&source));
+        watcher.backed_up(&source, Instant::now());
+        std::fs::write(&file, "created").unwrap();
+        assert_eq!(watcher.wait().unwrap(), [file]);
+    }
+
+    #[test]
+    fn waits_out_min_interval() {
+        let temp = tempfile::tempdir().unwrap();
+        let settings = WatchSettings {
+            debounce: Duration::from_millis(50),
+            min_interval: Duration::from_millis(300),
+        };
+        let source = temp.path().to_owned();
+        let mut watcher = Watcher::new(std::slice::from_ref(&source), settings, None).unwrap();
+        let backed_up = Instant::now();
+        watcher.backed_up(&source, backed_up);
+        std::fs::write(source.join("notes.txt"), "changed").unwrap();
+        assert_eq!(watcher.wait().unwrap(), std::slice::from_ref(&source));
+        assert!(backed_up.elapsed() >= settings.min_interval);
+    }
+}
@@ -0,0 +1,257 @@
+//! HTTP webhooks fired when backups are added or fail to be, and when stale ones are deleted.
+//!
+//! Each webhook gets a JSON payload summarizing the run. It has a `text` field with a one-line
+//! summary, which is what Slack and compatible services display.
+//!
+//! Monitoring services like healthchecks.io are pinged separately, at the start and end of
+//! every run, so they notice when backups stop running altogether.
+
+use crate::config::Webhook;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// What a webhook is fired for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Event {
+    /// Backups were added without errors
+    Success,
+    /// Adding backups failed
+    Failure,
+    /// Stale backups were deleted
+    Delete,
+}
+
+impl Event {
+    pub const ALL: [Self; 3] = [Self::Success, Self::Failure, Self::Delete];
+}
+
+/// Why backups were deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteReason {
+    /// They fulfilled no retention range
+    Stale,
+    /// They were picked by hand
+    Selected,
+    /// They were older than a given age
+    OlderThan,
+}
+
+/// A source that failed to be backed up.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Failure {
+    /// The source, unless the run failed as a whole
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    pub error: String,
+}
+
+/// The summary of a run sent to webhooks.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Payload {
+    pub event: Event,
+    /// One-line summary
+    pub text: String,
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Backups added
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<PathBuf>,
+    /// Sources that failed to be backed up
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<Failure>,
+    /// Backups deleted
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deleted: Vec<PathBuf>,
+    /// Seconds the run took
+    pub duration: f64,
+}
+
+impl Payload {
+    /// The summary of adding backups to `target`, a success unless something `failed`.
+    #[must_use]
+    pub fn added(
+        target: &str,
+        added: Vec<PathBuf>,
+        failed: Vec<Failure>,
+        duration: std::time::Duration,
+    ) -> Self {
+        let (event, text) = if failed.is_empty() {
+            let text = format!("Added {} backup(s) to {target}", added.len());
+            (Event::Success, text)
+        } else {
+            let text = format!(
+                "Failed to back up {} source(s) to {target}: {}",
+                failed.len(),
+                failed[0].error
+            );
+            (Event::Failure, text)
+        };
+        Self {
+            event,
+            text,
+            target: target.to_owned(),
+            hostname: crate::meta::hostname(),
+            added,
+            failed,
+            deleted: Vec::new(),
+            duration: duration.as_secs_f64(),
+        }
+    }
+
+    /// The summary of deleting backups from `target` for `reason`.
+    #[must_use]
+    pub fn deleted(
+        target: &str,
+        reason: DeleteReason,
+        deleted: Vec<PathBuf>,
+        duration: std::time::Duration,
+    ) -> Self {
+        let kind = match reason {
+            DeleteReason::Stale => "stale",
+            DeleteReason::Selected => "selected",
+            DeleteReason::OlderThan => "old",
+        };
+        Self {
+            event: Event::Delete,
+            text: format!("Deleted {} {kind} backup(s) from {target}", deleted.len()),
+            target: target.to_owned(),
+            hostname: crate::meta::hostname(),
+            added: Vec::new(),
+            failed: Vec::new(),
+            deleted,
+            duration: duration.as_secs_f64(),
+        }
+    }
+}
+
+/// Send `payload` to every webhook subscribed to its event, returning the errors of those that
+/// failed. A failing webhook does not stop the others.
+#[must_use]
+pub fn send(webhooks: &[Webhook], payload: &Payload) -> Vec<anyhow::Error> {
+    webhooks
+        .iter()
+        .filter(|webhook| webhook.events.contains(&payload.event))
+        .filter_map(|webhook| {
+            send_one(webhook, payload)
+                .map_err(|e| e.context(format!("send webhook to {}", webhook.url)))
+                .err()
+        })
+        .collect()
+}
+
+/// How far a run got, for pinging a monitoring url.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ping {
+    Start,
+    Success,
+    Failure,
+}
+
+/// Ping the monitoring url `url` the way healthchecks.io expects: `<url>/start` when a run
+/// starts, `<url>` when it succeeds and `<url>/fail` when it fails, with `body` kept in its log.
+pub fn ping(url: &str, ping: Ping, body: &str) -> Result<()> {
+    let url = url.trim_end_matches('/');
+    let url = match ping {
+        Ping::Start => format!("{url}/start"),
+        Ping::Success => url.to_owned(),
+        Ping::Failure => format!("{url}/fail"),
+    };
+    post(&url, &BTreeMap::new(), "text/plain", body).with_context(|| format!("ping {url}"))
+}
+
+fn send_one(webhook: &Webhook, payload: &Payload) -> Result<()> {
+    let body = serde_json::to_string(payload)?;
+    post(&webhook.url, &webhook.headers, "application/json", &body)
+}
+
+#[cfg(feature = "webhooks")]
+fn post(
+    url: &str,
+    headers: &BTreeMap<String, String>,
+    content_type: &str,
+    body: &str,
+) -> Result<()> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(std::time::Duration::from_secs(30))
+        .build();
+    let mut request = agent.post(url).set("Content-Type", content_type);
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    match request.send_string(body) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(code, response)) => {
+            let body = response.into_string().unwrap_or_default();
+            anyhow::bail!("status {code}: {body}")
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(not(feature = "webhooks"))]
+fn post(
+    _url: &str,
+    _headers: &BTreeMap<String, String>,
+    _content_type: &str,
+    _body: &str,
+) -> Result<()> {
+    anyhow::bail!("built without the webhooks feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn summarizes_runs() {
+        let added = vec![PathBuf::from("a.tar"), PathBuf::from("b.tar")];
+        let payload = Payload::added("/backups", added.clone(), Vec::new(), Duration::ZERO);
+        assert_eq!(payload.event, Event::Success);
+        assert_eq!(payload.text, "Added 2 backup(s) to /backups");
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["event"], "success");
+        assert!(json.get("failed").is_none());
+        assert!(json.get("deleted").is_none());
+
+        let failed = vec![Failure {
+            source: Some(PathBuf::from("/src")),
+            error: "denied".to_owned(),
+        }];
+        let payload = Payload::added("/backups", added, failed, Duration::ZERO);
+        assert_eq!(payload.event, Event::Failure);
+        assert_eq!(
+            payload.text,
+            "Failed to back up 1 source(s) to /backups: denied"
+        );
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["failed"][0]["source"], "/src");
+
+        let deleted = vec![PathBuf::from("a.tar")];
+        let payload = Payload::deleted("/backups", DeleteReason::Stale, deleted, Duration::ZERO);
+        assert_eq!(payload.event, Event::Delete);
+        assert_eq!(payload.text, "Deleted 1 stale backup(s) from /backups");
+        let deleted = vec![PathBuf::from("a.tar"), PathBuf::from("b.tar")];
+        let payload = Payload::deleted("/backups", DeleteReason::Selected, deleted, Duration::ZERO);
+        assert_eq!(payload.text, "Deleted 2 selected backup(s) from /backups");
+    }
+
+    #[test]
+    fn sends_only_to_subscribed_webhooks() {
+        let webhook = |events| Webhook {
+            url: "http://127.0.0.1:1/hook".to_owned(),
+            events,
+            headers: BTreeMap::new(),
+        };
+        let payload = Payload::deleted("/backups", DeleteReason::Stale, Vec::new(), Duration::ZERO);
+        let webhooks = [webhook(vec![Event::Success]), webhook(vec![Event::Delete])];
+        let errors = send(&webhooks, &payload);
+        assert_eq!(errors.len(), 1);
+        assert!(format!("{:#}", errors[0]).contains("send webhook to http://127.0.0.1:1/hook"));
+    }
+}